@@ -0,0 +1,429 @@
+use rulox::{
+    ast::{
+        BinaryOperator, Expr, ExprKind, Function, Identifier, Literal, LogicalOperator, Position,
+        Program, Span, Stmt, StmtKind, UnaryOperator,
+    },
+    parser,
+    vm::VmError,
+};
+use serde_json::{Value, json};
+
+use crate::cli::OutputFormat;
+
+/// Prints the syntax tree of the source in the given format.
+///
+/// Statements that failed to parse are printed as errors, and the syntax
+/// errors are reported on stderr.
+pub fn dump_ast(source: &str, format: OutputFormat) -> Result<(), VmError> {
+    let parse = parser::parse(source);
+
+    match format {
+        OutputFormat::Human => print_program(&parse.program),
+        OutputFormat::Json => println!("{}", program_json(&parse.program)),
+    }
+
+    if parse.errors.is_empty() {
+        return Ok(());
+    }
+
+    for diagnostic in &parse.errors {
+        eprintln!("{}", diagnostic.error);
+    }
+
+    Err(VmError::Compilation)
+}
+
+/// Prints the statements of the program as an indented tree, one node per
+/// line followed by its span.
+fn print_program(program: &Program) {
+    println!("Program");
+    for statement in &program.statements {
+        print_stmt(statement, 1, None);
+    }
+}
+
+/// Prints a node at the given depth, labelled with its role in its parent if
+/// that isn't clear from its position.
+fn print_node(depth: usize, role: Option<&str>, label: &str, span: Span) {
+    let role = role.map(|role| format!("{}: ", role)).unwrap_or_default();
+    println!(
+        "{:indent$}{}{} {}-{}",
+        "",
+        role,
+        label,
+        span.start,
+        span.end,
+        indent = depth * 2
+    );
+}
+
+fn print_stmt(statement: &Stmt, depth: usize, role: Option<&str>) {
+    let node = |label: &str| print_node(depth, role, label, statement.span);
+    let depth = depth + 1;
+
+    match &statement.kind {
+        StmtKind::Expression(expr) => {
+            node("Expression");
+            print_expr(expr, depth, None);
+        }
+        StmtKind::Print(expr) => {
+            node("Print");
+            print_expr(expr, depth, None);
+        }
+        StmtKind::Var { name, initializer } => {
+            node(&format!("Var {}", name.name));
+            if let Some(initializer) = initializer {
+                print_expr(initializer, depth, None);
+            }
+        }
+        StmtKind::Function(function) => {
+            node(&format!("Function {}", signature(function)));
+            for statement in &function.body {
+                print_stmt(statement, depth, None);
+            }
+        }
+        StmtKind::Block(statements) => {
+            node("Block");
+            for statement in statements {
+                print_stmt(statement, depth, None);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            node("If");
+            print_expr(condition, depth, Some("condition"));
+            print_stmt(then_branch, depth, Some("then"));
+            if let Some(else_branch) = else_branch {
+                print_stmt(else_branch, depth, Some("else"));
+            }
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            node("While");
+            print_expr(condition, depth, Some("condition"));
+            print_stmt(body, depth, Some("body"));
+        }
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            node("For");
+            if let Some(initializer) = initializer {
+                print_stmt(initializer, depth, Some("initializer"));
+            }
+            if let Some(condition) = condition {
+                print_expr(condition, depth, Some("condition"));
+            }
+            if let Some(increment) = increment {
+                print_expr(increment, depth, Some("increment"));
+            }
+            print_stmt(body, depth, Some("body"));
+        }
+        StmtKind::Return(value) => {
+            node("Return");
+            if let Some(value) = value {
+                print_expr(value, depth, None);
+            }
+        }
+        StmtKind::Error => node("Error"),
+    }
+}
+
+fn print_expr(expr: &Expr, depth: usize, role: Option<&str>) {
+    let node = |label: &str| print_node(depth, role, label, expr.span);
+    let depth = depth + 1;
+
+    match &expr.kind {
+        ExprKind::Literal(literal) => node(&format!("Literal {}", literal_source(literal))),
+        ExprKind::Grouping(inner) => {
+            node("Grouping");
+            print_expr(inner, depth, None);
+        }
+        ExprKind::Variable(name) => node(&format!("Variable {}", name.name)),
+        ExprKind::Assign { name, value } => {
+            node(&format!("Assign {}", name.name));
+            print_expr(value, depth, None);
+        }
+        ExprKind::Unary { operator, operand } => {
+            node(&format!("Unary {}", unary_operator(*operator)));
+            print_expr(operand, depth, None);
+        }
+        ExprKind::Binary {
+            operator,
+            left,
+            right,
+        } => {
+            node(&format!("Binary {}", binary_operator(*operator)));
+            print_expr(left, depth, None);
+            print_expr(right, depth, None);
+        }
+        ExprKind::Logical {
+            operator,
+            left,
+            right,
+            ..
+        } => {
+            node(&format!("Logical {}", logical_operator(*operator)));
+            print_expr(left, depth, None);
+            print_expr(right, depth, None);
+        }
+        ExprKind::Call { callee, arguments } => {
+            node("Call");
+            print_expr(callee, depth, Some("callee"));
+            for argument in arguments {
+                print_expr(argument, depth, Some("argument"));
+            }
+        }
+        ExprKind::Get { object, name } => {
+            node(&format!("Get {}", name.name));
+            print_expr(object, depth, None);
+        }
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => {
+            node(&format!("Set {}", name.name));
+            print_expr(object, depth, Some("object"));
+            print_expr(value, depth, Some("value"));
+        }
+        ExprKind::This => node("This"),
+        ExprKind::Super { method } => node(&format!("Super {}", method.name)),
+        ExprKind::Error => node("Error"),
+    }
+}
+
+/// Returns the name of the function followed by its parameters, e.g. `add(a, b)`.
+fn signature(function: &Function) -> String {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .map(|param| param.name.as_str())
+        .collect();
+    format!("{}({})", function.name.name, params.join(", "))
+}
+
+/// Returns the literal as it would be written in Lox.
+fn literal_source(literal: &Literal) -> String {
+    match literal {
+        Literal::Nil => "nil".to_string(),
+        Literal::Bool(value) => value.to_string(),
+        Literal::Number(value) => value.to_string(),
+        Literal::String(value) => format!("\"{}\"", value),
+    }
+}
+
+fn unary_operator(operator: UnaryOperator) -> &'static str {
+    match operator {
+        UnaryOperator::Not => "!",
+        UnaryOperator::Negate => "-",
+    }
+}
+
+fn binary_operator(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterEqual => ">=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessEqual => "<=",
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+    }
+}
+
+fn logical_operator(operator: LogicalOperator) -> &'static str {
+    match operator {
+        LogicalOperator::And => "and",
+        LogicalOperator::Or => "or",
+    }
+}
+
+/// Returns the program as a JSON object, with every node an object whose
+/// `type` names its kind.
+fn program_json(program: &Program) -> Value {
+    json!({
+        "type": "Program",
+        "statements": statements_json(&program.statements),
+        "end": position_json(program.end),
+    })
+}
+
+fn position_json(position: Position) -> Value {
+    json!({ "line": position.line, "column": position.column })
+}
+
+fn span_json(span: Span) -> Value {
+    json!({ "start": position_json(span.start), "end": position_json(span.end) })
+}
+
+fn identifier_json(identifier: &Identifier) -> Value {
+    json!({ "name": identifier.name, "span": span_json(identifier.span) })
+}
+
+fn statements_json(statements: &[Stmt]) -> Vec<Value> {
+    statements.iter().map(stmt_json).collect()
+}
+
+/// Returns a node with the type and span, followed by its fields.
+fn node_json(node_type: &str, span: Span, fields: Value) -> Value {
+    let mut node = json!({ "type": node_type, "span": span_json(span) });
+    if let (Value::Object(node), Value::Object(fields)) = (&mut node, fields) {
+        node.extend(fields);
+    }
+    node
+}
+
+fn stmt_json(statement: &Stmt) -> Value {
+    let node = |node_type, fields| node_json(node_type, statement.span, fields);
+
+    match &statement.kind {
+        StmtKind::Expression(expr) => node("Expression", json!({ "expression": expr_json(expr) })),
+        StmtKind::Print(expr) => node("Print", json!({ "expression": expr_json(expr) })),
+        StmtKind::Var { name, initializer } => node(
+            "Var",
+            json!({
+                "name": identifier_json(name),
+                "initializer": initializer.as_ref().map(expr_json),
+            }),
+        ),
+        StmtKind::Function(function) => node(
+            "Function",
+            json!({
+                "name": identifier_json(&function.name),
+                "params": function.params.iter().map(identifier_json).collect::<Vec<_>>(),
+                "body": statements_json(&function.body),
+            }),
+        ),
+        StmtKind::Block(statements) => node(
+            "Block",
+            json!({ "statements": statements_json(statements) }),
+        ),
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => node(
+            "If",
+            json!({
+                "condition": expr_json(condition),
+                "then": stmt_json(then_branch),
+                "else": else_branch.as_deref().map(stmt_json),
+            }),
+        ),
+        StmtKind::While {
+            condition, body, ..
+        } => node(
+            "While",
+            json!({ "condition": expr_json(condition), "body": stmt_json(body) }),
+        ),
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => node(
+            "For",
+            json!({
+                "initializer": initializer.as_deref().map(stmt_json),
+                "condition": condition.as_ref().map(expr_json),
+                "increment": increment.as_ref().map(expr_json),
+                "body": stmt_json(body),
+            }),
+        ),
+        StmtKind::Return(value) => {
+            node("Return", json!({ "value": value.as_ref().map(expr_json) }))
+        }
+        StmtKind::Error => node("Error", json!({})),
+    }
+}
+
+fn expr_json(expr: &Expr) -> Value {
+    let node = |node_type, fields| node_json(node_type, expr.span, fields);
+
+    match &expr.kind {
+        ExprKind::Literal(literal) => {
+            let value = match literal {
+                Literal::Nil => Value::Null,
+                Literal::Bool(value) => json!(value),
+                Literal::Number(value) => json!(value),
+                Literal::String(value) => json!(value),
+            };
+            node("Literal", json!({ "value": value }))
+        }
+        ExprKind::Grouping(inner) => node("Grouping", json!({ "expression": expr_json(inner) })),
+        ExprKind::Variable(name) => node("Variable", json!({ "name": identifier_json(name) })),
+        ExprKind::Assign { name, value } => node(
+            "Assign",
+            json!({ "name": identifier_json(name), "value": expr_json(value) }),
+        ),
+        ExprKind::Unary { operator, operand } => node(
+            "Unary",
+            json!({ "operator": unary_operator(*operator), "operand": expr_json(operand) }),
+        ),
+        ExprKind::Binary {
+            operator,
+            left,
+            right,
+        } => node(
+            "Binary",
+            json!({
+                "operator": binary_operator(*operator),
+                "left": expr_json(left),
+                "right": expr_json(right),
+            }),
+        ),
+        ExprKind::Logical {
+            operator,
+            left,
+            right,
+            ..
+        } => node(
+            "Logical",
+            json!({
+                "operator": logical_operator(*operator),
+                "left": expr_json(left),
+                "right": expr_json(right),
+            }),
+        ),
+        ExprKind::Call { callee, arguments } => node(
+            "Call",
+            json!({
+                "callee": expr_json(callee),
+                "arguments": arguments.iter().map(expr_json).collect::<Vec<_>>(),
+            }),
+        ),
+        ExprKind::Get { object, name } => node(
+            "Get",
+            json!({ "object": expr_json(object), "name": identifier_json(name) }),
+        ),
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => node(
+            "Set",
+            json!({
+                "object": expr_json(object),
+                "name": identifier_json(name),
+                "value": expr_json(value),
+            }),
+        ),
+        ExprKind::This => node("This", json!({})),
+        ExprKind::Super { method } => node("Super", json!({ "method": identifier_json(method) })),
+        ExprKind::Error => node("Error", json!({})),
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use rulox::{
+    collections::{List, Record},
+    compiler::{self, CompileOptions},
+    function::Function,
+    register::{self, Code},
+    stdlib::Capability,
+    vm::{ExecutionMode, VmBuilder},
+};
+
+use crate::{cli::Backend, conformance::find_tests};
+
+const MODES: [ExecutionMode; 2] = [ExecutionMode::Checked, ExecutionMode::Fast];
+
+/// Returns a builder for the VMs benchmarks run on, discarding their output.
+fn vm_builder() -> VmBuilder<Vec<u8>, Vec<u8>> {
+    VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .allow(Capability::Time)
+        .class::<Record>()
+        .class::<List>()
+}
+
+/// Runs the program in the given mode, returning its output and how long it took.
+///
+/// The script is shared between runs so fast mode only decodes it once.
+fn time(script: &Arc<Function>, mode: ExecutionMode) -> Result<(Vec<u8>, Duration)> {
+    let mut vm = vm_builder().execution_mode(mode).build();
+
+    let start = Instant::now();
+    let result = vm.interpret_function(script.clone());
+    let elapsed = start.elapsed();
+
+    let (out, err) = vm.into_writers();
+    if result.is_err() {
+        bail!("{}", String::from_utf8_lossy(&err).trim_end());
+    }
+
+    Ok((out, elapsed))
+}
+
+/// Runs the program on the register VM, returning its output, how many
+/// instructions it executed and how long it took.
+fn time_register(code: &Code) -> Result<(Vec<u8>, u64, Duration)> {
+    let mut vm = vm_builder().build_register();
+
+    let start = Instant::now();
+    let result = vm.interpret(code);
+    let elapsed = start.elapsed();
+
+    let instructions = vm.instruction_count();
+    let (out, err) = vm.into_writers();
+    if result.is_err() {
+        bail!("{}", String::from_utf8_lossy(&err).trim_end());
+    }
+
+    Ok((out, instructions, elapsed))
+}
+
+/// Returns how many instructions the stack VM executes running the program,
+/// measured by the fuel it uses in a separate run that is not timed.
+fn count_instructions(script: &Arc<Function>) -> Result<u64> {
+    let mut vm = vm_builder().fuel(u64::MAX).build();
+    let result = vm.interpret_function(script.clone());
+    let remaining = vm.fuel().unwrap_or_default();
+
+    let (_, err) = vm.into_writers();
+    if result.is_err() {
+        bail!("{}", String::from_utf8_lossy(&err).trim_end());
+    }
+
+    Ok(u64::MAX - remaining)
+}
+
+/// Finds the programs to run, given as files or directories of them.
+fn find_programs(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut programs = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            find_tests(path, &mut programs)?;
+        } else {
+            programs.push(path.to_path_buf());
+        }
+    }
+
+    programs.sort();
+    Ok(programs)
+}
+
+/// Times each program, compiled with the options, in every execution mode, printing the fastest of `runs`
+/// runs and the speedup over checked execution.
+///
+/// With the register backend, compares the stack VM in fast mode with the register VM instead.
+///
+/// Fails if a program fails, or prints something different in another mode.
+pub fn run_benchmarks(
+    paths: &[String],
+    runs: u32,
+    options: CompileOptions,
+    backend: Backend,
+) -> Result<()> {
+    let programs = find_programs(paths)?;
+    if backend == Backend::Register {
+        return compare_backends(&programs, runs, options);
+    }
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>8}",
+        "benchmark", "checked", "fast", "speedup"
+    );
+
+    for path in &programs {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read program from {}", path.display()))?;
+        let script = compiler::compile_with_options(&source, options)
+            .map(|chunk| Arc::new(Function::script(chunk)))
+            .with_context(|| format!("Failed to compile {}", path.display()))?;
+
+        let mut output = None;
+        let mut times = Vec::new();
+
+        for mode in MODES {
+            let mut best = Duration::MAX;
+
+            for _ in 0..runs.max(1) {
+                let (out, elapsed) = time(&script, mode)
+                    .with_context(|| format!("{} failed in {:?} mode", path.display(), mode))?;
+
+                if output.get_or_insert_with(|| out.clone()) != &out {
+                    bail!(
+                        "{} printed something else in {:?} mode",
+                        path.display(),
+                        mode
+                    );
+                }
+
+                best = best.min(elapsed);
+            }
+
+            times.push(best);
+        }
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        println!(
+            "{:<20} {:>11.3}s {:>11.3}s {:>7.2}x",
+            name,
+            times[0].as_secs_f64(),
+            times[1].as_secs_f64(),
+            times[0].as_secs_f64() / times[1].as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs each program on the stack VM in fast mode and on the register VM,
+/// printing how many instructions each executed, the fastest of `runs` runs
+/// and the speedup of the register VM.
+fn compare_backends(programs: &[PathBuf], runs: u32, options: CompileOptions) -> Result<()> {
+    println!(
+        "{:<20} {:>16} {:>16} {:>12} {:>12} {:>8}",
+        "benchmark", "stack instrs", "register instrs", "stack", "register", "speedup"
+    );
+
+    for path in programs {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read program from {}", path.display()))?;
+        let script = compiler::compile_with_options(&source, options)
+            .map(|chunk| Arc::new(Function::script(chunk)))
+            .with_context(|| format!("Failed to compile {}", path.display()))?;
+        let code = register::compile_with_options(&source, options)
+            .with_context(|| format!("Failed to compile {}", path.display()))?;
+
+        let stack_instructions = count_instructions(&script)
+            .with_context(|| format!("{} failed on the stack VM", path.display()))?;
+
+        let mut stack_output = None;
+        let mut stack_time = Duration::MAX;
+        for _ in 0..runs.max(1) {
+            let (out, elapsed) = time(&script, ExecutionMode::Fast)
+                .with_context(|| format!("{} failed on the stack VM", path.display()))?;
+            stack_output = Some(out);
+            stack_time = stack_time.min(elapsed);
+        }
+
+        let mut register_instructions = 0;
+        let mut register_time = Duration::MAX;
+        for _ in 0..runs.max(1) {
+            let (out, instructions, elapsed) = time_register(&code)
+                .with_context(|| format!("{} failed on the register VM", path.display()))?;
+
+            if stack_output.as_ref() != Some(&out) {
+                bail!(
+                    "{} printed something else on the register VM",
+                    path.display()
+                );
+            }
+
+            register_instructions = instructions;
+            register_time = register_time.min(elapsed);
+        }
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        println!(
+            "{:<20} {:>16} {:>16} {:>11.3}s {:>11.3}s {:>7.2}x",
+            name,
+            stack_instructions,
+            register_instructions,
+            stack_time.as_secs_f64(),
+            register_time.as_secs_f64(),
+            stack_time.as_secs_f64() / register_time.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, ValueEnum};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, error::ErrorKind};
 use tracing::Level;
 
 // pub const EX_DATAERR: u8 = 65;
@@ -8,6 +8,9 @@ use tracing::Level;
 #[derive(Parser)]
 #[clap(author, version, about)]
 pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to a Lox program to execute
     pub path: Option<String>,
 
@@ -20,19 +23,167 @@ pub struct Args {
     pub repl: bool,
 
     /// Enable verbose output, equivalent to setting the trace log level
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     pub verbose: bool,
 
     /// Log level to use
-    #[clap(short, long, value_enum, default_value_t = LogLevel::Warn)]
+    #[clap(short, long, value_enum, default_value_t = LogLevel::Warn, global = true)]
     level: LogLevel,
 
     /// Disassemble the input program
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     pub disassemble: bool,
+
+    /// Print the tokens of the input program instead of running it
+    #[clap(long, conflicts_with = "repl")]
+    pub tokens: bool,
+
+    /// Print the syntax tree of the input program instead of running it
+    #[clap(long, conflicts_with_all = ["repl", "tokens"])]
+    pub dump_ast: bool,
+
+    /// Output format to use for --tokens and --dump-ast
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
+    /// Allow programs to use a module of the standard library, can be repeated
+    #[clap(long, value_enum, global = true)]
+    pub allow: Vec<Module>,
+
+    /// Directory programs may access files in with --allow fs, defaults to the current directory
+    #[clap(long, global = true)]
+    pub fs_root: Option<String>,
+
+    /// Verify bytecode before running it, then execute it without checking each instruction
+    #[clap(long, global = true)]
+    pub fast: bool,
+
+    /// Optimization level: 0 compiles programs as written, 1 folds constants and removes dead code, 2 also fuses instructions
+    #[clap(short = 'O', long, global = true, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    pub opt_level: u8,
+
+    /// Compiler backend and VM to run programs with
+    #[clap(long, value_enum, default_value_t = Backend::Stack, global = true)]
+    pub backend: Backend,
+}
+
+/// The compiler backends, each with its own VM.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Bytecode for the stack VM
+    Stack,
+
+    /// Three-address instructions for the register VM, which has no REPL or bytecode files
+    Register,
+}
+
+/// The modules of the standard library that need to be allowed, `time` always is.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Module {
+    /// Reading from stdin
+    Io,
+
+    /// Reading and writing files
+    Fs,
+
+    /// Reading environment variables
+    Os,
+
+    /// Reading the system clock
+    Time,
+
+    /// Generating random numbers
+    Random,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text
+    Human,
+
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compile a Lox program to a bytecode file
+    Compile {
+        /// Path to the Lox program to compile
+        path: String,
+
+        /// Path to write the bytecode to, defaults to the program path with a .loxc extension
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+
+    /// Run a Lox program or a compiled bytecode file
+    Run {
+        /// Path to the Lox program or bytecode file to run
+        path: String,
+    },
+
+    /// Run a directory of Lox tests annotated in the style of the Crafting Interpreters test suite
+    Test {
+        /// Path to the directory containing the tests
+        path: String,
+
+        /// Only run tests whose path within the directory starts with this, e.g. "string" or "operator/add"
+        #[clap(short, long)]
+        filter: Vec<String>,
+    },
+
+    /// Time Lox programs in each execution mode of the VM, or on both VMs with --backend register
+    Bench {
+        /// Lox programs, or directories of them, to time
+        paths: Vec<String>,
+
+        /// How many times to run each program in each mode, the fastest run is reported
+        #[clap(long, default_value_t = 3)]
+        runs: u32,
+    },
+
+    /// Run programs on both the bytecode VM and the tree-walking interpreter, reporting any differences
+    Diff {
+        /// Lox programs, or directories of them, to check
+        paths: Vec<String>,
+
+        /// Also check this many randomly generated programs
+        #[clap(long, default_value_t = 0)]
+        random: u64,
+
+        /// Seed for the random programs, the same seed always generates the same programs
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
 }
 
 impl Args {
+    /// Parses the arguments, rejecting those that only apply without a
+    /// subcommand when one is given. The global options may come before it.
+    pub fn try_parse_checked() -> Result<Self, clap::Error> {
+        let mut command = Self::command();
+        let args = Self::from_arg_matches(&command.try_get_matches_from_mut(std::env::args_os())?)?;
+        if args.command.is_none() {
+            return Ok(args);
+        }
+
+        let ignored = [
+            ("[PATH]", args.path.is_some()),
+            ("-e <CODE>", args.code.is_some()),
+            ("--repl", args.repl),
+            ("--tokens", args.tokens),
+            ("--dump-ast", args.dump_ast),
+        ];
+        match ignored.iter().find(|(_, given)| *given) {
+            Some((name, _)) => Err(command.error(
+                ErrorKind::ArgumentConflict,
+                format!("the argument '{}' cannot be used with a subcommand", name),
+            )),
+            None => Ok(args),
+        }
+    }
+
     pub fn log_level(&self) -> Level {
         match self.level {
             LogLevel::Trace => Level::TRACE,
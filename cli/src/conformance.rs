@@ -0,0 +1,265 @@
+use std::{
+    fs,
+    io::Write,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use rulox::{
+    compiler::{self, CompileOptions},
+    register,
+    stdlib::Capability,
+    vm::{ExecutionMode, InterpretResult, VmBuilder, VmError},
+};
+
+use crate::cli::Backend;
+
+/// What a test file expects to happen when it is run, taken from its comments.
+///
+/// Uses the annotations of the [Crafting Interpreters test suite][suite]:
+///
+///  * `// expect: <output>` - a line printed to stdout.
+///  * `// expect runtime error: <message>` - a runtime error on that line.
+///  * `// Error at '<token>': <message>` - a compile error on that line.
+///  * `// [line <n>] Error ...` - a compile error on line `n`, optionally
+///    prefixed by an implementation (`[c line <n>]`) to only apply to that one.
+///
+/// [suite]: https://github.com/munificent/craftinginterpreters/tree/master/test
+#[derive(Debug, Default)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+
+            if let Some((_, output)) = line.split_once("// expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if let Some((_, error)) = line.split_once("// Error") {
+                expectations
+                    .compile_errors
+                    .push(format!("[line {}] Error{}", line_number, error));
+            } else if let Some((_, annotation)) = line.split_once("// [")
+                && let Some(error) = Self::parse_line_error(annotation)
+            {
+                expectations.compile_errors.push(error);
+            }
+        }
+
+        expectations
+    }
+
+    /// Parses a `[line <n>] Error ...` annotation, with the leading `[` removed.
+    ///
+    /// Returns `None` for annotations aimed at other implementations.
+    fn parse_line_error(annotation: &str) -> Option<String> {
+        let annotation = match annotation.split_once(' ') {
+            Some(("c", rest)) => rest,
+            Some(("java", _)) => return None,
+            _ => annotation,
+        };
+
+        let (line, error) = annotation.strip_prefix("line ")?.split_once("] ")?;
+        let line: usize = line.parse().ok()?;
+
+        Some(format!("[line {}] {}", line, error))
+    }
+
+    fn exit_code(&self) -> u8 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+/// What actually happened when a test file was run.
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    exit_code: u8,
+}
+
+/// Runs a program the same way the CLI would, capturing its output.
+fn execute(
+    source: &str,
+    mode: ExecutionMode,
+    options: CompileOptions,
+    backend: Backend,
+) -> Outcome {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+
+    let builder = VmBuilder::new()
+        .out(&mut out)
+        .err(&mut err)
+        .allow(Capability::Time)
+        .execution_mode(mode);
+
+    let result = match backend {
+        Backend::Stack => compiler::compile_with_options(source, options)
+            .map(|chunk| builder.build().interpret(&chunk)),
+        Backend::Register => register::compile_with_options(source, options)
+            .map(|code| builder.build_register().interpret(&code)),
+    };
+
+    let exit_code = match result {
+        Err(error) => {
+            let _ = writeln!(err, "{}", error);
+            65
+        }
+        Ok(result) => exit_code(result),
+    };
+
+    Outcome {
+        stdout: String::from_utf8_lossy(&out).into_owned(),
+        stderr: String::from_utf8_lossy(&err).into_owned(),
+        exit_code,
+    }
+}
+
+fn exit_code(result: InterpretResult) -> u8 {
+    match result {
+        Ok(()) => 0,
+        Err(VmError::Compilation) => 65,
+        Err(VmError::Runtime(_)) => 70,
+    }
+}
+
+/// Runs a single test file, returning a description of every failed expectation.
+fn run_test(
+    path: &Path,
+    mode: ExecutionMode,
+    options: CompileOptions,
+    backend: Backend,
+) -> Result<Vec<String>> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read test from {}", path.display()))?;
+    let expectations = Expectations::parse(&source);
+
+    let Ok(outcome) = panic::catch_unwind(AssertUnwindSafe(|| {
+        execute(&source, mode, options, backend)
+    })) else {
+        return Ok(vec!["Interpreter panicked".to_string()]);
+    };
+
+    let mut failures = Vec::new();
+
+    let output: Vec<_> = outcome.stdout.lines().collect();
+    if output != expectations.output {
+        failures.push(format!(
+            "Expected output {:?}, got {:?}",
+            expectations.output, output
+        ));
+    }
+
+    let errors: Vec<_> = outcome.stderr.lines().collect();
+    if let Some((message, line)) = &expectations.runtime_error {
+        let trace = format!("[line {}]", line);
+        let matches = errors.first() == Some(&message.as_str())
+            && errors.get(1).is_some_and(|error| error.starts_with(&trace));
+
+        if !matches {
+            failures.push(format!(
+                "Expected runtime error {:?} on line {}, got {:?}",
+                message, line, errors
+            ));
+        }
+    } else if errors != expectations.compile_errors {
+        failures.push(format!(
+            "Expected errors {:?}, got {:?}",
+            expectations.compile_errors, errors
+        ));
+    }
+
+    if outcome.exit_code != expectations.exit_code() {
+        failures.push(format!(
+            "Expected exit code {}, got {}",
+            expectations.exit_code(),
+            outcome.exit_code
+        ));
+    }
+
+    Ok(failures)
+}
+
+/// Finds all `.lox` files under the given directory.
+pub fn find_tests(dir: &Path, tests: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read tests from {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, tests)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            tests.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs every test under `root`, reporting failures and a summary.
+///
+/// If any filters are given, only tests whose path relative to `root` starts
+/// with one of them are run, e.g. `string` or `operator/add`.
+pub fn run_tests(
+    root: &str,
+    filters: &[String],
+    mode: ExecutionMode,
+    options: CompileOptions,
+    backend: Backend,
+) -> Result<()> {
+    let root = Path::new(root);
+    let mut tests = Vec::new();
+    find_tests(root, &mut tests)?;
+    tests.sort();
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for path in &tests {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let selected = filters.is_empty()
+            || filters
+                .iter()
+                .any(|filter| relative.to_string_lossy().starts_with(filter.as_str()));
+
+        if !selected {
+            continue;
+        }
+
+        let failures = run_test(path, mode, options, backend)?;
+        if failures.is_empty() {
+            passed += 1;
+            continue;
+        }
+
+        failed += 1;
+        println!("FAIL {}", relative.display());
+        for failure in failures {
+            println!("     {}", failure);
+        }
+    }
+
+    println!("Passed: {}, failed: {}", passed, failed);
+
+    if failed > 0 {
+        bail!("{} of {} tests failed", failed, passed + failed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use rulox::{differential, generate::Generator};
+
+use crate::conformance::find_tests;
+
+/// Checks that the VM and the tree-walking interpreter agree on the programs
+/// at the given paths, and on `random` generated programs.
+pub fn check_programs(paths: &[String], random: u64, seed: u64) -> Result<()> {
+    let mut programs = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            find_tests(path, &mut programs)?;
+        } else {
+            programs.push(path.to_path_buf());
+        }
+    }
+
+    programs.sort();
+
+    let mut checked = 0;
+    let mut mismatched = 0;
+
+    for path in &programs {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read program from {}", path.display()))?;
+
+        checked += 1;
+        if let Err(mismatch) = differential::check(&source) {
+            mismatched += 1;
+            println!("MISMATCH {}", path.display());
+            print!("{}", mismatch);
+        }
+    }
+
+    let mut generator = Generator::new(seed);
+    for index in 0..random {
+        let source = generator.program();
+
+        checked += 1;
+        if let Err(mismatch) = differential::check(&source) {
+            mismatched += 1;
+            println!("MISMATCH random program {} (seed {})", index, seed);
+            println!("== source ==");
+            println!("{}", source);
+            print!("{}", mismatch);
+        }
+    }
+
+    println!("Checked: {}, mismatched: {}", checked, mismatched);
+
+    if mismatched > 0 {
+        bail!("{} of {} programs behaved differently", mismatched, checked);
+    }
+
+    Ok(())
+}
@@ -1,18 +1,27 @@
+mod ast;
+mod bench;
 mod cli;
+mod conformance;
+mod differential;
 mod logging;
+mod repl;
+mod tokens;
 
 use std::{
     fs,
     io::{self, Read, Write},
+    path::{Path, PathBuf},
     process::ExitCode,
 };
 
-use crate::cli::Args;
-use anyhow::{Context, Result};
-use clap::Parser;
+use crate::cli::{Args, Backend, Command, Module};
+use anyhow::{Context, Result, bail};
 use rulox::{
-    compiler,
-    vm::{self, InterpretResult, VmError},
+    compiler::{self, Chunk, CompileOptions, OptLevel},
+    register,
+    serialize::{self, DeserializeError},
+    stdlib::Capability,
+    vm::{ExecutionMode, InterpretResult, VM, VmBuilder, VmError},
 };
 use tracing::Level;
 
@@ -32,7 +41,11 @@ fn main() -> ExitCode {
 
         for cause in err.chain() {
             if let Some(vm_err) = cause.downcast_ref::<VmError>() {
-                return (*vm_err).into();
+                return vm_err.clone().into();
+            }
+
+            if cause.downcast_ref::<DeserializeError>().is_some() {
+                return ExitCode::from(65);
             }
 
             if cause.downcast_ref::<io::Error>().is_some() {
@@ -47,7 +60,7 @@ fn main() -> ExitCode {
 }
 
 fn try_main() -> Result<()> {
-    let args = Args::try_parse()?;
+    let args = Args::try_parse_checked()?;
     let log_level = if args.verbose {
         Level::TRACE
     } else {
@@ -55,40 +68,217 @@ fn try_main() -> Result<()> {
     };
     logging::init_logging(log_level);
 
-    let mut out = &mut io::stdout();
-    let mut err = &mut io::stderr();
-    let mut vm = vm::VM::new(&mut out, &mut err);
+    let mut vm = vm_builder(&args)?.build();
+
+    match &args.command {
+        Some(Command::Compile { .. }) if args.backend == Backend::Register => {
+            bail!("Only the stack backend compiles to bytecode files");
+        }
+        Some(Command::Compile { path, output }) => {
+            return compile_to_file(path, output.as_deref(), &args);
+        }
+        Some(Command::Run { path }) => return run_file(&mut vm, path, &args),
+        Some(Command::Test { path, filter }) => {
+            return conformance::run_tests(
+                path,
+                filter,
+                execution_mode(&args),
+                compile_options(&args),
+                args.backend,
+            );
+        }
+        Some(Command::Bench { paths, runs }) => {
+            return bench::run_benchmarks(paths, *runs, compile_options(&args), args.backend);
+        }
+        Some(Command::Diff {
+            paths,
+            random,
+            seed,
+        }) => return differential::check_programs(paths, *random, *seed),
+        None => {}
+    }
+
+    if args.backend == Backend::Register && args.repl {
+        bail!("The REPL only runs on the stack backend");
+    }
 
     if args.repl {
-        return repl();
+        if args.code.is_some() || args.path.is_some() {
+            let contents = get_program_contents(&args).context("Failed to get program contents")?;
+
+            // Errors have already been reported, enter the REPL to inspect the state.
+            let _ = interpret(&mut vm, &contents, compile_options(&args), args.disassemble);
+        }
+
+        return repl::repl(&mut vm, compile_options(&args), args.disassemble);
     }
 
     let contents = get_program_contents(&args).context("Failed to get program contents")?;
 
-    interpret(&contents).context("Failed to interpret source")
+    if args.tokens {
+        tokens::dump_tokens(&contents, args.format);
+        return Ok(());
+    }
+
+    if args.dump_ast {
+        return ast::dump_ast(&contents, args.format).context("Failed to parse source");
+    }
+
+    if args.backend == Backend::Register {
+        return interpret_register(&contents, &args).context("Failed to interpret source");
+    }
+
+    interpret(&mut vm, &contents, compile_options(&args), args.disassemble)
+        .context("Failed to interpret source")
 }
 
-fn repl() -> Result<()> {
-    loop {
-        print!("> ");
-        io::stdout().flush()?;
-
-        let stdin = io::stdin();
-        let mut input = String::new();
-        if stdin.read_line(&mut input).is_ok() {
-            interpret(&input)?;
-        } else {
-            println!();
-        }
+fn compile_options(args: &Args) -> CompileOptions {
+    let opt_level = match args.opt_level {
+        0 => OptLevel::None,
+        1 => OptLevel::Basic,
+        _ => OptLevel::Full,
+    };
+
+    CompileOptions {
+        opt_level,
+        ..CompileOptions::default()
+    }
+}
+
+fn execution_mode(args: &Args) -> ExecutionMode {
+    if args.fast {
+        ExecutionMode::Fast
+    } else {
+        ExecutionMode::Checked
+    }
+}
+
+/// Returns a builder for VMs allowed to use the modules given with `--allow`.
+///
+/// Programs have always been able to call `clock`, so time is always allowed.
+fn vm_builder(args: &Args) -> Result<VmBuilder> {
+    let mut builder = VmBuilder::new()
+        .allow(Capability::Time)
+        .execution_mode(execution_mode(args));
+
+    for module in &args.allow {
+        let capability = match module {
+            Module::Io => Capability::Io,
+            Module::Fs => {
+                let root = match &args.fs_root {
+                    Some(root) => PathBuf::from(root),
+                    None => {
+                        std::env::current_dir().context("Failed to get the current directory")?
+                    }
+                };
+                Capability::Fs { root }
+            }
+            Module::Os => Capability::Os,
+            Module::Time => Capability::Time,
+            Module::Random => Capability::Random,
+        };
+
+        builder = builder.allow(capability);
+    }
+
+    Ok(builder)
+}
+
+fn interpret<O: Write, E: Write>(
+    vm: &mut VM<O, E>,
+    source: &str,
+    options: CompileOptions,
+    disassemble: bool,
+) -> InterpretResult {
+    let chunk = compile(source, options)?;
+    run(vm, &chunk, disassemble)
+}
+
+/// Compiles the source, reporting warnings and errors on stderr.
+fn compile(source: &str, options: CompileOptions) -> Result<Chunk, VmError> {
+    let (chunk, warnings) = compiler::compile_with_warnings(source, options).map_err(|error| {
+        eprintln!("{}", error);
+        VmError::Compilation
+    })?;
+
+    for warning in warnings {
+        eprintln!("{}", warning);
     }
+
+    Ok(chunk)
 }
 
-fn interpret(source: &str) -> InterpretResult {
-    compiler::compile(source);
+fn run<O: Write, E: Write>(vm: &mut VM<O, E>, chunk: &Chunk, disassemble: bool) -> InterpretResult {
+    if disassemble {
+        chunk.disassemble("code");
+    }
 
+    vm.interpret(chunk)
+}
+
+/// Compiles and runs the source with the register backend and VM.
+fn interpret_register(source: &str, args: &Args) -> Result<()> {
+    let (code, warnings) =
+        register::compile_with_warnings(source, compile_options(args)).map_err(|error| {
+            eprintln!("{}", error);
+            VmError::Compilation
+        })?;
+
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
+    if args.disassemble {
+        code.disassemble();
+    }
+
+    let mut vm = vm_builder(args)?.build_register();
+    vm.interpret(&code)?;
     Ok(())
 }
 
+fn compile_to_file(path: &str, output: Option<&str>, args: &Args) -> Result<()> {
+    let source = read_program_from_file(path)?;
+    let chunk = compile(&source, compile_options(args)).context("Failed to compile source")?;
+
+    if args.disassemble {
+        chunk.disassemble(path);
+    }
+
+    let output = match output {
+        Some(output) => Path::new(output).to_path_buf(),
+        None => Path::new(path).with_extension("loxc"),
+    };
+
+    fs::write(&output, chunk.serialize())
+        .with_context(|| format!("Failed to write bytecode to {}", output.display()))
+}
+
+fn run_file<O: Write, E: Write>(vm: &mut VM<O, E>, path: &str, args: &Args) -> Result<()> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read program from {}", path))?;
+
+    if serialize::is_bytecode(&bytes) {
+        if args.backend == Backend::Register {
+            bail!("{} is bytecode for the stack backend", path);
+        }
+
+        let chunk = Chunk::deserialize(&bytes)
+            .with_context(|| format!("Failed to load bytecode from {}", path))?;
+
+        return run(vm, &chunk, args.disassemble).context("Failed to run bytecode");
+    }
+
+    let source = String::from_utf8(bytes)
+        .with_context(|| format!("Failed to read program from {}", path))?;
+
+    if args.backend == Backend::Register {
+        return interpret_register(&source, args).context("Failed to interpret source");
+    }
+
+    interpret(vm, &source, compile_options(args), args.disassemble)
+        .context("Failed to interpret source")
+}
+
 fn get_program_contents(args: &Args) -> Result<String> {
     if let Some(code) = &args.code {
         return Ok(code.to_string());
@@ -101,7 +291,7 @@ fn get_program_contents(args: &Args) -> Result<String> {
 }
 
 fn read_program_from_file(path: &str) -> Result<String> {
-    let contents = fs::read_to_string(&path)
+    let contents = fs::read_to_string(path)
         .with_context(|| format!("Failed to read program from {}", &path))?;
 
     Ok(contents)
@@ -0,0 +1,220 @@
+use std::{env, fs, io::Write, path::PathBuf, time::Instant};
+
+use anyhow::Result;
+use rulox::{
+    compiler::CompileOptions,
+    scanner::{Scanner, TokenType},
+    value::Value,
+    vm::VM,
+};
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+use crate::{compile, interpret, run, tokens::print_tokens};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = ". ";
+
+const HELP: &str = "\
+Commands:
+  :dis <code>      Show the bytecode for the code without running it
+  :dis <function>  Show the bytecode of a global function
+  :tokens <code>   Show the tokens scanned from the code
+  :globals         Show the global variables
+  :stack           Show the values on the stack
+  :gc              Show how much memory the heap and stack are using
+  :load <path>     Run a Lox program in the current session
+  :reset           Remove all global variables and clear the stack
+  :time <code>     Run the code and show how long it took
+  :help            Show this help
+  :quit            Exit the REPL, same as Ctrl-D";
+
+/// Runs an interactive session on the given VM until the user ends it with Ctrl-D.
+///
+/// Entries spanning several lines are collected until they are complete, and the
+/// values of expression statements are printed. Lines starting with `:` are
+/// commands, see `:help`.
+pub fn repl<O: Write, E: Write>(
+    vm: &mut VM<O, E>,
+    options: CompileOptions,
+    disassemble: bool,
+) -> Result<()> {
+    let options = CompileOptions {
+        repl: true,
+        ..options
+    };
+
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+
+    if let Some(history) = &history {
+        // There is no history the first time the REPL is started.
+        let _ = editor.load_history(history);
+    }
+
+    let mut entry = String::new();
+
+    loop {
+        let prompt = if entry.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if entry.is_empty() && line.starts_with(':') {
+                    editor.add_history_entry(&line)?;
+                    if let Flow::Quit = command(vm, &line, options, disassemble) {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                entry.push_str(&line);
+                entry.push('\n');
+
+                if is_incomplete(&entry) {
+                    continue;
+                }
+
+                let source = std::mem::take(&mut entry);
+                if source.trim().is_empty() {
+                    continue;
+                }
+
+                editor.add_history_entry(source.trim_end())?;
+
+                evaluate(vm, &source, options, disassemble);
+            }
+            Err(ReadlineError::Interrupted) => entry.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+
+    Ok(())
+}
+
+/// Whether the REPL should keep going after a command.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Runs a REPL command, the line including its leading `:`.
+fn command<O: Write, E: Write>(
+    vm: &mut VM<O, E>,
+    line: &str,
+    options: CompileOptions,
+    disassemble: bool,
+) -> Flow {
+    let line = line[1..].trim();
+    let (name, argument) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(name, argument)| (name, argument.trim()));
+
+    match name {
+        "dis" => match vm.get_global(argument) {
+            Some(Value::Function(function)) => function.chunk.disassemble(&function.name),
+            _ => {
+                if let Ok(chunk) = compile(argument, options) {
+                    chunk.disassemble(argument);
+                }
+            }
+        },
+        "tokens" => print_tokens(argument),
+        "globals" => {
+            let mut globals: Vec<_> = vm.globals().collect();
+            globals.sort_by_key(|(name, _)| *name);
+
+            for (name, value) in globals {
+                println!("{} = {}", name, value);
+            }
+        }
+        "stack" => {
+            for value in vm.stack() {
+                print!("[ {} ]", value);
+            }
+
+            println!();
+        }
+        "gc" => {
+            let usage = vm.memory_usage();
+            println!("heap:  {} bytes", usage.heap_bytes);
+            println!("stack: {} values", usage.stack_values);
+        }
+        "load" => match fs::read_to_string(argument) {
+            // Errors have already been reported, keep the session going.
+            Ok(source) => {
+                let options = CompileOptions {
+                    repl: false,
+                    ..options
+                };
+                let _ = interpret(vm, &source, options, disassemble);
+            }
+            Err(error) => eprintln!("Failed to read program from {}: {}", argument, error),
+        },
+        "reset" => vm.reset(),
+        "time" => {
+            let start = Instant::now();
+            evaluate(vm, argument, options, disassemble);
+            eprintln!("Took {:?}", start.elapsed());
+        }
+        "help" => println!("{}", HELP),
+        "quit" => return Flow::Quit,
+        _ => eprintln!(
+            "Unknown command ':{}', see :help for a list of commands",
+            name
+        ),
+    }
+
+    Flow::Continue
+}
+
+/// Compiles and runs a REPL entry.
+fn evaluate<O: Write, E: Write>(
+    vm: &mut VM<O, E>,
+    source: &str,
+    options: CompileOptions,
+    disassemble: bool,
+) {
+    // Errors have already been reported, keep the session going.
+    if let Ok(chunk) = compile(source, options) {
+        let _ = run(vm, &chunk, disassemble);
+    }
+}
+
+/// Returns where the REPL history is stored, `~/.rulox_history` by default.
+///
+/// Can be overridden with the `RULOX_HISTORY` environment variable.
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RULOX_HISTORY") {
+        return Some(path.into());
+    }
+
+    env::home_dir().map(|home| home.join(".rulox_history"))
+}
+
+/// Returns whether the source ends in the middle of a string, block comment,
+/// or with unclosed parentheses or braces.
+fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0;
+
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+            // Both unterminated strings and block comments run to the end of the source.
+            TokenType::Error if token.lexeme.starts_with("Unterminated") => return true,
+            TokenType::EOF => return depth > 0,
+            _ => {}
+        }
+    }
+}
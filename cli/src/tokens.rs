@@ -0,0 +1,67 @@
+use rulox::scanner::{Scanner, TokenType};
+use serde_json::json;
+
+use crate::cli::OutputFormat;
+
+/// Prints every token in the source in the given format.
+pub fn dump_tokens(source: &str, format: OutputFormat) {
+    match format {
+        OutputFormat::Human => print_tokens(source),
+        OutputFormat::Json => print_tokens_json(source),
+    }
+}
+
+/// Prints every token in the source, one per line, prefixed by the line it is on.
+pub fn print_tokens(source: &str) {
+    let mut scanner = Scanner::new(source);
+    let mut line = -1;
+
+    loop {
+        let token = scanner.scan_token();
+
+        if token.line != line {
+            print!("{:4} ", token.line);
+            line = token.line;
+        } else {
+            print!("   | ");
+        }
+
+        let lexeme = match token.token_type {
+            TokenType::EOF => "",
+            _ => token.lexeme,
+        };
+        println!("{:?} '{}'", token.token_type, lexeme);
+
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+    }
+}
+
+/// Prints every token in the source as a JSON object, one per line.
+///
+/// For error tokens, the lexeme is the error message.
+fn print_tokens_json(source: &str) {
+    let mut scanner = Scanner::new(source);
+
+    loop {
+        let token = scanner.scan_token();
+        let lexeme = match token.token_type {
+            TokenType::EOF => "",
+            _ => token.lexeme,
+        };
+
+        let object = json!({
+            "type": format!("{:?}", token.token_type),
+            "lexeme": lexeme,
+            "line": token.line,
+            "column": token.column,
+        });
+
+        println!("{}", object);
+
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::process::Command;
+
+/// Runs the Lox tests in `tests/lox` through `rulox test` with the extra arguments.
+fn run_lox_tests(args: &[&str]) {
+    let tests = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_rulox"))
+        .arg("test")
+        .args(args)
+        .arg(tests)
+        .output()
+        .expect("failed to run rulox");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn lox_tests_pass() {
+    run_lox_tests(&[]);
+}
+
+#[test]
+fn lox_tests_pass_in_fast_mode() {
+    run_lox_tests(&["--fast"]);
+}
+
+#[test]
+fn lox_tests_pass_when_optimized() {
+    run_lox_tests(&["-O1"]);
+    run_lox_tests(&["-O1", "--fast"]);
+}
+
+#[test]
+fn lox_tests_pass_with_superinstructions() {
+    run_lox_tests(&["-O2"]);
+    run_lox_tests(&["-O2", "--fast"]);
+}
+
+#[test]
+fn lox_tests_pass_on_register_vm() {
+    run_lox_tests(&["--backend", "register"]);
+}
@@ -0,0 +1,147 @@
+use std::process::{Command, Output};
+
+use serde_json::{Value, json};
+
+/// Runs `rulox` with the arguments.
+fn rulox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rulox"))
+        .args(args)
+        .output()
+        .expect("failed to run rulox")
+}
+
+#[test]
+fn warnings_are_reported_on_stderr() {
+    let output = rulox(&["-e", "fun f() {\n  var unused = 1;\n}\nprint \"ran\";"]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"ran\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 2] Warning: Local variable 'unused' is never used.\n"
+    );
+}
+
+#[test]
+fn both_backends_report_the_same_errors_and_warnings() {
+    let source = "fun f() {\n  var unused = 1;\n}\nprint \"ran\";\nvar a = (;\nreturn 1;";
+
+    let stack = rulox(&["-e", source]);
+    let register = rulox(&["--backend", "register", "-e", source]);
+
+    assert_eq!(stack.status.code(), Some(65), "{:?}", stack);
+    assert_eq!(register.status.code(), Some(65), "{:?}", register);
+    let errors = "[line 5] Error at ';': Expect expression.\n\
+                  [line 6] Error at 'return': Can't return from top-level code.\n";
+    assert!(
+        String::from_utf8_lossy(&stack.stderr).starts_with(errors),
+        "{:?}",
+        stack
+    );
+    assert!(
+        String::from_utf8_lossy(&register.stderr).starts_with(errors),
+        "{:?}",
+        register
+    );
+
+    let source = "fun f() {\n  var unused = 1;\n}\nprint \"ran\";";
+    let register = rulox(&["--backend", "register", "-e", source]);
+
+    assert!(register.status.success(), "{:?}", register);
+    assert_eq!(register.stdout, b"ran\n");
+    assert_eq!(
+        String::from_utf8_lossy(&register.stderr),
+        "[line 2] Warning: Local variable 'unused' is never used.\n"
+    );
+}
+
+#[test]
+fn locals_of_enclosing_functions_are_errors() {
+    let source = "fun f() {\n  var x = 1;\n  fun g() { print x; }\n  g();\n}\nf();";
+
+    for backend in ["stack", "register"] {
+        let output = rulox(&["--backend", backend, "-e", source]);
+
+        assert_eq!(output.status.code(), Some(65), "{:?}", output);
+        assert!(output.stdout.is_empty(), "{:?}", output);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.starts_with(
+                "[line 3] Error at 'x': Can't refer to a local variable of an enclosing function.\n"
+            ),
+            "{:?}",
+            output
+        );
+        assert!(!stderr.contains("never used"), "{:?}", output);
+    }
+}
+
+#[test]
+fn syntax_trees_are_printed_for_humans() {
+    let output = rulox(&["--dump-ast", "-e", "var a = 1;\nif (a) print -a;"]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Program\n\
+         \x20 Var a 1:1-1:10\n\
+         \x20   Literal 1 1:9-1:9\n\
+         \x20 If 2:1-2:16\n\
+         \x20   condition: Variable a 2:5-2:5\n\
+         \x20   then: Print 2:8-2:16\n\
+         \x20     Unary - 2:14-2:15\n\
+         \x20       Variable a 2:15-2:15\n"
+    );
+}
+
+#[test]
+fn syntax_trees_are_printed_as_json() {
+    let output = rulox(&["--dump-ast", "--format", "json", "-e", "print f(1);"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let program: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let span = |start, end| {
+        json!({
+            "start": {"line": 1, "column": start},
+            "end": {"line": 1, "column": end},
+        })
+    };
+    assert_eq!(
+        program,
+        json!({
+            "type": "Program",
+            "statements": [{
+                "type": "Print",
+                "span": span(1, 11),
+                "expression": {
+                    "type": "Call",
+                    "span": span(7, 10),
+                    "callee": {
+                        "type": "Variable",
+                        "span": span(7, 7),
+                        "name": {"name": "f", "span": span(7, 7)},
+                    },
+                    "arguments": [{"type": "Literal", "span": span(9, 9), "value": 1.0}],
+                },
+            }],
+            "end": {"line": 1, "column": 12},
+        })
+    );
+}
+
+#[test]
+fn syntax_trees_keep_statements_that_failed_to_parse() {
+    let output = rulox(&["--dump-ast", "-e", "var = 1;\nprint 2;"]);
+
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Program\n  Error 1:1-1:8\n  Print 2:1-2:8\n    Literal 2 2:7-2:7\n"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .starts_with("[line 1] Error at '=': Expect variable name.\n"),
+        "{:?}",
+        output
+    );
+}
@@ -0,0 +1,86 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs a REPL session with the lines as input.
+fn repl(lines: &str) -> Output {
+    let history = concat!(env!("CARGO_TARGET_TMPDIR"), "/rulox_history");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rulox"))
+        .arg("-r")
+        .env("RULOX_HISTORY", history)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run rulox");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(lines.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+/// Returns the heap size reported by each `:gc` in the output.
+fn heap_sizes(stdout: &[u8]) -> Vec<usize> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("heap:"))
+        .map(|bytes| bytes.trim().trim_end_matches(" bytes").parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn gc_shows_the_heap_growing_and_shrinking() {
+    let output = repl(
+        ":gc\n\
+         var s = \"abcdefgh\"; for (var i = 0; i < 10; i = i + 1) s = s + s;\n\
+         :gc\n\
+         s = nil;\n\
+         :gc\n",
+    );
+
+    let sizes = heap_sizes(&output.stdout);
+    assert_eq!(
+        sizes.len(),
+        3,
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(sizes[1] >= sizes[0] + 8 * 1024, "{:?}", sizes);
+    assert!(sizes[2] + 8 * 1024 <= sizes[1], "{:?}", sizes);
+}
+
+#[test]
+fn dis_shows_global_functions() {
+    let output = repl("fun add(a, b) { return a + b; }\n:dis add\n");
+
+    let err = String::from_utf8_lossy(&output.stderr);
+    let add = err.find("== add ==").expect(&err);
+    assert!(err[add..].contains("OP_ADD"), "{}", err);
+}
+
+#[test]
+fn reset_keeps_natives() {
+    let output = repl("var a = 1;\n:reset\nprint clock() > 0;\nprint a;\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "true\n");
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("Undefined variable 'a'."), "{}", err);
+    assert!(!err.contains("'clock'"), "{}", err);
+}
+
+#[test]
+fn help_lists_every_command() {
+    let output = repl(":help\n");
+
+    let help = String::from_utf8_lossy(&output.stdout);
+    for command in [":dis <function>", ":gc", ":globals", ":stack", ":load"] {
+        assert!(help.contains(command), "{} missing from {}", command, help);
+    }
+}
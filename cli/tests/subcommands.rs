@@ -0,0 +1,61 @@
+use std::{
+    fs,
+    process::{Command, Output},
+};
+
+/// Runs `rulox` with the arguments.
+fn rulox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rulox"))
+        .args(args)
+        .output()
+        .expect("failed to run rulox")
+}
+
+/// Writes the source to a file in the target's temporary directory,
+/// returning its path.
+fn write_program(name: &str, source: &str) -> String {
+    let path = format!("{}/{}", env!("CARGO_TARGET_TMPDIR"), name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn global_options_can_come_before_the_subcommand() {
+    let path = write_program("global_options.lox", "print 1 + 2;");
+    let bytecode = format!("{}c", path);
+
+    let output = rulox(&["-O", "1", "compile", &path, "-o", &bytecode]);
+    assert!(output.status.success(), "{:?}", output);
+
+    for args in [
+        &["-d", "run", &bytecode][..],
+        &["--fast", "run", &bytecode],
+        &["--backend", "stack", "-v", "run", &path],
+        &["run", &bytecode, "--fast", "-d"],
+    ] {
+        let output = rulox(args);
+        assert!(output.status.success(), "{:?}: {:?}", args, output);
+        assert!(
+            output.stdout.starts_with(b"3\n"),
+            "{:?}: {:?}",
+            args,
+            output
+        );
+    }
+}
+
+#[test]
+fn programs_cannot_be_given_with_a_subcommand() {
+    let path = write_program("ignored.lox", "print 1;");
+
+    for args in [
+        &[&path, "run", &path][..],
+        &["-e", "print 2;", "run", &path],
+        &["-r", "run", &path],
+        &["--tokens", "run", &path],
+    ] {
+        let output = rulox(args);
+        assert_eq!(output.status.code(), Some(64), "{:?}: {:?}", args, output);
+        assert!(output.stdout.is_empty(), "{:?}: {:?}", args, output);
+    }
+}
@@ -0,0 +1,77 @@
+use std::process::{Command, Output};
+
+use serde_json::{Value, json};
+
+/// Runs `rulox` with the arguments.
+fn rulox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rulox"))
+        .args(args)
+        .output()
+        .expect("failed to run rulox")
+}
+
+/// Returns the tokens printed for the code with `--tokens --format json`.
+fn json_tokens(code: &str) -> Vec<Value> {
+    let output = rulox(&["--tokens", "--format", "json", "-e", code]);
+    assert!(output.status.success(), "{:?}", output);
+
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect(line))
+        .collect()
+}
+
+#[test]
+fn tokens_are_printed_as_json_lines() {
+    assert_eq!(
+        json_tokens("var a =\n  \"é\";"),
+        [
+            json!({"type": "Var", "lexeme": "var", "line": 1, "column": 1}),
+            json!({"type": "Identifier", "lexeme": "a", "line": 1, "column": 5}),
+            json!({"type": "Equal", "lexeme": "=", "line": 1, "column": 7}),
+            json!({"type": "String", "lexeme": "\"é\"", "line": 2, "column": 3}),
+            json!({"type": "Semicolon", "lexeme": ";", "line": 2, "column": 6}),
+            json!({"type": "EOF", "lexeme": "", "line": 2, "column": 7}),
+        ]
+    );
+}
+
+#[test]
+fn error_tokens_carry_their_message() {
+    let tokens = json_tokens("@");
+
+    assert_eq!(
+        tokens[0],
+        json!({"type": "Error", "lexeme": "Unexpected character.", "line": 1, "column": 1})
+    );
+}
+
+#[test]
+fn tokens_are_printed_for_humans_by_default() {
+    let output = rulox(&["--tokens", "-e", "print 1;\nprint 2;"]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "   1 Print 'print'\n\
+         \x20  | Number '1'\n\
+         \x20  | Semicolon ';'\n\
+         \x20  2 Print 'print'\n\
+         \x20  | Number '2'\n\
+         \x20  | Semicolon ';'\n\
+         \x20  | EOF ''\n"
+    );
+}
+
+#[test]
+fn tokens_can_not_be_printed_in_the_repl() {
+    let output = rulox(&["--tokens", "-r"]);
+
+    assert_eq!(output.status.code(), Some(64));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("cannot be used with"),
+        "{:?}",
+        output
+    );
+}
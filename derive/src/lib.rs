@@ -0,0 +1,224 @@
+/*!
+Derive macro for [`rulox::class::LoxClass`][LoxClass], exposing Rust structs to
+Lox as classes. Enable the `derive` feature of `rulox` to use it.
+
+[LoxClass]: https://docs.rs/rulox/latest/rulox/class/trait.LoxClass.html
+*/
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, LitStr, Path, Result, parse_macro_input};
+
+/// Implements `LoxClass` for a struct with named fields.
+///
+/// The class gets a constructor taking the fields in declaration order, and a
+/// property for each field. Field types must implement `Clone`, `Into<Value>`
+/// and `TryFrom<Value>`.
+///
+/// # Attributes
+///
+/// On the struct:
+///
+///  * `#[lox(name = "Name")]` - the name of the class in Lox, by default the
+///    name of the struct.
+///  * `#[lox(methods = path)]` - a function `fn(&mut ClassBuilder<Self>)`
+///    called to add methods, or anything else the derive cannot describe.
+///
+/// On fields:
+///
+///  * `#[lox(skip)]` - hides the field from Lox. It is initialized with
+///    `Default::default()` by the constructor.
+///  * `#[lox(read_only)]` - makes the property read-only.
+///
+/// # Example
+///
+/// ```
+/// use rulox::class::{ClassBuilder, LoxClass};
+///
+/// #[derive(LoxClass)]
+/// #[lox(methods = Self::methods)]
+/// struct Point {
+///     x: f64,
+///     #[lox(read_only)]
+///     y: f64,
+///     #[lox(skip)]
+///     moves: u32,
+/// }
+///
+/// impl Point {
+///     fn methods(class: &mut ClassBuilder<Self>) {
+///         class.method("move", 2, |point, _, args| {
+///             point.x += rulox::native::arg::<f64>(args, 0)?;
+///             point.y += rulox::native::arg::<f64>(args, 1)?;
+///             point.moves += 1;
+///             Ok(rulox::value::Value::Nil)
+///         });
+///     }
+/// }
+/// ```
+#[proc_macro_derive(LoxClass, attributes(lox))]
+pub fn derive_lox_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The options given in `#[lox(...)]` on the struct.
+#[derive(Default)]
+struct ClassOptions {
+    name: Option<LitStr>,
+    methods: Option<Path>,
+}
+
+/// The options given in `#[lox(...)]` on a field.
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    read_only: bool,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let ident = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "LoxClass cannot be derived for generic types",
+        ));
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            ident,
+            "LoxClass can only be derived for structs",
+        ));
+    };
+
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &data.fields,
+            "LoxClass can only be derived for structs with named fields",
+        ));
+    };
+
+    let options = class_options(&input)?;
+    let name = options
+        .name
+        .unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+
+    let mut initializers = Vec::new();
+    let mut properties = Vec::new();
+
+    for field in &fields.named {
+        let field_ident = field.ident.as_ref().expect("named fields have names");
+        let field_options = field_options(field)?;
+
+        if field_options.skip {
+            initializers.push(quote! {
+                #field_ident: ::core::default::Default::default()
+            });
+            continue;
+        }
+
+        // Every field exposed so far has a property and takes an argument.
+        let index = properties.len();
+        initializers.push(quote! {
+            #field_ident: ::rulox::native::arg(args, #index)?
+        });
+
+        let property = LitStr::new(&field_ident.to_string(), field_ident.span());
+        let get = quote! {
+            |this| ::core::convert::Into::into(::core::clone::Clone::clone(&this.#field_ident))
+        };
+
+        properties.push(if field_options.read_only {
+            quote! { class.read_only_property(#property, #get); }
+        } else {
+            quote! {
+                class.property(#property, #get, |this, value| {
+                    this.#field_ident = ::core::convert::TryFrom::try_from(value).map_err(|_| {
+                        ::rulox::vm::RuntimeError::InvalidPropertyValue(
+                            ::std::string::String::from(#property),
+                        )
+                    })?;
+                    ::core::result::Result::Ok(())
+                });
+            }
+        });
+    }
+
+    let arity = properties.len();
+    if arity > u8::MAX as usize {
+        return Err(Error::new_spanned(
+            ident,
+            "LoxClass constructors can't have more than 255 parameters",
+        ));
+    }
+    let arity = arity as u8;
+
+    let methods = options.methods.map(|methods| quote! { #methods(class); });
+
+    Ok(quote! {
+        impl ::rulox::class::LoxClass for #ident {
+            const NAME: &'static str = #name;
+
+            fn register(class: &mut ::rulox::class::ClassBuilder<Self>) {
+                class.constructor(#arity, |_, args| {
+                    ::core::result::Result::Ok(Self { #(#initializers),* })
+                });
+                #(#properties)*
+                #methods
+            }
+        }
+    })
+}
+
+fn class_options(input: &DeriveInput) -> Result<ClassOptions> {
+    let mut options = ClassOptions::default();
+
+    for attribute in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("lox"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("methods") {
+                options.methods = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `name` or `methods`"))
+            }
+        })?;
+    }
+
+    Ok(options)
+}
+
+fn field_options(field: &syn::Field) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+
+    for attribute in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("lox"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                options.skip = true;
+                Ok(())
+            } else if meta.path.is_ident("read_only") {
+                options.read_only = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `read_only`"))
+            }
+        })?;
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use rulox::{
+    class::{ClassBuilder, Instance, LoxClass},
+    compiler, native,
+    value::Value,
+    vm::{RuntimeError, VM, VmError},
+};
+
+#[derive(LoxClass)]
+#[lox(methods = Self::methods)]
+struct Point {
+    x: f64,
+    #[lox(read_only)]
+    y: f64,
+    #[lox(skip)]
+    moves: u32,
+}
+
+impl Point {
+    fn methods(class: &mut ClassBuilder<Self>) {
+        class.method("move", 2, |point, _, args| {
+            point.x += native::arg::<f64>(args, 0)?;
+            point.y += native::arg::<f64>(args, 1)?;
+            point.moves += 1;
+            Ok(Value::Nil)
+        });
+    }
+}
+
+#[derive(LoxClass)]
+#[lox(name = "Person")]
+struct User {
+    name: String,
+    admin: bool,
+}
+
+/// Compiles and runs the source with the test classes defined, returning
+/// stdout, stderr and the result.
+fn run(source: &str) -> (String, String, Result<(), VmError>) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<Point>();
+    vm.define_class::<User>();
+    let result = vm.interpret(&chunk);
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        result,
+    )
+}
+
+#[test]
+fn fields_become_constructor_parameters_and_properties() {
+    let source = "
+        var p = Point(1, 2);
+        print p.x;
+        print p.y;
+        p.x = 10;
+        p.move(1, 1);
+        print p.x;
+        print p.y;
+    ";
+    let (out, _, result) = run(source);
+    assert!(result.is_ok());
+    assert_eq!(out, "1\n2\n11\n3\n");
+}
+
+#[test]
+fn classes_can_be_renamed() {
+    let (out, _, result) = run("var u = Person(\"Ada\", true); print u; print u.name;");
+    assert!(result.is_ok());
+    assert_eq!(out, "Person instance\nAda\n");
+}
+
+#[test]
+fn attributes_restrict_access() {
+    let (_, err, _) = run("var p = Point(1, 2);\np.y = 3;");
+    assert_eq!(
+        err,
+        "Can't assign to read-only property 'y'.\n[line 2] in script\n"
+    );
+
+    let (_, err, _) = run("var p = Point(1, 2);\nprint p.moves;");
+    assert_eq!(err, "Undefined property 'moves'.\n[line 2] in script\n");
+}
+
+#[test]
+fn values_are_type_checked() {
+    let (_, err, result) = run("var u = Person(\"Ada\", true);\nu.admin = \"yes\";");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::InvalidPropertyValue(_)))
+    ));
+    assert_eq!(
+        err,
+        "Invalid value for property 'admin'.\n[line 2] in script\n"
+    );
+
+    let (_, err, _) = run("Point(1, \"2\");");
+    assert_eq!(err, "Invalid type for argument 2.\n[line 1] in script\n");
+}
+
+#[test]
+fn instances_can_be_downcast() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<Point>();
+
+    let chunk = compiler::compile("var p = Point(3, 4); p.move(1, 1);").unwrap();
+    vm.interpret(&chunk).unwrap();
+
+    let point: Arc<Instance> = vm.get_global("p").unwrap().try_into().unwrap();
+    let point = point.borrow::<Point>().unwrap();
+    assert_eq!((point.x, point.y, point.moves), (4.0, 5.0, 1));
+    assert_eq!(Point::NAME, "Point");
+}
@@ -0,0 +1,223 @@
+/*!
+The syntax tree of a Lox program, as produced by the [`parser`][crate::parser].
+
+Every node has a [`Span`] locating it in the source, and nodes that compile to
+code at a token other than their last one, such as the operator of `and`, keep
+the [`Position`] of that token as well. That is enough for the
+[`compiler`][crate::compiler] to report errors and attribute code to the same
+lines as when it parsed the source itself, and for tools to map nodes back to
+the text they came from.
+
+Code that failed to parse is kept as [`StmtKind::Error`] and [`ExprKind::Error`]
+nodes, so that the rest of the program can still be analyzed.
+*/
+
+use std::fmt::{self, Display, Formatter};
+
+/// Where a token starts in the source.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: i32,
+
+    /// The column, counting from 1.
+    pub column: i32,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The tokens a node was parsed from.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    /// Where the first token starts.
+    pub start: Position,
+
+    /// Where the last token starts.
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns whether the span includes the token at the given position.
+    pub fn contains(&self, position: Position) -> bool {
+        self.start <= position && position <= self.end
+    }
+}
+
+/// A whole program, or a line entered into the REPL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+
+    /// Where the end of the source is.
+    pub end: Position,
+}
+
+/// The name of a variable, parameter or property.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StmtKind {
+    Expression(Expr),
+    Print(Expr),
+    Var {
+        name: Identifier,
+        initializer: Option<Expr>,
+    },
+    Function(Function),
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+
+        /// The `)` after the condition.
+        right_paren: Position,
+
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+
+        /// The `)` after the condition.
+        right_paren: Position,
+
+        body: Box<Stmt>,
+    },
+    For {
+        /// A [`StmtKind::Var`] or [`StmtKind::Expression`].
+        initializer: Option<Box<Stmt>>,
+
+        condition: Option<Expr>,
+
+        /// The `;` after the condition.
+        semicolon: Position,
+
+        increment: Option<Expr>,
+
+        /// The `)` after the clauses.
+        right_paren: Position,
+
+        body: Box<Stmt>,
+    },
+    Return(Option<Expr>),
+
+    /// A statement that failed to parse.
+    Error,
+}
+
+/// A function declaration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    Grouping(Box<Expr>),
+    Variable(Identifier),
+    Assign {
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `and` or `or`, which only evaluate the right operand if needed.
+    Logical {
+        operator: LogicalOperator,
+
+        /// Where the operator is.
+        position: Position,
+
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Identifier,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    This,
+    Super {
+        method: Identifier,
+    },
+
+    /// An expression that failed to parse.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOperator {
+    /// `!`
+    Not,
+
+    /// `-`
+    Negate,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOperator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogicalOperator {
+    And,
+    Or,
+}
@@ -0,0 +1,228 @@
+/*!
+Inline caches for property access in the fast execution mode, see
+[`ExecutionMode::Fast`][crate::vm::ExecutionMode::Fast].
+
+Each `GetProperty` and `SetProperty` instruction of a decoded chunk has a cache
+remembering where the property was found for the last few kinds of object it
+was used on. The kind of an instance is its class, together with its
+[`Shape`] for records, whose fields vary from record to record.
+
+A cache holding one kind is monomorphic, and up to [`POLYMORPHIC_LIMIT`] are
+kept. Instructions that see more kinds than that keep the ones they saw first
+and look up the others by name.
+*/
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    mem::size_of,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    class::{self, Class, Instance, Member},
+    collections::Record,
+    shape::Shape,
+    value::Value,
+    vm::{CacheStats, RuntimeError},
+};
+
+/// How many kinds of object a single instruction caches the property of.
+pub(crate) const POLYMORPHIC_LIMIT: usize = 4;
+
+/// Where the property is found.
+#[derive(Clone)]
+enum Access {
+    /// A property or method of the class.
+    Member(Member),
+
+    /// The field in the slot of a record.
+    Field(usize),
+
+    /// A field not set yet, which gives the record the shape when added.
+    AddField(Arc<Shape>),
+}
+
+struct Entry {
+    class: Arc<Class>,
+
+    /// The shape of the record, `None` for other instances.
+    shape: Option<Arc<Shape>>,
+
+    access: Access,
+}
+
+/// The cache of a single `GetProperty` or `SetProperty` instruction.
+///
+/// Entries are only ever added, each to the first free place, so they can be
+/// read without locking while another thread running the same function adds
+/// one.
+#[derive(Default)]
+pub(crate) struct InlineCache {
+    entries: [OnceLock<Entry>; POLYMORPHIC_LIMIT],
+}
+
+impl InlineCache {
+    /// Returns the number of bytes used by the cache, without the classes and
+    /// shapes it shares with the instances.
+    pub(crate) fn size(&self) -> usize {
+        size_of::<Self>()
+    }
+
+    /// Returns the value of a property of the object, as
+    /// [`class::get_property`] does.
+    pub(crate) fn get(
+        &self,
+        object: &Value,
+        name: &str,
+        stats: &mut CacheStats,
+    ) -> Result<Value, RuntimeError> {
+        let Value::Instance(instance) = object else {
+            return class::get_property(object, name);
+        };
+
+        // The record stays borrowed until its field is read.
+        let record = instance.borrow::<Record>();
+        let shape = record.as_deref().map(Record::shape);
+
+        let missed;
+        let access = match self.lookup(instance, shape) {
+            Some(access) => {
+                stats.hits += 1;
+                access
+            }
+            None => {
+                stats.misses += 1;
+                missed = match instance.class().member(name) {
+                    Some(member) => Access::Member(member),
+                    None => match shape.and_then(|shape| shape.slot(name)) {
+                        Some(slot) => Access::Field(slot),
+                        // Missing properties are errors, and properties of
+                        // other classes found by their fallback can't be cached.
+                        None => {
+                            drop(record);
+                            return instance.get(name);
+                        }
+                    },
+                };
+
+                self.insert(instance, shape.cloned(), missed.clone());
+                &missed
+            }
+        };
+
+        match (access, record) {
+            (Access::Field(slot), Some(record)) => Ok(record.slot(*slot).clone()),
+            (Access::Member(member), record) => {
+                drop(record);
+                instance.get_member(member)
+            }
+            _ => unreachable!("fields are only cached for records, and only added by assignments"),
+        }
+    }
+
+    /// Assigns a property of the object, as [`class::set_property`] does.
+    pub(crate) fn set(
+        &self,
+        object: &Value,
+        name: &str,
+        value: Value,
+        stats: &mut CacheStats,
+    ) -> Result<(), RuntimeError> {
+        let Value::Instance(instance) = object else {
+            return class::set_property(object, name, value);
+        };
+
+        // The record may be in use by a native function.
+        let mut record = instance.borrow_mut::<Record>();
+        let shape = record.as_deref().map(Record::shape);
+
+        let missed;
+        let access = match self.lookup(instance, shape) {
+            Some(access) => {
+                stats.hits += 1;
+                access
+            }
+            None => {
+                stats.misses += 1;
+                missed = match (instance.class().member(name), shape) {
+                    (Some(Member::Property(property)), _) => {
+                        Access::Member(Member::Property(property))
+                    }
+                    (None, Some(shape)) => match shape.slot(name) {
+                        Some(slot) => Access::Field(slot),
+                        None => Access::AddField(shape.with(name)),
+                    },
+                    // Methods can't be assigned, and other classes need their
+                    // fallback.
+                    _ => {
+                        drop(record);
+                        return instance.set(name, value);
+                    }
+                };
+
+                self.insert(instance, shape.cloned(), missed.clone());
+                &missed
+            }
+        };
+
+        if let Access::Member(Member::Property(property)) = access {
+            drop(record);
+            return instance.set_member(name, property, value);
+        }
+
+        let Some(record) = record.as_deref_mut() else {
+            unreachable!("fields are only cached for records");
+        };
+
+        match access {
+            Access::Field(slot) => record.set_slot(*slot, value),
+            Access::AddField(shape) => record.add_field(shape.clone(), value),
+            Access::Member(_) => unreachable!("only properties are cached for assignments"),
+        }
+
+        Ok(())
+    }
+
+    /// Returns where the property was found for the kind of the instance.
+    fn lookup(&self, instance: &Instance, shape: Option<&Arc<Shape>>) -> Option<&Access> {
+        self.entries
+            .iter()
+            .map_while(OnceLock::get)
+            .find(|entry| {
+                Arc::ptr_eq(&entry.class, instance.class())
+                    && match (&entry.shape, shape) {
+                        (Some(cached), Some(shape)) => Arc::ptr_eq(cached, shape),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            })
+            .map(|entry| &entry.access)
+    }
+
+    /// Adds an entry in the first free place, unless the cache is full.
+    fn insert(&self, instance: &Instance, shape: Option<Arc<Shape>>, access: Access) {
+        let mut entry = Entry {
+            class: instance.class().clone(),
+            shape,
+            access,
+        };
+
+        for place in &self.entries {
+            match place.set(entry) {
+                Ok(()) => return,
+                Err(taken) => entry = taken,
+            }
+        }
+    }
+}
+
+impl Debug for InlineCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("InlineCache")
+            .field(
+                "entries",
+                &self.entries.iter().map_while(OnceLock::get).count(),
+            )
+            .finish()
+    }
+}
@@ -0,0 +1,606 @@
+/*!
+Rust types exposed to Lox as classes.
+
+A type implementing [`LoxClass`] describes its constructor, properties and
+methods to a [`ClassBuilder`], and is registered with
+[`VM::define_class`][crate::vm::VM::define_class]:
+
+```
+use rulox::{
+    class::{ClassBuilder, LoxClass},
+    compiler,
+    native::{self, VmContext},
+    value::Value,
+    vm::{RuntimeError, VM},
+};
+
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+impl LoxClass for Point {
+    const NAME: &'static str = "Point";
+
+    fn register(class: &mut ClassBuilder<Self>) {
+        class
+            .constructor(2, |_, args| {
+                Ok(Point {
+                    x: native::arg(args, 0)?,
+                    y: native::arg(args, 1)?,
+                })
+            })
+            .property("x", |point| point.x.into(), |point, value| {
+                point.x = value.try_into()?;
+                Ok(())
+            })
+            .read_only_property("y", |point| point.y.into())
+            .method("length", 0, |point, _, _| {
+                Ok(point.x.hypot(point.y).into())
+            });
+    }
+}
+
+let mut out = Vec::new();
+let mut err = Vec::new();
+let mut vm = VM::new(&mut out, &mut err);
+vm.define_class::<Point>();
+
+let chunk = compiler::compile("var p = Point(3, 4); p.x = 6; print p.x; print p.length();").unwrap();
+vm.interpret(&chunk).unwrap();
+assert_eq!(out, b"6\n7.211102550927978\n");
+```
+
+With the **`derive`** feature enabled, `LoxClass` can instead be derived for
+structs with named fields, exposing each field as a property.
+
+Instances are reference counted like every other value: the Rust value is
+dropped as soon as the last reference to it, from either Lox or the host, goes
+away. The host can keep hold of an instance and get back to the Rust value with
+[`Instance::borrow`] and [`Instance::borrow_mut`], which check its type.
+
+Values can be moved between threads along with the VM holding them, so the
+Rust types must be `Send` and `Sync`.
+*/
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{self, Debug, Display, Formatter},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
+};
+
+use crate::{memory::Tracer, native::VmContext, value::Value, vm::RuntimeError};
+
+#[cfg(feature = "derive")]
+pub use rulox_derive::LoxClass;
+
+/// A Rust type that can be used from Lox as a class.
+pub trait LoxClass: Any + Send + Sync + Sized {
+    /// The name of the class in Lox.
+    const NAME: &'static str;
+
+    /// Describes the constructor, properties and methods of the class.
+    fn register(class: &mut ClassBuilder<Self>);
+
+    /// Reports the values the instance holds, and any memory it owns outside
+    /// of its own struct, so that it counts towards the memory used by the VM.
+    ///
+    /// Only types holding values or growable buffers need to implement it.
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+/// Creates a new instance from the arguments the class was called with.
+pub type Constructor<T> = fn(&mut VmContext, &[Value]) -> Result<T, RuntimeError>;
+
+/// Returns the value of a property.
+pub type Getter<T> = fn(&T) -> Value;
+
+/// Sets the value of a property, failing if the value has the wrong type.
+pub type Setter<T> = fn(&mut T, Value) -> Result<(), RuntimeError>;
+
+/// Returns the value of a property that was not added by name, if the instance has it.
+pub type FallbackGetter<T> = fn(&T, &str) -> Option<Value>;
+
+/// Sets the value of a property that was not added by name.
+pub type FallbackSetter<T> = fn(&mut T, &str, Value) -> Result<(), RuntimeError>;
+
+/// A method, receiving the instance it is called on along with its arguments.
+pub type MethodFn<T> = fn(&mut T, &mut VmContext, &[Value]) -> Result<Value, RuntimeError>;
+
+/// The Rust value wrapped by an instance, of the type of its class.
+type Data = Box<dyn Any + Send + Sync>;
+
+type ErasedConstructor =
+    Box<dyn Fn(&mut VmContext, &[Value]) -> Result<Data, RuntimeError> + Send + Sync>;
+type ErasedGetter = Box<dyn Fn(&dyn Any) -> Value + Send + Sync>;
+type ErasedSetter = Box<dyn Fn(&mut dyn Any, Value) -> Result<(), RuntimeError> + Send + Sync>;
+type ErasedFallbackGetter = Box<dyn Fn(&dyn Any, &str) -> Option<Value> + Send + Sync>;
+type ErasedFallbackSetter =
+    Box<dyn Fn(&mut dyn Any, &str, Value) -> Result<(), RuntimeError> + Send + Sync>;
+type ErasedMethod = Box<
+    dyn Fn(&mut dyn Any, &mut VmContext, &[Value]) -> Result<Value, RuntimeError> + Send + Sync,
+>;
+
+/// Collects the members of a class as it is registered.
+pub struct ClassBuilder<T> {
+    class: Class,
+    _type: PhantomData<T>,
+}
+
+impl<T: LoxClass> ClassBuilder<T> {
+    /// Makes the class callable from Lox with `arity` arguments to create new instances.
+    ///
+    /// Without a constructor, instances can only be created by the host.
+    pub fn constructor(&mut self, arity: u8, constructor: Constructor<T>) -> &mut Self {
+        let constructor: ErasedConstructor = Box::new(move |context, args| {
+            constructor(context, args).map(|data| Box::new(data) as Data)
+        });
+
+        self.class.constructor = Some((arity, constructor));
+        self
+    }
+
+    /// Adds a property that can be both read and assigned to.
+    pub fn property(&mut self, name: &str, get: Getter<T>, set: Setter<T>) -> &mut Self {
+        let set: ErasedSetter = Box::new(move |data, value| set(downcast_mut(data), value));
+        self.add_property(name, get, Some(set))
+    }
+
+    /// Adds a property that can only be read.
+    pub fn read_only_property(&mut self, name: &str, get: Getter<T>) -> &mut Self {
+        self.add_property(name, get, None)
+    }
+
+    /// Handles properties that were not added by name, such as the entries of a
+    /// map. Without a setter, assigning them is an error.
+    pub fn fallback_property(
+        &mut self,
+        get: FallbackGetter<T>,
+        set: Option<FallbackSetter<T>>,
+    ) -> &mut Self {
+        let get: ErasedFallbackGetter = Box::new(move |data, name| get(downcast_ref(data), name));
+        let set = set.map(|set| -> ErasedFallbackSetter {
+            Box::new(move |data, name, value| set(downcast_mut(data), name, value))
+        });
+
+        self.class.fallback = Some(Fallback { get, set });
+        self
+    }
+
+    /// Adds a method taking `arity` arguments.
+    pub fn method(&mut self, name: &str, arity: u8, method: MethodFn<T>) -> &mut Self {
+        let function: ErasedMethod =
+            Box::new(move |data, context, args| method(downcast_mut(data), context, args));
+
+        let method = Method {
+            name: name.into(),
+            arity,
+            function,
+        };
+
+        self.class.methods.insert(name.into(), Arc::new(method));
+        self
+    }
+
+    fn add_property(&mut self, name: &str, get: Getter<T>, set: Option<ErasedSetter>) -> &mut Self {
+        let get: ErasedGetter = Box::new(move |data| get(downcast_ref(data)));
+        self.class
+            .properties
+            .insert(name.into(), Arc::new(Property { get, set }));
+        self
+    }
+}
+
+fn downcast_ref<T: Any>(data: &dyn Any) -> &T {
+    data.downcast_ref()
+        .expect("instances always hold the type of their class")
+}
+
+fn downcast_mut<T: Any>(data: &mut dyn Any) -> &mut T {
+    data.downcast_mut()
+        .expect("instances always hold the type of their class")
+}
+
+pub(crate) struct Property {
+    get: ErasedGetter,
+
+    /// How to assign the property, `None` if it is read-only.
+    set: Option<ErasedSetter>,
+}
+
+/// A property or method found by name, see [`Class::member`].
+#[derive(Clone)]
+pub(crate) enum Member {
+    Property(Arc<Property>),
+    Method(Arc<Method>),
+}
+
+/// How to access the properties a class does not know the names of up front.
+struct Fallback {
+    get: ErasedFallbackGetter,
+    set: Option<ErasedFallbackSetter>,
+}
+
+/// A method of a class, not yet bound to an instance.
+pub struct Method {
+    pub name: Arc<str>,
+
+    /// The number of arguments the method must be called with.
+    pub arity: u8,
+
+    function: ErasedMethod,
+}
+
+/// A class implemented in Rust, as stored in a [`Value`].
+pub struct Class {
+    pub name: Arc<str>,
+
+    /// The Rust type of the instances of the class.
+    type_id: TypeId,
+
+    /// The size of the Rust type, in bytes.
+    pub(crate) size: usize,
+
+    trace: fn(&dyn Any, &mut Tracer),
+
+    /// The arity and constructor, if the class can be called from Lox.
+    constructor: Option<(u8, ErasedConstructor)>,
+
+    properties: HashMap<Arc<str>, Arc<Property>>,
+    methods: HashMap<Arc<str>, Arc<Method>>,
+
+    /// Handles the properties not found in `properties` or `methods`.
+    fallback: Option<Fallback>,
+}
+
+impl Class {
+    /// Builds the class for the Rust type `T`.
+    pub fn new<T: LoxClass>() -> Self {
+        let mut builder = ClassBuilder::<T> {
+            class: Self {
+                name: T::NAME.into(),
+                type_id: TypeId::of::<T>(),
+                size: size_of::<T>(),
+                trace: |data, tracer| downcast_ref::<T>(data).trace(tracer),
+                constructor: None,
+                properties: HashMap::new(),
+                methods: HashMap::new(),
+                fallback: None,
+            },
+            _type: PhantomData,
+        };
+
+        T::register(&mut builder);
+        builder.class
+    }
+
+    /// Returns the number of arguments the class must be called with, or `None`
+    /// if it has no constructor and cannot be called.
+    pub fn arity(&self) -> Option<u8> {
+        self.constructor.as_ref().map(|(arity, _)| *arity)
+    }
+
+    /// Wraps a Rust value in a new instance of the class.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the class was not built for the type `T`.
+    pub fn instantiate<T: LoxClass>(self: &Arc<Self>, data: T) -> Value {
+        assert_eq!(
+            self.type_id,
+            TypeId::of::<T>(),
+            "class {} was not built for this type",
+            self.name
+        );
+
+        Value::Instance(Arc::new(Instance {
+            class: self.clone(),
+            data: RwLock::new(Box::new(data)),
+        }))
+    }
+
+    /// Returns the property or method with the name, if the class has one.
+    pub(crate) fn member(&self, name: &str) -> Option<Member> {
+        if let Some(property) = self.properties.get(name) {
+            return Some(Member::Property(property.clone()));
+        }
+
+        self.methods.get(name).cloned().map(Member::Method)
+    }
+
+    /// Calls the constructor, creating a new instance.
+    pub(crate) fn construct(
+        self: &Arc<Self>,
+        context: &mut VmContext,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let Some((_, constructor)) = &self.constructor else {
+            return Err(RuntimeError::NotCallable);
+        };
+
+        let data = constructor(context, args)?;
+        Ok(Value::Instance(Arc::new(Instance {
+            class: self.clone(),
+            data: RwLock::new(data),
+        })))
+    }
+}
+
+impl Debug for Class {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Classes are only equal to themselves.
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// An instance of a [`Class`], wrapping a value of its Rust type.
+pub struct Instance {
+    class: Arc<Class>,
+    data: RwLock<Data>,
+}
+
+impl Instance {
+    pub fn class(&self) -> &Arc<Class> {
+        &self.class
+    }
+
+    /// Returns whether the instance wraps a value of type `T`.
+    pub fn is<T: Any>(&self) -> bool {
+        self.class.type_id == TypeId::of::<T>()
+    }
+
+    /// Borrows the Rust value, if it has type `T` and is not currently borrowed
+    /// mutably, such as by one of its methods.
+    pub fn borrow<T: Any>(&self) -> Option<InstanceRef<'_, T>> {
+        if !self.is::<T>() {
+            return None;
+        }
+
+        let data = self.read().ok()?;
+        Some(InstanceRef {
+            data,
+            _type: PhantomData,
+        })
+    }
+
+    /// Mutably borrows the Rust value, if it has type `T` and is not currently borrowed.
+    pub fn borrow_mut<T: Any>(&self) -> Option<InstanceRefMut<'_, T>> {
+        if !self.is::<T>() {
+            return None;
+        }
+
+        let data = self.write().ok()?;
+        Some(InstanceRefMut {
+            data,
+            _type: PhantomData,
+        })
+    }
+
+    /// Borrows the Rust value, failing if it is borrowed mutably.
+    ///
+    /// A method that panicked leaves the value as it was when it did, like a
+    /// `RefCell` would.
+    fn read(&self) -> Result<RwLockReadGuard<'_, Data>, RuntimeError> {
+        match self.data.try_read() {
+            Ok(data) => Ok(data),
+            Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(RuntimeError::InstanceInUse),
+        }
+    }
+
+    /// Mutably borrows the Rust value, failing if it is borrowed.
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Data>, RuntimeError> {
+        match self.data.try_write() {
+            Ok(data) => Ok(data),
+            Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(RuntimeError::InstanceInUse),
+        }
+    }
+
+    /// Counts the Rust value and what it holds. Values held by an instance
+    /// that is mutably borrowed, such as by a running method, are skipped.
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        tracer.bytes(self.class.size);
+        if let Ok(data) = self.read() {
+            (self.class.trace)(data.as_ref(), tracer);
+        }
+    }
+
+    /// Returns the value of a property, or a method bound to the instance.
+    pub(crate) fn get(self: &Arc<Self>, name: &str) -> Result<Value, RuntimeError> {
+        if let Some(property) = self.class.properties.get(name) {
+            let data = self.read()?;
+            return Ok((property.get)(data.as_ref()));
+        }
+
+        if let Some(method) = self.class.methods.get(name) {
+            return Ok(Value::BoundMethod(Arc::new(BoundMethod {
+                receiver: self.clone(),
+                method: method.clone(),
+            })));
+        }
+
+        if let Some(fallback) = &self.class.fallback {
+            let data = self.read()?;
+
+            if let Some(value) = (fallback.get)(data.as_ref(), name) {
+                return Ok(value);
+            }
+        }
+
+        Err(RuntimeError::UndefinedProperty(name.to_string()))
+    }
+
+    /// Returns the value of a property, or a method bound to the instance.
+    pub(crate) fn get_member(self: &Arc<Self>, member: &Member) -> Result<Value, RuntimeError> {
+        match member {
+            Member::Property(property) => {
+                let data = self.read()?;
+                Ok((property.get)(data.as_ref()))
+            }
+            Member::Method(method) => Ok(Value::BoundMethod(Arc::new(BoundMethod {
+                receiver: self.clone(),
+                method: method.clone(),
+            }))),
+        }
+    }
+
+    /// Assigns the property with the name.
+    pub(crate) fn set_member(
+        &self,
+        name: &str,
+        property: &Property,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let Some(set) = &property.set else {
+            return Err(RuntimeError::ReadOnlyProperty(name.to_string()));
+        };
+
+        let mut data = self.write()?;
+        set(data.as_mut(), value)
+    }
+
+    /// Assigns a property.
+    pub(crate) fn set(&self, name: &str, value: Value) -> Result<(), RuntimeError> {
+        let data = || self.write();
+
+        let property = self.class.properties.get(name).map(Arc::as_ref);
+        match (property, &self.class.fallback) {
+            (Some(Property { set: Some(set), .. }), _) => set(data()?.as_mut(), value),
+            (None, Some(Fallback { set: Some(set), .. })) => set(data()?.as_mut(), name, value),
+            (Some(_), _) | (None, Some(_)) => Err(RuntimeError::ReadOnlyProperty(name.to_string())),
+            (None, None) => Err(RuntimeError::UndefinedProperty(name.to_string())),
+        }
+    }
+}
+
+impl Debug for Instance {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+/// Instances are only equal to themselves.
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl TryFrom<Value> for Arc<Instance> {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Instance(instance) => Ok(instance),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+}
+
+/// The Rust value of an [`Instance`], borrowed with [`Instance::borrow`].
+pub struct InstanceRef<'a, T> {
+    data: RwLockReadGuard<'a, Data>,
+    _type: PhantomData<&'a T>,
+}
+
+impl<T: Any> Deref for InstanceRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        downcast_ref(self.data.as_ref())
+    }
+}
+
+/// The Rust value of an [`Instance`], mutably borrowed with [`Instance::borrow_mut`].
+pub struct InstanceRefMut<'a, T> {
+    data: RwLockWriteGuard<'a, Data>,
+    _type: PhantomData<&'a mut T>,
+}
+
+impl<T: Any> Deref for InstanceRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        downcast_ref(self.data.as_ref())
+    }
+}
+
+impl<T: Any> DerefMut for InstanceRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        downcast_mut(self.data.as_mut())
+    }
+}
+
+/// A method together with the instance it was accessed on, created by
+/// accessing a method as a property.
+pub struct BoundMethod {
+    pub receiver: Arc<Instance>,
+    pub method: Arc<Method>,
+}
+
+impl BoundMethod {
+    pub(crate) fn call(
+        &self,
+        context: &mut VmContext,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let mut data = self.receiver.write()?;
+
+        (self.method.function)(data.as_mut(), context, args)
+    }
+}
+
+impl Debug for BoundMethod {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<method {}.{}/{}>",
+            self.receiver.class.name, self.method.name, self.method.arity
+        )
+    }
+}
+
+/// Bound methods are only equal to themselves.
+impl PartialEq for BoundMethod {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Returns the value of a property of an instance.
+pub(crate) fn get_property(object: &Value, name: &str) -> Result<Value, RuntimeError> {
+    match object {
+        Value::Instance(instance) => instance.get(name),
+        _ => Err(RuntimeError::OnlyInstancesHaveProperties),
+    }
+}
+
+/// Assigns a property of an instance.
+pub(crate) fn set_property(object: &Value, name: &str, value: Value) -> Result<(), RuntimeError> {
+    match object {
+        Value::Instance(instance) => instance.set(name, value),
+        _ => Err(RuntimeError::OnlyInstancesHaveFields),
+    }
+}
@@ -0,0 +1,258 @@
+/*!
+Maps and lists for passing structured data between Rust and Lox.
+
+Lox has no collection types of its own, so these are [classes][crate::class]
+implemented in Rust. They are what maps and sequences become when converting
+Rust data with the **`serde`** feature, and scripts can create them too once
+the host defines them:
+
+```
+use rulox::{collections::{List, Record}, compiler, vm::VM};
+
+let mut out = Vec::new();
+let mut err = Vec::new();
+let mut vm = VM::new(&mut out, &mut err);
+vm.define_class::<Record>();
+vm.define_class::<List>();
+
+let source = "
+    var point = Record();
+    point.x = 1;
+    var points = List();
+    points.push(point);
+    print points.get(0).x;
+    print points.len();
+";
+vm.interpret(&compiler::compile(source).unwrap()).unwrap();
+assert_eq!(out, b"1\n1\n");
+```
+*/
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    mem::size_of,
+    sync::{Arc, LazyLock},
+};
+
+use crate::{
+    class::{Class, ClassBuilder, LoxClass},
+    memory::Tracer,
+    native::{self, VmContext},
+    shape::Shape,
+    value::Value,
+    vm::RuntimeError,
+};
+
+static RECORD: LazyLock<Arc<Class>> = LazyLock::new(|| Arc::new(Class::new::<Record>()));
+static LIST: LazyLock<Arc<Class>> = LazyLock::new(|| Arc::new(Class::new::<List>()));
+
+/// Named values, accessed from Lox as properties of the instance.
+///
+/// Fields keep the order they were first set in. Their names are kept in a
+/// [`Shape`] shared with the other records that had the same fields set in
+/// the same order.
+#[derive(Clone)]
+pub struct Record {
+    shape: Arc<Shape>,
+
+    /// The values of the fields, in the order of the names in the shape.
+    values: Vec<Value>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self {
+            shape: Shape::empty(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Returns the value of a field, if it is set.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.shape.slot(name).map(|slot| &self.values[slot])
+    }
+
+    /// Sets the value of a field, adding it if it is not already set.
+    pub fn set<T>(&mut self, name: &str, value: T)
+    where
+        T: Into<Value>,
+    {
+        let value = value.into();
+        match self.shape.slot(name) {
+            Some(slot) => self.values[slot] = value,
+            None => {
+                self.shape = self.shape.with(name);
+                self.values.push(value);
+            }
+        }
+    }
+
+    /// Returns the fields, in the order they were first set.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.shape
+            .names()
+            .into_iter()
+            .map(|name| name.as_ref())
+            .zip(&self.values)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the shape naming the fields.
+    pub fn shape(&self) -> &Arc<Shape> {
+        &self.shape
+    }
+
+    /// Returns the value in a slot of the shape.
+    pub(crate) fn slot(&self, slot: usize) -> &Value {
+        &self.values[slot]
+    }
+
+    /// Sets the value in a slot of the shape.
+    pub(crate) fn set_slot(&mut self, slot: usize, value: Value) {
+        self.values[slot] = value;
+    }
+
+    /// Adds a field, given the shape of the record with it added.
+    pub(crate) fn add_field(&mut self, shape: Arc<Shape>, value: Value) {
+        self.shape = shape;
+        self.values.push(value);
+    }
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Record {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_map().entries(self.fields()).finish()
+    }
+}
+
+/// Records are equal if they have equal fields, set in the same order.
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        (Arc::ptr_eq(&self.shape, &other.shape) || self.shape.names() == other.shape.names())
+            && self.values == other.values
+    }
+}
+
+impl LoxClass for Record {
+    const NAME: &'static str = "Record";
+
+    fn register(class: &mut ClassBuilder<Self>) {
+        class
+            .constructor(0, |_, _| Ok(Record::new()))
+            .fallback_property(
+                |record, name| record.get(name).cloned(),
+                Some(|record, name, value| {
+                    record.set(name, value);
+                    Ok(())
+                }),
+            );
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.bytes(self.values.capacity() * size_of::<Value>());
+        for (name, value) in self.shape.names().into_iter().zip(&self.values) {
+            tracer.string(name);
+            tracer.value(value);
+        }
+    }
+}
+
+/// Wraps the record in an instance of the `Record` class.
+impl From<Record> for Value {
+    fn from(record: Record) -> Self {
+        RECORD.instantiate(record)
+    }
+}
+
+/// A list of values, accessed from Lox with the methods `len()`, `get(index)`,
+/// `set(index, value)` and `push(value)`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct List {
+    pub items: Vec<Value>,
+}
+
+impl List {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn len(&mut self, _: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+        Ok((self.items.len() as f64).into())
+    }
+
+    fn get(&mut self, _: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+        let index = self.index(args)?;
+        Ok(self.items[index].clone())
+    }
+
+    fn set(&mut self, _: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+        let index = self.index(args)?;
+        self.items[index] = args[1].clone();
+        Ok(args[1].clone())
+    }
+
+    fn push(&mut self, _: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.items.push(args[0].clone());
+        Ok(Value::Nil)
+    }
+
+    /// Returns the index given as the first argument, if it refers to an item.
+    fn index(&self, args: &[Value]) -> Result<usize, RuntimeError> {
+        let index: f64 = native::arg(args, 0)?;
+
+        if index.fract() != 0.0 || index < 0.0 || index >= self.items.len() as f64 {
+            return Err(RuntimeError::Native(format!(
+                "List index {} out of range.",
+                index
+            )));
+        }
+
+        Ok(index as usize)
+    }
+}
+
+impl LoxClass for List {
+    const NAME: &'static str = "List";
+
+    fn register(class: &mut ClassBuilder<Self>) {
+        class
+            .constructor(0, |_, _| Ok(List::new()))
+            .method("len", 0, List::len)
+            .method("get", 1, List::get)
+            .method("set", 2, List::set)
+            .method("push", 1, List::push);
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.bytes(self.items.capacity() * size_of::<Value>());
+        for item in &self.items {
+            tracer.value(item);
+        }
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(items: Vec<Value>) -> Self {
+        Self { items }
+    }
+}
+
+/// Wraps the list in an instance of the `List` class.
+impl From<List> for Value {
+    fn from(list: List) -> Self {
+        LIST.instantiate(list)
+    }
+}
@@ -1,42 +1,1022 @@
+/*!
+Compiles source code into a [`Chunk`] of bytecode for the [`VM`][crate::vm::VM].
+
+The source is [parsed][crate::parser] into a syntax tree, its names are
+[resolved][crate::resolver], and the code is generated by walking the tree.
+Errors from all three are reported together, in the order they appear in the
+source, leaving out those likely caused by an earlier error.
+*/
+
+use std::{
+    cmp::Ordering::{Greater, Less},
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    mem,
+    sync::Arc,
+};
+
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use thiserror::Error;
 
 use crate::{
+    ast::{
+        self, BinaryOperator, Expr, ExprKind, Identifier, Literal, LogicalOperator, Position,
+        Program, Stmt, StmtKind, UnaryOperator,
+    },
+    function::Function,
+    parser::{self, Diagnostic, Parse},
+    peephole,
+    resolver::{self, Resolution, Warning},
     scanner::{Scanner, TokenType},
     value::Value,
 };
 
-pub fn compile(source: &str) {
-    let mut scanner = Scanner::new(source);
-    let mut line = -1;
+/// Options controlling how source code is compiled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompileOptions {
+    /// Compile the code for interactive use, printing the value of every
+    /// expression statement and allowing the final semicolon to be left out.
+    pub repl: bool,
 
-    loop {
-        let token = scanner.scan_token();
+    /// How much to optimize the generated code.
+    pub opt_level: OptLevel,
+}
 
-        if token.line != line {
-            print!("{:4} ", token.line);
-            line = token.line;
-        } else {
-            print!("   | ");
+/// How much the compiler optimizes the code it generates.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Generate code exactly as written.
+    #[default]
+    None,
+
+    /// Fold constant expressions, remove code that can never run and store
+    /// each distinct constant once per chunk.
+    Basic,
+
+    /// Also fuse common sequences of instructions into superinstructions,
+    /// see [`peephole`].
+    Full,
+}
+
+/// Compiles the given source code into a [`Chunk`] of bytecode.
+pub fn compile(source: &str) -> Result<Chunk, CompileError> {
+    compile_with_options(source, CompileOptions::default())
+}
+
+/// Compiles the given source code into a [`Chunk`] of bytecode using the given options.
+pub fn compile_with_options(source: &str, options: CompileOptions) -> Result<Chunk, CompileError> {
+    compile_with_warnings(source, options).map(|(chunk, _)| chunk)
+}
+
+/// Compiles the given source code like [`compile_with_options`], also
+/// returning the [warnings][Warning] about code that runs but is likely a
+/// mistake, such as unused local variables.
+///
+/// ```
+/// use rulox::compiler::{self, CompileOptions};
+///
+/// let (_, warnings) =
+///     compiler::compile_with_warnings("{ var a = 1; }", CompileOptions::default()).unwrap();
+/// assert_eq!(
+///     warnings[0].to_string(),
+///     "[line 1] Warning: Local variable 'a' is never used."
+/// );
+/// ```
+pub fn compile_with_warnings(
+    source: &str,
+    options: CompileOptions,
+) -> Result<(Chunk, Vec<Warning>), CompileError> {
+    let parse = parser::parse_with_options(source, options);
+    let resolution = resolver::resolve(&parse.program);
+
+    let mut codegen = Codegen::new(&resolution, options);
+    let script = codegen.script(&parse.program);
+
+    let mut found = resolution.errors.clone();
+    found.append(&mut codegen.errors);
+    check(source, &parse, found)?;
+
+    // Nested functions are disassembled along with the script.
+    #[cfg(feature = "trace")]
+    script.chunk.disassemble("code");
+
+    Ok((script.chunk, resolution.warnings))
+}
+
+/// Fails with the errors found by the parser and those `found` by the passes
+/// after it, if there are any, in the order they appear in the source.
+///
+/// The other backends use this to report the same errors as this compiler.
+pub(crate) fn check(
+    source: &str,
+    parse: &Parse,
+    mut found: Vec<Diagnostic>,
+) -> Result<(), CompileError> {
+    if parse.errors.is_empty() && found.is_empty() {
+        return Ok(());
+    }
+
+    locate(source, &mut found);
+    let errors = report(parse.errors.clone(), found, &parse.resumes);
+    Err(CompileError::Syntax(errors))
+}
+
+/// Returns the errors to report out of those found by the parser and those
+/// found later, in the order they appear in the source.
+///
+/// Errors found after another one before the parser recovered from it are
+/// likely caused by it, and are left out. The parser already leaves out its
+/// own, but doesn't know about the errors found later.
+fn report(
+    parsed: Vec<Diagnostic>,
+    mut found: Vec<Diagnostic>,
+    resumes: &HashMap<Position, Position>,
+) -> Vec<SyntaxError> {
+    found.sort_by_key(|diagnostic| diagnostic.found);
+    let mut found = found.into_iter().peekable();
+
+    let mut diagnostics = Vec::new();
+    for diagnostic in parsed {
+        while let Some(earlier) = found.next_if(|found| found.found < diagnostic.found) {
+            diagnostics.push((false, earlier));
+        }
+
+        diagnostics.push((true, diagnostic));
+    }
+    diagnostics.extend(found.map(|diagnostic| (false, diagnostic)));
+
+    let mut errors = Vec::new();
+    let mut last: Option<(bool, Diagnostic)> = None;
+
+    for (parsed, diagnostic) in diagnostics {
+        if let Some((last_parsed, last)) = &last
+            && !(parsed && *last_parsed)
+        {
+            // Found in the same declaration, or in the tokens skipped after it.
+            let resume = last.declaration.and_then(|start| resumes.get(&start));
+            if diagnostic.declaration == last.declaration
+                || resume.is_some_and(|resume| diagnostic.position < *resume)
+            {
+                continue;
+            }
         }
 
-        println!("{:?} '{}'", token.token_type, token.lexeme);
+        errors.push(diagnostic.error.clone());
+        last = Some((parsed, diagnostic));
+    }
 
+    errors
+}
+
+/// Sets where on their lines the errors are from the tokens they were found at.
+fn locate(source: &str, errors: &mut [Diagnostic]) {
+    if errors.is_empty() {
+        return;
+    }
+
+    let mut scanner = Scanner::new(source);
+    let mut tokens = HashMap::new();
+    loop {
+        let token = scanner.scan_token();
+        if token.token_type != TokenType::Error {
+            tokens.insert(token.position(), token);
+        }
         if token.token_type == TokenType::EOF {
             break;
         }
     }
+
+    for error in errors {
+        // Errors are only ever found at tokens that were parsed.
+        if let Some(&token) = tokens.get(&error.position) {
+            error.error.location = parser::location(token);
+        }
+    }
 }
 
-#[derive(Default)]
+/// A single error reported while parsing source code.
+#[derive(Clone, Debug)]
+pub struct SyntaxError {
+    pub line: i32,
+
+    /// Where on the line the error occurred, e.g. ` at end` or ` at '+'`.
+    pub location: String,
+
+    pub message: String,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FunctionKind {
+    Function,
+    Script,
+}
+
+/// A point in the chunk being compiled, which the code after it can be discarded back to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Mark {
+    /// The offset of the next instruction.
+    code: usize,
+
+    /// The number of constants, any added later are only used by later code.
+    constants: usize,
+}
+
+/// A constant value pushed by the code at the end of a chunk, which may be
+/// folded into the operation applied to it.
+struct Folded {
+    /// Where the instruction pushing the value starts.
+    start: Mark,
+
+    value: Value,
+}
+
+/// A constant that may be shared by every instruction using the same value.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum ConstantKey {
+    /// A number by its bits, so that `0` and `-0` are kept apart.
+    Number(u64),
+    String(Arc<str>),
+}
+
+impl ConstantKey {
+    fn new(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(ConstantKey::Number(n.to_bits())),
+            Value::String(s) => Some(ConstantKey::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a function being compiled.
+struct Compiler {
+    function: Function,
+    kind: FunctionKind,
+
+    /// The number of locals declared in each scope, with the innermost last.
+    scopes: Vec<usize>,
+
+    /// The constants pushed by the most recent instructions, with nothing
+    /// else emitted or jumping in between them.
+    folds: Vec<Folded>,
+
+    /// The index of each constant already in the chunk, when optimizing.
+    constants: HashMap<ConstantKey, usize>,
+}
+
+impl Compiler {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        Self {
+            function: Function::new(name),
+            kind,
+            scopes: Vec::new(),
+            folds: Vec::new(),
+            constants: HashMap::new(),
+        }
+    }
+}
+
+/// Generates code from a resolved syntax tree.
+struct Codegen<'a> {
+    resolution: &'a Resolution,
+
+    /// The functions being compiled, with the innermost last.
+    compilers: Vec<Compiler>,
+
+    /// The token the code being emitted was compiled from, which gives its
+    /// line. Code is attributed to the last token that was parsed when it
+    /// would have been emitted while parsing.
+    position: Position,
+
+    /// The errors found, located on their lines later.
+    errors: Vec<Diagnostic>,
+    options: CompileOptions,
+}
+
+impl<'a> Codegen<'a> {
+    fn new(resolution: &'a Resolution, options: CompileOptions) -> Self {
+        Self {
+            resolution,
+            compilers: vec![Compiler::new("script", FunctionKind::Script)],
+            position: Position::default(),
+            errors: Vec::new(),
+            options,
+        }
+    }
+
+    /// Compiles the top-level code of the program.
+    fn script(&mut self, program: &Program) -> Function {
+        for statement in &program.statements {
+            self.declaration(statement);
+        }
+
+        self.position = program.end;
+        self.end()
+    }
+
+    fn compiler(&mut self) -> &mut Compiler {
+        self.compilers
+            .last_mut()
+            .expect("there is always a function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler().function.chunk
+    }
+
+    fn optimize(&self) -> bool {
+        self.options.opt_level >= OptLevel::Basic
+    }
+
+    /// Returns the current point in the chunk being compiled.
+    fn mark(&mut self) -> Mark {
+        let chunk = self.chunk();
+        Mark {
+            code: chunk.code.len(),
+            constants: chunk.constants.len(),
+        }
+    }
+
+    /// Finishes compiling the innermost function, returning it.
+    fn end(&mut self) -> Function {
+        self.emit_return();
+
+        let mut compiler = self
+            .compilers
+            .pop()
+            .expect("there is always a function being compiled");
+
+        if self.options.opt_level >= OptLevel::Full {
+            peephole::optimize(&mut compiler.function.chunk);
+        }
+
+        compiler.function
+    }
+
+    fn scope_depth(&mut self) -> usize {
+        self.compiler().scopes.len()
+    }
+
+    fn begin_scope(&mut self) {
+        self.compiler().scopes.push(0);
+    }
+
+    /// Ends the innermost scope, popping its locals off the stack.
+    fn end_scope(&mut self) {
+        let locals = self.compiler().scopes.pop().unwrap_or_default();
+
+        for _ in 0..locals {
+            self.emit_byte(OpCode::Pop);
+        }
+    }
+
+    /// Compiles a statement that the parser may recover from errors after.
+    fn declaration(&mut self, stmt: &Stmt) {
+        self.statement(stmt);
+        parser::end_declaration(&mut self.errors, stmt.span.start);
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let end = stmt.span.end;
+
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+                self.position = end;
+
+                let compiler = self.compiler();
+                let top_level = compiler.kind == FunctionKind::Script && compiler.scopes.is_empty();
+
+                if self.options.repl && top_level {
+                    self.emit_byte(OpCode::Print);
+                } else {
+                    self.emit_byte(OpCode::Pop);
+                }
+            }
+            StmtKind::Print(expr) => {
+                self.expression(expr);
+                self.position = end;
+                self.emit_byte(OpCode::Print);
+            }
+            StmtKind::Var { name, initializer } => {
+                self.var_declaration(name, initializer.as_ref(), end);
+            }
+            StmtKind::Function(function) => self.fun_declaration(function, end),
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                self.block(statements);
+                self.position = end;
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                right_paren,
+                then_branch,
+                else_branch,
+            } => self.if_statement(condition, *right_paren, then_branch, else_branch.as_deref()),
+            StmtKind::While {
+                condition,
+                right_paren,
+                body,
+            } => self.while_statement(condition, *right_paren, body),
+            StmtKind::For {
+                initializer,
+                condition,
+                semicolon,
+                increment,
+                right_paren,
+                body,
+            } => {
+                self.begin_scope();
+
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+
+                self.for_loop(
+                    condition.as_ref(),
+                    *semicolon,
+                    increment.as_ref(),
+                    *right_paren,
+                    body,
+                );
+
+                self.end_scope();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                    self.position = end;
+                    self.emit_byte(OpCode::Return);
+                } else {
+                    self.position = end;
+                    self.emit_return();
+                }
+            }
+            StmtKind::Error => {}
+        }
+
+        self.position = end;
+    }
+
+    fn fun_declaration(&mut self, function: &ast::Function, end: Position) {
+        let global = self.declare_variable(&function.name);
+        self.function(function, end);
+        self.define_variable(global);
+    }
+
+    /// Compiles the parameters and body of a function, emitting it as a constant.
+    fn function(&mut self, function: &ast::Function, end: Position) {
+        let mut compiler = Compiler::new(&function.name.name, FunctionKind::Function);
+        compiler.function.arity = function.params.len().min(u8::MAX as usize) as u8;
+        self.compilers.push(compiler);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare_variable(param);
+        }
+
+        self.block(&function.body);
+
+        // The scope does not need to be ended, returning discards the whole frame.
+        self.position = end;
+        let function = self.end();
+        self.emit_constant(Value::Function(Arc::new(function)));
+    }
+
+    fn var_declaration(&mut self, name: &Identifier, initializer: Option<&Expr>, end: Position) {
+        let global = self.declare_variable(name);
+
+        match initializer {
+            Some(initializer) => self.expression(initializer),
+            None => self.emit_byte(OpCode::Nil),
+        }
+
+        self.position = end;
+        self.define_variable(global);
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        let mut returned = false;
+
+        for statement in statements {
+            let start = self.mark();
+
+            // Statements after a return are still compiled to report errors
+            // in them, but can never run.
+            self.declaration(statement);
+            if returned {
+                self.discard_code(start);
+            }
+
+            returned |= matches!(statement.kind, StmtKind::Return(_)) && self.optimize();
+        }
+    }
+
+    fn if_statement(
+        &mut self,
+        condition: &Expr,
+        right_paren: Position,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) {
+        let condition_start = self.mark();
+        self.expression(condition);
+        self.position = right_paren;
+
+        // Only the branch taken by a constant condition is kept.
+        if let Some(condition) = self.constant_since(condition_start) {
+            self.discard_code(condition_start);
+
+            let truthy = !condition.is_falsey();
+            self.branch(then_branch, truthy);
+            if let Some(else_branch) = else_branch {
+                self.branch(else_branch, !truthy);
+            }
+
+            return;
+        }
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement(then_branch);
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::Pop);
+
+        if let Some(else_branch) = else_branch {
+            self.statement(else_branch);
+        }
+
+        self.patch_jump(else_jump);
+    }
+
+    /// Compiles a branch of an `if` statement, discarding its code unless it is `taken`.
+    fn branch(&mut self, branch: &Stmt, taken: bool) {
+        let start = self.mark();
+        self.statement(branch);
+
+        if !taken {
+            self.discard_code(start);
+        }
+    }
+
+    fn while_statement(&mut self, condition: &Expr, right_paren: Position, body: &Stmt) {
+        let loop_start = self.chunk().code.len();
+
+        self.expression(condition);
+        self.position = right_paren;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement(body);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::Pop);
+    }
+
+    /// Compiles the clauses after the initializer of a `for` loop, and its body.
+    fn for_loop(
+        &mut self,
+        condition: Option<&Expr>,
+        semicolon: Position,
+        increment: Option<&Expr>,
+        right_paren: Position,
+        body: &Stmt,
+    ) {
+        let mut loop_start = self.chunk().code.len();
+
+        let mut exit_jump = None;
+        if let Some(condition) = condition {
+            self.expression(condition);
+            self.position = semicolon;
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_byte(OpCode::Pop);
+        }
+        self.position = semicolon;
+
+        // The increment is compiled before the body but runs after it, so the
+        // body jumps back to it and it jumps back to the condition.
+        if let Some(increment) = increment {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().code.len();
+
+            self.expression(increment);
+            self.emit_byte(OpCode::Pop);
+            self.position = right_paren;
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement(body);
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::Pop);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                self.position = expr.span.end;
+
+                let value = match literal {
+                    Literal::Nil => Value::Nil,
+                    Literal::Bool(b) => Value::Bool(*b),
+                    Literal::Number(n) => Value::Number(*n),
+                    Literal::String(s) => s.as_str().into(),
+                };
+                self.emit_value(value);
+            }
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Variable(name) => {
+                let (get, _, arg) = self.named_variable(name);
+                self.emit_bytes(get, arg);
+            }
+            ExprKind::Assign { name, value } => {
+                let (_, set, arg) = self.named_variable(name);
+                self.expression(value);
+                self.emit_bytes(set, arg);
+            }
+            ExprKind::Unary { operator, operand } => self.unary(*operator, operand),
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => self.binary(*operator, left, right),
+            ExprKind::Logical {
+                operator,
+                position,
+                left,
+                right,
+            } => {
+                self.expression(left);
+                self.position = *position;
+
+                match operator {
+                    LogicalOperator::And => self.and(right),
+                    LogicalOperator::Or => self.or(right),
+                }
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+
+                self.position = expr.span.end;
+                let arg_count = arguments.len().min(u8::MAX as usize) as u8;
+                self.emit_bytes(OpCode::Call, arg_count);
+            }
+            ExprKind::Get { object, name } => {
+                self.expression(object);
+                self.position = name.span.start;
+
+                let name = self.identifier_constant(name);
+                self.emit_bytes(OpCode::GetProperty, name);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.position = name.span.start;
+
+                let name = self.identifier_constant(name);
+                self.expression(value);
+                self.emit_bytes(OpCode::SetProperty, name);
+            }
+            // These are reported by the resolver, and never compiled.
+            ExprKind::This | ExprKind::Super { .. } | ExprKind::Error => {}
+        }
+
+        self.position = expr.span.end;
+    }
+
+    /// Returns the instructions getting and setting the variable with the
+    /// given name, and their operand.
+    fn named_variable(&mut self, name: &Identifier) -> (OpCode, OpCode, u8) {
+        self.position = name.span.start;
+
+        match self.resolution.slot(name) {
+            Some(slot) => (OpCode::GetLocal, OpCode::SetLocal, slot),
+            None => (
+                OpCode::GetGlobal,
+                OpCode::SetGlobal,
+                self.identifier_constant(name),
+            ),
+        }
+    }
+
+    fn unary(&mut self, operator: UnaryOperator, operand: &Expr) {
+        let operand_start = self.mark();
+        self.expression(operand);
+
+        if let Some(value) = self.constant_since(operand_start)
+            && let Some(value) = fold_unary(operator, &value)
+        {
+            self.discard_code(operand_start);
+            self.emit_value(value);
+            return;
+        }
+
+        match operator {
+            UnaryOperator::Not => self.emit_byte(OpCode::Not),
+            UnaryOperator::Negate => self.emit_byte(OpCode::Negate),
+        }
+    }
+
+    fn binary(&mut self, operator: BinaryOperator, left: &Expr, right: &Expr) {
+        self.expression(left);
+
+        let right_start = self.mark();
+        self.expression(right);
+
+        if self.fold_binary(operator, right_start) {
+            return;
+        }
+
+        match operator {
+            BinaryOperator::NotEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
+            BinaryOperator::Equal => self.emit_byte(OpCode::Equal),
+            BinaryOperator::Greater => self.emit_byte(OpCode::Greater),
+            BinaryOperator::GreaterEqual => self.emit_bytes(OpCode::Less, OpCode::Not),
+            BinaryOperator::Less => self.emit_byte(OpCode::Less),
+            BinaryOperator::LessEqual => self.emit_bytes(OpCode::Greater, OpCode::Not),
+            BinaryOperator::Add => self.emit_byte(OpCode::Add),
+            BinaryOperator::Subtract => self.emit_byte(OpCode::Subtract),
+            BinaryOperator::Multiply => self.emit_byte(OpCode::Multiply),
+            BinaryOperator::Divide => self.emit_byte(OpCode::Divide),
+        }
+    }
+
+    /// Replaces the code for a binary operation on two constants with its
+    /// result, returning whether it could be folded.
+    fn fold_binary(&mut self, operator: BinaryOperator, right_start: Mark) -> bool {
+        let Some(right) = self.constant_since(right_start) else {
+            return false;
+        };
+
+        let folds = &self.compiler().folds;
+        let Some(left) = folds.len().checked_sub(2).map(|index| &folds[index]) else {
+            return false;
+        };
+
+        let left_start = left.start;
+        let Some(value) = fold_binary(operator, &left.value, &right) else {
+            return false;
+        };
+
+        self.discard_code(left_start);
+        self.emit_value(value);
+        true
+    }
+
+    /// Compiles the right operand of `and`, which is skipped if the left is falsey.
+    fn and(&mut self, right: &Expr) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit_byte(OpCode::Pop);
+        self.expression(right);
+
+        self.patch_jump(end_jump);
+    }
+
+    /// Compiles the right operand of `or`, which is skipped if the left is truthy.
+    fn or(&mut self, right: &Expr) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::Pop);
+        self.expression(right);
+
+        self.patch_jump(end_jump);
+    }
+
+    /// Declares a variable, returning the constant holding its name for
+    /// globals, and 0 for locals, which are not looked up by name.
+    fn declare_variable(&mut self, name: &Identifier) -> u8 {
+        self.position = name.span.start;
+
+        match self.compiler().scopes.last_mut() {
+            Some(locals) => {
+                *locals += 1;
+                0
+            }
+            None => self.identifier_constant(name),
+        }
+    }
+
+    /// Defines a declared variable if it is a global, locals being defined by
+    /// leaving their value on the stack.
+    fn define_variable(&mut self, global: u8) {
+        if self.scope_depth() == 0 {
+            self.emit_bytes(OpCode::DefineGlobal, global);
+        }
+    }
+
+    /// Adds the name of the identifier to the constant table, returning its index.
+    fn identifier_constant(&mut self, name: &Identifier) -> u8 {
+        self.make_constant(name.name.as_str().into())
+    }
+
+    /// Adds a constant to the chunk, reusing an equal one when optimizing.
+    fn add_constant(&mut self, value: Value) -> Result<usize, CompileError> {
+        let key = ConstantKey::new(&value).filter(|_| self.optimize());
+        if let Some(key) = &key
+            && let Some(&index) = self.compiler().constants.get(key)
+        {
+            return Ok(index);
+        }
+
+        let index = self.chunk().add_constant(value)?;
+        if let Some(key) = key {
+            self.compiler().constants.insert(key, index);
+        }
+
+        Ok(index)
+    }
+
+    /// Adds a constant that is referred to by a single byte operand.
+    fn make_constant(&mut self, value: Value) -> u8 {
+        match self.add_constant(value) {
+            Ok(index) if index <= u8::MAX as usize => index as u8,
+            _ => {
+                self.error("Too many constants in one chunk.");
+                0
+            }
+        }
+    }
+
+    fn emit_byte<T>(&mut self, byte: T)
+    where
+        T: Into<u8>,
+    {
+        let line = self.position.line as usize;
+        let compiler = self.compiler();
+        compiler.folds.clear();
+        compiler.function.chunk.write(byte, line);
+    }
+
+    fn emit_bytes<T, U>(&mut self, first: T, second: U)
+    where
+        T: Into<u8>,
+        U: Into<u8>,
+    {
+        self.emit_byte(first);
+        self.emit_byte(second);
+    }
+
+    /// Emits a forward jump with a placeholder offset, returning the offset of
+    /// the placeholder so it can be patched once the target is known.
+    fn emit_jump(&mut self, opcode: OpCode) -> usize {
+        self.emit_byte(opcode);
+        self.emit_bytes(0xFF, 0xFF);
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump whose placeholder is at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize) {
+        // The code before the jump target runs on a different path than the
+        // code after it, so they can't be folded together.
+        self.compiler().folds.clear();
+
+        let jump = self.chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error("Too much code to jump over.");
+            return;
+        };
+
+        let [high, low] = jump.to_be_bytes();
+        let code = &mut self.chunk().code;
+        code[offset] = high;
+        code[offset + 1] = low;
+    }
+
+    /// Emits a backward jump to the instruction at `loop_start`.
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OpCode::Loop);
+
+        let jump = self.chunk().code.len() - loop_start + 2;
+        let jump = u16::try_from(jump).unwrap_or_else(|_| {
+            self.error("Loop body too large.");
+            0
+        });
+
+        let [high, low] = jump.to_be_bytes();
+        self.emit_bytes(high, low);
+    }
+
+    /// Emits an implicit `return nil`.
+    fn emit_return(&mut self) {
+        self.emit_bytes(OpCode::Nil, OpCode::Return);
+    }
+
+    fn emit_constant<T>(&mut self, value: T)
+    where
+        T: Into<Value>,
+    {
+        let line = self.position.line as usize;
+        self.compiler().folds.clear();
+
+        match self.add_constant(value.into()) {
+            Ok(index) => self.chunk().write_constant_index(index, line),
+            Err(error) => self.error(&format!("{}.", error)),
+        }
+    }
+
+    /// Emits code pushing a number, string, boolean or `nil`, which may be
+    /// folded into the operations applied to it when optimizing.
+    fn emit_value(&mut self, value: Value) {
+        let start = self.mark();
+        let mut folds = mem::take(&mut self.compiler().folds);
+
+        match value {
+            Value::Nil => self.emit_byte(OpCode::Nil),
+            Value::Bool(true) => self.emit_byte(OpCode::True),
+            Value::Bool(false) => self.emit_byte(OpCode::False),
+            _ => self.emit_constant(value.clone()),
+        }
+
+        if self.optimize() {
+            folds.push(Folded { start, value });
+            self.compiler().folds = folds;
+        }
+    }
+
+    /// Returns the value pushed by the code since `start`, if it is a constant
+    /// that may be folded.
+    fn constant_since(&mut self, start: Mark) -> Option<Value> {
+        self.compiler()
+            .folds
+            .last()
+            .filter(|folded| folded.start == start)
+            .map(|folded| folded.value.clone())
+    }
+
+    /// Removes the code from `start` on, which must be at an instruction
+    /// nothing jumps into, along with the constants only it used.
+    fn discard_code(&mut self, start: Mark) {
+        let compiler = self.compiler();
+        compiler
+            .folds
+            .retain(|folded| folded.start.code < start.code);
+        if compiler.function.chunk.constants.len() > start.constants {
+            compiler
+                .constants
+                .retain(|_, &mut index| index < start.constants);
+        }
+
+        let chunk = &mut compiler.function.chunk;
+        chunk.truncate(start.code);
+        chunk.constants.truncate(start.constants);
+    }
+
+    /// Reports an error at the token the code being emitted was compiled from.
+    fn error(&mut self, message: &str) {
+        let error = Diagnostic::new(self.position, "", message);
+        self.errors.push(error);
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Chunk {
-    code: Vec<u8>,
+    pub(crate) code: Vec<u8>,
 
-    lines: Vec<usize>,
+    /// Run-length encoded source lines of the code, ordered by offset.
+    pub(crate) lines: Vec<LineRun>,
 
     pub constants: Vec<Value>,
 }
 
+/// A run of consecutive bytes of code that were all compiled from the same line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct LineRun {
+    /// The offset of the first byte in the run.
+    pub(crate) start: usize,
+
+    pub(crate) line: usize,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum OpCode {
@@ -44,6 +1024,26 @@ pub enum OpCode {
 
     ConstantLong,
 
+    Nil,
+
+    True,
+
+    False,
+
+    Pop,
+
+    GetGlobal,
+
+    DefineGlobal,
+
+    SetGlobal,
+
+    Equal,
+
+    Greater,
+
+    Less,
+
     Add,
 
     Subtract,
@@ -52,16 +1052,56 @@ pub enum OpCode {
 
     Divide,
 
+    Not,
+
     Negate,
 
+    Print,
+
     Return,
+
+    Call,
+
+    GetLocal,
+
+    SetLocal,
+
+    GetProperty,
+
+    SetProperty,
+
+    /// Jumps forward by the two byte operand.
+    Jump,
+
+    /// Jumps forward by the two byte operand if the value on top of the stack
+    /// is falsey, leaving it on the stack.
+    JumpIfFalse,
+
+    /// Jumps backward by the two byte operand.
+    Loop,
+
+    /// Adds the constant in the second operand to the local in the slot given
+    /// by the first, pushing the result. Fused from
+    /// `GetLocal; Constant; Add; SetLocal`.
+    IncrLocal,
+
+    /// Adds the constant to the value on top of the stack. Fused from
+    /// `Constant; Add`.
+    AddConst,
+
+    /// Pops two numbers and jumps forward by the two byte operand unless the
+    /// first is less than the second. Fused from `Less; JumpIfFalse; Pop`.
+    JumpIfNotLess,
 }
 
 /// Errors that can occur during compilation.
 #[derive(Error, Clone, Debug)]
 pub enum CompileError {
-    #[error("Too many constants")]
+    #[error("Too many constants in one chunk")]
     TooManyConstants,
+
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Syntax(Vec<SyntaxError>),
 }
 
 /// The maximum number of constants that can be stored in a chunk.
@@ -76,16 +1116,35 @@ impl Chunk {
         }
     }
 
-    pub fn read(&self, idx: usize) -> u8 {
-        self.code[idx]
+    /// Returns the byte at the given offset, or `None` if it is outside of the code.
+    pub fn read(&self, offset: usize) -> Option<u8> {
+        self.code.get(offset).copied()
     }
 
     pub fn write<T>(&mut self, data: T, line: usize)
     where
         T: Into<u8>,
     {
+        if self.lines.last().is_none_or(|run| run.line != line) {
+            self.lines.push(LineRun {
+                start: self.code.len(),
+                line,
+            });
+        }
+
         self.code.push(data.into());
-        self.lines.push(line);
+    }
+
+    /// Returns the source line that the byte at the given offset was compiled from.
+    ///
+    /// Returns `None` if the offset is outside of the code.
+    pub fn line_for_offset(&self, offset: usize) -> Option<usize> {
+        if offset >= self.code.len() {
+            return None;
+        }
+
+        let index = self.lines.partition_point(|run| run.start <= offset);
+        Some(self.lines[index - 1].line)
     }
 
     pub fn write_constant<T>(&mut self, value: T, line: usize) -> Result<(), CompileError>
@@ -93,6 +1152,13 @@ impl Chunk {
         T: Into<Value>,
     {
         let index = self.add_constant(value.into())?;
+        self.write_constant_index(index, line);
+
+        Ok(())
+    }
+
+    /// Writes an instruction pushing the constant at `index`.
+    pub(crate) fn write_constant_index(&mut self, index: usize, line: usize) {
         let opcode = if index > u8::MAX as usize {
             OpCode::ConstantLong
         } else {
@@ -111,11 +1177,17 @@ impl Chunk {
             self.write(mid, line);
             self.write(low, line);
         }
+    }
 
-        Ok(())
+    /// Removes the code from `len` on, along with its lines.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        while self.lines.last().is_some_and(|run| run.start >= len) {
+            self.lines.pop();
+        }
     }
 
-    fn add_constant(&mut self, value: Value) -> Result<usize, CompileError> {
+    pub(crate) fn add_constant(&mut self, value: Value) -> Result<usize, CompileError> {
         if self.constants.len() >= MAX_CONSTANTS {
             return Err(CompileError::TooManyConstants);
         }
@@ -124,7 +1196,8 @@ impl Chunk {
         Ok(self.constants.len() - 1)
     }
 
-    /// Disassembles the chunk, printing the given header to identify it.
+    /// Disassembles the chunk, printing the given header to identify it, followed
+    /// by the functions declared in it.
     pub fn disassemble(&self, name: &str) {
         eprintln!("== {} ==", name);
 
@@ -132,32 +1205,65 @@ impl Chunk {
         while offset < self.code.len() {
             offset = self.disassemble_instruction(offset);
         }
+
+        for constant in &self.constants {
+            if let Value::Function(function) = constant {
+                function.chunk.disassemble(&function.name);
+            }
+        }
     }
 
     /// Disassemble the instruction at the given offset.
     pub fn disassemble_instruction(&self, offset: usize) -> usize {
         eprint!("{:04} ", offset);
-        let line = self.lines[offset];
+        let line = self.line_for_offset(offset);
 
-        if offset > 0 && line == self.lines[offset - 1] {
+        if offset > 0 && line == self.line_for_offset(offset - 1) {
             eprint!("   | ");
         } else {
-            eprint!("{:4} ", line);
+            eprint!("{:4} ", line.unwrap_or_default());
         }
 
-        let instruction = self.code[offset];
+        let Some(&instruction) = self.code.get(offset) else {
+            eprintln!("<truncated>");
+            return offset + 1;
+        };
         let opcode = OpCode::try_from(instruction);
         match opcode {
             Ok(OpCode::Constant) => constant_instruction("OP_CONSTANT", self, offset),
             Ok(OpCode::ConstantLong) => constant_long_instruction("OP_CONSTANT_LONG", self, offset),
 
+            Ok(OpCode::Nil) => simple_instruction("OP_NIL", offset),
+            Ok(OpCode::True) => simple_instruction("OP_TRUE", offset),
+            Ok(OpCode::False) => simple_instruction("OP_FALSE", offset),
+            Ok(OpCode::Pop) => simple_instruction("OP_POP", offset),
+            Ok(OpCode::GetGlobal) => constant_instruction("OP_GET_GLOBAL", self, offset),
+            Ok(OpCode::DefineGlobal) => constant_instruction("OP_DEFINE_GLOBAL", self, offset),
+            Ok(OpCode::SetGlobal) => constant_instruction("OP_SET_GLOBAL", self, offset),
+
+            Ok(OpCode::Equal) => simple_instruction("OP_EQUAL", offset),
+            Ok(OpCode::Greater) => simple_instruction("OP_GREATER", offset),
+            Ok(OpCode::Less) => simple_instruction("OP_LESS", offset),
             Ok(OpCode::Add) => simple_instruction("OP_ADD", offset),
             Ok(OpCode::Subtract) => simple_instruction("OP_SUBTRACT", offset),
             Ok(OpCode::Multiply) => simple_instruction("OP_MULTIPLY", offset),
             Ok(OpCode::Divide) => simple_instruction("OP_DIVIDE", offset),
+            Ok(OpCode::Not) => simple_instruction("OP_NOT", offset),
             Ok(OpCode::Negate) => simple_instruction("OP_NEGATE", offset),
 
+            Ok(OpCode::Print) => simple_instruction("OP_PRINT", offset),
             Ok(OpCode::Return) => simple_instruction("OP_RETURN", offset),
+            Ok(OpCode::Call) => byte_instruction("OP_CALL", self, offset),
+            Ok(OpCode::GetLocal) => byte_instruction("OP_GET_LOCAL", self, offset),
+            Ok(OpCode::SetLocal) => byte_instruction("OP_SET_LOCAL", self, offset),
+            Ok(OpCode::GetProperty) => constant_instruction("OP_GET_PROPERTY", self, offset),
+            Ok(OpCode::SetProperty) => constant_instruction("OP_SET_PROPERTY", self, offset),
+            Ok(OpCode::Jump) => jump_instruction("OP_JUMP", 1, self, offset),
+            Ok(OpCode::JumpIfFalse) => jump_instruction("OP_JUMP_IF_FALSE", 1, self, offset),
+            Ok(OpCode::Loop) => jump_instruction("OP_LOOP", -1, self, offset),
+            Ok(OpCode::IncrLocal) => incr_local_instruction("OP_INCR_LOCAL", self, offset),
+            Ok(OpCode::AddConst) => constant_instruction("OP_ADD_CONST", self, offset),
+            Ok(OpCode::JumpIfNotLess) => jump_instruction("OP_JUMP_IF_NOT_LESS", 1, self, offset),
 
             Err(_) => {
                 eprintln!("Unknown opcode {}", instruction);
@@ -167,6 +1273,71 @@ impl Chunk {
     }
 }
 
+/// Returns the result of applying a unary operator to a constant, if it can
+/// be computed without running the program.
+fn fold_unary(operator: UnaryOperator, operand: &Value) -> Option<Value> {
+    match (operator, operand) {
+        (UnaryOperator::Not, operand) => Some(Value::Bool(operand.is_falsey())),
+        (UnaryOperator::Negate, Value::Number(n)) => Some(Value::Number(-n)),
+        _ => None,
+    }
+}
+
+/// Returns the result of applying a binary operator to two constants, if it
+/// can be computed without running the program.
+///
+/// Operands the operator would fail on at runtime are left alone, so that the
+/// error is still reported when the code runs.
+fn fold_binary(operator: BinaryOperator, left: &Value, right: &Value) -> Option<Value> {
+    let value = match (operator, left, right) {
+        (BinaryOperator::Equal, left, right) => Value::Bool(left == right),
+        (BinaryOperator::NotEqual, left, right) => Value::Bool(left != right),
+        (BinaryOperator::Add, Value::String(left), Value::String(right)) => {
+            Value::String(format!("{}{}", left, right).into())
+        }
+        (_, Value::Number(left), Value::Number(right)) => match operator {
+            BinaryOperator::Add => Value::Number(left + right),
+            BinaryOperator::Subtract => Value::Number(left - right),
+            BinaryOperator::Multiply => Value::Number(left * right),
+            BinaryOperator::Divide => Value::Number(left / right),
+            BinaryOperator::Greater => Value::Bool(left > right),
+            // These compile to the opposite comparison negated, which differs for NaN.
+            BinaryOperator::GreaterEqual => Value::Bool(left.partial_cmp(right) != Some(Less)),
+            BinaryOperator::LessEqual => Value::Bool(left.partial_cmp(right) != Some(Greater)),
+            BinaryOperator::Less => Value::Bool(left < right),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Returns the `N` operand bytes of the instruction at the given offset, or
+/// `None` if the code ends before them.
+///
+/// The VM traces code before it is verified, so the operands may be missing.
+fn operands<const N: usize>(chunk: &Chunk, offset: usize) -> Option<[u8; N]> {
+    let bytes = chunk.code.get(offset + 1..offset + 1 + N)?;
+    bytes.try_into().ok()
+}
+
+/// Formats the constant at the given index, which may be missing in code that
+/// has not been verified.
+fn constant_value(chunk: &Chunk, index: usize) -> String {
+    chunk
+        .constants
+        .get(index)
+        .map_or_else(|| "<bad constant>".to_string(), ToString::to_string)
+}
+
+/// Disassembles an instruction whose operands are cut off by the end of the code.
+fn truncated_instruction(name: &str, chunk: &Chunk) -> usize {
+    eprintln!("{:16} <truncated>", name);
+
+    chunk.code.len()
+}
+
 /// Disassembles a simple single-byte instruction.
 fn simple_instruction(name: &str, offset: usize) -> usize {
     eprintln!("{}", name);
@@ -174,10 +1345,45 @@ fn simple_instruction(name: &str, offset: usize) -> usize {
     offset + 1
 }
 
+/// Disassembles an instruction with a single byte operand.
+fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let Some([operand]) = operands(chunk, offset) else {
+        return truncated_instruction(name, chunk);
+    };
+    eprintln!("{:16} {:4}", name, operand);
+
+    offset + 2
+}
+
+/// Disassembles a jump, showing the offset it jumps to in the given direction.
+fn jump_instruction(name: &str, sign: isize, chunk: &Chunk, offset: usize) -> usize {
+    let Some(operands) = operands(chunk, offset) else {
+        return truncated_instruction(name, chunk);
+    };
+    let jump = u16::from_be_bytes(operands);
+    let target = (offset + 3).wrapping_add_signed(sign * jump as isize);
+    eprintln!("{:16} {:4} -> {}", name, offset, target);
+
+    offset + 3
+}
+
+/// Disassembles an instruction with a local slot and a constant operand.
+fn incr_local_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+    let Some([slot, constant]) = operands(chunk, offset) else {
+        return truncated_instruction(name, chunk);
+    };
+    let value = constant_value(chunk, constant as usize);
+    eprintln!("{:16} {:4} {:4} '{}'", name, slot, constant, value);
+
+    offset + 3
+}
+
 /// Disassembles a simple constant instruction.
 fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    let constant = chunk.code[offset + 1];
-    let value = chunk.constants[constant as usize];
+    let Some([constant]) = operands(chunk, offset) else {
+        return truncated_instruction(name, chunk);
+    };
+    let value = constant_value(chunk, constant as usize);
     eprintln!("{:16} {:4} '{}'", name, constant, value);
 
     offset + 2
@@ -185,11 +1391,11 @@ fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
 
 /// Disassembles a more complex "long constant" instruction.
 fn constant_long_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    let high = chunk.code[offset + 1] as usize;
-    let mid = chunk.code[offset + 2] as usize;
-    let low = chunk.code[offset + 3] as usize;
-    let constant = high << 16 | mid << 8 | low;
-    let value = chunk.constants[constant];
+    let Some([high, mid, low]) = operands(chunk, offset) else {
+        return truncated_instruction(name, chunk);
+    };
+    let constant = (high as usize) << 16 | (mid as usize) << 8 | low as usize;
+    let value = constant_value(chunk, constant);
     eprintln!("{:16} {:8} '{}'", name, constant, value);
 
     offset + 4
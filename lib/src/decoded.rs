@@ -0,0 +1,174 @@
+/*!
+Chunks decoded ahead of time for the fast execution mode, see
+[`ExecutionMode::Fast`][crate::vm::ExecutionMode::Fast].
+
+Decoding verifies the chunk first, so the VM can run the instructions without
+checking opcodes, operands, constant indices or jump targets as it goes.
+Constants and names are resolved to their values, and jumps to the index of
+the instruction they land on. Each property access gets an [`InlineCache`]
+kept alongside the instructions.
+*/
+
+use std::{mem::size_of, sync::Arc};
+
+use crate::{
+    cache::InlineCache,
+    compiler::{Chunk, OpCode},
+    value::Value,
+    verify::{self, VerifyError},
+};
+
+/// A decoded instruction, with its operand resolved.
+#[derive(Clone, Debug)]
+pub(crate) enum Op {
+    Constant(Value),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(usize),
+    SetLocal(usize),
+    GetGlobal(Arc<str>),
+    DefineGlobal(Arc<str>),
+    SetGlobal(Arc<str>),
+
+    /// Gets the property with the name, using the cache with the index.
+    GetProperty(Arc<str>, usize),
+
+    /// Sets the property with the name, using the cache with the index.
+    SetProperty(Arc<str>, usize),
+
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Call(u8),
+
+    /// Continues at the instruction with the index.
+    Jump(usize),
+
+    /// Continues at the instruction with the index if the top of the stack is falsey.
+    JumpIfFalse(usize),
+
+    /// Adds the constant to the local in the slot.
+    IncrLocal(usize, Value),
+
+    AddConst(Value),
+
+    /// Continues at the instruction with the index unless the first of the
+    /// two numbers popped is less than the second.
+    JumpIfNotLess(usize),
+
+    Return,
+}
+
+/// The decoded instructions of a chunk.
+#[derive(Debug)]
+pub(crate) struct Decoded {
+    pub(crate) ops: Vec<Op>,
+
+    /// The offset each instruction was decoded from, to report where errors
+    /// occurred the same way as for undecoded chunks.
+    pub(crate) offsets: Vec<usize>,
+
+    /// The caches of the property accesses, by index.
+    pub(crate) caches: Vec<InlineCache>,
+}
+
+impl Decoded {
+    /// Returns the index of the instruction at the offset.
+    ///
+    /// The offset must be that of an instruction, as for jump targets in a
+    /// verified chunk.
+    fn index_of(offsets: &[usize], offset: usize) -> usize {
+        offsets.partition_point(|&start| start < offset)
+    }
+
+    /// Returns the number of bytes used by the decoded instructions.
+    pub(crate) fn size(&self) -> usize {
+        self.ops.capacity() * size_of::<Op>()
+            + self.offsets.capacity() * size_of::<usize>()
+            + self.caches.iter().map(InlineCache::size).sum::<usize>()
+    }
+}
+
+/// Verifies and decodes the chunk of a function with `arity` parameters,
+/// without the functions declared in it, which are decoded when they are
+/// first called.
+pub(crate) fn decode(chunk: &Chunk, arity: u8) -> Result<Decoded, VerifyError> {
+    let instructions = verify::verify_code(chunk, arity)?;
+    let offsets: Vec<usize> = instructions
+        .iter()
+        .map(|instruction| instruction.offset)
+        .collect();
+
+    let constant = |index: usize| chunk.constants[index].clone();
+    let name = |index: usize| match &chunk.constants[index] {
+        Value::String(name) => name.clone(),
+        _ => unreachable!("verified chunks only use strings as names"),
+    };
+
+    let mut caches = 0;
+    let mut cache = || {
+        caches += 1;
+        caches - 1
+    };
+
+    let ops = instructions
+        .iter()
+        .map(|instruction| {
+            let operand = instruction.operand;
+            let next = instruction.offset + 1 + verify::operand_len(instruction.opcode);
+
+            match instruction.opcode {
+                OpCode::Constant | OpCode::ConstantLong => Op::Constant(constant(operand)),
+                OpCode::Nil => Op::Nil,
+                OpCode::True => Op::True,
+                OpCode::False => Op::False,
+                OpCode::Pop => Op::Pop,
+                OpCode::GetLocal => Op::GetLocal(operand),
+                OpCode::SetLocal => Op::SetLocal(operand),
+                OpCode::GetGlobal => Op::GetGlobal(name(operand)),
+                OpCode::DefineGlobal => Op::DefineGlobal(name(operand)),
+                OpCode::SetGlobal => Op::SetGlobal(name(operand)),
+                OpCode::GetProperty => Op::GetProperty(name(operand), cache()),
+                OpCode::SetProperty => Op::SetProperty(name(operand), cache()),
+                OpCode::Equal => Op::Equal,
+                OpCode::Greater => Op::Greater,
+                OpCode::Less => Op::Less,
+                OpCode::Add => Op::Add,
+                OpCode::Subtract => Op::Subtract,
+                OpCode::Multiply => Op::Multiply,
+                OpCode::Divide => Op::Divide,
+                OpCode::Not => Op::Not,
+                OpCode::Negate => Op::Negate,
+                OpCode::Print => Op::Print,
+                OpCode::Call => Op::Call(operand as u8),
+                OpCode::Jump => Op::Jump(Decoded::index_of(&offsets, next + operand)),
+                OpCode::JumpIfFalse => Op::JumpIfFalse(Decoded::index_of(&offsets, next + operand)),
+                OpCode::Loop => Op::Jump(Decoded::index_of(&offsets, next - operand)),
+                OpCode::Return => Op::Return,
+                OpCode::IncrLocal => {
+                    Op::IncrLocal(operand, constant(instruction.constant.unwrap_or_default()))
+                }
+                OpCode::AddConst => Op::AddConst(constant(operand)),
+                OpCode::JumpIfNotLess => {
+                    Op::JumpIfNotLess(Decoded::index_of(&offsets, next + operand))
+                }
+            }
+        })
+        .collect();
+
+    let caches = (0..caches).map(|_| InlineCache::default()).collect();
+    Ok(Decoded {
+        ops,
+        offsets,
+        caches,
+    })
+}
@@ -0,0 +1,139 @@
+/*!
+Differential testing of the bytecode [`VM`] against the tree-walking [`Interpreter`].
+
+Both implementations run the same program, and everything they make observable
+is compared: the output, the errors, and whether the program failed to compile,
+failed at runtime, or ran to completion. Any difference points to a bug in one
+of them, most likely in the compiler.
+
+Programs can come from files or from the [`Generator`][crate::generate::Generator].
+The [`register`] backend can be observed the same way, with [`run_register_vm`].
+*/
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io::Write,
+};
+
+use crate::{
+    compiler::{self, CompileError},
+    interpreter::{self, Interpreter},
+    register::{self, RegisterVm},
+    vm::{InterpretResult, VM, VmError},
+};
+
+/// How running a program ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    Success,
+    CompileError,
+    RuntimeError,
+}
+
+/// Everything observable about a single run of a program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Observation {
+    pub stdout: String,
+
+    /// Compile or runtime errors, formatted the way the CLI reports them.
+    pub stderr: String,
+
+    pub status: Status,
+}
+
+impl Observation {
+    fn capture(run: impl FnOnce(&mut Vec<u8>, &mut Vec<u8>) -> Status) -> Self {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let status = run(&mut out, &mut err);
+
+        Self {
+            stdout: String::from_utf8_lossy(&out).into_owned(),
+            stderr: String::from_utf8_lossy(&err).into_owned(),
+            status,
+        }
+    }
+}
+
+impl Display for Observation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "status: {:?}", self.status)?;
+        writeln!(f, "stdout:")?;
+        for line in self.stdout.lines() {
+            writeln!(f, "  {}", line)?;
+        }
+
+        writeln!(f, "stderr:")?;
+        for line in self.stderr.lines() {
+            writeln!(f, "  {}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Two different observations of the same program.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub vm: Observation,
+    pub interpreter: Observation,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "== vm ==")?;
+        write!(f, "{}", self.vm)?;
+        writeln!(f, "== interpreter ==")?;
+        write!(f, "{}", self.interpreter)
+    }
+}
+
+/// Runs the source with the bytecode compiler and [`VM`].
+pub fn run_vm(source: &str) -> Observation {
+    Observation::capture(|out, err| match compiler::compile(source) {
+        Ok(chunk) => status(VM::new(out, err).interpret(&chunk)),
+        Err(error) => report_compile_error(err, error),
+    })
+}
+
+/// Runs the source with the [`register`] backend and its [`RegisterVm`].
+pub fn run_register_vm(source: &str) -> Observation {
+    Observation::capture(|out, err| match register::compile(source) {
+        Ok(code) => status(RegisterVm::new(out, err).interpret(&code)),
+        Err(error) => report_compile_error(err, error),
+    })
+}
+
+/// Runs the source with the tree-walking [`Interpreter`].
+pub fn run_interpreter(source: &str) -> Observation {
+    Observation::capture(|out, err| match interpreter::parse(source) {
+        Ok(program) => status(Interpreter::new(out, err).interpret(&program)),
+        Err(error) => report_compile_error(err, error),
+    })
+}
+
+/// Runs the source on both implementations, returning their observations if they differ.
+pub fn check(source: &str) -> Result<(), Box<Mismatch>> {
+    let vm = run_vm(source);
+    let interpreter = run_interpreter(source);
+
+    if vm == interpreter {
+        Ok(())
+    } else {
+        Err(Box::new(Mismatch { vm, interpreter }))
+    }
+}
+
+fn status(result: InterpretResult) -> Status {
+    match result {
+        Ok(()) => Status::Success,
+        Err(VmError::Compilation) => Status::CompileError,
+        Err(VmError::Runtime(_)) => Status::RuntimeError,
+    }
+}
+
+fn report_compile_error(err: &mut Vec<u8>, error: CompileError) -> Status {
+    // Writing to a `Vec` cannot fail.
+    let _ = writeln!(err, "{}", error);
+    Status::CompileError
+}
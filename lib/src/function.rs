@@ -0,0 +1,84 @@
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    compiler::Chunk,
+    decoded::{self, Decoded},
+    verify::VerifyError,
+};
+
+/// A function declared in Lox, compiled to its own chunk of bytecode.
+pub struct Function {
+    pub name: Arc<str>,
+
+    /// The number of parameters the function must be called with.
+    pub arity: u8,
+
+    pub chunk: Chunk,
+
+    /// The chunk decoded for the fast execution mode, once it has been run that way.
+    decoded: OnceLock<Result<Decoded, VerifyError>>,
+}
+
+impl Function {
+    pub fn new(name: &str) -> Self {
+        Self::with_chunk(name, 0, Chunk::new())
+    }
+
+    pub fn with_chunk(name: &str, arity: u8, chunk: Chunk) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            chunk,
+            decoded: OnceLock::new(),
+        }
+    }
+
+    /// Returns the function a compiled script runs as, which takes no arguments.
+    pub fn script(chunk: Chunk) -> Self {
+        Self::with_chunk("script", 0, chunk)
+    }
+
+    /// Returns whether the chunk has been verified and decoded for the fast
+    /// execution mode.
+    pub fn is_decoded(&self) -> bool {
+        self.decoded.get().is_some()
+    }
+
+    /// Returns the chunk decoded for the fast execution mode, verifying and
+    /// decoding it the first time.
+    ///
+    /// The chunk must not be changed once it has been decoded.
+    pub(crate) fn decoded(&self) -> Result<&Decoded, VerifyError> {
+        self.decoded
+            .get_or_init(|| decoded::decode(&self.chunk, self.arity))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Returns the decoded chunk, if it has been decoded.
+    pub(crate) fn decoded_if_ready(&self) -> Option<&Decoded> {
+        self.decoded.get()?.as_ref().ok()
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<fn {}/{}>", self.name, self.arity)
+    }
+}
+
+/// Functions are only equal to themselves.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
@@ -0,0 +1,317 @@
+/*!
+Generation of random Lox programs for [differential testing][crate::differential].
+
+Programs are built from the statements and expressions the language supports,
+keeping track of the types of values so that most of them run to completion
+rather than stopping at the first type error. Some use an undefined variable
+or an operator on the wrong types, to also compare runtime errors, and a few
+are corrupted by removing a character to compare how syntax errors are reported.
+
+Generation is deterministic: the same seed always produces the same programs.
+*/
+
+/// The most statements in a generated program, kept small so that programs stay
+/// within the compiler's limit of 256 constants.
+const MAX_STATEMENTS: u64 = 8;
+
+/// How deeply expressions are nested.
+const MAX_DEPTH: u32 = 4;
+
+const NAMES: &[&str] = &["a", "b", "c", "value", "_x", "y2"];
+
+const STRINGS: &[&str] = &["", "a", "foo", "bar", "Hello, world!", "1"];
+
+const BINARY_OPERATORS: &[&str] = &["+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">="];
+
+const NUMBERS: &[&str] = &[
+    "0", "1", "2", "10", "0.5", "1.25", "3.0", "1e3", "2.5e-1", "0x1F", "0b101", "0o17", "1_000",
+];
+
+/// The type of value an expression is generated to produce.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Number,
+    String,
+    Bool,
+    Nil,
+}
+
+const KINDS: &[Kind] = &[Kind::Number, Kind::String, Kind::Bool, Kind::Nil];
+
+/// A generator of random, mostly valid, Lox programs.
+pub struct Generator {
+    /// State of the xorshift random number generator, never zero.
+    state: u64,
+
+    /// The variables defined so far in the program being generated, and the
+    /// type of value they hold.
+    globals: Vec<(&'static str, Kind)>,
+
+    tokens: Vec<String>,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // Scramble the seed so that small seeds give different programs.
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            globals: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Generates the source code of a new program.
+    pub fn program(&mut self) -> String {
+        self.globals.clear();
+        self.tokens.clear();
+
+        for _ in 0..=self.below(MAX_STATEMENTS) {
+            self.statement();
+        }
+
+        let mut source = String::new();
+        for token in std::mem::take(&mut self.tokens) {
+            source.push_str(&token);
+            source.push(if self.chance(8) { '\n' } else { ' ' });
+        }
+
+        if self.chance(10) && !source.is_empty() {
+            // Only ASCII is generated, so every index is a character boundary.
+            let index = self.below(source.len() as u64) as usize;
+            source.remove(index);
+        }
+
+        source
+    }
+
+    fn statement(&mut self) {
+        match self.below(5) {
+            0 => {
+                let name = self.pick(NAMES);
+                let kind = if self.chance(5) {
+                    Kind::Nil
+                } else {
+                    self.pick(KINDS)
+                };
+
+                self.push("var");
+                self.push(name);
+                if kind != Kind::Nil || self.chance(2) {
+                    self.push("=");
+                    self.expression(kind, 0);
+                }
+
+                self.globals.retain(|&(global, _)| global != name);
+                self.globals.push((name, kind));
+            }
+            1 if !self.globals.is_empty() => {
+                let (name, kind) = self.pick(&self.globals.clone());
+                self.assignment(name, kind, 0);
+            }
+            2 => {
+                // Branches only print, as declarations are not allowed in
+                // them and would not be visible to the statements after.
+                self.push("if");
+                self.push("(");
+                let kind = self.pick(KINDS);
+                self.expression(kind, 0);
+                self.push(")");
+                self.print_statement();
+
+                if self.chance(2) {
+                    self.push("else");
+                    self.print_statement();
+                }
+
+                return;
+            }
+            _ => return self.print_statement(),
+        }
+
+        self.push(";");
+    }
+
+    fn print_statement(&mut self) {
+        self.push("print");
+        let kind = self.pick(KINDS);
+        self.expression(kind, 0);
+        self.push(";");
+    }
+
+    fn assignment(&mut self, name: &str, kind: Kind, depth: u32) {
+        self.push(name);
+        self.push("=");
+        self.expression(kind, depth + 1);
+    }
+
+    /// Generates an expression that evaluates to a value of the given kind,
+    /// except for the occasional one that fails at runtime.
+    fn expression(&mut self, kind: Kind, depth: u32) {
+        if self.chance(40) {
+            return self.ill_typed(depth);
+        }
+
+        let choice = if depth >= MAX_DEPTH {
+            self.below(2)
+        } else {
+            self.below(6)
+        };
+
+        match choice {
+            0 => self.literal(kind),
+            1 => match self.variable(kind) {
+                Some(name) => self.push(name),
+                None => self.literal(kind),
+            },
+            2 => {
+                self.push("(");
+                self.expression(kind, depth + 1);
+                self.push(")");
+            }
+            3 => match self.variable(kind) {
+                Some(name) => {
+                    self.push("(");
+                    self.assignment(name, kind, depth);
+                    self.push(")");
+                }
+                None => self.literal(kind),
+            },
+            _ => self.operation(kind, depth),
+        }
+    }
+
+    /// Generates an operator expression producing the given kind.
+    fn operation(&mut self, kind: Kind, depth: u32) {
+        match kind {
+            Kind::Number if self.chance(4) => {
+                self.push("-");
+                self.expression(Kind::Number, depth + 1);
+            }
+            Kind::Number => {
+                self.expression(Kind::Number, depth + 1);
+                let operator = self.pick(&["+", "-", "*", "/"]);
+                self.push(operator);
+                self.expression(Kind::Number, depth + 1);
+            }
+            Kind::String => {
+                self.expression(Kind::String, depth + 1);
+                self.push("+");
+                self.expression(Kind::String, depth + 1);
+            }
+            Kind::Bool => match self.below(4) {
+                0 => {
+                    self.push("!");
+                    let operand = self.pick(KINDS);
+                    self.expression(operand, depth + 1);
+                }
+                1 => {
+                    self.expression(Kind::Bool, depth + 1);
+                    let operator = self.pick(&["and", "or"]);
+                    self.push(operator);
+                    self.expression(Kind::Bool, depth + 1);
+                }
+                2 => {
+                    self.expression(Kind::Number, depth + 1);
+                    let operator = self.pick(&["<", "<=", ">", ">="]);
+                    self.push(operator);
+                    self.expression(Kind::Number, depth + 1);
+                }
+                _ => {
+                    let left = self.pick(KINDS);
+                    let right = if self.chance(2) {
+                        left
+                    } else {
+                        self.pick(KINDS)
+                    };
+                    self.expression(left, depth + 1);
+                    let operator = self.pick(&["==", "!="]);
+                    self.push(operator);
+                    self.expression(right, depth + 1);
+                }
+            },
+            Kind::Nil => self.literal(Kind::Nil),
+        }
+    }
+
+    /// Generates an expression that is likely to fail at runtime, either by
+    /// using an operator on the wrong types or an undefined variable.
+    fn ill_typed(&mut self, depth: u32) {
+        if self.chance(3) {
+            self.push("undefined");
+            return;
+        }
+
+        let left = self.pick(KINDS);
+        let right = self.pick(KINDS);
+
+        if self.chance(3) {
+            self.push("-");
+            self.literal(left);
+        } else {
+            self.expression(left, depth + 1);
+            let operator = self.pick(BINARY_OPERATORS);
+            self.push(operator);
+            self.expression(right, depth + 1);
+        }
+    }
+
+    fn literal(&mut self, kind: Kind) {
+        match kind {
+            Kind::Number => {
+                let number = self.pick(NUMBERS);
+                self.push(number);
+            }
+            Kind::String => {
+                let string = format!("\"{}\"", self.pick(STRINGS));
+                self.push(&string);
+            }
+            Kind::Bool => {
+                let literal = if self.chance(2) { "true" } else { "false" };
+                self.push(literal);
+            }
+            Kind::Nil => self.push("nil"),
+        }
+    }
+
+    /// Returns the name of a defined variable holding the given kind of value, if any.
+    fn variable(&mut self, kind: Kind) -> Option<&'static str> {
+        let candidates: Vec<_> = self
+            .globals
+            .iter()
+            .filter(|&&(_, global)| global == kind)
+            .map(|&(name, _)| name)
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        Some(self.pick(&candidates))
+    }
+
+    fn push(&mut self, token: &str) {
+        self.tokens.push(token.to_string());
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Returns true with a probability of one in `n`.
+    fn chance(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+
+    /// Returns a random number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Returns the next number from a xorshift64* generator.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
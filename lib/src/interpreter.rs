@@ -0,0 +1,784 @@
+/*!
+A tree-walking interpreter for Lox, in the style of the book's `jlox`.
+
+It shares the [`parser`] and [`resolver`] with the bytecode compiler, and
+lowers the syntax tree into a simpler one that is evaluated directly. It is
+much slower than the [`VM`][crate::vm::VM] and exists as a simple reference
+implementation to check the compiler and VM against, see
+[`differential`][crate::differential].
+
+To make the two comparable it reports the same errors as the bytecode
+implementation, including the lines runtime errors are reported on, which are
+those of the instructions the compiler would have emitted. Local variables are
+stored in the slots the resolver assigns them, and like in the VM functions
+cannot refer to the locals of the functions they are declared in.
+*/
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    io::{self, Write},
+    sync::Arc,
+};
+
+use crate::{
+    ast::{self, BinaryOperator, ExprKind, Literal, LogicalOperator, StmtKind, UnaryOperator},
+    class::{self, Class, LoxClass},
+    compiler::{self, CompileError},
+    function::Function,
+    native::{Native, NativeFn, Runtime, VmContext},
+    parser,
+    resolver::{self, Resolution},
+    stdlib::Capability,
+    value::Value,
+    vm::{self, FRAMES_MAX, InterpretResult, RuntimeError},
+};
+
+/// A parsed program, ready to be run by an [`Interpreter`].
+#[derive(Debug)]
+pub struct Program {
+    statements: Vec<Stmt>,
+}
+
+/// Where a variable is stored.
+#[derive(Debug)]
+enum Variable {
+    Global(Arc<str>),
+
+    /// A slot in the current call frame.
+    Local,
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Expression(Expr),
+    Print {
+        value: Expr,
+        line: i32,
+    },
+    Var {
+        variable: Variable,
+        initializer: Expr,
+    },
+    Function {
+        variable: Variable,
+        function: Arc<Function>,
+        body: Arc<[Stmt]>,
+    },
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    Return(Expr),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+        line: i32,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        line: i32,
+    },
+    Global {
+        name: Arc<str>,
+        line: i32,
+    },
+    Local(usize),
+    /// `and` or `or`, which only evaluate the right operand if needed.
+    Logical {
+        operator: LogicalOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    AssignGlobal {
+        name: Arc<str>,
+        value: Box<Expr>,
+        line: i32,
+    },
+    AssignLocal {
+        slot: usize,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+        line: i32,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Arc<str>,
+        line: i32,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Arc<str>,
+        value: Box<Expr>,
+        line: i32,
+    },
+}
+
+/// Parses the given source code into a [`Program`].
+///
+/// Reports the same errors as [`compiler::compile`][crate::compiler::compile],
+/// except that there is no limit on the number of constants.
+pub fn parse(source: &str) -> Result<Program, CompileError> {
+    let parse = parser::parse(source);
+    let resolution = resolver::resolve(&parse.program);
+    compiler::check(source, &parse, resolution.errors.clone())?;
+
+    let mut lowering = Lowering {
+        resolution: &resolution,
+        scope_depth: 0,
+    };
+    let statements = parse
+        .program
+        .statements
+        .iter()
+        .map(|statement| lowering.statement(statement))
+        .collect();
+
+    Ok(Program { statements })
+}
+
+/// Lowers a resolved syntax tree without errors into the one the interpreter
+/// runs.
+///
+/// Each node that can fail at runtime keeps the line the compiler would
+/// attribute its instruction to.
+struct Lowering<'a> {
+    resolution: &'a Resolution,
+
+    /// The depth of the innermost scope, 0 at the top level, where variables
+    /// are global.
+    scope_depth: usize,
+}
+
+impl Lowering<'_> {
+    fn statements(&mut self, statements: &[ast::Stmt]) -> Vec<Stmt> {
+        statements
+            .iter()
+            .map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    fn scoped<T>(&mut self, lower: impl FnOnce(&mut Self) -> T) -> T {
+        self.scope_depth += 1;
+        let lowered = lower(self);
+        self.scope_depth -= 1;
+        lowered
+    }
+
+    fn variable(&self, name: &ast::Identifier) -> Variable {
+        if self.scope_depth == 0 {
+            Variable::Global(name.name.as_str().into())
+        } else {
+            Variable::Local
+        }
+    }
+
+    fn statement(&mut self, statement: &ast::Stmt) -> Stmt {
+        match &statement.kind {
+            StmtKind::Expression(expr) => Stmt::Expression(self.expression(expr)),
+            StmtKind::Print(expr) => Stmt::Print {
+                value: self.expression(expr),
+                line: statement.span.end.line,
+            },
+            StmtKind::Var { name, initializer } => Stmt::Var {
+                variable: self.variable(name),
+                initializer: initializer
+                    .as_ref()
+                    .map_or(Expr::Literal(Value::Nil), |initializer| {
+                        self.expression(initializer)
+                    }),
+            },
+            StmtKind::Function(function) => {
+                let variable = self.variable(&function.name);
+
+                // Functions are always declared in a scope of their own,
+                // starting over at the top of a new frame.
+                let depth = std::mem::replace(&mut self.scope_depth, 1);
+                let body = self.statements(&function.body);
+                self.scope_depth = depth;
+
+                let mut lowered = Function::new(&function.name.name);
+                lowered.arity = function.params.len().min(u8::MAX as usize) as u8;
+
+                Stmt::Function {
+                    variable,
+                    function: Arc::new(lowered),
+                    body: body.into(),
+                }
+            }
+            StmtKind::Block(statements) => {
+                Stmt::Block(self.scoped(|lowering| lowering.statements(statements)))
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => Stmt::If {
+                condition: self.expression(condition),
+                then_branch: Box::new(self.statement(then_branch)),
+                else_branch: else_branch
+                    .as_ref()
+                    .map(|else_branch| Box::new(self.statement(else_branch))),
+            },
+            StmtKind::While {
+                condition, body, ..
+            } => Stmt::While {
+                condition: self.expression(condition),
+                body: Box::new(self.statement(body)),
+            },
+            // A `for` loop is a `while` loop in a block that scopes the
+            // initializer, like the compiler runs it.
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => self.scoped(|lowering| {
+                let mut statements = Vec::new();
+                if let Some(initializer) = initializer {
+                    statements.push(lowering.statement(initializer));
+                }
+
+                let condition = condition
+                    .as_ref()
+                    .map_or(Expr::Literal(Value::Bool(true)), |condition| {
+                        lowering.expression(condition)
+                    });
+
+                let mut body = lowering.statement(body);
+                if let Some(increment) = increment {
+                    body =
+                        Stmt::Block(vec![body, Stmt::Expression(lowering.expression(increment))]);
+                }
+
+                statements.push(Stmt::While {
+                    condition,
+                    body: Box::new(body),
+                });
+                Stmt::Block(statements)
+            }),
+            StmtKind::Return(value) => Stmt::Return(
+                value
+                    .as_ref()
+                    .map_or(Expr::Literal(Value::Nil), |value| self.expression(value)),
+            ),
+            StmtKind::Error => unreachable!("programs with syntax errors are not run"),
+        }
+    }
+
+    fn expression(&mut self, expr: &ast::Expr) -> Expr {
+        let line = expr.span.end.line;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => Expr::Literal(match literal {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Number(n) => Value::Number(*n),
+                Literal::String(s) => s.as_str().into(),
+            }),
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Variable(name) => match self.resolution.slot(name) {
+                Some(slot) => Expr::Local(slot as usize),
+                None => Expr::Global {
+                    name: name.name.as_str().into(),
+                    line: name.span.start.line,
+                },
+            },
+            ExprKind::Assign { name, value } => {
+                let value = Box::new(self.expression(value));
+                match self.resolution.slot(name) {
+                    Some(slot) => Expr::AssignLocal {
+                        slot: slot as usize,
+                        value,
+                    },
+                    None => Expr::AssignGlobal {
+                        name: name.name.as_str().into(),
+                        value,
+                        line,
+                    },
+                }
+            }
+            ExprKind::Unary { operator, operand } => Expr::Unary {
+                operator: *operator,
+                operand: Box::new(self.expression(operand)),
+                line,
+            },
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => Expr::Binary {
+                operator: *operator,
+                left: Box::new(self.expression(left)),
+                right: Box::new(self.expression(right)),
+                line,
+            },
+            ExprKind::Logical {
+                operator,
+                left,
+                right,
+                ..
+            } => Expr::Logical {
+                operator: *operator,
+                left: Box::new(self.expression(left)),
+                right: Box::new(self.expression(right)),
+            },
+            ExprKind::Call { callee, arguments } => Expr::Call {
+                callee: Box::new(self.expression(callee)),
+                arguments: arguments
+                    .iter()
+                    .map(|argument| self.expression(argument))
+                    .collect(),
+                line,
+            },
+            ExprKind::Get { object, name } => Expr::Get {
+                object: Box::new(self.expression(object)),
+                name: name.name.as_str().into(),
+                line: name.span.start.line,
+            },
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => Expr::Set {
+                object: Box::new(self.expression(object)),
+                name: name.name.as_str().into(),
+                value: Box::new(self.expression(value)),
+                line,
+            },
+            ExprKind::This | ExprKind::Super { .. } | ExprKind::Error => {
+                unreachable!("programs with errors are not run")
+            }
+        }
+    }
+}
+
+/// A call in progress.
+struct Frame {
+    /// The name of the function being run, `None` for the top-level script.
+    name: Option<Arc<str>>,
+
+    /// The function itself followed by its arguments and locals, in the same
+    /// order as on the VM's stack.
+    slots: Vec<Value>,
+
+    /// The line being executed, recorded while unwinding from a runtime error.
+    line: i32,
+}
+
+/// Why execution of a function body stopped early.
+enum Unwind {
+    Return(Value),
+
+    /// A runtime error, along with the line it occurred on.
+    Error(RuntimeError, i32),
+}
+
+pub struct Interpreter<'a, O: Write, E: Write> {
+    globals: HashMap<Arc<str>, Value>,
+    frames: Vec<Frame>,
+
+    /// The bodies of the functions declared so far, keyed by their address.
+    /// The functions are kept alive so that the addresses are never reused.
+    bodies: HashMap<*const Function, (Arc<Function>, Arc<[Stmt]>)>,
+
+    out: &'a mut O,
+    err: &'a mut E,
+}
+
+impl<'a, O: Write, E: Write> Interpreter<'a, O, E> {
+    pub fn new(out: &'a mut O, err: &'a mut E) -> Self {
+        Self {
+            globals: HashMap::new(),
+            frames: Vec::new(),
+            bodies: HashMap::new(),
+            out,
+            err,
+        }
+    }
+
+    /// Defines a global function implemented in Rust, replacing any existing
+    /// global with the same name.
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        let native = Native::new(name, arity, function);
+        self.globals
+            .insert(name.into(), Value::Native(Arc::new(native)));
+    }
+
+    /// Defines a global class for the Rust type `T`, replacing any existing
+    /// global with the same name.
+    pub fn define_class<T: LoxClass>(&mut self) -> Arc<Class> {
+        let class = Arc::new(Class::new::<T>());
+        self.globals
+            .insert(T::NAME.into(), Value::Class(class.clone()));
+        class
+    }
+
+    /// Runs the program, reporting runtime errors the same way the VM does.
+    pub fn interpret(&mut self, program: &Program) -> InterpretResult {
+        self.frames.push(Frame {
+            name: None,
+            slots: vec![Value::Nil],
+            line: 0,
+        });
+
+        for statement in &program.statements {
+            match self.execute(statement) {
+                Ok(()) => {}
+                // The parser does not allow returning from the top level.
+                Err(Unwind::Return(_)) => break,
+                Err(Unwind::Error(error, line)) => {
+                    self.frames[0].line = line;
+                    self.report_runtime_error(&error)?;
+                    return Err(error.into());
+                }
+            }
+        }
+
+        self.frames.clear();
+        Ok(())
+    }
+
+    /// Returns the global variables currently defined, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value))
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames
+            .last_mut()
+            .expect("there is always a call in progress while executing")
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> Result<(), Unwind> {
+        match statement {
+            Stmt::Expression(expression) => {
+                self.evaluate(expression)?;
+            }
+            Stmt::Print { value, line } => {
+                let value = self.evaluate(value)?;
+                writeln!(self.out, "{}", value)
+                    .map_err(|_| Unwind::Error(RuntimeError::Io, *line))?;
+            }
+            Stmt::Var {
+                variable,
+                initializer,
+            } => {
+                let value = self.evaluate(initializer)?;
+                self.define(variable, value);
+            }
+            Stmt::Function {
+                variable,
+                function,
+                body,
+            } => {
+                self.bodies
+                    .insert(Arc::as_ptr(function), (function.clone(), body.clone()));
+                self.define(variable, Value::Function(function.clone()));
+            }
+            Stmt::Block(statements) => {
+                let locals = self.frame().slots.len();
+                for statement in statements {
+                    self.execute(statement)?;
+                }
+
+                self.frame().slots.truncate(locals);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if !self.evaluate(condition)?.is_falsey() {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::While { condition, body } => {
+                while !self.evaluate(condition)?.is_falsey() {
+                    self.execute(body)?;
+                }
+            }
+            Stmt::Return(value) => {
+                let value = self.evaluate(value)?;
+                return Err(Unwind::Return(value));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, variable: &Variable, value: Value) {
+        match variable {
+            Variable::Global(name) => {
+                self.globals.insert(name.clone(), value);
+            }
+            Variable::Local => self.frame().slots.push(value),
+        }
+    }
+
+    fn evaluate(&mut self, expression: &Expr) -> Result<Value, Unwind> {
+        match expression {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Unary {
+                operator,
+                operand,
+                line,
+            } => {
+                let operand = self.evaluate(operand)?;
+                match operator {
+                    UnaryOperator::Not => Ok(Value::Bool(operand.is_falsey())),
+                    UnaryOperator::Negate => match operand {
+                        Value::Number(value) => Ok(Value::Number(-value)),
+                        _ => Err(Unwind::Error(RuntimeError::OperandMustBeNumber, *line)),
+                    },
+                }
+            }
+            Expr::Binary {
+                operator,
+                left,
+                right,
+                line,
+            } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                binary(*operator, left, right).map_err(|error| Unwind::Error(error, *line))
+            }
+            Expr::Global { name, line } => self.globals.get(name).cloned().ok_or_else(|| {
+                Unwind::Error(RuntimeError::UndefinedVariable(name.to_string()), *line)
+            }),
+            Expr::Local(slot) => Ok(self.frame().slots[*slot].clone()),
+            Expr::Logical {
+                operator,
+                left,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+                if left.is_falsey() == (*operator == LogicalOperator::And) {
+                    Ok(left)
+                } else {
+                    self.evaluate(right)
+                }
+            }
+            Expr::AssignGlobal { name, value, line } => {
+                let value = self.evaluate(value)?;
+                let Some(global) = self.globals.get_mut(name) else {
+                    return Err(Unwind::Error(
+                        RuntimeError::UndefinedVariable(name.to_string()),
+                        *line,
+                    ));
+                };
+
+                *global = value.clone();
+                Ok(value)
+            }
+            Expr::AssignLocal { slot, value } => {
+                let value = self.evaluate(value)?;
+                self.frame().slots[*slot] = value.clone();
+                Ok(value)
+            }
+            Expr::Call {
+                callee,
+                arguments,
+                line,
+            } => {
+                let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.call_value(&callee, &arguments)
+                    .map_err(|error| Unwind::Error(error, *line))
+            }
+            Expr::Get { object, name, line } => {
+                let object = self.evaluate(object)?;
+                class::get_property(&object, name).map_err(|error| Unwind::Error(error, *line))
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+                line,
+            } => {
+                let object = self.evaluate(object)?;
+                let value = self.evaluate(value)?;
+                class::set_property(&object, name, value.clone())
+                    .map_err(|error| Unwind::Error(error, *line))?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let got = args.len();
+        let expected = vm::arity(callee).ok_or(RuntimeError::NotCallable)?;
+        if got != expected {
+            return Err(RuntimeError::WrongArity { expected, got });
+        }
+
+        match callee {
+            Value::Function(function) => self.call_function(function, args),
+            _ => {
+                let frames = self.frames.len();
+                let result = vm::call_host(&mut VmContext::new(self), callee, args)?;
+
+                // A failed call back into Lox leaves its frames behind for the
+                // stack trace, which is not needed if the native function recovered.
+                self.frames.truncate(frames);
+                Ok(result)
+            }
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: &Arc<Function>,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        if self.frames.len() == FRAMES_MAX {
+            return Err(RuntimeError::StackOverflow);
+        }
+
+        let Some((_, body)) = self.bodies.get(&Arc::as_ptr(function)) else {
+            // Compiled functions handed over from a VM cannot be run.
+            return Err(RuntimeError::NotCallable);
+        };
+
+        let body = body.clone();
+        let mut slots = vec![Value::Function(function.clone())];
+        slots.extend_from_slice(args);
+
+        let index = self.frames.len();
+        self.frames.push(Frame {
+            name: Some(function.name.clone()),
+            slots,
+            line: 0,
+        });
+
+        let mut result = Ok(Value::Nil);
+        for statement in body.iter() {
+            match self.execute(statement) {
+                Ok(()) => {}
+                Err(Unwind::Return(value)) => {
+                    result = Ok(value);
+                    break;
+                }
+                Err(Unwind::Error(error, line)) => {
+                    // The frame is kept for the stack trace.
+                    self.frames[index].line = line;
+                    return Err(error);
+                }
+            }
+        }
+
+        self.frames.pop();
+        result
+    }
+
+    /// Reports a runtime error along with the call stack it occurred in.
+    fn report_runtime_error(&mut self, error: &RuntimeError) -> Result<(), std::io::Error> {
+        writeln!(self.err, "{}", error)?;
+        for frame in self.frames.drain(..).rev() {
+            match frame.name {
+                Some(name) => writeln!(self.err, "[line {}] in {}()", frame.line, name)?,
+                None => writeln!(self.err, "[line {}] in script", frame.line)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: Write, E: Write> Runtime for Interpreter<'_, O, E> {
+    fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.into(), value);
+    }
+
+    fn out(&mut self) -> &mut dyn Write {
+        self.out
+    }
+
+    /// The interpreter runs programs without input.
+    fn read_line(&mut self, _: &mut String) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.call_value(callee, args)
+    }
+
+    /// The interpreter is only used for differential testing, which runs
+    /// scripts without access to the standard library.
+    fn capabilities(&self) -> &[Capability] {
+        &[]
+    }
+}
+
+/// Applies a binary operator to its evaluated operands.
+fn binary(operator: BinaryOperator, left: Value, right: Value) -> Result<Value, RuntimeError> {
+    if let BinaryOperator::Equal | BinaryOperator::NotEqual = operator {
+        let equal = left == right;
+        return Ok(Value::Bool(equal == (operator == BinaryOperator::Equal)));
+    }
+
+    if operator == BinaryOperator::Add {
+        return match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            (Value::String(left), Value::String(right)) => {
+                Ok(Value::String(format!("{}{}", left, right).into()))
+            }
+            _ => Err(RuntimeError::OperandsMustBeNumbersOrStrings),
+        };
+    }
+
+    let (Value::Number(left), Value::Number(right)) = (left, right) else {
+        return Err(RuntimeError::OperandsMustBeNumbers);
+    };
+
+    let result = match operator {
+        BinaryOperator::Subtract => Value::Number(left - right),
+        BinaryOperator::Multiply => Value::Number(left * right),
+        BinaryOperator::Divide => Value::Number(left / right),
+        BinaryOperator::Greater => Value::Bool(left > right),
+        BinaryOperator::Less => Value::Bool(left < right),
+        // The VM compiles these as the negation of the opposite comparison,
+        // which is true for NaN.
+        BinaryOperator::GreaterEqual => {
+            Value::Bool(left.partial_cmp(&right) != Some(Ordering::Less))
+        }
+        BinaryOperator::LessEqual => {
+            Value::Bool(left.partial_cmp(&right) != Some(Ordering::Greater))
+        }
+        BinaryOperator::Equal | BinaryOperator::NotEqual | BinaryOperator::Add => {
+            unreachable!("{:?} is applied above", operator)
+        }
+    };
+
+    Ok(result)
+}
@@ -4,8 +4,20 @@ This crate provides a library for parsing, compiling, and executing [Lox][] prog
 It is a Rust implementation of the bytecode virtual machine described in the book
 [Crafting Interpreters][crafting-interpreters] by [Robert Nystrom][bob].
 
+Source code is parsed into the syntax tree in [`ast`] by the [`parser`], and
+its names are resolved by the [`resolver`] before the [`compiler`] generates
+bytecode from it, so that tools can reuse the same front end.
+
+A simple tree-walking [`interpreter`] is included as a reference to test the
+virtual machine against, see [`differential`].
+
 # Crate features
 
+ * **`derive`** -
+   Enables `#[derive(LoxClass)]` for [exposing Rust types as classes][class].
+ * **`serde`** -
+   Enables the `serde` module, converting between values and Rust data with serde.
+
 ## Debugging features
 
  * **`trace`** -
@@ -14,9 +26,31 @@ It is a Rust implementation of the bytecode virtual machine described in the boo
 [crafting-interpreters]: https://craftinginterpreters.com
 [lox]: https://craftinginterpreters.com/the-lox-language.html
 [bob]: https://stuffwithstuff.com/
+[class]: crate::class
 */
 
+pub mod ast;
+mod cache;
+pub mod class;
+pub mod collections;
 pub mod compiler;
+mod decoded;
+pub mod differential;
+pub mod function;
+pub mod generate;
+pub mod interpreter;
+pub mod memory;
+pub mod native;
+pub mod parser;
+pub mod peephole;
+pub mod register;
+pub mod resolver;
 pub mod scanner;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod serialize;
+pub mod shape;
+pub mod stdlib;
 pub mod value;
+pub mod verify;
 pub mod vm;
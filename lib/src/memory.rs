@@ -0,0 +1,174 @@
+/*!
+Accounting for the memory used by a [`VM`][crate::vm::VM].
+
+Values are reference counted rather than garbage collected, so there is no
+allocator that knows how much memory a VM holds on to. Instead the heap is
+measured by tracing every value reachable from the VM's globals, stack and
+running functions, counting each allocation once however many times it is
+referred to. Sizes are estimates based on the layout of the values, not on
+what the allocator actually reserved.
+
+Measuring takes time proportional to the size of the heap, so with a limit set
+the VM keeps a running count instead. It adds to the count as it allocates,
+and takes off the values it drops when nothing else refers to them. Memory
+freed in other ways, such as the fields of a freed instance, stays counted,
+so the VM only measures the heap when the count goes over the limit.
+
+Memory allocated by native functions is not seen until the next measurement,
+but every call is charged for its arguments and result so that repeatedly
+calling one eventually triggers a measurement. Natives that build large
+values can check that they fit with [`VmContext::check_allocation`] first.
+
+[`VmContext::check_allocation`]: crate::native::VmContext::check_allocation
+
+Classes implemented in Rust report the values their instances hold with
+[`LoxClass::trace`][crate::class::LoxClass::trace].
+*/
+
+use std::{collections::HashSet, mem::size_of, sync::Arc};
+
+use crate::{compiler::Chunk, value::Value};
+
+/// The reference counts stored in front of the value in each `Arc` allocation.
+const RC_HEADER: usize = 2 * size_of::<usize>();
+
+/// The memory a VM is using.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryUsage {
+    /// The estimated size of all values reachable from the VM, in bytes.
+    pub heap_bytes: usize,
+
+    /// The number of values on the stack.
+    pub stack_values: usize,
+
+    /// The number of calls in progress.
+    pub frames: usize,
+}
+
+/// Adds up the memory used by values, counting each allocation once.
+pub struct Tracer {
+    /// The addresses of the allocations already counted.
+    visited: HashSet<*const ()>,
+
+    bytes: usize,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Self {
+            visited: HashSet::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Counts a value and everything it refers to, unless already counted.
+    ///
+    /// The slot the value is stored in is not counted, as it is part of
+    /// whatever holds the value.
+    pub fn value(&mut self, value: &Value) {
+        match value {
+            Value::Nil | Value::Bool(_) | Value::Number(_) => {}
+            Value::String(string) => self.string(string),
+            Value::Function(function) => {
+                if self.first_visit(function) {
+                    self.bytes += RC_HEADER + size_of_val(function.as_ref());
+                    self.string(&function.name);
+                    self.chunk(&function.chunk);
+
+                    // Decoded instructions share the constants of the chunk.
+                    if let Some(decoded) = function.decoded_if_ready() {
+                        self.bytes += decoded.size();
+                    }
+                }
+            }
+            Value::Native(native) => {
+                if self.first_visit(native) {
+                    self.bytes += RC_HEADER + size_of_val(native.as_ref());
+                    self.string(&native.name);
+                }
+            }
+            Value::Class(class) => {
+                if self.first_visit(class) {
+                    self.bytes += RC_HEADER + size_of_val(class.as_ref());
+                    self.string(&class.name);
+                }
+            }
+            Value::Instance(instance) => {
+                if self.first_visit(instance) {
+                    self.bytes += RC_HEADER + size_of_val(instance.as_ref());
+                    self.value(&Value::Class(instance.class().clone()));
+                    instance.trace(self);
+                }
+            }
+            Value::BoundMethod(method) => {
+                if self.first_visit(method) {
+                    self.bytes += RC_HEADER + size_of_val(method.as_ref());
+                    self.value(&Value::Instance(method.receiver.clone()));
+                }
+            }
+        }
+    }
+
+    /// Counts a string, unless already counted.
+    pub fn string(&mut self, string: &Arc<str>) {
+        if self.first_visit(string) {
+            self.bytes += string_size(string.len());
+        }
+    }
+
+    /// Counts memory owned outside of values, such as the buffer of a `Vec`.
+    pub fn bytes(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+
+    /// Counts the code, line table and constants of a chunk.
+    pub(crate) fn chunk(&mut self, chunk: &Chunk) {
+        self.bytes += chunk.code.capacity()
+            + size_of_val(chunk.lines.as_slice())
+            + chunk.constants.len() * size_of::<Value>();
+
+        for constant in &chunk.constants {
+            self.value(constant);
+        }
+    }
+
+    /// Returns the number of bytes counted.
+    pub(crate) fn total(&self) -> usize {
+        self.bytes
+    }
+
+    fn first_visit<T: ?Sized>(&mut self, value: &Arc<T>) -> bool {
+        self.visited.insert(Arc::as_ptr(value) as *const ())
+    }
+}
+
+/// Returns the size of the allocation a newly created value owns, without
+/// anything it refers to.
+pub(crate) fn allocation_size(value: &Value) -> usize {
+    match value {
+        Value::String(string) => string_size(string.len()),
+        Value::Instance(instance) => {
+            RC_HEADER + size_of_val(instance.as_ref()) + instance.class().size
+        }
+        Value::BoundMethod(method) => RC_HEADER + size_of_val(method.as_ref()),
+        _ => 0,
+    }
+}
+
+/// Returns the size of the allocation dropping the value frees, which is only
+/// its own allocation, if nothing else refers to it.
+pub(crate) fn freed_size(value: &Value) -> usize {
+    let unique = match value {
+        Value::String(string) => Arc::strong_count(string) == 1,
+        Value::Instance(instance) => Arc::strong_count(instance) == 1,
+        Value::BoundMethod(method) => Arc::strong_count(method) == 1,
+        _ => false,
+    };
+
+    if unique { allocation_size(value) } else { 0 }
+}
+
+/// Returns the size of the allocation for a string of `len` bytes.
+pub(crate) fn string_size(len: usize) -> usize {
+    RC_HEADER + len
+}
@@ -0,0 +1,165 @@
+/*!
+Native functions, implemented in Rust and callable from Lox.
+
+Hosts register them with [`VM::define_native`][crate::vm::VM::define_native]:
+
+```
+use rulox::{compiler, native::{self, VmContext}, value::Value, vm::{RuntimeError, VM}};
+
+fn add(_: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let a: f64 = native::arg(args, 0)?;
+    let b: f64 = native::arg(args, 1)?;
+    Ok((a + b).into())
+}
+
+let mut out = Vec::new();
+let mut err = Vec::new();
+let mut vm = VM::new(&mut out, &mut err);
+vm.define_native("add", 2, add);
+
+let chunk = compiler::compile("print add(1, 2);").unwrap();
+vm.interpret(&chunk).unwrap();
+assert_eq!(out, b"3\n");
+```
+*/
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    io::{self, Write},
+    sync::Arc,
+};
+
+use crate::{stdlib::Capability, value::Value, vm::RuntimeError};
+
+/// The signature of a native function.
+///
+/// It receives exactly as many arguments as its arity, and returns the result
+/// of the call or an error that is reported like any other runtime error.
+pub type NativeFn = fn(&mut VmContext, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A native function, as stored in a [`Value`].
+pub struct Native {
+    pub name: Arc<str>,
+
+    /// The number of arguments the function must be called with.
+    pub arity: u8,
+
+    pub function: NativeFn,
+}
+
+impl Native {
+    pub fn new(name: &str, arity: u8, function: NativeFn) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            function,
+        }
+    }
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<native fn {}/{}>", self.name, self.arity)
+    }
+}
+
+/// Natives are only equal to themselves.
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// What native functions may do with the interpreter running them, implemented
+/// by both the VM and the tree-walking interpreter.
+pub(crate) trait Runtime {
+    fn global(&self, name: &str) -> Option<&Value>;
+
+    fn define_global(&mut self, name: &str, value: Value);
+
+    fn out(&mut self) -> &mut dyn Write;
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize>;
+
+    fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError>;
+
+    fn capabilities(&self) -> &[Capability];
+
+    /// Checks that `bytes` can be allocated without going over the memory
+    /// limit. Runtimes without a limit accept anything.
+    fn check_allocation(&mut self, _bytes: usize) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+/// The parts of the running interpreter a native function has access to.
+pub struct VmContext<'a> {
+    runtime: &'a mut dyn Runtime,
+}
+
+impl<'a> VmContext<'a> {
+    pub(crate) fn new(runtime: &'a mut dyn Runtime) -> Self {
+        Self { runtime }
+    }
+
+    /// Returns the value of a global variable, if it is defined.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.runtime.global(name)
+    }
+
+    /// Defines a global variable, or replaces the value of an existing one.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.runtime.define_global(name, value);
+    }
+
+    /// Returns where the program's output, such as that of `print`, is written to.
+    pub fn out(&mut self) -> &mut dyn Write {
+        self.runtime.out()
+    }
+
+    /// Reads a line of the program's input, such as stdin, appending it to
+    /// `line` with its line ending and returning the number of bytes read.
+    pub fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        self.runtime.read_line(line)
+    }
+
+    /// Calls a Lox or native function with the given arguments, returning its result.
+    ///
+    /// The call runs on top of the current one, so a function calling back
+    /// into the native function is fine as long as the stack does not overflow.
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.runtime.call(callee, args)
+    }
+
+    /// Returns the capabilities the host allowed, see
+    /// [`VmBuilder::allow`][crate::vm::VmBuilder::allow].
+    pub fn capabilities(&self) -> &[Capability] {
+        self.runtime.capabilities()
+    }
+
+    /// Checks that a value using `bytes` of memory can be created without
+    /// going over the VM's memory limit, failing with
+    /// [`RuntimeError::OutOfMemory`] otherwise.
+    ///
+    /// The result of a native function is only accounted for once it returns,
+    /// so one building a large value should check first.
+    pub fn check_allocation(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.runtime.check_allocation(bytes)
+    }
+}
+
+/// Converts the argument at `index` into the type the native function expects,
+/// using its `TryFrom<Value>` implementation.
+///
+/// Returns [`RuntimeError::InvalidArgument`] if the argument has the wrong type
+/// or is missing.
+pub fn arg<T>(args: &[Value], index: usize) -> Result<T, RuntimeError>
+where
+    T: TryFrom<Value, Error = RuntimeError>,
+{
+    args.get(index)
+        .cloned()
+        .ok_or(RuntimeError::InvalidArgument(index + 1))?
+        .try_into()
+        .map_err(|_| RuntimeError::InvalidArgument(index + 1))
+}
@@ -0,0 +1,836 @@
+/*!
+Parses the tokens from the [`Scanner`] into a syntax tree, see [`ast`][crate::ast].
+
+The parser reports the syntax errors the [`compiler`][crate::compiler] reports
+and recovers from them the same way, discarding tokens up to the next likely
+statement boundary after each error. Problems that need to know what names
+refer to, such as reading a local variable in its own initializer, are left to
+the [`resolver`][crate::resolver].
+*/
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{
+        BinaryOperator, Expr, ExprKind, Function, Identifier, Literal, LogicalOperator, Position,
+        Program, Span, Stmt, StmtKind, UnaryOperator,
+    },
+    compiler::{CompileOptions, SyntaxError},
+    scanner::{Scanner, Token, TokenType},
+};
+
+/// Parses the given source code into a [`Program`].
+pub fn parse(source: &str) -> Parse {
+    parse_with_options(source, CompileOptions::default())
+}
+
+/// Parses the given source code into a [`Program`] using the given options.
+///
+/// Only [`CompileOptions::repl`] affects parsing, allowing the semicolon after
+/// the last expression statement to be left out.
+pub fn parse_with_options(source: &str, options: CompileOptions) -> Parse {
+    let mut parser = Parser::new(source, options);
+    let mut statements = Vec::new();
+
+    parser.advance();
+    while !parser.match_token(TokenType::EOF) {
+        statements.push(parser.declaration());
+    }
+
+    Parse {
+        program: Program {
+            statements,
+            end: parser.previous.position(),
+        },
+        errors: parser.errors,
+        resumes: parser.resumes,
+    }
+}
+
+/// The result of parsing source code.
+#[derive(Clone, Debug)]
+pub struct Parse {
+    /// The parsed program, with the code that failed to parse left out.
+    pub program: Program,
+
+    pub errors: Vec<Diagnostic>,
+
+    /// Where parsing resumes after an error in each declaration, by where
+    /// the declaration starts.
+    pub(crate) resumes: HashMap<Position, Position>,
+}
+
+/// An error found in the source code, at the token it was found at.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// Where the token starts.
+    pub position: Position,
+
+    pub error: SyntaxError,
+
+    /// When a parser generating code as it goes would have found the error:
+    /// after the token at the position, and before or after the other errors
+    /// found there by rank.
+    pub(crate) found: (Position, u8),
+
+    /// The start of the first declaration to end after the error was found.
+    /// Other errors found before parsing resumes after it are likely caused
+    /// by this one.
+    pub(crate) declaration: Option<Position>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for an error at the token with the given lexeme.
+    pub(crate) fn new(position: Position, lexeme: &str, message: &str) -> Self {
+        Self {
+            position,
+            error: SyntaxError {
+                line: position.line,
+                location: format!(" at '{}'", lexeme),
+                message: message.to_string(),
+            },
+            found: (position, 1),
+            declaration: None,
+        }
+    }
+
+    /// Creates a diagnostic for an error at the given token.
+    pub(crate) fn at(token: Token, message: &str) -> Self {
+        let mut diagnostic = Self::new(token.position(), token.lexeme, message);
+        diagnostic.error.location = location(token);
+        diagnostic
+    }
+}
+
+/// Returns where on its line an error at the token is, e.g. ` at end` or ` at '+'`.
+pub(crate) fn location(token: Token) -> String {
+    match token.token_type {
+        TokenType::EOF => " at end".to_string(),
+        TokenType::Error => String::new(),
+        _ => format!(" at '{}'", token.lexeme),
+    }
+}
+
+/// Assigns the errors found since the last declaration ended to the one
+/// that just ended at `start`.
+pub(crate) fn end_declaration(errors: &mut [Diagnostic], start: Position) {
+    for error in errors.iter_mut().rev() {
+        if error.declaration.is_some() {
+            break;
+        }
+
+        error.declaration = Some(start);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call => Precedence::Primary,
+            Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+/// A prefix parse function, taking whether the expression may be an assignment target.
+type PrefixFn<'a> = fn(&mut Parser<'a>, bool) -> Expr;
+
+/// An infix parse function, taking the left operand and whether the
+/// expression may be an assignment target.
+type InfixFn<'a> = fn(&mut Parser<'a>, Expr, bool) -> Expr;
+
+struct ParseRule<'a> {
+    prefix: Option<PrefixFn<'a>>,
+    infix: Option<InfixFn<'a>>,
+    precedence: Precedence,
+}
+
+impl<'a> ParseRule<'a> {
+    fn new(
+        prefix: Option<PrefixFn<'a>>,
+        infix: Option<InfixFn<'a>>,
+        precedence: Precedence,
+    ) -> Self {
+        Self {
+            prefix,
+            infix,
+            precedence,
+        }
+    }
+}
+
+struct Parser<'a> {
+    scanner: Scanner<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    panic_mode: bool,
+    errors: Vec<Diagnostic>,
+    resumes: HashMap<Position, Position>,
+
+    /// How many blocks and functions the code being parsed is nested in.
+    depth: usize,
+
+    options: CompileOptions,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str, options: CompileOptions) -> Self {
+        let placeholder = Token {
+            token_type: TokenType::EOF,
+            lexeme: "",
+            line: 1,
+            column: 1,
+        };
+
+        Self {
+            scanner: Scanner::new(source),
+            current: placeholder,
+            previous: placeholder,
+            panic_mode: false,
+            errors: Vec::new(),
+            resumes: HashMap::new(),
+            depth: 0,
+            options,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current;
+
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.token_type != TokenType::Error {
+                break;
+            }
+
+            self.error_at_current(self.current.lexeme);
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) {
+        if self.current.token_type == token_type {
+            self.advance();
+        } else {
+            self.error_at_current(message);
+        }
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+
+        self.advance();
+        true
+    }
+
+    /// Returns the span from `start` to the previous token.
+    fn span(&self, start: Position) -> Span {
+        Span::new(start, self.previous.position())
+    }
+
+    fn stmt(&self, kind: StmtKind, start: Position) -> Stmt {
+        Stmt {
+            kind,
+            span: self.span(start),
+        }
+    }
+
+    fn expr(&self, kind: ExprKind, start: Position) -> Expr {
+        Expr {
+            kind,
+            span: self.span(start),
+        }
+    }
+
+    /// Returns the previous token as an identifier.
+    fn identifier(&self) -> Identifier {
+        let position = self.previous.position();
+        Identifier {
+            name: self.previous.lexeme.to_string(),
+            span: Span::new(position, position),
+        }
+    }
+
+    fn declaration(&mut self) -> Stmt {
+        let start = self.current.position();
+
+        let stmt = if self.match_token(TokenType::Fun) {
+            self.fun_declaration()
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        let resume = if self.panic_mode {
+            self.synchronize();
+            self.current.position()
+        } else {
+            self.recovery_point()
+        };
+
+        self.resumes.insert(start, resume);
+        end_declaration(&mut self.errors, start);
+
+        stmt
+    }
+
+    fn fun_declaration(&mut self) -> Stmt {
+        let start = self.previous.position();
+        let named = self.check(TokenType::Identifier);
+        self.consume(TokenType::Identifier, "Expect function name.");
+        let name = self.identifier();
+
+        self.depth += 1;
+        let function = self.function(name);
+        self.depth -= 1;
+
+        // The rest is still parsed to recover from the error the same way.
+        let kind = if named {
+            StmtKind::Function(function)
+        } else {
+            StmtKind::Error
+        };
+        self.stmt(kind, start)
+    }
+
+    /// Parses the parameters and body of a function.
+    fn function(&mut self, name: Identifier) -> Function {
+        let mut params = Vec::new();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() == u8::MAX as usize {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+
+                if self.check(TokenType::Identifier) {
+                    self.advance();
+                    params.push(self.identifier());
+                } else {
+                    self.error_at_current("Expect parameter name.");
+                }
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        let body = self.block();
+
+        Function { name, params, body }
+    }
+
+    fn var_declaration(&mut self) -> Stmt {
+        let start = self.previous.position();
+        let named = self.check(TokenType::Identifier);
+        self.consume(TokenType::Identifier, "Expect variable name.");
+        let name = self.identifier();
+
+        let initializer = if self.match_token(TokenType::Equal) {
+            Some(self.expression())
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+
+        let kind = if named {
+            StmtKind::Var { name, initializer }
+        } else {
+            StmtKind::Error
+        };
+        self.stmt(kind, start)
+    }
+
+    fn statement(&mut self) -> Stmt {
+        let start = self.current.position();
+
+        let kind = if self.match_token(TokenType::Print) {
+            self.print_statement()
+        } else if self.match_token(TokenType::If) {
+            self.if_statement()
+        } else if self.match_token(TokenType::While) {
+            self.while_statement()
+        } else if self.match_token(TokenType::For) {
+            self.for_statement()
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement()
+        } else if self.match_token(TokenType::LeftBrace) {
+            self.depth += 1;
+            let statements = self.block();
+            self.depth -= 1;
+
+            StmtKind::Block(statements)
+        } else {
+            self.expression_statement()
+        };
+
+        self.stmt(kind, start)
+    }
+
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::EOF) {
+            statements.push(self.declaration());
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+        statements
+    }
+
+    fn if_statement(&mut self) -> StmtKind {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        let right_paren = self.previous.position();
+
+        let then_branch = Box::new(self.statement());
+        let else_branch = if self.match_token(TokenType::Else) {
+            Some(Box::new(self.statement()))
+        } else {
+            None
+        };
+
+        StmtKind::If {
+            condition,
+            right_paren,
+            then_branch,
+            else_branch,
+        }
+    }
+
+    fn while_statement(&mut self) -> StmtKind {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        let right_paren = self.previous.position();
+
+        let body = Box::new(self.statement());
+
+        StmtKind::While {
+            condition,
+            right_paren,
+            body,
+        }
+    }
+
+    fn for_statement(&mut self) -> StmtKind {
+        // The variable declared in the initializer is scoped to the loop.
+        self.depth += 1;
+
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        let initializer = if self.match_token(TokenType::Semicolon) {
+            None
+        } else if self.match_token(TokenType::Var) {
+            Some(Box::new(self.var_declaration()))
+        } else {
+            let start = self.current.position();
+            let expression = self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+
+            Some(Box::new(self.stmt(StmtKind::Expression(expression), start)))
+        };
+
+        let mut condition = None;
+        if !self.match_token(TokenType::Semicolon) {
+            condition = Some(self.expression());
+            self.consume(TokenType::Semicolon, "Expect ';' after loop condition.");
+        }
+        let semicolon = self.previous.position();
+
+        let mut increment = None;
+        if !self.match_token(TokenType::RightParen) {
+            increment = Some(self.expression());
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+        }
+        let right_paren = self.previous.position();
+
+        let body = Box::new(self.statement());
+        self.depth -= 1;
+
+        StmtKind::For {
+            initializer,
+            condition,
+            semicolon,
+            increment,
+            right_paren,
+            body,
+        }
+    }
+
+    fn return_statement(&mut self) -> StmtKind {
+        if self.match_token(TokenType::Semicolon) {
+            return StmtKind::Return(None);
+        }
+
+        let value = self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.");
+
+        StmtKind::Return(Some(value))
+    }
+
+    fn print_statement(&mut self) -> StmtKind {
+        let value = self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after value.");
+
+        StmtKind::Print(value)
+    }
+
+    fn expression_statement(&mut self) -> StmtKind {
+        let expression = self.expression();
+
+        // The REPL prints the value of a statement entered on its own, which
+        // needs no semicolon.
+        if !(self.options.repl && self.depth == 0 && self.check(TokenType::EOF)) {
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        }
+
+        StmtKind::Expression(expression)
+    }
+
+    /// Skips tokens until a likely statement boundary, to avoid cascading errors.
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while !at_boundary(self.previous.token_type, self.current.token_type) {
+            self.advance();
+        }
+    }
+
+    /// Returns where parsing would resume if an error had been found in the
+    /// declaration just parsed, without skipping any tokens.
+    fn recovery_point(&self) -> Position {
+        let mut scanner = self.scanner.clone();
+        let mut previous = self.previous.token_type;
+        let mut current = self.current;
+
+        while !at_boundary(previous, current.token_type) {
+            previous = current.token_type;
+            current = scanner.scan_token();
+        }
+
+        current.position()
+    }
+
+    fn expression(&mut self) -> Expr {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
+        self.advance();
+        let Some(prefix) = Self::rule(self.previous.token_type).prefix else {
+            self.error("Expect expression.");
+            return self.expr(ExprKind::Error, self.previous.position());
+        };
+
+        let can_assign = precedence <= Precedence::Assignment;
+        let mut expr = prefix(self, can_assign);
+
+        while precedence <= Self::rule(self.current.token_type).precedence {
+            self.advance();
+            if let Some(infix) = Self::rule(self.previous.token_type).infix {
+                expr = infix(self, expr, can_assign);
+            }
+        }
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.error("Invalid assignment target.");
+        }
+
+        expr
+    }
+
+    fn rule(token_type: TokenType) -> ParseRule<'a> {
+        match token_type {
+            TokenType::LeftParen => {
+                ParseRule::new(Some(Self::grouping), Some(Self::call), Precedence::Call)
+            }
+            TokenType::Dot => ParseRule::new(None, Some(Self::dot), Precedence::Call),
+            TokenType::Minus => {
+                ParseRule::new(Some(Self::unary), Some(Self::binary), Precedence::Term)
+            }
+            TokenType::Plus => ParseRule::new(None, Some(Self::binary), Precedence::Term),
+            TokenType::Slash => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            TokenType::Star => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            TokenType::Bang => ParseRule::new(Some(Self::unary), None, Precedence::None),
+            TokenType::BangEqual | TokenType::EqualEqual => {
+                ParseRule::new(None, Some(Self::binary), Precedence::Equality)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                ParseRule::new(None, Some(Self::binary), Precedence::Comparison)
+            }
+            TokenType::Identifier => ParseRule::new(Some(Self::variable), None, Precedence::None),
+            TokenType::String => ParseRule::new(Some(Self::string), None, Precedence::None),
+            TokenType::Number => ParseRule::new(Some(Self::number), None, Precedence::None),
+            TokenType::False | TokenType::Nil | TokenType::True => {
+                ParseRule::new(Some(Self::literal), None, Precedence::None)
+            }
+            TokenType::And => ParseRule::new(None, Some(Self::logical), Precedence::And),
+            TokenType::Or => ParseRule::new(None, Some(Self::logical), Precedence::Or),
+            TokenType::This => ParseRule::new(Some(Self::this), None, Precedence::None),
+            TokenType::Super => ParseRule::new(Some(Self::super_), None, Precedence::None),
+            _ => ParseRule::new(None, None, Precedence::None),
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) -> Expr {
+        let start = self.previous.position();
+        let expression = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after expression.");
+
+        self.expr(ExprKind::Grouping(Box::new(expression)), start)
+    }
+
+    fn call(&mut self, callee: Expr, _can_assign: bool) -> Expr {
+        let start = callee.span.start;
+        let arguments = self.argument_list();
+
+        let callee = Box::new(callee);
+        self.expr(ExprKind::Call { callee, arguments }, start)
+    }
+
+    fn dot(&mut self, object: Expr, can_assign: bool) -> Expr {
+        let start = object.span.start;
+        let object = Box::new(object);
+
+        self.consume(TokenType::Identifier, "Expect property name after '.'.");
+        let name = self.identifier();
+
+        let kind = if can_assign && self.match_token(TokenType::Equal) {
+            let value = Box::new(self.expression());
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            }
+        } else {
+            ExprKind::Get { object, name }
+        };
+
+        self.expr(kind, start)
+    }
+
+    fn argument_list(&mut self) -> Vec<Expr> {
+        let mut arguments = Vec::new();
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                arguments.push(self.expression());
+
+                if arguments.len() > u8::MAX as usize {
+                    self.error("Can't have more than 255 arguments.");
+                }
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        arguments
+    }
+
+    fn number(&mut self, _can_assign: bool) -> Expr {
+        let value = self.previous.number_value().unwrap_or_default();
+        self.literal_expr(Literal::Number(value))
+    }
+
+    fn string(&mut self, _can_assign: bool) -> Expr {
+        let lexeme = self.previous.lexeme;
+        self.literal_expr(Literal::String(lexeme[1..lexeme.len() - 1].to_string()))
+    }
+
+    fn literal(&mut self, _can_assign: bool) -> Expr {
+        let literal = match self.previous.token_type {
+            TokenType::False => Literal::Bool(false),
+            TokenType::Nil => Literal::Nil,
+            TokenType::True => Literal::Bool(true),
+            _ => unreachable!("literal() called for non-literal {:?}", self.previous),
+        };
+
+        self.literal_expr(literal)
+    }
+
+    fn literal_expr(&self, literal: Literal) -> Expr {
+        self.expr(ExprKind::Literal(literal), self.previous.position())
+    }
+
+    fn variable(&mut self, can_assign: bool) -> Expr {
+        let name = self.identifier();
+        let start = name.span.start;
+
+        let kind = if can_assign && self.match_token(TokenType::Equal) {
+            let value = Box::new(self.expression());
+            ExprKind::Assign { name, value }
+        } else {
+            ExprKind::Variable(name)
+        };
+
+        self.expr(kind, start)
+    }
+
+    fn this(&mut self, _can_assign: bool) -> Expr {
+        self.expr(ExprKind::This, self.previous.position())
+    }
+
+    fn super_(&mut self, _can_assign: bool) -> Expr {
+        let start = self.previous.position();
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenType::Identifier, "Expect superclass method name.");
+        let method = self.identifier();
+
+        self.expr(ExprKind::Super { method }, start)
+    }
+
+    fn unary(&mut self, _can_assign: bool) -> Expr {
+        let start = self.previous.position();
+        let operator = match self.previous.token_type {
+            TokenType::Bang => UnaryOperator::Not,
+            TokenType::Minus => UnaryOperator::Negate,
+            _ => unreachable!("unary() called for non-unary operator {:?}", self.previous),
+        };
+
+        let operand = Box::new(self.parse_precedence(Precedence::Unary));
+        self.expr(ExprKind::Unary { operator, operand }, start)
+    }
+
+    fn binary(&mut self, left: Expr, _can_assign: bool) -> Expr {
+        let start = left.span.start;
+        let token_type = self.previous.token_type;
+        let operator = match token_type {
+            TokenType::BangEqual => BinaryOperator::NotEqual,
+            TokenType::EqualEqual => BinaryOperator::Equal,
+            TokenType::Greater => BinaryOperator::Greater,
+            TokenType::GreaterEqual => BinaryOperator::GreaterEqual,
+            TokenType::Less => BinaryOperator::Less,
+            TokenType::LessEqual => BinaryOperator::LessEqual,
+            TokenType::Plus => BinaryOperator::Add,
+            TokenType::Minus => BinaryOperator::Subtract,
+            TokenType::Star => BinaryOperator::Multiply,
+            TokenType::Slash => BinaryOperator::Divide,
+            _ => unreachable!("binary() called for non-binary operator {:?}", token_type),
+        };
+
+        let rule = Self::rule(token_type);
+        let right = self.parse_precedence(rule.precedence.next());
+
+        let kind = ExprKind::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        self.expr(kind, start)
+    }
+
+    /// Parses the right operand of `and` or `or`.
+    fn logical(&mut self, left: Expr, _can_assign: bool) -> Expr {
+        let start = left.span.start;
+        let position = self.previous.position();
+        let (operator, precedence) = match self.previous.token_type {
+            TokenType::And => (LogicalOperator::And, Precedence::And),
+            TokenType::Or => (LogicalOperator::Or, Precedence::Or),
+            _ => unreachable!("logical() called for {:?}", self.previous),
+        };
+
+        let right = self.parse_precedence(precedence);
+
+        let kind = ExprKind::Logical {
+            operator,
+            position,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        self.expr(kind, start)
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous, message);
+    }
+
+    fn error_at(&mut self, token: Token, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+
+        self.panic_mode = true;
+
+        // Invalid tokens are found as soon as the token before them is parsed,
+        // and the next token is only checked once the previous is dealt with.
+        let rank = if token.token_type == TokenType::Error {
+            0
+        } else if token.position() == self.current.position() {
+            2
+        } else {
+            1
+        };
+
+        let mut diagnostic = Diagnostic::at(token, message);
+        diagnostic.found = (self.previous.position(), rank);
+        self.errors.push(diagnostic);
+    }
+}
+
+/// Returns whether a statement likely starts at the current token, given the
+/// type of the previous one.
+fn at_boundary(previous: TokenType, current: TokenType) -> bool {
+    previous == TokenType::Semicolon
+        || matches!(
+            current,
+            TokenType::EOF
+                | TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+        )
+}
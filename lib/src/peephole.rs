@@ -0,0 +1,182 @@
+/*!
+A peephole pass fusing common sequences of instructions into superinstructions.
+
+The compiler runs it over every function it compiles at
+[`OptLevel::Full`][crate::compiler::OptLevel::Full]. Each superinstruction does
+the work of the sequence it replaces with a single dispatch:
+
+| Sequence                                    | Superinstruction  |
+|---------------------------------------------|-------------------|
+| `GetLocal s; Constant c; Add; SetLocal s`   | `IncrLocal s c`   |
+| `Constant c; Add`                           | `AddConst c`      |
+| `Less; JumpIfFalse j; Pop`, with a `Pop` at the target of the jump | `JumpIfNotLess` |
+
+The last one drops the comparison result instead of pushing it, so it jumps
+past the `Pop` at the original target.
+
+Sequences are only fused when nothing jumps into the middle of them. Jump
+offsets are recomputed for the shorter code, and each superinstruction keeps
+the line of the instruction in it that can fail, so runtime errors are
+reported on the same line as before.
+*/
+
+use crate::{
+    compiler::{Chunk, OpCode},
+    verify::{self, Instruction},
+};
+
+/// Fuses the instructions of the chunk into superinstructions where possible.
+///
+/// The functions declared in the chunk are left alone. Code that can't be
+/// decoded, or that jumps somewhere other than to an instruction, is left
+/// unchanged.
+pub fn optimize(chunk: &mut Chunk) {
+    let Ok(instructions) = verify::decode(chunk) else {
+        return;
+    };
+
+    let index_of = |offset: usize| {
+        instructions
+            .binary_search_by_key(&offset, |instruction| instruction.offset)
+            .ok()
+    };
+
+    // Whether an instruction is the target of a jump, by index.
+    let mut targets = vec![false; instructions.len()];
+    for instruction in instructions
+        .iter()
+        .filter(|instruction| is_jump(instruction.opcode))
+    {
+        let Some(index) = jump_target(instruction).and_then(index_of) else {
+            return;
+        };
+
+        targets[index] = true;
+    }
+
+    let mut optimized = Chunk::new();
+
+    // Where each instruction starts in the optimized code, by index. The
+    // instructions fused into another start where it does.
+    let mut starts = Vec::with_capacity(instructions.len());
+
+    // The jumps in the optimized code, with the index they jump to.
+    let mut jumps = Vec::new();
+
+    let mut index = 0;
+    while index < instructions.len() {
+        let rest = &instructions[index..];
+        let start = optimized.code.len();
+        let line = |instruction: &Instruction| {
+            chunk
+                .line_for_offset(instruction.offset)
+                .unwrap_or_default()
+        };
+        let fusable = |len: usize| !targets[index + 1..index + len].contains(&true);
+
+        let fused = match rest {
+            [get, constant, add, set, ..]
+                if get.opcode == OpCode::GetLocal
+                    && constant.opcode == OpCode::Constant
+                    && add.opcode == OpCode::Add
+                    && set.opcode == OpCode::SetLocal
+                    && set.operand == get.operand
+                    && fusable(4) =>
+            {
+                let line = line(add);
+                optimized.write(OpCode::IncrLocal, line);
+                optimized.write(get.operand as u8, line);
+                optimized.write(constant.operand as u8, line);
+                4
+            }
+
+            [constant, add, ..]
+                if constant.opcode == OpCode::Constant
+                    && add.opcode == OpCode::Add
+                    && fusable(2) =>
+            {
+                let line = line(add);
+                optimized.write(OpCode::AddConst, line);
+                optimized.write(constant.operand as u8, line);
+                2
+            }
+
+            [less, jump, pop, ..]
+                if less.opcode == OpCode::Less
+                    && jump.opcode == OpCode::JumpIfFalse
+                    && pop.opcode == OpCode::Pop
+                    && fusable(3)
+                    && let Some(target) = jump_target(jump).and_then(index_of)
+                    && instructions[target].opcode == OpCode::Pop =>
+            {
+                let line = line(less);
+                optimized.write(OpCode::JumpIfNotLess, line);
+                optimized.write(0xFF, line);
+                optimized.write(0xFF, line);
+                jumps.push((start, target + 1));
+                3
+            }
+
+            [instruction, ..] => {
+                let line = line(instruction);
+                let end = instruction.offset + 1 + verify::operand_len(instruction.opcode);
+                for &byte in &chunk.code[instruction.offset..end] {
+                    optimized.write(byte, line);
+                }
+
+                if let Some(target) = jump_target(instruction).and_then(index_of) {
+                    jumps.push((start, target));
+                }
+
+                1
+            }
+
+            [] => unreachable!("there are instructions left"),
+        };
+
+        starts.extend(std::iter::repeat_n(start, fused));
+        index += fused;
+    }
+
+    // The `Pop` skipped by a fused jump may be the last instruction.
+    starts.push(optimized.code.len());
+
+    for (offset, target) in jumps {
+        let next = offset + 3;
+        let target = starts[target];
+        let jump = if optimized.code[offset] == OpCode::Loop as u8 {
+            next - target
+        } else {
+            target - next
+        };
+
+        // The code only gets shorter, so the jump still fits.
+        let [high, low] = (jump as u16).to_be_bytes();
+        optimized.code[offset + 1] = high;
+        optimized.code[offset + 2] = low;
+    }
+
+    chunk.code = optimized.code;
+    chunk.lines = optimized.lines;
+}
+
+fn is_jump(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotLess | OpCode::Loop
+    )
+}
+
+/// Returns the offset a jump instruction jumps to, or `None` if it is not a
+/// jump or would jump before the start of the code.
+fn jump_target(instruction: &Instruction) -> Option<usize> {
+    let next = instruction.offset + 1 + verify::operand_len(instruction.opcode);
+
+    match instruction.opcode {
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotLess => {
+            Some(next + instruction.operand)
+        }
+        OpCode::Loop => next.checked_sub(instruction.operand),
+        _ => None,
+    }
+}
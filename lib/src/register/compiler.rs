@@ -0,0 +1,845 @@
+/*!
+Generates register [`Code`] from the syntax tree produced by the [`parser`] and
+resolved by the [`resolver`], the same front end as the stack
+[`compiler`][crate::compiler], so both report the same errors.
+
+Expressions leave their value in a register the caller can read without
+copying it: a local variable's own register, a temporary, or the result of the
+instruction just emitted, whose destination register is filled in once it is
+known. That way `a = b + c` adds straight into `a`.
+
+Local variables live in the registers numbered by the slots the resolver gives
+them. Temporaries are allocated above the locals like a stack, and are all free
+again at the end of each statement.
+*/
+
+use std::sync::Arc;
+
+use crate::{
+    ast::{
+        self, BinaryOperator, Expr, ExprKind, Identifier, Literal, LogicalOperator, Position,
+        Program, Stmt, StmtKind, UnaryOperator,
+    },
+    compiler::{self, Chunk, CompileError, CompileOptions},
+    function::Function,
+    parser::{self, Diagnostic},
+    resolver::{self, Resolution, Warning},
+    value::Value,
+};
+
+use super::{Code, Instruction, Register};
+
+/// Compiles the given source code into register [`Code`].
+pub fn compile(source: &str) -> Result<Code, CompileError> {
+    compile_with_options(source, CompileOptions::default())
+}
+
+/// Compiles the given source code into register [`Code`] using the given
+/// options.
+///
+/// The code is never optimized, so the optimization level is ignored.
+pub fn compile_with_options(source: &str, options: CompileOptions) -> Result<Code, CompileError> {
+    compile_with_warnings(source, options).map(|(code, _)| code)
+}
+
+/// Compiles the given source code like [`compile_with_options`], also
+/// returning the warnings the stack compiler returns from
+/// [`compile_with_warnings`][crate::compiler::compile_with_warnings].
+pub fn compile_with_warnings(
+    source: &str,
+    options: CompileOptions,
+) -> Result<(Code, Vec<Warning>), CompileError> {
+    let parse = parser::parse_with_options(source, options);
+    let resolution = resolver::resolve(&parse.program);
+
+    let mut codegen = Codegen::new(&resolution, options);
+    let script = codegen.script(&parse.program);
+
+    let mut found = resolution.errors.clone();
+    found.append(&mut codegen.errors);
+    compiler::check(source, &parse, found)?;
+
+    Ok((script, resolution.warnings))
+}
+
+/// Where the value of a compiled expression is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operand {
+    /// In the register of a local variable.
+    Local(Register),
+
+    /// In a temporary register.
+    Temp(Register),
+
+    /// Computed by the last instruction emitted, which does not have its
+    /// destination register yet.
+    Pending,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FunctionKind {
+    Function,
+    Script,
+}
+
+/// The state of a function being compiled.
+struct Compiler {
+    code: Code,
+    name: String,
+    arity: u8,
+    kind: FunctionKind,
+
+    /// The number of locals declared in each scope, with the innermost last.
+    scopes: Vec<usize>,
+
+    /// The number of registers holding locals, including the first one,
+    /// which holds the function itself.
+    locals: usize,
+
+    /// The first free temporary register.
+    next: usize,
+}
+
+impl Compiler {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        Self {
+            code: Code::new(),
+            name: name.to_string(),
+            arity: 0,
+            kind,
+            scopes: Vec::new(),
+            locals: 1,
+            next: 1,
+        }
+    }
+}
+
+/// Generates register code from a resolved syntax tree.
+struct Codegen<'a> {
+    resolution: &'a Resolution,
+
+    /// The functions being compiled, with the innermost last.
+    compilers: Vec<Compiler>,
+
+    /// The token the code being emitted was compiled from, which gives its
+    /// line, as in the stack compiler.
+    position: Position,
+
+    /// The errors found, located on their lines later.
+    errors: Vec<Diagnostic>,
+    options: CompileOptions,
+}
+
+impl<'a> Codegen<'a> {
+    fn new(resolution: &'a Resolution, options: CompileOptions) -> Self {
+        Self {
+            resolution,
+            compilers: vec![Compiler::new("script", FunctionKind::Script)],
+            position: Position::default(),
+            errors: Vec::new(),
+            options,
+        }
+    }
+
+    /// Compiles the top-level code of the program.
+    fn script(&mut self, program: &Program) -> Code {
+        for statement in &program.statements {
+            self.declaration(statement);
+        }
+
+        self.position = program.end;
+        self.end()
+    }
+
+    fn compiler(&mut self) -> &mut Compiler {
+        self.compilers
+            .last_mut()
+            .expect("there is always a function being compiled")
+    }
+
+    /// Finishes compiling the innermost function, returning its code.
+    fn end(&mut self) -> Code {
+        self.emit(Instruction::ReturnNil);
+
+        let compiler = self
+            .compilers
+            .pop()
+            .expect("there is always a function being compiled");
+
+        let mut code = compiler.code;
+        if compiler.kind == FunctionKind::Function {
+            let function = Function::with_chunk(&compiler.name, compiler.arity, Chunk::new());
+            code.function = Some(Arc::new(function));
+        }
+
+        code
+    }
+
+    fn begin_scope(&mut self) {
+        self.compiler().scopes.push(0);
+    }
+
+    /// Ends the innermost scope, freeing the registers of its locals.
+    fn end_scope(&mut self) {
+        let compiler = self.compiler();
+        let locals = compiler.scopes.pop().unwrap_or_default();
+        compiler.locals -= locals;
+        compiler.next = compiler.locals;
+    }
+
+    /// Compiles a statement that the parser may recover from errors after.
+    fn declaration(&mut self, stmt: &Stmt) {
+        self.statement(stmt);
+        parser::end_declaration(&mut self.errors, stmt.span.start);
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let end = stmt.span.end;
+
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                let value = self.expression(expr);
+                let src = self.register(value);
+                self.position = end;
+
+                let compiler = self.compiler();
+                let top_level = compiler.kind == FunctionKind::Script && compiler.scopes.is_empty();
+
+                if self.options.repl && top_level {
+                    self.emit(Instruction::Print { src });
+                }
+            }
+            StmtKind::Print(expr) => {
+                let value = self.expression(expr);
+                let src = self.register(value);
+                self.position = end;
+                self.emit(Instruction::Print { src });
+            }
+            StmtKind::Var { name, initializer } => {
+                self.var_declaration(name, initializer.as_ref(), end);
+            }
+            StmtKind::Function(function) => self.fun_declaration(function, end),
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.declaration(statement);
+                }
+                self.position = end;
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                right_paren,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.condition(condition, *right_paren);
+                let then_jump = self.emit_exit_jump(condition);
+                self.statement(then_branch);
+
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_jump = self.emit(Instruction::Jump { target: 0 });
+                        self.patch_jump(then_jump);
+                        self.statement(else_branch);
+                        self.patch_jump(else_jump);
+                    }
+                    None => self.patch_jump(then_jump),
+                }
+            }
+            StmtKind::While {
+                condition,
+                right_paren,
+                body,
+            } => {
+                let loop_start = self.compiler().code.instructions.len();
+
+                let condition = self.condition(condition, *right_paren);
+                let exit_jump = self.emit_exit_jump(condition);
+                self.statement(body);
+                self.emit(Instruction::Jump { target: loop_start });
+
+                self.patch_jump(exit_jump);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                semicolon,
+                increment,
+                right_paren,
+                body,
+            } => {
+                self.begin_scope();
+
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+
+                self.for_loop(
+                    condition.as_ref(),
+                    *semicolon,
+                    increment.as_ref(),
+                    *right_paren,
+                    body,
+                );
+
+                self.end_scope();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    let value = self.expression(value);
+                    let src = self.register(value);
+                    self.position = end;
+                    self.emit(Instruction::Return { src });
+                } else {
+                    self.position = end;
+                    self.emit(Instruction::ReturnNil);
+                }
+            }
+            StmtKind::Error => {}
+        }
+
+        self.position = end;
+        self.free_temporaries();
+    }
+
+    fn fun_declaration(&mut self, function: &ast::Function, end: Position) {
+        let global = self.declare_variable(&function.name);
+        let index = self.function(function, end);
+
+        if self.compiler().scopes.is_empty() {
+            let src = self.emit_register(Instruction::Function {
+                dst: 0,
+                function: index,
+            });
+            self.emit(Instruction::DefineGlobal { name: global, src });
+        } else {
+            let dst = self.local_register();
+            self.emit(Instruction::Function {
+                dst,
+                function: index,
+            });
+        }
+    }
+
+    /// Compiles the parameters and body of a function, returning its index
+    /// among the functions declared in the enclosing one.
+    fn function(&mut self, function: &ast::Function, end: Position) -> usize {
+        let mut compiler = Compiler::new(&function.name.name, FunctionKind::Function);
+        compiler.arity = function.params.len().min(u8::MAX as usize) as u8;
+        self.compilers.push(compiler);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare_variable(param);
+        }
+
+        for statement in &function.body {
+            self.declaration(statement);
+        }
+
+        // The scope does not need to be ended, returning discards the whole frame.
+        self.position = end;
+        let code = self.end();
+        let functions = &mut self.compiler().code.functions;
+        functions.push(Arc::new(code));
+        functions.len() - 1
+    }
+
+    fn var_declaration(&mut self, name: &Identifier, initializer: Option<&Expr>, end: Position) {
+        let global = self.declare_variable(name);
+
+        if !self.compiler().scopes.is_empty() {
+            // The initializer is compiled straight into the local's register.
+            let dst = self.local_register();
+            match initializer {
+                Some(initializer) => {
+                    let value = self.expression(initializer);
+                    self.discharge(value, dst);
+                }
+                None => {
+                    self.emit(Instruction::LoadNil { dst });
+                }
+            }
+        } else {
+            let src = match initializer {
+                Some(initializer) => {
+                    let value = self.expression(initializer);
+                    self.register(value)
+                }
+                None => self.emit_register(Instruction::LoadNil { dst: 0 }),
+            };
+
+            self.position = end;
+            self.emit(Instruction::DefineGlobal { name: global, src });
+        }
+    }
+
+    /// Compiles the clauses after the initializer of a `for` loop, and its body.
+    fn for_loop(
+        &mut self,
+        condition: Option<&Expr>,
+        semicolon: Position,
+        increment: Option<&Expr>,
+        right_paren: Position,
+        body: &Stmt,
+    ) {
+        let mut loop_start = self.compiler().code.instructions.len();
+
+        let exit_jump = condition.map(|condition| {
+            let condition = self.condition(condition, semicolon);
+            self.emit_exit_jump(condition)
+        });
+        self.position = semicolon;
+
+        // The increment is compiled before the body but runs after it, so the
+        // body jumps back to it and it jumps back to the condition.
+        if let Some(increment) = increment {
+            let body_jump = self.emit(Instruction::Jump { target: 0 });
+            let increment_start = self.compiler().code.instructions.len();
+
+            let value = self.expression(increment);
+            self.register(value);
+            self.free_temporaries();
+            self.position = right_paren;
+
+            self.emit(Instruction::Jump { target: loop_start });
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement(body);
+        self.emit(Instruction::Jump { target: loop_start });
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+        }
+    }
+
+    /// Compiles the condition of a branch or loop into a register, which
+    /// stays in use until it has been jumped on from the token at `end`.
+    fn condition(&mut self, condition: &Expr, end: Position) -> Register {
+        let condition = self.expression(condition);
+        let register = self.register(condition);
+        self.position = end;
+        register
+    }
+
+    /// Emits the jump out of a branch or loop, freeing the temporaries used
+    /// by its condition.
+    fn emit_exit_jump(&mut self, condition: Register) -> usize {
+        let jump = self.emit_jump_if(false, condition);
+        self.free_temporaries();
+        jump
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Operand {
+        // The first temporary register the whole expression may use.
+        let base = self.compiler().next;
+
+        let operand = match &expr.kind {
+            ExprKind::Literal(literal) => {
+                self.position = expr.span.end;
+                self.literal(literal)
+            }
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Variable(name) => {
+                self.position = name.span.start;
+                match self.resolution.slot(name) {
+                    Some(slot) => Operand::Local(slot as Register),
+                    None => {
+                        let name = self.identifier_constant(name);
+                        self.emit_pending(Instruction::GetGlobal { dst: 0, name })
+                    }
+                }
+            }
+            ExprKind::Assign { name, value } => self.assign(name, value, base),
+            ExprKind::Unary { operator, operand } => {
+                let operand = self.expression(operand);
+                let src = self.register(operand);
+                self.free(base);
+                self.position = expr.span.end;
+
+                match operator {
+                    UnaryOperator::Not => self.emit_pending(Instruction::Not { dst: 0, src }),
+                    UnaryOperator::Negate => self.emit_pending(Instruction::Negate { dst: 0, src }),
+                }
+            }
+            ExprKind::Binary {
+                operator,
+                left,
+                right,
+            } => self.binary(*operator, left, right, base),
+            ExprKind::Logical {
+                operator,
+                position,
+                left,
+                right,
+            } => {
+                let left = self.expression(left);
+                self.position = *position;
+                let skip_if = *operator == LogicalOperator::Or;
+                self.logical(skip_if, left, right, base)
+            }
+            ExprKind::Call { callee, arguments } => self.call(callee, arguments, expr, base),
+            ExprKind::Get { object, name } => {
+                let object = self.expression(object);
+                self.position = name.span.start;
+                let name = self.identifier_constant(name);
+
+                let object = self.register(object);
+                self.free(base);
+                self.emit_pending(Instruction::GetProperty {
+                    dst: 0,
+                    object,
+                    name,
+                })
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                let object = self.expression(object);
+                self.position = name.span.start;
+                let name = self.identifier_constant(name);
+
+                let object = self.hold(object, value);
+                let value = self.expression(value);
+                let src = self.register(value);
+                self.position = expr.span.end;
+                self.emit(Instruction::SetProperty { object, name, src });
+
+                self.free(base.max(src as usize + 1));
+                self.operand_in(src)
+            }
+            // These are reported by the resolver, and never compiled.
+            ExprKind::This | ExprKind::Super { .. } | ExprKind::Error => Operand::Local(0),
+        };
+
+        self.position = expr.span.end;
+        operand
+    }
+
+    fn literal(&mut self, literal: &Literal) -> Operand {
+        match literal {
+            Literal::Nil => self.emit_pending(Instruction::LoadNil { dst: 0 }),
+            Literal::Bool(value) => self.emit_pending(Instruction::LoadBool {
+                dst: 0,
+                value: *value,
+            }),
+            Literal::Number(value) => self.emit_constant((*value).into()),
+            Literal::String(value) => self.emit_constant(value.as_str().into()),
+        }
+    }
+
+    fn assign(&mut self, name: &Identifier, value: &Expr, base: usize) -> Operand {
+        self.position = name.span.start;
+
+        if let Some(slot) = self.resolution.slot(name) {
+            let local = slot as Register;
+            let value = self.expression(value);
+            self.discharge(value, local);
+            self.free(base);
+            return Operand::Local(local);
+        }
+
+        let name = self.identifier_constant(name);
+        let value = self.expression(value);
+        let src = self.register(value);
+        self.emit(Instruction::SetGlobal { name, src });
+        self.operand_in(src)
+    }
+
+    fn binary(
+        &mut self,
+        operator: BinaryOperator,
+        left: &Expr,
+        right: &Expr,
+        base: usize,
+    ) -> Operand {
+        let left = self.expression(left);
+        let left = self.hold(left, right);
+        let right = self.expression(right);
+        let right = self.register(right);
+        self.free(base);
+
+        // `!=`, `>=` and `<=` negate the result of the opposite comparison.
+        let negated = |codegen: &mut Self, instruction: Instruction| {
+            let src = codegen.emit_register(instruction);
+            codegen.free(base);
+            codegen.emit_pending(Instruction::Not { dst: 0, src })
+        };
+
+        // The destination is filled in once the result is discharged.
+        let dst = 0;
+
+        match operator {
+            BinaryOperator::NotEqual => negated(self, Instruction::Equal { dst, left, right }),
+            BinaryOperator::Equal => self.emit_pending(Instruction::Equal { dst, left, right }),
+            BinaryOperator::Greater => self.emit_pending(Instruction::Greater { dst, left, right }),
+            BinaryOperator::GreaterEqual => negated(self, Instruction::Less { dst, left, right }),
+            BinaryOperator::Less => self.emit_pending(Instruction::Less { dst, left, right }),
+            BinaryOperator::LessEqual => negated(self, Instruction::Greater { dst, left, right }),
+            BinaryOperator::Add => self.emit_pending(Instruction::Add { dst, left, right }),
+            BinaryOperator::Subtract => {
+                self.emit_pending(Instruction::Subtract { dst, left, right })
+            }
+            BinaryOperator::Multiply => {
+                self.emit_pending(Instruction::Multiply { dst, left, right })
+            }
+            BinaryOperator::Divide => self.emit_pending(Instruction::Divide { dst, left, right }),
+        }
+    }
+
+    /// Compiles the right operand of `and` or `or` into the register holding
+    /// the left, unless the left is falsey or truthy respectively, as given
+    /// by `skip_if`.
+    fn logical(&mut self, skip_if: bool, left: Operand, right: &Expr, base: usize) -> Operand {
+        self.free(base);
+        let dst = self.allocate();
+        self.discharge(left, dst);
+
+        let end_jump = self.emit_jump_if(skip_if, dst);
+        let right = self.expression(right);
+        self.discharge(right, dst);
+        self.free(dst as usize + 1);
+
+        self.patch_jump(end_jump);
+        Operand::Temp(dst)
+    }
+
+    /// Compiles a call, moving the callee into the first free register with
+    /// the arguments after it.
+    fn call(&mut self, callee: &Expr, arguments: &[Expr], expr: &Expr, base: usize) -> Operand {
+        let callee = self.expression(callee);
+        self.free(base);
+        let register = self.allocate();
+        self.discharge(callee, register);
+
+        for argument in arguments {
+            let next = self.compiler().next;
+            let value = self.expression(argument);
+            self.free(next);
+            let dst = self.allocate();
+            self.discharge(value, dst);
+        }
+
+        self.position = expr.span.end;
+        let arg_count = arguments.len().min(u8::MAX as usize) as u8;
+        self.emit(Instruction::Call {
+            callee: register,
+            arg_count,
+        });
+
+        self.free(register as usize + 1);
+        Operand::Temp(register)
+    }
+
+    /// Declares a variable, returning the constant holding its name for
+    /// globals, and 0 for locals, which are not looked up by name.
+    fn declare_variable(&mut self, name: &Identifier) -> usize {
+        self.position = name.span.start;
+
+        let compiler = self.compiler();
+        let Some(locals) = compiler.scopes.last_mut() else {
+            return self.identifier_constant(name);
+        };
+
+        *locals += 1;
+        compiler.locals += 1;
+
+        // Locals are only declared between statements, when no temporaries are in use.
+        compiler.next = compiler.locals;
+        compiler.code.registers = compiler.code.registers.max(compiler.next);
+        0
+    }
+
+    /// Returns the register of the most recently declared local.
+    fn local_register(&mut self) -> Register {
+        (self.compiler().locals - 1) as Register
+    }
+
+    /// Adds the name of the identifier to the constants, returning its index.
+    fn identifier_constant(&mut self, name: &Identifier) -> usize {
+        self.add_constant(name.name.as_str().into())
+    }
+
+    fn add_constant(&mut self, value: Value) -> usize {
+        let constants = &mut self.compiler().code.constants;
+        constants.push(value);
+        constants.len() - 1
+    }
+
+    /// Allocates a temporary register.
+    fn allocate(&mut self) -> Register {
+        let compiler = self.compiler();
+        let register = compiler.next;
+        compiler.next += 1;
+        compiler.code.registers = compiler.code.registers.max(compiler.next);
+
+        match Register::try_from(register) {
+            Ok(register) => register,
+            Err(_) => {
+                self.error("Too many registers in function.");
+                0
+            }
+        }
+    }
+
+    /// Frees the temporary registers from `next` on.
+    fn free(&mut self, next: usize) {
+        self.compiler().next = next;
+    }
+
+    /// Frees all temporary registers, at the end of a statement.
+    fn free_temporaries(&mut self) {
+        let compiler = self.compiler();
+        compiler.next = compiler.locals;
+    }
+
+    /// Returns a register holding the value of the operand.
+    fn register(&mut self, operand: Operand) -> Register {
+        match operand {
+            Operand::Local(register) | Operand::Temp(register) => register,
+            Operand::Pending => {
+                let register = self.allocate();
+                self.discharge(operand, register);
+                register
+            }
+        }
+    }
+
+    /// Returns a register holding the value of an operand that is used after
+    /// `rest` of the expression, such as the left operand of a binary
+    /// operator.
+    ///
+    /// A local assigned by the rest of the expression is copied first, so
+    /// that its value from before the assignment is used.
+    fn hold(&mut self, operand: Operand, rest: &Expr) -> Register {
+        match operand {
+            Operand::Local(src) if assigns(self.resolution, rest, src) => {
+                let dst = self.allocate();
+                self.emit(Instruction::Move { dst, src });
+                dst
+            }
+            _ => self.register(operand),
+        }
+    }
+
+    /// Returns the operand for a value in the register.
+    fn operand_in(&mut self, register: Register) -> Operand {
+        if (register as usize) < self.compiler().locals {
+            Operand::Local(register)
+        } else {
+            Operand::Temp(register)
+        }
+    }
+
+    /// Puts the value of the operand into the register.
+    fn discharge(&mut self, operand: Operand, dst: Register) {
+        match operand {
+            Operand::Local(src) | Operand::Temp(src) => {
+                if src != dst {
+                    self.emit(Instruction::Move { dst, src });
+                }
+            }
+            Operand::Pending => {
+                let instructions = &mut self.compiler().code.instructions;
+                let instruction = instructions
+                    .last_mut()
+                    .expect("a pending operand's instruction was just emitted");
+                instruction.set_dst(dst);
+            }
+        }
+    }
+
+    /// Emits an instruction, returning its index.
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        let line = self.position.line as usize;
+        let code = &mut self.compiler().code;
+        code.instructions.push(instruction);
+        code.lines.push(line);
+        code.instructions.len() - 1
+    }
+
+    /// Emits an instruction whose destination register is chosen later.
+    fn emit_pending(&mut self, instruction: Instruction) -> Operand {
+        self.emit(instruction);
+        Operand::Pending
+    }
+
+    /// Emits an instruction writing to a new temporary register, returning it.
+    fn emit_register(&mut self, instruction: Instruction) -> Register {
+        let operand = self.emit_pending(instruction);
+        self.register(operand)
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Operand {
+        let constant = self.add_constant(value);
+        self.emit_pending(Instruction::LoadConstant { dst: 0, constant })
+    }
+
+    /// Emits a jump taken if the value in the register is truthy or falsey as
+    /// given, returning its index so it can be patched once the target is
+    /// known.
+    fn emit_jump_if(&mut self, truthy: bool, condition: Register) -> usize {
+        let jump = if truthy {
+            Instruction::JumpIfTrue {
+                condition,
+                target: 0,
+            }
+        } else {
+            Instruction::JumpIfFalse {
+                condition,
+                target: 0,
+            }
+        };
+
+        self.emit(jump)
+    }
+
+    /// Points the jump at the index to the next instruction.
+    fn patch_jump(&mut self, index: usize) {
+        let code = &mut self.compiler().code;
+        let next = code.instructions.len();
+        match &mut code.instructions[index] {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::JumpIfTrue { target, .. } => *target = next,
+            instruction => unreachable!("{:?} is not a jump", instruction),
+        }
+    }
+
+    /// Reports an error at the token the code being emitted was compiled from.
+    fn error(&mut self, message: &str) {
+        let error = Diagnostic::new(self.position, "", message);
+        self.errors.push(error);
+    }
+}
+
+/// Returns whether the expression assigns the local variable in the register.
+///
+/// Functions cannot assign the locals of their callers, so calls are only
+/// searched for assignments in their arguments.
+fn assigns(resolution: &Resolution, expr: &Expr, register: Register) -> bool {
+    let assigns = |expr: &Expr| assigns(resolution, expr, register);
+
+    match &expr.kind {
+        ExprKind::Assign { name, value } => {
+            resolution.slot(name) == Some(register as u8) || assigns(value)
+        }
+        ExprKind::Grouping(expr) | ExprKind::Unary { operand: expr, .. } => assigns(expr),
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            assigns(left) || assigns(right)
+        }
+        ExprKind::Call { callee, arguments } => assigns(callee) || arguments.iter().any(assigns),
+        ExprKind::Get { object, .. } => assigns(object),
+        ExprKind::Set { object, value, .. } => assigns(object) || assigns(value),
+        ExprKind::Literal(_)
+        | ExprKind::Variable(_)
+        | ExprKind::This
+        | ExprKind::Super { .. }
+        | ExprKind::Error => false,
+    }
+}
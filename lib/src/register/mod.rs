@@ -0,0 +1,357 @@
+/*!
+An alternative backend compiling Lox to register-based code, run by its own
+[`RegisterVm`].
+
+The stack [`compiler`][crate::compiler] and [`VM`][crate::vm::VM] push every
+operand before using it. Here each function instead has a fixed number of
+registers, and every [`Instruction`] names the registers it reads and the one
+it writes, so `a = b + c` between locals is a single `ADD` rather than three
+pushes, an add and a pop. Local variables live in registers of their own,
+with temporaries above them.
+
+Both backends share the [`Scanner`][crate::scanner::Scanner] and the
+[`Value`] model, including native functions and classes, and report the same
+errors on the same lines. The register backend is an experiment to compare
+against the stack VM, see `rulox bench --backend register`, and so it has
+none of the limits or optimizations the stack VM has.
+
+# Performance
+
+`rulox bench --backend register cli/benchmarks --runs 5` on a release build,
+against the stack VM in fast mode, fastest of five runs, from the median of
+five invocations:
+
+| benchmark       | stack instrs | register instrs | stack  | register | speedup |
+|-----------------|-------------:|----------------:|-------:|---------:|--------:|
+| binary_trees    |   12,739,314 |       7,700,309 | 0.289s |   0.266s |   1.10x |
+| fib             |   32,310,449 |      22,886,569 | 0.492s |   0.551s |   0.87x |
+| string_equality |   17,400,029 |      13,400,027 | 0.297s |   0.248s |   1.20x |
+| zoo             |   13,500,038 |      12,500,030 | 0.302s |   0.327s |   0.92x |
+
+Repeated invocations vary by about 0.1x. fib is slower despite running 29%
+fewer instructions because it is almost all calls, and every call here clones
+the callee, looks its code up by pointer in a hash map and leaves the dispatch
+loop to push the frame. zoo is almost all field reads, which the stack VM
+finds through its inline caches and the register VM looks up by name.
+
+```
+use rulox::{register, vm::VmBuilder};
+
+let code = register::compile("var a = 1; var b = 2; print a + b;").unwrap();
+let mut vm = VmBuilder::new().out(Vec::new()).build_register();
+vm.interpret(&code).unwrap();
+assert_eq!(vm.into_writers().0, b"3\n");
+```
+*/
+
+mod compiler;
+mod vm;
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use crate::{function::Function, value::Value};
+
+pub use self::{
+    compiler::{compile, compile_with_options, compile_with_warnings},
+    vm::RegisterVm,
+};
+
+/// The index of a register in the frame of a function. The first holds the
+/// function being called, followed by its parameters.
+pub type Register = u16;
+
+/// A register instruction, naming the registers it reads and writes.
+///
+/// Jump targets are indices of instructions in the same function.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Instruction {
+    /// Loads a constant of the function.
+    LoadConstant {
+        dst: Register,
+        constant: usize,
+    },
+    LoadNil {
+        dst: Register,
+    },
+    LoadBool {
+        dst: Register,
+        value: bool,
+    },
+    Move {
+        dst: Register,
+        src: Register,
+    },
+
+    /// Loads the global named by the constant.
+    GetGlobal {
+        dst: Register,
+        name: usize,
+    },
+    DefineGlobal {
+        name: usize,
+        src: Register,
+    },
+    SetGlobal {
+        name: usize,
+        src: Register,
+    },
+
+    GetProperty {
+        dst: Register,
+        object: Register,
+        name: usize,
+    },
+    SetProperty {
+        object: Register,
+        name: usize,
+        src: Register,
+    },
+
+    Equal {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Greater {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Less {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Add {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Subtract {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Multiply {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Divide {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Not {
+        dst: Register,
+        src: Register,
+    },
+    Negate {
+        dst: Register,
+        src: Register,
+    },
+
+    Print {
+        src: Register,
+    },
+
+    Jump {
+        target: usize,
+    },
+    JumpIfFalse {
+        condition: Register,
+        target: usize,
+    },
+    JumpIfTrue {
+        condition: Register,
+        target: usize,
+    },
+
+    /// Calls the value in `callee` with the arguments in the registers after
+    /// it, storing the result in `callee`.
+    Call {
+        callee: Register,
+        arg_count: u8,
+    },
+
+    /// Loads a function declared in this one, by its index in
+    /// [`Code::functions`].
+    Function {
+        dst: Register,
+        function: usize,
+    },
+
+    Return {
+        src: Register,
+    },
+    ReturnNil,
+}
+
+impl Instruction {
+    /// Changes the register the instruction writes to, for instructions
+    /// compiled before it was known where their result goes.
+    fn set_dst(&mut self, register: Register) {
+        match self {
+            Instruction::LoadConstant { dst, .. }
+            | Instruction::LoadNil { dst }
+            | Instruction::LoadBool { dst, .. }
+            | Instruction::Move { dst, .. }
+            | Instruction::GetGlobal { dst, .. }
+            | Instruction::GetProperty { dst, .. }
+            | Instruction::Equal { dst, .. }
+            | Instruction::Greater { dst, .. }
+            | Instruction::Less { dst, .. }
+            | Instruction::Add { dst, .. }
+            | Instruction::Subtract { dst, .. }
+            | Instruction::Multiply { dst, .. }
+            | Instruction::Divide { dst, .. }
+            | Instruction::Not { dst, .. }
+            | Instruction::Negate { dst, .. }
+            | Instruction::Function { dst, .. } => *dst = register,
+            _ => unreachable!("{:?} does not write a register", self),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let binary = |f: &mut Formatter, name: &str, dst, left, right| {
+            write!(f, "{:<16} r{}, r{}, r{}", name, dst, left, right)
+        };
+
+        match *self {
+            Instruction::LoadConstant { dst, constant } => {
+                write!(f, "{:<16} r{}, k{}", "LOAD_CONSTANT", dst, constant)
+            }
+            Instruction::LoadNil { dst } => write!(f, "{:<16} r{}", "LOAD_NIL", dst),
+            Instruction::LoadBool { dst, value } => {
+                write!(f, "{:<16} r{}, {}", "LOAD_BOOL", dst, value)
+            }
+            Instruction::Move { dst, src } => write!(f, "{:<16} r{}, r{}", "MOVE", dst, src),
+            Instruction::GetGlobal { dst, name } => {
+                write!(f, "{:<16} r{}, k{}", "GET_GLOBAL", dst, name)
+            }
+            Instruction::DefineGlobal { name, src } => {
+                write!(f, "{:<16} k{}, r{}", "DEFINE_GLOBAL", name, src)
+            }
+            Instruction::SetGlobal { name, src } => {
+                write!(f, "{:<16} k{}, r{}", "SET_GLOBAL", name, src)
+            }
+            Instruction::GetProperty { dst, object, name } => {
+                write!(f, "{:<16} r{}, r{}, k{}", "GET_PROPERTY", dst, object, name)
+            }
+            Instruction::SetProperty { object, name, src } => {
+                write!(f, "{:<16} r{}, k{}, r{}", "SET_PROPERTY", object, name, src)
+            }
+            Instruction::Equal { dst, left, right } => binary(f, "EQUAL", dst, left, right),
+            Instruction::Greater { dst, left, right } => binary(f, "GREATER", dst, left, right),
+            Instruction::Less { dst, left, right } => binary(f, "LESS", dst, left, right),
+            Instruction::Add { dst, left, right } => binary(f, "ADD", dst, left, right),
+            Instruction::Subtract { dst, left, right } => binary(f, "SUBTRACT", dst, left, right),
+            Instruction::Multiply { dst, left, right } => binary(f, "MULTIPLY", dst, left, right),
+            Instruction::Divide { dst, left, right } => binary(f, "DIVIDE", dst, left, right),
+            Instruction::Not { dst, src } => write!(f, "{:<16} r{}, r{}", "NOT", dst, src),
+            Instruction::Negate { dst, src } => write!(f, "{:<16} r{}, r{}", "NEGATE", dst, src),
+            Instruction::Print { src } => write!(f, "{:<16} r{}", "PRINT", src),
+            Instruction::Jump { target } => write!(f, "{:<16} -> {}", "JUMP", target),
+            Instruction::JumpIfFalse { condition, target } => {
+                write!(f, "{:<16} r{} -> {}", "JUMP_IF_FALSE", condition, target)
+            }
+            Instruction::JumpIfTrue { condition, target } => {
+                write!(f, "{:<16} r{} -> {}", "JUMP_IF_TRUE", condition, target)
+            }
+            Instruction::Call { callee, arg_count } => {
+                write!(f, "{:<16} r{}, {}", "CALL", callee, arg_count)
+            }
+            Instruction::Function { dst, function } => {
+                write!(f, "{:<16} r{}, f{}", "FUNCTION", dst, function)
+            }
+            Instruction::Return { src } => write!(f, "{:<16} r{}", "RETURN", src),
+            Instruction::ReturnNil => write!(f, "RETURN_NIL"),
+        }
+    }
+}
+
+/// A function compiled to register instructions.
+#[derive(Clone, Debug)]
+pub struct Code {
+    /// The function as seen by Lox code, `None` for the top-level script.
+    pub(crate) function: Option<Arc<Function>>,
+
+    pub(crate) instructions: Vec<Instruction>,
+
+    /// The source line of each instruction.
+    pub(crate) lines: Vec<usize>,
+
+    pub constants: Vec<Value>,
+
+    /// The functions declared in this one, loaded by [`Instruction::Function`].
+    pub(crate) functions: Vec<Arc<Code>>,
+
+    /// The number of registers a call to the function needs.
+    pub(crate) registers: usize,
+}
+
+impl Code {
+    fn new() -> Self {
+        Self {
+            function: None,
+            instructions: Vec::new(),
+            lines: Vec::new(),
+            constants: Vec::new(),
+            functions: Vec::new(),
+            registers: 1,
+        }
+    }
+
+    /// Returns the name of the function, or `script`.
+    pub fn name(&self) -> &str {
+        self.function
+            .as_ref()
+            .map_or("script", |function| &function.name)
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Returns the source line of the instruction at the index.
+    pub fn line(&self, index: usize) -> Option<usize> {
+        self.lines.get(index).copied()
+    }
+
+    /// Returns the functions declared in this one, by their index in
+    /// [`Instruction::Function`].
+    pub fn functions(&self) -> &[Arc<Code>] {
+        &self.functions
+    }
+
+    /// Returns the number of registers a call to the function needs, including
+    /// the one holding the function itself.
+    pub fn registers(&self) -> usize {
+        self.registers
+    }
+
+    /// Disassembles the code to stderr, followed by the functions declared in it.
+    pub fn disassemble(&self) {
+        eprintln!("== {} ({} registers) ==", self.name(), self.registers);
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            eprint!("{:04} ", index);
+            if index > 0 && self.lines[index] == self.lines[index - 1] {
+                eprint!("   | ");
+            } else {
+                eprint!("{:4} ", self.lines[index]);
+            }
+
+            eprintln!("{}", instruction);
+        }
+
+        for function in &self.functions {
+            function.disassemble();
+        }
+    }
+}
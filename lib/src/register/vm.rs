@@ -0,0 +1,487 @@
+/*!
+The virtual machine running register [`Code`].
+
+All frames share one vector of registers. A call reuses the callee's register
+and the arguments after it as the first registers of the new frame, so
+arguments are never copied, and the result is returned in the callee's
+register.
+*/
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    sync::Arc,
+};
+
+use crate::{
+    class::{self, Class, LoxClass},
+    function::Function,
+    native::{Native, NativeFn, Runtime, VmContext},
+    stdlib::Capability,
+    value::Value,
+    vm::{self, FRAMES_MAX, InterpretResult, RuntimeError},
+};
+
+use super::{Code, Instruction, Register};
+
+/// A call in progress.
+struct Frame {
+    code: Arc<Code>,
+
+    /// The index of the next instruction to execute, saved while the frame
+    /// is not running.
+    pc: usize,
+
+    /// The index of the instruction being executed, which for a frame waiting
+    /// on a call is the call itself. Used to report where a runtime error
+    /// occurred.
+    instruction: usize,
+
+    /// The index of the first register of the frame, which holds the function
+    /// being called.
+    base: usize,
+}
+
+/// Why a frame stopped executing.
+enum Exit {
+    /// A Lox function was called, and its frame pushed.
+    Call,
+
+    Return(Value),
+}
+
+/// A virtual machine running code compiled by the register backend, writing
+/// the program's output to `O` and errors to `E`.
+///
+/// Create one with [`VmBuilder::build_register`][crate::vm::VmBuilder::build_register]
+/// to have the standard library defined.
+pub struct RegisterVm<O: Write, E: Write> {
+    registers: Vec<Value>,
+    frames: Vec<Frame>,
+    pub(crate) globals: HashMap<Arc<str>, Value>,
+
+    /// The code of the functions declared so far, keyed by their address.
+    /// The code keeps the functions alive so that the addresses are never
+    /// reused.
+    bodies: HashMap<*const Function, Arc<Code>>,
+
+    /// The most calls that may be in progress at once, including the script.
+    pub(crate) frame_limit: usize,
+
+    /// What the standard library is allowed to do.
+    pub(crate) capabilities: Vec<Capability>,
+
+    /// What `readLine()` reads from, stdin if `None`.
+    pub(crate) input: Option<Box<dyn BufRead + Send>>,
+
+    /// The number of instructions executed so far.
+    instruction_count: u64,
+
+    out: O,
+    err: E,
+}
+
+impl<O: Write, E: Write> RegisterVm<O, E> {
+    /// Creates a VM without the standard library.
+    pub fn new(out: O, err: E) -> Self {
+        Self {
+            registers: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            bodies: HashMap::new(),
+            frame_limit: FRAMES_MAX,
+            capabilities: Vec::new(),
+            input: None,
+            instruction_count: 0,
+            out,
+            err,
+        }
+    }
+
+    /// Runs the compiled script, reporting runtime errors the same way the
+    /// stack VM does.
+    pub fn interpret(&mut self, code: &Code) -> InterpretResult {
+        self.registers.clear();
+        self.frames.clear();
+
+        self.registers.resize(code.registers, Value::Nil);
+        self.frames.push(Frame {
+            code: Arc::new(code.clone()),
+            pc: 0,
+            instruction: 0,
+            base: 0,
+        });
+
+        let result = self.run(0);
+        self.registers.clear();
+
+        match result {
+            Ok(_) => {
+                self.frames.clear();
+                Ok(())
+            }
+            Err(error) => {
+                self.report_runtime_error(&error)?;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Returns the number of instructions executed since the VM was created.
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// Consumes the VM, returning the output and error writers.
+    pub fn into_writers(self) -> (O, E) {
+        (self.out, self.err)
+    }
+
+    /// Defines a global function implemented in Rust, replacing any existing
+    /// global with the same name.
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        let native = Native::new(name, arity, function);
+        self.globals
+            .insert(name.into(), Value::Native(Arc::new(native)));
+    }
+
+    /// Defines a global class for the Rust type `T`, replacing any existing
+    /// global with the same name, and returns it so the host can create instances.
+    pub fn define_class<T: LoxClass>(&mut self) -> Arc<Class> {
+        let class = Arc::new(Class::new::<T>());
+        self.globals
+            .insert(T::NAME.into(), Value::Class(class.clone()));
+        class
+    }
+
+    /// Returns the global variables currently defined, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value))
+    }
+
+    /// Calls a function on behalf of a native function, leaving the
+    /// registers as they were.
+    fn call_nested(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let start = self.registers.len();
+        let frames = self.frames.len();
+
+        self.registers.push(callee.clone());
+        self.registers.extend_from_slice(args);
+
+        let result = self.call_value(start, args.len()).and_then(|called| {
+            if called {
+                self.run(frames)
+            } else {
+                Ok(self.registers[start].clone())
+            }
+        });
+
+        self.registers.truncate(start);
+        result
+    }
+
+    /// Executes frames until the one at index `depth` returns, returning the
+    /// value it returns.
+    ///
+    /// Frames are left in place if execution fails, for the stack trace.
+    fn run(&mut self, depth: usize) -> Result<Value, RuntimeError> {
+        loop {
+            let index = self.frames.len() - 1;
+            let frame = &self.frames[index];
+            let code = frame.code.clone();
+            let base = frame.base;
+            let mut pc = frame.pc;
+
+            let exit = self.execute(&code, base, &mut pc);
+
+            let frame = &mut self.frames[index];
+            frame.pc = pc;
+            frame.instruction = pc.saturating_sub(1);
+
+            match exit? {
+                Exit::Call => {}
+                Exit::Return(value) => {
+                    self.frames.pop();
+                    if index == depth {
+                        return Ok(value);
+                    }
+
+                    // The result replaces the callee in the caller's registers.
+                    let caller = self.frames.last().expect("the caller is still running");
+                    let end = caller.base + caller.code.registers;
+                    self.registers.truncate(base + 1);
+                    self.registers[base] = value;
+                    self.registers.resize(end, Value::Nil);
+                }
+            }
+        }
+    }
+
+    /// Executes the code of the frame with its registers starting at `base`,
+    /// from the instruction at `pc`, until it calls a Lox function or returns.
+    fn execute(&mut self, code: &Code, base: usize, pc: &mut usize) -> Result<Exit, RuntimeError> {
+        let register = |register: Register| base + register as usize;
+
+        loop {
+            let instruction = *code
+                .instructions
+                .get(*pc)
+                .ok_or(RuntimeError::IpOutOfRange)?;
+            *pc += 1;
+            self.instruction_count += 1;
+
+            match instruction {
+                Instruction::LoadConstant { dst, constant } => {
+                    self.registers[register(dst)] = code.constants[constant].clone();
+                }
+                Instruction::LoadNil { dst } => self.registers[register(dst)] = Value::Nil,
+                Instruction::LoadBool { dst, value } => {
+                    self.registers[register(dst)] = Value::Bool(value);
+                }
+                Instruction::Move { dst, src } => {
+                    self.registers[register(dst)] = self.registers[register(src)].clone();
+                }
+
+                Instruction::GetGlobal { dst, name } => {
+                    let name = constant_name(code, name);
+                    let value = self
+                        .globals
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))?;
+                    self.registers[register(dst)] = value;
+                }
+                Instruction::DefineGlobal { name, src } => {
+                    let Value::String(name) = &code.constants[name] else {
+                        unreachable!("global names are strings");
+                    };
+                    let value = self.registers[register(src)].clone();
+                    self.globals.insert(name.clone(), value);
+                }
+                Instruction::SetGlobal { name, src } => {
+                    let name = constant_name(code, name);
+                    let Some(global) = self.globals.get_mut(name) else {
+                        return Err(RuntimeError::UndefinedVariable(name.to_string()));
+                    };
+                    *global = self.registers[register(src)].clone();
+                }
+
+                Instruction::GetProperty { dst, object, name } => {
+                    let name = constant_name(code, name);
+                    let value = class::get_property(&self.registers[register(object)], name)?;
+                    self.registers[register(dst)] = value;
+                }
+                Instruction::SetProperty { object, name, src } => {
+                    let name = constant_name(code, name);
+                    let value = self.registers[register(src)].clone();
+                    class::set_property(&self.registers[register(object)], name, value)?;
+                }
+
+                Instruction::Equal { dst, left, right } => {
+                    let equal = self.registers[register(left)] == self.registers[register(right)];
+                    self.registers[register(dst)] = Value::Bool(equal);
+                }
+                Instruction::Greater { dst, left, right } => {
+                    let (left, right) = self.numbers(register(left), register(right))?;
+                    self.registers[register(dst)] = Value::Bool(left > right);
+                }
+                Instruction::Less { dst, left, right } => {
+                    let (left, right) = self.numbers(register(left), register(right))?;
+                    self.registers[register(dst)] = Value::Bool(left < right);
+                }
+                Instruction::Add { dst, left, right } => {
+                    let result = match (
+                        &self.registers[register(left)],
+                        &self.registers[register(right)],
+                    ) {
+                        (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                        (Value::String(left), Value::String(right)) => {
+                            Value::String(format!("{}{}", left, right).into())
+                        }
+                        _ => return Err(RuntimeError::OperandsMustBeNumbersOrStrings),
+                    };
+                    self.registers[register(dst)] = result;
+                }
+                Instruction::Subtract { dst, left, right } => {
+                    let (left, right) = self.numbers(register(left), register(right))?;
+                    self.registers[register(dst)] = Value::Number(left - right);
+                }
+                Instruction::Multiply { dst, left, right } => {
+                    let (left, right) = self.numbers(register(left), register(right))?;
+                    self.registers[register(dst)] = Value::Number(left * right);
+                }
+                Instruction::Divide { dst, left, right } => {
+                    let (left, right) = self.numbers(register(left), register(right))?;
+                    self.registers[register(dst)] = Value::Number(left / right);
+                }
+                Instruction::Not { dst, src } => {
+                    let falsey = self.registers[register(src)].is_falsey();
+                    self.registers[register(dst)] = Value::Bool(falsey);
+                }
+                Instruction::Negate { dst, src } => {
+                    let Value::Number(value) = self.registers[register(src)] else {
+                        return Err(RuntimeError::OperandMustBeNumber);
+                    };
+                    self.registers[register(dst)] = Value::Number(-value);
+                }
+
+                Instruction::Print { src } => {
+                    writeln!(self.out, "{}", self.registers[register(src)])?;
+                }
+
+                Instruction::Jump { target } => *pc = target,
+                Instruction::JumpIfFalse { condition, target } => {
+                    if self.registers[register(condition)].is_falsey() {
+                        *pc = target;
+                    }
+                }
+                Instruction::JumpIfTrue { condition, target } => {
+                    if !self.registers[register(condition)].is_falsey() {
+                        *pc = target;
+                    }
+                }
+
+                Instruction::Call { callee, arg_count } => {
+                    if self.call_value(register(callee), arg_count as usize)? {
+                        return Ok(Exit::Call);
+                    }
+                }
+
+                Instruction::Function { dst, function } => {
+                    let code = &code.functions[function];
+                    let function = code
+                        .function
+                        .clone()
+                        .expect("declared functions have a name");
+                    self.bodies
+                        .entry(Arc::as_ptr(&function))
+                        .or_insert_with(|| code.clone());
+                    self.registers[register(dst)] = Value::Function(function);
+                }
+
+                Instruction::Return { src } => {
+                    return Ok(Exit::Return(self.registers[register(src)].clone()));
+                }
+                Instruction::ReturnNil => return Ok(Exit::Return(Value::Nil)),
+            }
+        }
+    }
+
+    /// Calls the value in the register with the arguments in the registers
+    /// after it.
+    ///
+    /// Functions implemented in Rust run straight away, leaving their result
+    /// in the callee's register. Returns whether a frame was pushed for a Lox
+    /// function instead.
+    fn call_value(&mut self, callee: usize, arg_count: usize) -> Result<bool, RuntimeError> {
+        let function = self.registers[callee].clone();
+        let expected = vm::arity(&function).ok_or(RuntimeError::NotCallable)?;
+        if arg_count != expected {
+            return Err(RuntimeError::WrongArity {
+                expected,
+                got: arg_count,
+            });
+        }
+
+        if let Value::Function(function) = &function {
+            let Some(code) = self.bodies.get(&Arc::as_ptr(function)) else {
+                // Functions compiled for the stack VM cannot be run.
+                return Err(RuntimeError::NotCallable);
+            };
+
+            if self.frames.len() == self.frame_limit {
+                return Err(RuntimeError::StackOverflow);
+            }
+
+            // The caller's registers past the arguments are no longer in use.
+            let code = code.clone();
+            self.registers.truncate(callee + 1 + arg_count);
+            self.registers.resize(callee + code.registers, Value::Nil);
+            self.frames.push(Frame {
+                code,
+                pc: 0,
+                instruction: 0,
+                base: callee,
+            });
+
+            return Ok(true);
+        }
+
+        let args = self.registers[callee + 1..=callee + arg_count].to_vec();
+        let frames = self.frames.len();
+        let result = vm::call_host(&mut VmContext::new(self), &function, &args)?;
+
+        // A failed call back into Lox leaves its frames behind for the stack
+        // trace, which is not needed if the native function recovered.
+        self.frames.truncate(frames);
+        self.registers[callee] = result;
+
+        Ok(false)
+    }
+
+    /// Returns the numbers in the registers, the operands of a numeric binary
+    /// operator.
+    fn numbers(&self, left: usize, right: usize) -> Result<(f64, f64), RuntimeError> {
+        match (&self.registers[left], &self.registers[right]) {
+            (Value::Number(left), Value::Number(right)) => Ok((*left, *right)),
+            _ => Err(RuntimeError::OperandsMustBeNumbers),
+        }
+    }
+
+    /// Reports a runtime error along with the call stack it occurred in.
+    fn report_runtime_error(&mut self, error: &RuntimeError) -> Result<(), io::Error> {
+        writeln!(self.err, "{}", error)?;
+        for frame in self.frames.drain(..).rev() {
+            match frame.code.line(frame.instruction) {
+                Some(line) => write!(self.err, "[line {}] in ", line)?,
+                None => write!(self.err, "[instruction {}] in ", frame.instruction)?,
+            }
+
+            match &frame.code.function {
+                Some(function) => writeln!(self.err, "{}()", function.name)?,
+                None => writeln!(self.err, "script")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: Write, E: Write> Runtime for RegisterVm<O, E> {
+    fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.into(), value);
+    }
+
+    fn out(&mut self) -> &mut dyn Write {
+        &mut self.out
+    }
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(line),
+            None => io::stdin().lock().read_line(line),
+        }
+    }
+
+    fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.call_nested(callee, args)
+    }
+
+    fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+}
+
+/// Returns the name in the constant of the code.
+fn constant_name(code: &Code, index: usize) -> &str {
+    match &code.constants[index] {
+        Value::String(name) => name,
+        value => unreachable!("name constant {} is not a string", value),
+    }
+}
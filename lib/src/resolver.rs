@@ -0,0 +1,436 @@
+/*!
+Resolves the names in a [`Program`] to the variables they refer to.
+
+Local variables live in stack slots, numbered in the order they are declared
+in their function, starting at 1 since the function being called takes slot 0.
+Any name that is not a local in the enclosing function refers to a global,
+which is looked up by name when the code runs. There are no closures, so
+referring to a local of an enclosing function is an error.
+
+Along the way the resolver reports the errors that depend on scopes, such as
+declaring the same local twice or returning from top-level code, and warns
+about local variables that are never read.
+*/
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    ast::{Expr, ExprKind, Function, Identifier, Position, Program, Span, Stmt, StmtKind},
+    parser::{self, Diagnostic},
+};
+
+/// The maximum number of local variables in scope at once in a function,
+/// including the slot reserved for the function itself.
+const MAX_LOCALS: usize = 256;
+
+/// Resolves the names in the given program.
+pub fn resolve(program: &Program) -> Resolution {
+    let mut resolver = Resolver::new();
+
+    for statement in &program.statements {
+        resolver.declaration(statement);
+    }
+
+    let mut resolution = resolver.resolution;
+    resolution
+        .warnings
+        .sort_by_key(|warning| warning.span.start);
+    resolution
+}
+
+/// What the names in a program refer to, and the problems found resolving them.
+#[derive(Clone, Debug, Default)]
+pub struct Resolution {
+    /// The slot of each local variable that is read or assigned, by where its name is.
+    slots: HashMap<Position, u8>,
+
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Resolution {
+    /// Returns the stack slot of the local variable the name refers to, or
+    /// `None` if it refers to a global.
+    pub fn slot(&self, name: &Identifier) -> Option<u8> {
+        self.slots.get(&name.span.start).copied()
+    }
+}
+
+/// A likely mistake in code that can still run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning: {}",
+            self.span.start.line, self.message
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FunctionKind {
+    Function,
+    Script,
+}
+
+struct Local<'a> {
+    name: &'a Identifier,
+
+    /// The depth of the scope the local was declared in, or `None` while its
+    /// initializer is being resolved.
+    depth: Option<usize>,
+
+    /// Whether the local is read anywhere, parameters counting as read.
+    used: bool,
+}
+
+/// The scopes of a function being resolved.
+struct FunctionScope<'a> {
+    kind: FunctionKind,
+
+    /// The locals in scope, in the order of their slots, not counting slot 0.
+    locals: Vec<Local<'a>>,
+
+    scope_depth: usize,
+}
+
+impl FunctionScope<'_> {
+    fn new(kind: FunctionKind) -> Self {
+        Self {
+            kind,
+            locals: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+struct Resolver<'a> {
+    /// The functions being resolved, with the innermost last.
+    functions: Vec<FunctionScope<'a>>,
+
+    resolution: Resolution,
+}
+
+impl<'a> Resolver<'a> {
+    fn new() -> Self {
+        Self {
+            functions: vec![FunctionScope::new(FunctionKind::Script)],
+            resolution: Resolution::default(),
+        }
+    }
+
+    fn function(&mut self) -> &mut FunctionScope<'a> {
+        self.functions
+            .last_mut()
+            .expect("there is always a function being resolved")
+    }
+
+    fn begin_scope(&mut self) {
+        self.function().scope_depth += 1;
+    }
+
+    /// Ends the innermost scope, warning about its locals that were never read.
+    fn end_scope(&mut self) {
+        let function = self.function();
+        function.scope_depth -= 1;
+
+        let depth = function.scope_depth;
+        let in_scope = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|local_depth| local_depth > depth))
+            .count();
+
+        let remaining = function.locals.len() - in_scope;
+        let locals = function.locals.split_off(remaining);
+        self.warn_unused(locals);
+    }
+
+    fn warn_unused(&mut self, locals: Vec<Local>) {
+        let unused = locals
+            .into_iter()
+            .filter(|local| !local.used && !local.name.name.starts_with('_'))
+            .map(|local| Warning {
+                span: local.name.span,
+                message: format!("Local variable '{}' is never used.", local.name.name),
+            });
+
+        self.resolution.warnings.extend(unused);
+    }
+
+    /// Resolves a statement that the parser may recover from errors after.
+    fn declaration(&mut self, stmt: &'a Stmt) {
+        self.statement(stmt);
+        parser::end_declaration(&mut self.resolution.errors, stmt.span.start);
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Expression(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Var { name, initializer } => {
+                self.declare_variable(name);
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.mark_initialized();
+            }
+            StmtKind::Function(function) => {
+                self.declare_variable(&function.name);
+                // The function may refer to itself.
+                self.mark_initialized();
+                self.function_body(function);
+            }
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.declaration(statement);
+                }
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+                self.statement(body);
+                self.end_scope();
+            }
+            StmtKind::Return(value) => {
+                if self.function().kind == FunctionKind::Script {
+                    self.error(
+                        stmt.span.start,
+                        "return",
+                        "Can't return from top-level code.",
+                    );
+                }
+
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StmtKind::Error => {}
+        }
+    }
+
+    fn function_body(&mut self, function: &'a Function) {
+        self.functions
+            .push(FunctionScope::new(FunctionKind::Function));
+        self.begin_scope();
+
+        for param in &function.params {
+            self.declare_variable(param);
+            self.mark_initialized();
+
+            // Functions are often passed arguments they have no use for.
+            if let Some(local) = self.function().locals.last_mut() {
+                local.used = true;
+            }
+        }
+
+        for statement in &function.body {
+            self.declaration(statement);
+        }
+
+        // The scope is never ended, returning discards the whole frame.
+        let scope = self.functions.pop().expect("the function was pushed");
+        self.warn_unused(scope.locals);
+    }
+
+    fn expression(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) | ExprKind::Error => {}
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Variable(name) => self.resolve_local(name, true),
+            ExprKind::Assign { name, value } => {
+                self.resolve_local(name, false);
+                self.expression(value);
+            }
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Get { object, .. } => self.expression(object),
+            ExprKind::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            // There are no classes to use them in.
+            ExprKind::This => {
+                self.error(
+                    expr.span.start,
+                    "this",
+                    "Can't use 'this' outside of a class.",
+                );
+            }
+            ExprKind::Super { .. } => {
+                self.error(
+                    expr.span.start,
+                    "super",
+                    "Can't use 'super' outside of a class.",
+                );
+            }
+        }
+    }
+
+    /// Records the slot of the local variable with the given name, if there
+    /// is one in scope.
+    fn resolve_local(&mut self, name: &Identifier, read: bool) {
+        let function = self.function();
+        let Some((index, local)) = function
+            .locals
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.name == name.name)
+        else {
+            self.resolve_enclosing(name);
+            return;
+        };
+
+        local.used |= read;
+        let initialized = local.depth.is_some();
+
+        if !initialized {
+            self.error(
+                name.span.start,
+                &name.name,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+
+        self.resolution
+            .slots
+            .insert(name.span.start, index as u8 + 1);
+    }
+
+    /// Reports a name that is a local of an enclosing function, marking that
+    /// local as used so it is not also warned about.
+    fn resolve_enclosing(&mut self, name: &Identifier) {
+        let enclosing = self
+            .functions
+            .iter_mut()
+            .rev()
+            .skip(1)
+            .find_map(|function| {
+                function
+                    .locals
+                    .iter_mut()
+                    .rev()
+                    .find(|local| local.name.name == name.name)
+            });
+
+        if let Some(local) = enclosing {
+            local.used = true;
+            self.error(
+                name.span.start,
+                &name.name,
+                "Can't refer to a local variable of an enclosing function.",
+            );
+        }
+    }
+
+    /// Declares a local variable, unless at the top level.
+    fn declare_variable(&mut self, name: &'a Identifier) {
+        let function = self.function();
+        let depth = function.scope_depth;
+
+        if depth == 0 {
+            return;
+        }
+
+        let duplicate = function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|local_depth| local_depth >= depth))
+            .any(|local| local.name.name == name.name);
+
+        if duplicate {
+            self.error(
+                name.span.start,
+                &name.name,
+                "Already a variable with this name in this scope.",
+            );
+        }
+
+        if self.function().locals.len() + 1 == MAX_LOCALS {
+            self.error(
+                name.span.start,
+                &name.name,
+                "Too many local variables in function.",
+            );
+            return;
+        }
+
+        self.function().locals.push(Local {
+            name,
+            depth: None,
+            used: false,
+        });
+    }
+
+    /// Marks the most recently declared local as ready for use.
+    fn mark_initialized(&mut self) {
+        let function = self.function();
+        if function.scope_depth == 0 {
+            return;
+        }
+
+        let depth = function.scope_depth;
+        if let Some(local) = function.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn error(&mut self, position: Position, lexeme: &str, message: &str) {
+        let diagnostic = Diagnostic::new(position, lexeme, message);
+        self.resolution.errors.push(diagnostic);
+    }
+}
@@ -1,8 +1,20 @@
+use crate::ast::Position;
+
+#[derive(Clone)]
 pub struct Scanner<'a> {
     source: &'a str,
+
+    /// The byte offsets of the token being scanned and of the next character.
     start: usize,
     current: usize,
     line: i32,
+
+    /// The column of the next character, counting characters rather than bytes.
+    current_column: i32,
+
+    /// The line and column of the first character of the token being scanned.
+    start_line: i32,
+    column: i32,
 }
 
 impl<'a> Scanner<'a> {
@@ -12,12 +24,20 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            current_column: 1,
+            start_line: 1,
+            column: 1,
         }
     }
 
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+    pub fn scan_token(&mut self) -> Token<'a> {
+        if let Err(token) = self.skip_whitespace() {
+            return token;
+        }
+
         self.start = self.current;
+        self.start_line = self.line;
+        self.column = self.current_column;
 
         let c = if let Some(c) = self.advance() {
             c
@@ -70,87 +90,137 @@ impl<'a> Scanner<'a> {
                 }
             }
             '"' => self.string(),
-            '0'..='9' => self.number(),
+            c @ '0'..='9' => self.number(c),
             'a'..='z' | 'A'..='Z' | '_' => self.identifier(),
             _ => self.error_token("Unexpected character."),
         }
     }
 
+    fn rest(&self) -> &'a str {
+        &self.source[self.current..]
+    }
+
     fn peek(&self) -> Option<char> {
-        self.source.chars().nth(self.current)
+        self.rest().chars().next()
     }
 
     fn peek_next(&self) -> Option<char> {
-        self.source.chars().nth(self.current + 1)
+        self.rest().chars().nth(1)
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.current += 1;
-        self.source.chars().nth(self.current - 1)
+        let c = self.peek()?;
+        self.current += c.len_utf8();
+        self.current_column += 1;
+        Some(c)
     }
 
-    fn match_char(&mut self, c: char) -> bool {
-        if let Some(current) = self.source.chars().next() {
-            if current != c {
-                return false;
-            }
-
-            self.current += 1;
+    /// Consumes a newline character and moves on to the next line.
+    fn newline(&mut self) {
+        self.advance();
+        self.line += 1;
+        self.current_column = 1;
+    }
 
-            true
-        } else {
-            false
+    fn match_char(&mut self, c: char) -> bool {
+        if self.peek() != Some(c) {
+            return false;
         }
+
+        self.advance();
+        true
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace and comments.
+    ///
+    /// Returns an error token if a block comment is left unterminated.
+    fn skip_whitespace(&mut self) -> Result<(), Token<'a>> {
         loop {
             match self.peek() {
                 Some(' ') | Some('\r') | Some('\t') => {
                     self.advance();
                 }
                 Some('\n') => {
-                    self.line += 1;
-                    self.advance();
+                    self.newline();
                 }
-                Some('/') => {
-                    if let Some('/') = self.peek_next() {
-                        loop {
-                            match self.peek() {
-                                Some('\n') => break,
-                                Some(_) => {
-                                    self.advance();
-                                }
-                                None => break,
+                Some('/') => match self.peek_next() {
+                    Some('/') => loop {
+                        match self.peek() {
+                            Some('\n') => break,
+                            Some(_) => {
+                                self.advance();
                             }
+                            None => break,
                         }
-                    } else {
-                        return;
+                    },
+                    Some('*') => self.block_comment()?,
+                    _ => return Ok(()),
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Skips a (possibly nested) `/* ... */` block comment.
+    fn block_comment(&mut self) -> Result<(), Token<'a>> {
+        let start_line = self.line;
+        let start_column = self.current_column;
+        let mut depth = 0;
+
+        loop {
+            match (self.peek(), self.peek_next()) {
+                (Some('/'), Some('*')) => {
+                    self.advance();
+                    self.advance();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.advance();
+                    self.advance();
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Ok(());
                     }
                 }
-                _ => return,
+                (Some('\n'), _) => {
+                    self.newline();
+                }
+                (Some(_), _) => {
+                    self.advance();
+                }
+                (None, _) => {
+                    return Err(Token {
+                        token_type: TokenType::Error,
+                        lexeme: "Unterminated block comment.",
+                        line: start_line,
+                        column: start_column,
+                    });
+                }
             }
         }
     }
 
-    fn make_token(&self, token_type: TokenType) -> Token {
+    fn make_token(&self, token_type: TokenType) -> Token<'a> {
         let lexeme = &self.source[self.start..self.current];
         Token {
             token_type,
             lexeme,
-            line: self.line,
+            line: self.start_line,
+            column: self.column,
         }
     }
 
-    fn make_eof(&self) -> Token {
+    fn make_eof(&self) -> Token<'a> {
         Token {
             token_type: TokenType::EOF,
             lexeme: "\0",
             line: self.line,
+            column: self.column,
         }
     }
 
-    fn string(&mut self) -> Token {
+    fn string(&mut self) -> Token<'a> {
         loop {
             match self.peek() {
                 Some('"') => {
@@ -158,8 +228,7 @@ impl<'a> Scanner<'a> {
                     break;
                 }
                 Some('\n') => {
-                    self.line += 1;
-                    self.advance();
+                    self.newline();
                 }
                 Some(_) => {
                     self.advance();
@@ -173,9 +242,23 @@ impl<'a> Scanner<'a> {
         self.make_token(TokenType::String)
     }
 
-    fn number(&mut self) -> Token {
-        while let Some('0'..='9') = self.peek() {
-            self.advance();
+    fn number(&mut self, first: char) -> Token<'a> {
+        if first == '0' {
+            let radix = match self.peek() {
+                Some('x') | Some('X') => Some(Radix::Hexadecimal),
+                Some('b') | Some('B') => Some(Radix::Binary),
+                Some('o') | Some('O') => Some(Radix::Octal),
+                _ => None,
+            };
+
+            if let Some(radix) = radix {
+                self.advance();
+                return self.radix_number(radix);
+            }
+        }
+
+        if let Err(message) = self.digits(10, true) {
+            return self.number_error(message);
         }
 
         let has_fraction = matches!(self.peek(), Some('.'));
@@ -184,16 +267,91 @@ impl<'a> Scanner<'a> {
         if has_fraction && next_digit {
             self.advance();
 
-            while let Some('0'..='9') = self.peek() {
+            if let Err(message) = self.digits(10, false) {
+                return self.number_error(message);
+            }
+        }
+
+        if let Some('e') | Some('E') = self.peek() {
+            self.advance();
+
+            if let Some('+') | Some('-') = self.peek() {
                 self.advance();
             }
+
+            match self.digits(10, false) {
+                Ok(0) => return self.number_error("Missing digits in exponent."),
+                Ok(_) => {}
+                Err(message) => return self.number_error(message),
+            }
         }
 
         self.make_token(TokenType::Number)
     }
 
-    fn identifier(&mut self) -> Token {
-        while let Some('a'..='z') | Some('A'..='Z') | Some('_') = self.peek() {
+    /// Scans the digits of a number with a base prefix (`0x`, `0b` or `0o`),
+    /// the prefix having already been consumed.
+    fn radix_number(&mut self, radix: Radix) -> Token<'a> {
+        match self.digits(radix.value(), false) {
+            Ok(0) => return self.number_error(radix.missing_digits_message()),
+            Ok(_) => {}
+            Err(message) => return self.number_error(message),
+        }
+
+        if self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            return self.number_error(radix.invalid_digit_message());
+        }
+
+        self.make_token(TokenType::Number)
+    }
+
+    /// Skips the remainder of a malformed number literal and reports an error,
+    /// so that the rest of the literal does not show up as separate tokens.
+    fn number_error(&mut self, message: &'a str) -> Token<'a> {
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.advance();
+        }
+
+        self.error_token(message)
+    }
+
+    /// Consumes a run of digits in the given radix, allowing `_` as a
+    /// separator between digits.
+    ///
+    /// `after_digit` indicates whether a digit was consumed right before
+    /// this run, which allows the run to start with a separator.
+    ///
+    /// Returns the number of digits consumed.
+    fn digits(&mut self, radix: u32, after_digit: bool) -> Result<usize, &'static str> {
+        let mut count = 0;
+        let mut after_digit = after_digit;
+
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => {
+                    self.advance();
+                    count += 1;
+                    after_digit = true;
+                }
+                Some('_') => {
+                    let next_digit = self.peek_next().is_some_and(|c| c.is_digit(radix));
+                    if !after_digit || !next_digit {
+                        return Err("Digit separator must be between digits.");
+                    }
+
+                    self.advance();
+                    after_digit = false;
+                }
+                _ => return Ok(count),
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Token<'a> {
+        while let Some('a'..='z') | Some('A'..='Z') | Some('0'..='9') | Some('_') = self.peek() {
             self.advance();
         }
 
@@ -201,7 +359,8 @@ impl<'a> Scanner<'a> {
     }
 
     fn identifier_type(&self) -> TokenType {
-        match self.source.chars().nth(self.start) {
+        let mut chars = self.source[self.start..].chars();
+        match chars.next() {
             Some('a') => self.check_keyword(1, "nd", TokenType::And),
             Some('c') => self.check_keyword(1, "lass", TokenType::Class),
             Some('e') => self.check_keyword(1, "lse", TokenType::Else),
@@ -213,13 +372,13 @@ impl<'a> Scanner<'a> {
             Some('s') => self.check_keyword(1, "uper", TokenType::Super),
             Some('v') => self.check_keyword(1, "ar", TokenType::Var),
             Some('w') => self.check_keyword(1, "hile", TokenType::While),
-            Some('f') => match self.source.chars().nth(self.start + 1) {
+            Some('f') => match chars.next() {
                 Some('a') => self.check_keyword(2, "lse", TokenType::False),
                 Some('o') => self.check_keyword(2, "r", TokenType::For),
                 Some('u') => self.check_keyword(2, "n", TokenType::Fun),
                 _ => TokenType::Identifier,
             },
-            Some('t') => match self.source.chars().nth(self.start + 1) {
+            Some('t') => match chars.next() {
                 Some('h') => self.check_keyword(2, "is", TokenType::This),
                 Some('r') => self.check_keyword(2, "ue", TokenType::True),
                 _ => TokenType::Identifier,
@@ -240,19 +399,92 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn error_token(&self, message: &'a str) -> Token {
+    fn error_token(&self, message: &'a str) -> Token<'a> {
         Token {
             token_type: TokenType::Error,
             lexeme: message,
             line: self.line,
+            column: self.column,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Token<'a> {
     pub token_type: TokenType,
     pub lexeme: &'a str,
+    /// The line the token starts on.
     pub line: i32,
+
+    /// The column the token starts at, counting from 1.
+    pub column: i32,
+}
+
+impl Token<'_> {
+    /// Returns where the token starts.
+    pub fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Returns the numeric value of a [`TokenType::Number`] token.
+    ///
+    /// Handles base prefixes (`0x`, `0b`, `0o`), exponents and `_` digit separators.
+    /// Returns `None` if the token is not a number.
+    pub fn number_value(&self) -> Option<f64> {
+        if self.token_type != TokenType::Number {
+            return None;
+        }
+
+        let digits = self.lexeme.replace('_', "");
+        let radix = match digits.get(..2) {
+            Some("0x") | Some("0X") => Radix::Hexadecimal,
+            Some("0b") | Some("0B") => Radix::Binary,
+            Some("0o") | Some("0O") => Radix::Octal,
+            _ => return digits.parse().ok(),
+        };
+
+        digits[2..].chars().try_fold(0.0, |value, c| {
+            let digit = c.to_digit(radix.value())?;
+            Some(value * radix.value() as f64 + digit as f64)
+        })
+    }
+}
+
+/// The base of a prefixed integer literal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Radix {
+    Binary,
+    Octal,
+    Hexadecimal,
+}
+
+impl Radix {
+    fn value(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    fn missing_digits_message(self) -> &'static str {
+        match self {
+            Radix::Binary => "Missing digits in binary number.",
+            Radix::Octal => "Missing digits in octal number.",
+            Radix::Hexadecimal => "Missing digits in hexadecimal number.",
+        }
+    }
+
+    fn invalid_digit_message(self) -> &'static str {
+        match self {
+            Radix::Binary => "Invalid digit in binary number.",
+            Radix::Octal => "Invalid digit in octal number.",
+            Radix::Hexadecimal => "Invalid digit in hexadecimal number.",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -0,0 +1,999 @@
+/*!
+Conversion between [`Value`]s and Rust data using [serde](https://serde.rs), enabled by
+the **`serde`** feature.
+
+[`to_value`] converts anything implementing `Serialize`, such as a config
+struct or a parsed JSON document, into a value scripts can use. [`from_value`]
+converts a value returned by a script back into a typed Rust value.
+
+| Rust                         | Lox                                    |
+|------------------------------|----------------------------------------|
+| `()`, `None`, unit structs   | `nil`                                  |
+| `bool`                       | `true` or `false`                      |
+| Integers and floats          | Numbers                                |
+| Strings and `char`s          | Strings                                |
+| Structs and maps             | [`Record`] instances                   |
+| Sequences, tuples and bytes  | [`List`] instances                     |
+| Unit enum variants           | The name of the variant as a string    |
+| Other enum variants          | A record with the variant as its field |
+
+Integers must fit in a Lox number exactly, and converting a number back into
+an integer fails unless it is a whole number in range. Errors point out where
+in the data the problem is:
+
+```
+use rulox::{collections::Record, serde::from_value};
+
+#[derive(Debug, serde::Deserialize)]
+struct Config {
+    name: String,
+    port: u16,
+}
+
+let mut record = Record::new();
+record.set("name", "server");
+record.set("port", 80.5);
+
+let error = from_value::<Config>(record.into()).unwrap_err();
+assert_eq!(error.to_string(), "port: invalid value: floating point `80.5`, expected u16");
+```
+*/
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use ::serde::{
+    Deserialize, Serialize,
+    de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor},
+    forward_to_deserialize_any,
+    ser::{self, SerializeMap, SerializeSeq},
+};
+
+use crate::{
+    collections::{List, Record},
+    value::Value,
+};
+
+/// The largest integer every smaller integer can be represented exactly
+/// as a Lox number, which is an `f64`.
+const MAX_SAFE_INTEGER: i128 = (1 << 53) - 1;
+
+/// A step in the path to the part of the data an [`Error`] is about.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// An error converting between values and Rust data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    /// Where the error occurred, from the outermost value inwards.
+    pub path: Vec<Segment>,
+    pub message: String,
+}
+
+impl Error {
+    /// Adds the segment to the start of the path, as the error is passed up
+    /// out of the value it occurred in.
+    fn at(mut self, segment: Segment) -> Self {
+        self.path.insert(0, segment);
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (index, segment) in self.path.iter().enumerate() {
+            match segment {
+                Segment::Field(name) if index == 0 => write!(f, "{}", name)?,
+                Segment::Field(name) => write!(f, ".{}", name)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+
+        if !self.path.is_empty() {
+            write!(f, ": ")?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        Self {
+            path: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        Self {
+            path: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Converts Rust data into a value.
+pub fn to_value<T>(value: &T) -> Result<Value, Error>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(Serializer)
+}
+
+/// Converts a value into Rust data.
+pub fn from_value<T>(value: Value) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(Deserializer::new(value))
+}
+
+/// Converts an integer into a number, if it can be represented exactly.
+fn integer<T>(value: T) -> Result<Value, String>
+where
+    T: TryInto<i128> + Display + Copy,
+{
+    match value.try_into() {
+        Ok(integer) if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&integer) => {
+            Ok(Value::Number(integer as f64))
+        }
+        _ => Err(format!(
+            "integer {} cannot be represented exactly as a number",
+            value
+        )),
+    }
+}
+
+/// Serializes values as themselves, and records and lists as maps and sequences.
+///
+/// Functions, classes and instances of other classes cannot be serialized.
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(value) => serializer.serialize_bool(*value),
+            Value::Number(value) => serializer.serialize_f64(*value),
+            Value::String(value) => serializer.serialize_str(value),
+            Value::Instance(instance) => {
+                if let Some(record) = instance.borrow::<Record>() {
+                    let mut map = serializer.serialize_map(Some(record.len()))?;
+                    for (name, value) in record.fields() {
+                        map.serialize_entry(name, value)?;
+                    }
+                    map.end()
+                } else if let Some(list) = instance.borrow::<List>() {
+                    let mut seq = serializer.serialize_seq(Some(list.items.len()))?;
+                    for item in &list.items {
+                        seq.serialize_element(item)?;
+                    }
+                    seq.end()
+                } else {
+                    Err(ser::Error::custom(format!("cannot serialize {}", self)))
+                }
+            }
+            Value::Function(_) | Value::Native(_) | Value::Class(_) | Value::BoundMethod(_) => {
+                Err(ser::Error::custom(format!("cannot serialize {}", self)))
+            }
+        }
+    }
+}
+
+/// Deserializes any self-describing data into the value [`to_value`] would
+/// convert it into.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a value representable in Lox")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        integer(value).map_err(E::custom)
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> Result<Value, E> {
+        integer(value).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        integer(value).map_err(E::custom)
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<Value, E> {
+        integer(value).map_err(E::custom)
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(Value::Number(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut list = List::new();
+        while let Some(item) = seq.next_element()? {
+            list.items.push(item);
+        }
+
+        Ok(list.into())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut record = Record::new();
+        while let Some((name, value)) = map.next_entry::<String, Value>()? {
+            record.set(&name, value);
+        }
+
+        Ok(record.into())
+    }
+}
+
+/// A serializer producing [`Value`]s, see [`to_value`].
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeRecord;
+    type SerializeStruct = SerializeRecord;
+    type SerializeStructVariant = SerializeRecord;
+
+    fn serialize_bool(self, value: bool) -> Result<Value, Error> {
+        Ok(Value::Bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value, Error> {
+        integer(value).map_err(ser::Error::custom)
+    }
+
+    fn serialize_i128(self, value: i128) -> Result<Value, Error> {
+        integer(value).map_err(ser::Error::custom)
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value, Error> {
+        integer(value).map_err(ser::Error::custom)
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<Value, Error> {
+        integer(value).map_err(ser::Error::custom)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value, Error> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value, Error> {
+        Ok(Value::Number(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value, Error> {
+        Ok(value.to_string().into())
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value, Error> {
+        Ok(value.into())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value, Error> {
+        let items = value.iter().map(|&byte| Value::Number(byte.into()));
+        Ok(List::from(items.collect::<Vec<_>>()).into())
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(variant.into())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = value
+            .serialize(self)
+            .map_err(|error| error.at(Segment::Field(variant.to_string())))?;
+
+        let mut record = Record::new();
+        record.set(variant, value);
+        Ok(record.into())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len.unwrap_or_default()),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeRecord, Error> {
+        Ok(SerializeRecord {
+            record: Record::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeRecord, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeRecord, Error> {
+        Ok(SerializeRecord {
+            record: Record::new(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+/// Wraps the value of an enum variant in a record with the variant as its field.
+fn wrap_variant(variant: Option<&'static str>, value: Value) -> Value {
+    match variant {
+        Some(variant) => {
+            let mut record = Record::new();
+            record.set(variant, value);
+            record.into()
+        }
+        None => value,
+    }
+}
+
+/// Adds the variant, if any, to the start of the error's path.
+fn in_variant(error: Error, variant: Option<&'static str>) -> Error {
+    match variant {
+        Some(variant) => error.at(Segment::Field(variant.to_string())),
+        None => error,
+    }
+}
+
+/// Serializes sequences, tuples and tuple variants into a [`List`].
+pub struct SerializeList {
+    items: Vec<Value>,
+
+    /// The enum variant being serialized, if any.
+    variant: Option<&'static str>,
+}
+
+impl SerializeList {
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let index = self.items.len();
+        let value = value
+            .serialize(Serializer)
+            .map_err(|error| in_variant(error.at(Segment::Index(index)), self.variant))?;
+
+        self.items.push(value);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        wrap_variant(self.variant, List::from(self.items).into())
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+/// Serializes maps, structs and struct variants into a [`Record`].
+pub struct SerializeRecord {
+    record: Record,
+
+    /// The key of the map entry whose value is being serialized.
+    key: Option<Arc<str>>,
+
+    /// The enum variant being serialized, if any.
+    variant: Option<&'static str>,
+}
+
+impl SerializeRecord {
+    fn field<T>(&mut self, name: &str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = value.serialize(Serializer).map_err(|error| {
+            in_variant(error.at(Segment::Field(name.to_string())), self.variant)
+        })?;
+
+        self.record.set(name, value);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        wrap_variant(self.variant, self.record.into())
+    }
+}
+
+impl ser::SerializeMap for SerializeRecord {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        match key.serialize(Serializer)? {
+            Value::String(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            key => Err(ser::Error::custom(format!(
+                "map keys must be strings, found {}",
+                key
+            ))),
+        }
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_key is called before serialize_value");
+
+        self.field(&key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SerializeRecord {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeRecord {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+/// A deserializer reading Rust data out of a [`Value`], see [`from_value`].
+pub struct Deserializer {
+    value: Value,
+}
+
+impl Deserializer {
+    pub fn new(value: Value) -> Self {
+        Self { value }
+    }
+
+    fn invalid_type(&self, expected: &dyn de::Expected) -> Error {
+        let description;
+        let unexpected = match &self.value {
+            Value::Nil => Unexpected::Unit,
+            Value::Bool(value) => Unexpected::Bool(*value),
+            Value::Number(value) => unexpected_number(*value),
+            Value::String(value) => Unexpected::Str(value),
+            value => {
+                description = value.to_string();
+                Unexpected::Other(&description)
+            }
+        };
+
+        de::Error::invalid_type(unexpected, expected)
+    }
+}
+
+/// Describes a number in an error, as an integer if it is a whole number.
+fn unexpected_number(value: f64) -> Unexpected<'static> {
+    if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER as f64 {
+        Unexpected::Signed(value as i64)
+    } else {
+        Unexpected::Float(value)
+    }
+}
+
+/// Deserializes integers of the given types, which numbers must be whole and in
+/// range for.
+macro_rules! deserialize_integers {
+    ($($method:ident => $type:ty as $visit:ident),* $(,)?) => { $(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Error>
+        where
+            V: Visitor<'de>,
+        {
+            let range = (<$type>::MIN as f64, <$type>::MAX as f64);
+            match self.value {
+                Value::Number(value)
+                    if value.fract() == 0.0 && value >= range.0 && value <= range.1 =>
+                {
+                    visitor.$visit(value as $type)
+                }
+                Value::Number(value) => Err(de::Error::invalid_value(
+                    unexpected_number(value),
+                    &visitor,
+                )),
+                _ => Err(self.invalid_type(&visitor)),
+            }
+        }
+    )* };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match &self.value {
+            Value::Nil => visitor.visit_unit(),
+            Value::Bool(value) => visitor.visit_bool(*value),
+            Value::Number(value) => visitor.visit_f64(*value),
+            Value::String(value) => visitor.visit_str(value),
+            Value::Instance(instance) => {
+                if let Some(record) = instance.borrow::<Record>() {
+                    let fields: Vec<_> = record
+                        .fields()
+                        .map(|(name, value)| (name.to_string(), value.clone()))
+                        .collect();
+                    drop(record);
+
+                    visitor.visit_map(RecordAccess {
+                        fields: fields.into_iter(),
+                        value: None,
+                    })
+                } else if let Some(list) = instance.borrow::<List>() {
+                    let items = list.items.clone();
+                    drop(list);
+
+                    visitor.visit_seq(ListAccess {
+                        items: items.into_iter().enumerate(),
+                    })
+                } else {
+                    Err(self.invalid_type(&visitor))
+                }
+            }
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    deserialize_integers! {
+        deserialize_i8 => i8 as visit_i8,
+        deserialize_i16 => i16 as visit_i16,
+        deserialize_i32 => i32 as visit_i32,
+        deserialize_i64 => i64 as visit_i64,
+        deserialize_u8 => u8 as visit_u8,
+        deserialize_u16 => u16 as visit_u16,
+        deserialize_u32 => u32 as visit_u32,
+        deserialize_u64 => u64 as visit_u64,
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Nil => visitor.visit_unit(),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let Value::String(variant) = &self.value {
+            return visitor.visit_enum(variant.to_string().into_deserializer());
+        }
+
+        let variant = match &self.value {
+            Value::Instance(instance) => instance.borrow::<Record>().and_then(|record| {
+                let mut fields = record.fields();
+                match (fields.next(), fields.next()) {
+                    (Some((name, value)), None) => Some((name.to_string(), value.clone())),
+                    _ => None,
+                }
+            }),
+            _ => None,
+        };
+
+        match variant {
+            Some((variant, value)) => visitor.visit_enum(VariantAccess { variant, value }),
+            None => Err(self.invalid_type(&"a string or a record with a single field")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool f32 f64 i128 u128 char str string bytes byte_buf seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Visits the fields of a record as the entries of a map.
+struct RecordAccess {
+    fields: std::vec::IntoIter<(String, Value)>,
+
+    /// The field whose value is to be visited next.
+    value: Option<(String, Value)>,
+}
+
+impl<'de> de::MapAccess<'de> for RecordAccess {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        let Some((name, value)) = self.fields.next() else {
+            return Ok(None);
+        };
+
+        let key = seed.deserialize(Deserializer::new(name.as_str().into()))?;
+        self.value = Some((name, value));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        let (name, value) = self
+            .value
+            .take()
+            .expect("next_key_seed is called before next_value_seed");
+
+        seed.deserialize(Deserializer::new(value))
+            .map_err(|error| error.at(Segment::Field(name)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+/// Visits the items of a list as a sequence.
+struct ListAccess {
+    items: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        let Some((index, item)) = self.items.next() else {
+            return Ok(None);
+        };
+
+        seed.deserialize(Deserializer::new(item))
+            .map(Some)
+            .map_err(|error| error.at(Segment::Index(index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// Visits an enum variant stored as a record with the variant as its only field.
+struct VariantAccess {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(Deserializer::new(self.variant.as_str().into()))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        let variant = self.variant;
+        de::Deserialize::deserialize(Deserializer::new(self.value))
+            .map_err(|error: Error| error.at(Segment::Field(variant)))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        let variant = self.variant;
+        seed.deserialize(Deserializer::new(self.value))
+            .map_err(|error| error.at(Segment::Field(variant)))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        let variant = self.variant;
+        de::Deserializer::deserialize_seq(Deserializer::new(self.value), visitor)
+            .map_err(|error| error.at(Segment::Field(variant)))
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        let variant = self.variant;
+        de::Deserializer::deserialize_map(Deserializer::new(self.value), visitor)
+            .map_err(|error| error.at(Segment::Field(variant)))
+    }
+}
@@ -0,0 +1,333 @@
+/*!
+Binary serialization of compiled [`Chunk`]s.
+
+A serialized chunk starts with a fixed header followed by the chunk body.
+All integers are stored in little-endian byte order.
+
+| Field    | Size    | Description                                       |
+|----------|---------|---------------------------------------------------|
+| magic    | 4 bytes | Always [`MAGIC`] (`LOXC`)                         |
+| version  | `u16`   | Format version, currently [`FORMAT_VERSION`]      |
+| checksum | `u32`   | CRC-32 of everything following the header         |
+| body     |         | The serialized chunk                              |
+
+A chunk is made up of three sections:
+
+ * **code** - a `u32` length followed by the raw bytecode.
+ * **lines** - a `u32` run count followed by that many `(line: u32, count: u32)`
+   pairs, where each pair covers `count` consecutive bytes of code.
+ * **constants** - a `u32` count followed by that many tagged values.
+
+Each constant starts with a one-byte tag identifying its type:
+
+| Tag | Type     | Payload                                  |
+|-----|----------|------------------------------------------|
+| `0` | `Number` | `f64` as its IEEE bits                   |
+| `1` | `String` | `u32` length followed by UTF-8 bytes     |
+| `2` | `Nil`    | None                                     |
+| `3` | `Bool`   | One byte, `0` for false and `1` for true |
+| `4` | Function | Name as for strings, a one-byte arity, then its chunk |
+*/
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{Chunk, LineRun},
+    function::Function,
+    value::Value,
+    verify::{self, VerifyError},
+};
+
+/// The magic bytes every serialized chunk starts with.
+pub const MAGIC: [u8; 4] = *b"LOXC";
+
+/// The version of the format written by [`Chunk::serialize`].
+pub const FORMAT_VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 4;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_NIL: u8 = 2;
+const TAG_BOOL: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+/// How deeply functions may be nested in a chunk, to bound the recursion when reading one.
+const MAX_NESTING: usize = 256;
+
+/// Errors that can occur when deserializing a chunk.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum DeserializeError {
+    #[error("Not a rulox bytecode file")]
+    InvalidMagic,
+
+    #[error("Unsupported bytecode format version {}", .0)]
+    UnsupportedVersion(u16),
+
+    #[error("Checksum mismatch: expected {expected:#010x}, found {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    #[error("Unexpected end of bytecode")]
+    UnexpectedEof,
+
+    #[error("Invalid constant tag: {}", .0)]
+    InvalidConstantTag(u8),
+
+    #[error("String constant is not valid UTF-8")]
+    InvalidString,
+
+    #[error("Line table does not match the code")]
+    InvalidLineTable,
+
+    #[error("Functions are nested too deeply")]
+    TooDeeplyNested,
+
+    #[error("Unexpected data after the end of the chunk")]
+    TrailingData,
+
+    #[error("Invalid bytecode: {}", .0)]
+    Verify(#[from] VerifyError),
+}
+
+/// Returns whether the given bytes look like a serialized chunk.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+impl Chunk {
+    /// Serializes the chunk into the binary format described in [`crate::serialize`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a constant is a value created by the host, such as a native
+    /// function, which the compiler never creates.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        write_chunk(&mut body, self);
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+
+        bytes
+    }
+
+    /// Deserializes a chunk previously produced by [`Chunk::serialize`].
+    ///
+    /// The chunk is [verified](verify::verify) before it is returned, so it is
+    /// safe to execute even if the bytes came from an untrusted source.
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, DeserializeError> {
+        if !is_bytecode(bytes) {
+            return Err(DeserializeError::InvalidMagic);
+        }
+
+        let mut header = Reader::new(&bytes[MAGIC.len()..]);
+        let version = header.read_u16()?;
+        if version != FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+
+        let expected = header.read_u32()?;
+        let body = &bytes[HEADER_SIZE..];
+        let actual = crc32fast::hash(body);
+        if expected != actual {
+            return Err(DeserializeError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut reader = Reader::new(body);
+        let chunk = reader.read_chunk()?;
+
+        if !reader.is_at_end() {
+            return Err(DeserializeError::TrailingData);
+        }
+
+        verify::verify(&chunk)?;
+
+        Ok(chunk)
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len() as u32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(bytes, chunk.code.len() as u32);
+    bytes.extend_from_slice(&chunk.code);
+
+    write_u32(bytes, chunk.lines.len() as u32);
+    for (index, run) in chunk.lines.iter().enumerate() {
+        let end = chunk
+            .lines
+            .get(index + 1)
+            .map_or(chunk.code.len(), |next| next.start);
+
+        write_u32(bytes, run.line as u32);
+        write_u32(bytes, (end - run.start) as u32);
+    }
+
+    write_u32(bytes, chunk.constants.len() as u32);
+    for constant in &chunk.constants {
+        write_value(bytes, constant);
+    }
+}
+
+fn write_value(bytes: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Number(n) => {
+            bytes.push(TAG_NUMBER);
+            bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        Value::String(s) => {
+            bytes.push(TAG_STRING);
+            write_str(bytes, s);
+        }
+        Value::Nil => bytes.push(TAG_NIL),
+        Value::Bool(b) => {
+            bytes.push(TAG_BOOL);
+            bytes.push(*b as u8);
+        }
+        Value::Function(function) => {
+            bytes.push(TAG_FUNCTION);
+            write_str(bytes, &function.name);
+            bytes.push(function.arity);
+            write_chunk(bytes, &function.chunk);
+        }
+        Value::Native(_) | Value::Class(_) | Value::Instance(_) | Value::BoundMethod(_) => {
+            panic!("Cannot serialize {:?}", value)
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+
+    /// How many functions the chunk being read is nested in.
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            offset: 0,
+            depth: 0,
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DeserializeError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        let bytes = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or(DeserializeError::UnexpectedEof)?;
+        self.offset = end;
+
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DeserializeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DeserializeError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DeserializeError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, DeserializeError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_str(&mut self) -> Result<&'a str, DeserializeError> {
+        let len = self.read_u32()? as usize;
+        std::str::from_utf8(self.read_bytes(len)?).map_err(|_| DeserializeError::InvalidString)
+    }
+
+    fn read_chunk(&mut self) -> Result<Chunk, DeserializeError> {
+        let code_len = self.read_u32()? as usize;
+        let code = self.read_bytes(code_len)?.to_vec();
+
+        let run_count = self.read_u32()?;
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for _ in 0..run_count {
+            let line = self.read_u32()? as usize;
+            let count = self.read_u32()? as usize;
+            if count == 0 || start + count > code_len {
+                return Err(DeserializeError::InvalidLineTable);
+            }
+
+            lines.push(LineRun { start, line });
+            start += count;
+        }
+
+        if start != code_len {
+            return Err(DeserializeError::InvalidLineTable);
+        }
+
+        let constant_count = self.read_u32()?;
+        let mut constants = Vec::new();
+        for _ in 0..constant_count {
+            constants.push(self.read_value()?);
+        }
+
+        Ok(Chunk {
+            code,
+            lines,
+            constants,
+        })
+    }
+
+    fn read_value(&mut self) -> Result<Value, DeserializeError> {
+        match self.read_u8()? {
+            TAG_NUMBER => Ok(Value::Number(f64::from_bits(self.read_u64()?))),
+            TAG_STRING => Ok(Value::String(self.read_str()?.into())),
+            TAG_NIL => Ok(Value::Nil),
+            TAG_BOOL => Ok(Value::Bool(self.read_u8()? != 0)),
+            TAG_FUNCTION => {
+                let name = self.read_str()?;
+                let arity = self.read_u8()?;
+
+                if self.depth == MAX_NESTING {
+                    return Err(DeserializeError::TooDeeplyNested);
+                }
+
+                self.depth += 1;
+                let chunk = self.read_chunk()?;
+                self.depth -= 1;
+
+                Ok(Value::Function(Arc::new(Function::with_chunk(
+                    name, arity, chunk,
+                ))))
+            }
+            tag => Err(DeserializeError::InvalidConstantTag(tag)),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+/*!
+Shapes, also known as hidden classes, describing the layout of the fields of
+[`Record`][crate::collections::Record]s.
+
+A record stores its field values in a plain list, in the order the fields
+were added, and points to a shape naming them. Records that had the same
+fields added in the same order share a shape, so the VM can remember where a
+field was found on one record and reuse that slot for every other record with
+the same shape, without comparing names. See [`vm::CacheStats`] for how often
+that happens.
+
+Shapes form a tree rooted at the empty shape, with an edge, or transition,
+for each field added to a record of the parent shape.
+
+[`vm::CacheStats`]: crate::vm::CacheStats
+*/
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, LazyLock, Mutex, PoisonError, Weak},
+};
+
+static EMPTY: LazyLock<Arc<Shape>> = LazyLock::new(|| {
+    Arc::new(Shape {
+        parent: None,
+        name: None,
+        len: 0,
+        transitions: Mutex::default(),
+    })
+});
+
+/// The names of the fields of a record, in the order they were added.
+pub struct Shape {
+    /// The shape before the last field was added, `None` for the empty shape.
+    parent: Option<Arc<Shape>>,
+
+    /// The name of the last field.
+    name: Option<Arc<str>>,
+
+    /// The number of fields.
+    len: usize,
+
+    /// The shapes with one more field, while any record has them.
+    transitions: Mutex<HashMap<Arc<str>, Weak<Shape>>>,
+}
+
+impl Shape {
+    /// Returns the shape without any fields, shared by every empty record.
+    pub fn empty() -> Arc<Shape> {
+        EMPTY.clone()
+    }
+
+    /// Returns the number of fields.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the slot of the field with the name, if the shape has it.
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        let mut shape = self;
+        while let Some(parent) = &shape.parent {
+            if shape.name.as_deref() == Some(name) {
+                return Some(shape.len - 1);
+            }
+
+            shape = parent;
+        }
+
+        None
+    }
+
+    /// Returns the names of the fields, in the order of their slots.
+    pub fn names(&self) -> Vec<&Arc<str>> {
+        let mut names = Vec::with_capacity(self.len);
+        let mut shape = self;
+        while let (Some(parent), Some(name)) = (&shape.parent, &shape.name) {
+            names.push(name);
+            shape = parent;
+        }
+
+        names.reverse();
+        names
+    }
+
+    /// Returns the shape with a field added after the existing ones, which
+    /// is the same for every record of this shape the field is added to.
+    pub fn with(self: &Arc<Self>, name: &str) -> Arc<Shape> {
+        let mut transitions = self
+            .transitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(shape) = transitions.get(name).and_then(Weak::upgrade) {
+            return shape;
+        }
+
+        let name: Arc<str> = name.into();
+        let shape = Arc::new(Shape {
+            parent: Some(self.clone()),
+            name: Some(name.clone()),
+            len: self.len + 1,
+            transitions: Mutex::default(),
+        });
+
+        // Shapes no record has anymore have been dropped, forget them too.
+        transitions.retain(|_, shape| shape.strong_count() > 0);
+        transitions.insert(name, Arc::downgrade(&shape));
+
+        shape
+    }
+}
+
+/// Drops the parents no other shape or record has iteratively, as records
+/// may have more fields than the stack could hold frames for.
+impl Drop for Shape {
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(shape) = parent {
+            parent = match Arc::try_unwrap(shape) {
+                Ok(mut shape) => shape.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl Debug for Shape {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
@@ -0,0 +1,232 @@
+/*!
+The standard library of native functions, grouped into modules that each need
+a [`Capability`] to be used.
+
+Every VM created with a [`VmBuilder`] defines all of the functions, but they
+fail with [`RuntimeError::CapabilityDenied`] unless the host allowed the
+capability they need. That way the same scripts get the same errors whether
+they run as trusted tooling or sandboxed user code:
+
+```
+use rulox::{compiler, stdlib::Capability, vm::VmBuilder};
+
+let mut vm = VmBuilder::new()
+    .out(Vec::new())
+    .err(Vec::new())
+    .allow(Capability::Time)
+    .build();
+
+let chunk = compiler::compile("print clock() > 0;\ngetEnv(\"HOME\");").unwrap();
+assert!(vm.interpret(&chunk).is_err());
+assert_eq!(vm.out(), b"true\n");
+assert_eq!(
+    vm.err(),
+    b"Calling 'getEnv' requires the 'os' capability.\n[line 2] in script\n"
+);
+```
+
+| Module   | Functions                                                        |
+|----------|------------------------------------------------------------------|
+| `io`     | `readLine()`, returning `nil` at the end of the input            |
+| `fs`     | `readFile(path)`, `writeFile(path, contents)`, `fileExists(path)` |
+| `os`     | `getEnv(name)`, returning `nil` if the variable is not set       |
+| `time`   | `clock()`, the number of seconds since the Unix epoch            |
+| `random` | `random()`, a number between 0 (inclusive) and 1 (exclusive)     |
+
+[`VmBuilder`]: crate::vm::VmBuilder
+*/
+
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    memory,
+    native::{self, NativeFn, VmContext},
+    value::Value,
+    vm::RuntimeError,
+};
+
+/// Permission to use one module of the standard library.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Capability {
+    /// Reading from standard input.
+    Io,
+
+    /// Reading and writing files below `root`. Scripts give paths relative to
+    /// it, and cannot use `..` or absolute paths to leave it, though symbolic
+    /// links inside it are followed.
+    Fs { root: PathBuf },
+
+    /// Reading environment variables.
+    Os,
+
+    /// Reading the system clock.
+    Time,
+
+    /// Generating random numbers.
+    Random,
+}
+
+impl Capability {
+    /// Returns the name of the module the capability allows.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Io => "io",
+            Capability::Fs { .. } => "fs",
+            Capability::Os => "os",
+            Capability::Time => "time",
+            Capability::Random => "random",
+        }
+    }
+}
+
+/// The functions of the standard library, with their arity.
+const FUNCTIONS: &[(&str, u8, NativeFn)] = &[
+    ("readLine", 0, read_line),
+    ("readFile", 1, read_file),
+    ("writeFile", 2, write_file),
+    ("fileExists", 1, file_exists),
+    ("getEnv", 1, get_env),
+    ("clock", 0, clock),
+    ("random", 0, random),
+];
+
+/// Calls `define` with the name, arity and implementation of every function
+/// in the standard library.
+pub(crate) fn define(mut define: impl FnMut(&str, u8, NativeFn)) {
+    for &(name, arity, function) in FUNCTIONS {
+        define(name, arity, function);
+    }
+}
+
+/// Returns the capability for the module, failing if the host has not allowed it.
+fn require<'a>(
+    context: &'a VmContext,
+    function: &'static str,
+    module: &'static str,
+) -> Result<&'a Capability, RuntimeError> {
+    context
+        .capabilities()
+        .iter()
+        .find(|capability| capability.name() == module)
+        .ok_or(RuntimeError::CapabilityDenied {
+            function,
+            capability: module,
+        })
+}
+
+fn read_line(context: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+    require(context, "readLine", "io")?;
+
+    let mut line = String::new();
+    if context.read_line(&mut line)? == 0 {
+        return Ok(Value::Nil);
+    }
+
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(line.into())
+}
+
+fn read_file(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path: String = native::arg(args, 0)?;
+    let resolved = resolve(require(context, "readFile", "fs")?, &path)?;
+
+    // Refuse files too big for the VM before reading them into memory.
+    let len = fs::metadata(&resolved)
+        .map_err(|error| file_error("read", &path, error))?
+        .len();
+    context.check_allocation(memory::string_size(len as usize))?;
+
+    fs::read_to_string(resolved)
+        .map(Value::from)
+        .map_err(|error| file_error("read", &path, error))
+}
+
+fn write_file(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path: String = native::arg(args, 0)?;
+    let contents: String = native::arg(args, 1)?;
+    let resolved = resolve(require(context, "writeFile", "fs")?, &path)?;
+
+    fs::write(resolved, contents).map_err(|error| file_error("write", &path, error))?;
+    Ok(Value::Nil)
+}
+
+fn file_exists(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path: String = native::arg(args, 0)?;
+    let resolved = resolve(require(context, "fileExists", "fs")?, &path)?;
+
+    Ok(resolved.is_file().into())
+}
+
+/// Resolves a path given by a script against the root of the `fs` capability,
+/// refusing paths that could lead outside of it.
+fn resolve(capability: &Capability, path: &str) -> Result<PathBuf, RuntimeError> {
+    let Capability::Fs { root } = capability else {
+        unreachable!("the fs module is only allowed by the Fs capability");
+    };
+
+    let relative = Path::new(path);
+    let inside = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !inside || path.is_empty() {
+        return Err(RuntimeError::Native(format!(
+            "Path '{}' is outside the allowed directory.",
+            path
+        )));
+    }
+
+    Ok(root.join(relative))
+}
+
+fn file_error(action: &str, path: &str, error: io::Error) -> RuntimeError {
+    RuntimeError::Native(format!("Could not {} file '{}': {}.", action, path, error))
+}
+
+fn get_env(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    require(context, "getEnv", "os")?;
+    let name: String = native::arg(args, 0)?;
+
+    Ok(std::env::var(name).map_or(Value::Nil, Value::from))
+}
+
+/// Returns the number of seconds since the Unix epoch.
+fn clock(context: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+    require(context, "clock", "time")?;
+
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| RuntimeError::Native(error.to_string()))?;
+
+    Ok(elapsed.as_secs_f64().into())
+}
+
+thread_local! {
+    /// State of the xorshift random number generator, never zero.
+    static RANDOM: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+fn random(context: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+    require(context, "random", "random")?;
+
+    let bits = RANDOM.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    });
+
+    // The top 53 bits fill the mantissa of a number in [0, 1).
+    Ok(((bits >> 11) as f64 / (1u64 << 53) as f64).into())
+}
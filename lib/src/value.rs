@@ -1,10 +1,35 @@
-use std::fmt::{self, Display, Formatter};
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
 
-use crate::vm::RuntimeError;
+use crate::{
+    class::{BoundMethod, Class, Instance},
+    function::Function,
+    native::Native,
+    vm::RuntimeError,
+};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
+    Nil,
+    Bool(bool),
     Number(f64),
+    String(Arc<str>),
+    Function(Arc<Function>),
+    Native(Arc<Native>),
+    Class(Arc<Class>),
+    Instance(Arc<Instance>),
+    BoundMethod(Arc<BoundMethod>),
+}
+
+impl Value {
+    /// Returns whether the value is considered false in a boolean context.
+    ///
+    /// Only `nil` and `false` are falsey, every other value is truthy.
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
 }
 
 impl From<f64> for Value {
@@ -19,13 +44,70 @@ impl From<i32> for Value {
     }
 }
 
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value.into())
+    }
+}
+
+impl From<Arc<str>> for Value {
+    fn from(value: Arc<str>) -> Self {
+        Self::String(value)
+    }
+}
+
 impl TryFrom<Value> for f64 {
     type Error = RuntimeError;
 
     fn try_from(value: Value) -> Result<Self, RuntimeError> {
         match value {
             Value::Number(value) => Ok(value),
-            // _ => Err(RuntimeError::TypeError),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Bool(value) => Ok(value),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+}
+
+impl TryFrom<Value> for Arc<str> {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::String(value) => Ok(value),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::String(value) => Ok(value.to_string()),
+            _ => Err(RuntimeError::TypeError),
         }
     }
 }
@@ -33,7 +115,14 @@ impl TryFrom<Value> for f64 {
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            Value::Number(n) => write!(f, "{:?}", n),
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(function) => write!(f, "{}", function),
+            Value::Native(_) | Value::BoundMethod(_) => write!(f, "<native fn>"),
+            Value::Class(class) => write!(f, "{}", class),
+            Value::Instance(instance) => write!(f, "{}", instance),
         }
     }
 }
@@ -0,0 +1,293 @@
+/*!
+Static verification of [`Chunk`]s that did not come straight from the compiler.
+
+The VM trusts the bytecode it executes, so a chunk loaded from an untrusted
+source (e.g. a deserialized `.loxc` file) should be verified before it is run.
+*/
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{Chunk, OpCode},
+    value::Value,
+};
+
+/// Problems found while verifying a chunk.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum VerifyError {
+    #[error("Invalid opcode {opcode} at offset {offset}")]
+    InvalidOpCode { offset: usize, opcode: u8 },
+
+    #[error("Instruction at offset {offset} is missing its operands")]
+    TruncatedInstruction { offset: usize },
+
+    #[error("Instruction at offset {offset} refers to missing constant {index}")]
+    InvalidConstant { offset: usize, index: usize },
+
+    /// A variable or property name that is not a string.
+    #[error("Instruction at offset {offset} uses constant {index} as a name")]
+    InvalidName { offset: usize, index: usize },
+
+    #[error("Instruction at offset {offset} refers to missing local slot {slot}")]
+    InvalidLocal { offset: usize, slot: usize },
+
+    #[error("Instruction at offset {offset} pops from an empty stack")]
+    StackUnderflow { offset: usize },
+
+    #[error("Instruction at offset {offset} jumps to {target}, which is not an instruction")]
+    InvalidJump { offset: usize, target: usize },
+
+    /// An instruction that can be reached with different numbers of values
+    /// on the stack, so the slots of locals would not be known.
+    #[error("Instruction at offset {offset} is reached with different stack depths")]
+    InconsistentStack { offset: usize },
+
+    #[error("Execution can run past the end of the code")]
+    MissingReturn,
+}
+
+/// A decoded instruction.
+pub(crate) struct Instruction {
+    pub(crate) offset: usize,
+    pub(crate) opcode: OpCode,
+
+    /// The operand of the instruction, zero if it has none. Only constants
+    /// and jumps have operands longer than a byte.
+    pub(crate) operand: usize,
+
+    /// The index of the constant the instruction uses, if any. For most
+    /// instructions this is the operand, but not for `IncrLocal`.
+    pub(crate) constant: Option<usize>,
+}
+
+/// Verifies that the chunk, and every function declared in it, can be executed
+/// without reading outside its code, constants or stack frame.
+pub fn verify(chunk: &Chunk) -> Result<(), VerifyError> {
+    verify_with_arity(chunk, 0)
+}
+
+fn verify_with_arity(chunk: &Chunk, arity: u8) -> Result<(), VerifyError> {
+    verify_code(chunk, arity)?;
+
+    for constant in &chunk.constants {
+        if let Value::Function(function) = constant {
+            verify_with_arity(&function.chunk, function.arity)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies the code of a chunk for a function with `arity` parameters,
+/// without the functions declared in it, returning its instructions.
+pub(crate) fn verify_code(chunk: &Chunk, arity: u8) -> Result<Vec<Instruction>, VerifyError> {
+    let instructions = decode(chunk)?;
+    check_stack(&instructions, arity)?;
+    Ok(instructions)
+}
+
+/// Decodes every instruction, checking opcodes and operands.
+pub(crate) fn decode(chunk: &Chunk) -> Result<Vec<Instruction>, VerifyError> {
+    let code = &chunk.code;
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let byte = code[offset];
+        let opcode = OpCode::try_from(byte).map_err(|_| VerifyError::InvalidOpCode {
+            offset,
+            opcode: byte,
+        })?;
+
+        let operands = code
+            .get(offset + 1..offset + 1 + operand_len(opcode))
+            .ok_or(VerifyError::TruncatedInstruction { offset })?;
+
+        let constant = match opcode {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::AddConst => Some(operands[0] as usize),
+            OpCode::IncrLocal => Some(operands[1] as usize),
+            OpCode::ConstantLong => Some(
+                (operands[0] as usize) << 16 | (operands[1] as usize) << 8 | operands[2] as usize,
+            ),
+            _ => None,
+        };
+
+        let operand = match opcode {
+            OpCode::ConstantLong => constant.unwrap_or_default(),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::JumpIfNotLess => {
+                (operands[0] as usize) << 8 | operands[1] as usize
+            }
+            _ => operands.first().copied().unwrap_or_default() as usize,
+        };
+
+        if let Some(index) = constant {
+            let Some(value) = chunk.constants.get(index) else {
+                return Err(VerifyError::InvalidConstant { offset, index });
+            };
+
+            let is_name = !matches!(
+                opcode,
+                OpCode::Constant | OpCode::ConstantLong | OpCode::AddConst | OpCode::IncrLocal
+            );
+            if is_name && !matches!(value, Value::String(_)) {
+                return Err(VerifyError::InvalidName { offset, index });
+            }
+        }
+
+        instructions.push(Instruction {
+            offset,
+            opcode,
+            operand,
+            constant,
+        });
+        offset += 1 + operands.len();
+    }
+
+    Ok(instructions)
+}
+
+/// Returns the number of operand bytes following the opcode.
+pub(crate) fn operand_len(opcode: OpCode) -> usize {
+    match opcode {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::Call
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::AddConst => 1,
+        OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::Loop
+        | OpCode::IncrLocal
+        | OpCode::JumpIfNotLess => 2,
+        OpCode::ConstantLong => 3,
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::Print
+        | OpCode::Return => 0,
+    }
+}
+
+/// Simulates the stack depth of every reachable instruction to make sure
+/// nothing pops more values than have been pushed or accesses a local slot
+/// that does not exist, that jumps land on instructions, and that execution
+/// cannot run off the end of the code.
+///
+/// Every path to an instruction must reach it with the same stack depth, as
+/// the compiler never leaves values behind in a branch or loop.
+fn check_stack(instructions: &[Instruction], arity: u8) -> Result<(), VerifyError> {
+    let index_of = |offset: usize, target: usize| {
+        instructions
+            .binary_search_by_key(&target, |instruction| instruction.offset)
+            .map_err(|_| VerifyError::InvalidJump { offset, target })
+    };
+
+    // The stack depth each instruction is reached with, once known. Slot zero
+    // holds the function being called, and cannot be popped, followed by the
+    // arguments.
+    let mut depths = vec![None; instructions.len()];
+    let mut pending = vec![(0, 1 + arity as usize)];
+
+    while let Some((index, depth)) = pending.pop() {
+        let Some(instruction) = instructions.get(index) else {
+            return Err(VerifyError::MissingReturn);
+        };
+
+        let offset = instruction.offset;
+        match depths[index] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(VerifyError::InconsistentStack { offset }),
+            None => depths[index] = Some(depth),
+        }
+
+        if let OpCode::GetLocal | OpCode::SetLocal | OpCode::IncrLocal = instruction.opcode
+            && instruction.operand >= depth
+        {
+            return Err(VerifyError::InvalidLocal {
+                offset,
+                slot: instruction.operand,
+            });
+        }
+
+        let (pops, pushes) = stack_effect(instruction);
+        let depth = depth
+            .checked_sub(pops)
+            .filter(|&depth| depth >= 1)
+            .ok_or(VerifyError::StackUnderflow { offset })?
+            + pushes;
+
+        let next = offset + 1 + operand_len(instruction.opcode);
+        match instruction.opcode {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push((index_of(offset, next + instruction.operand)?, depth)),
+            OpCode::JumpIfFalse | OpCode::JumpIfNotLess => {
+                pending.push((index_of(offset, next + instruction.operand)?, depth));
+                pending.push((index + 1, depth));
+            }
+            OpCode::Loop => {
+                let target = next
+                    .checked_sub(instruction.operand)
+                    .ok_or(VerifyError::InvalidJump { offset, target: 0 })?;
+                pending.push((index_of(offset, target)?, depth));
+            }
+            _ => pending.push((index + 1, depth)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns how many values the instruction pops from and pushes onto the stack.
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction.opcode {
+        OpCode::Constant
+        | OpCode::ConstantLong
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetGlobal
+        | OpCode::GetLocal
+        | OpCode::IncrLocal => (0, 1),
+        OpCode::Pop | OpCode::DefineGlobal | OpCode::Print => (1, 0),
+        OpCode::SetGlobal
+        | OpCode::SetLocal
+        | OpCode::GetProperty
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse
+        | OpCode::AddConst => (1, 1),
+        OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::SetProperty => (2, 1),
+        OpCode::Call => (instruction.operand + 1, 1),
+        OpCode::JumpIfNotLess => (2, 0),
+        OpCode::Return => (1, 0),
+        OpCode::Jump | OpCode::Loop => (0, 0),
+    }
+}
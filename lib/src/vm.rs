@@ -1,17 +1,31 @@
 use std::{
-    io::{self, Write},
+    collections::HashMap,
+    io::{self, BufRead, Stderr, Stdout, Write},
+    mem,
     process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 use crate::{
+    class::{self, Class, LoxClass},
     compiler::{Chunk, OpCode},
+    decoded::{Decoded, Op},
+    function::Function,
+    memory::{self, MemoryUsage, Tracer},
+    native::{Native, NativeFn, Runtime, VmContext},
+    register::RegisterVm,
+    stdlib::{self, Capability},
     value::Value,
+    verify::VerifyError,
 };
 use thiserror::Error;
 use tracing::error;
 
 /// Errors that can occur when the VM executes.
-#[derive(Error, Debug, Copy, Clone)]
+#[derive(Error, Debug, Clone)]
 pub enum VmError {
     #[error("Compilation error")]
     Compilation,
@@ -21,7 +35,7 @@ pub enum VmError {
 }
 
 /// Errors that can occur during runtime.
-#[derive(Error, Debug, Copy, Clone)]
+#[derive(Error, Debug, Clone)]
 pub enum RuntimeError {
     #[error("Invalid opcode: {}", .0)]
     InvalidOpCode(u8),
@@ -29,9 +43,100 @@ pub enum RuntimeError {
     #[error("Attempt to pop value from empty stack")]
     PoppedEmptyStack,
 
+    /// The code ended in the middle of an instruction.
+    #[error("Truncated instruction")]
+    TruncatedInstruction,
+
+    /// An instruction referred to a constant the chunk does not have.
+    #[error("Invalid constant index: {}", .0)]
+    InvalidConstant(usize),
+
+    /// An instruction referred to a local variable slot outside of the stack.
+    #[error("Invalid local slot: {}", .0)]
+    InvalidLocal(usize),
+
+    /// Execution jumped outside of the code, or ran past its end.
+    #[error("Instruction pointer out of range")]
+    IpOutOfRange,
+
+    /// A function run in [`ExecutionMode::Fast`] failed verification.
+    #[error("Invalid bytecode: {}", .0)]
+    InvalidBytecode(VerifyError),
+
     #[error("Type error")]
     TypeError,
 
+    #[error("Operand must be a number.")]
+    OperandMustBeNumber,
+
+    #[error("Operands must be numbers.")]
+    OperandsMustBeNumbers,
+
+    #[error("Operands must be two numbers or two strings.")]
+    OperandsMustBeNumbersOrStrings,
+
+    #[error("Undefined variable '{}'.", .0)]
+    UndefinedVariable(String),
+
+    #[error("Can only call functions and classes.")]
+    NotCallable,
+
+    #[error("Expected {expected} arguments but got {got}.")]
+    WrongArity { expected: usize, got: usize },
+
+    #[error("Stack overflow.")]
+    StackOverflow,
+
+    /// Values reachable from the VM would use more memory than allowed, see
+    /// [`VM::set_heap_limit`].
+    #[error("Out of memory.")]
+    OutOfMemory,
+
+    /// Execution used up all of its fuel, see [`VM::set_fuel`].
+    #[error("Out of fuel.")]
+    OutOfFuel,
+
+    /// Execution was stopped with an [`InterruptHandle`].
+    #[error("Interrupted.")]
+    Interrupted,
+
+    /// A standard library function was called without the host allowing the
+    /// capability it needs, see [`VmBuilder::allow`].
+    #[error("Calling '{function}' requires the '{capability}' capability.")]
+    CapabilityDenied {
+        function: &'static str,
+        capability: &'static str,
+    },
+
+    #[error("Only instances have properties.")]
+    OnlyInstancesHaveProperties,
+
+    #[error("Only instances have fields.")]
+    OnlyInstancesHaveFields,
+
+    #[error("Undefined property '{}'.", .0)]
+    UndefinedProperty(String),
+
+    #[error("Can't assign to read-only property '{}'.", .0)]
+    ReadOnlyProperty(String),
+
+    /// A property was assigned a value of the wrong type.
+    #[error("Invalid value for property '{}'.", .0)]
+    InvalidPropertyValue(String),
+
+    /// An instance was used while one of its methods is running, such as by
+    /// Lox code the method called back into.
+    #[error("Instance is already in use.")]
+    InstanceInUse,
+
+    /// A native function was called with an argument of the wrong type, counting from 1.
+    #[error("Invalid type for argument {}.", .0)]
+    InvalidArgument(usize),
+
+    /// An error raised by a native function.
+    #[error("{}", .0)]
+    Native(String),
+
     #[error("Input/Output failure")]
     Io,
 }
@@ -44,6 +149,12 @@ impl From<io::Error> for VmError {
     }
 }
 
+impl From<io::Error> for RuntimeError {
+    fn from(_: io::Error) -> Self {
+        Self::Io
+    }
+}
+
 impl From<VmError> for ExitCode {
     fn from(error: VmError) -> Self {
         match error {
@@ -53,122 +164,1469 @@ impl From<VmError> for ExitCode {
     }
 }
 
+/// Pops two numbers off the VM's stack and pushes the result of applying the
+/// operator to them, wrapped in the value variant.
+macro_rules! binary_op {
+    ($vm:ident, $wrap:path, $op:tt) => { {
+        let (left, right) = $vm.pop_numbers()?;
+        $vm.stack.push($wrap(left $op right));
+    } };
+}
+
 struct IP<'a> {
     chunk: &'a Chunk,
     offset: usize,
+
+    /// The offset of the instruction currently being executed.
+    instruction: usize,
 }
 
 impl<'a> IP<'a> {
     fn new(chunk: &'a Chunk, offset: usize) -> Self {
-        Self { chunk, offset }
+        Self {
+            chunk,
+            offset,
+            instruction: offset,
+        }
     }
 
-    fn read(&mut self) -> u8 {
-        let value = self.chunk.read(self.offset);
+    fn read_instruction(&mut self) -> Result<u8, RuntimeError> {
+        self.instruction = self.offset;
+        self.read().map_err(|_| RuntimeError::IpOutOfRange)
+    }
+
+    /// Reads the next byte of the current instruction.
+    fn read(&mut self) -> Result<u8, RuntimeError> {
+        let value = self
+            .chunk
+            .read(self.offset)
+            .ok_or(RuntimeError::TruncatedInstruction)?;
         self.offset += 1;
-        value
+        Ok(value)
+    }
+
+    /// Reads the two byte operand of a jump.
+    fn read_short(&mut self) -> Result<usize, RuntimeError> {
+        let high = self.read()? as usize;
+        let low = self.read()? as usize;
+        Ok((high << 8) | low)
+    }
+
+    fn read_constant(&mut self, long: bool) -> Result<Value, RuntimeError> {
+        self.read_constant_ref(long).cloned()
     }
 
-    fn read_constant(&mut self, long: bool) -> Value {
+    /// Reads a constant without cloning it out of the chunk.
+    fn read_constant_ref(&mut self, long: bool) -> Result<&'a Value, RuntimeError> {
         let index = if long {
-            let high = self.read() as usize;
-            let mid = self.read() as usize;
-            let low = self.read() as usize;
+            let high = self.read()? as usize;
+            let mid = self.read()? as usize;
+            let low = self.read()? as usize;
             (high << 16) | (mid << 8) | low
         } else {
-            self.read() as usize
+            self.read()? as usize
         };
 
-        self.chunk.constants[index]
+        let chunk = self.chunk;
+        chunk
+            .constants
+            .get(index)
+            .ok_or(RuntimeError::InvalidConstant(index))
     }
 
+    /// Reads a constant that holds a string, such as the name of a variable.
+    fn read_string(&mut self) -> Result<&'a Arc<str>, RuntimeError> {
+        match self.read_constant_ref(false)? {
+            Value::String(string) => Ok(string),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+
+    #[cfg(feature = "trace")]
     fn offset(&self) -> usize {
         self.offset
     }
 }
 
-pub struct VM<'a, O: Write, E: Write> {
+/// The maximum number of calls in progress at once, including the script.
+pub(crate) const FRAMES_MAX: usize = 64;
+
+/// A call in progress.
+struct CallFrame {
+    function: Arc<Function>,
+
+    /// Whether the frame runs the top-level script rather than a function.
+    script: bool,
+
+    /// The offset of the next instruction to execute, or its index in the
+    /// decoded code in [`ExecutionMode::Fast`], saved while the frame is not
+    /// running.
+    ip: usize,
+
+    /// The offset of the instruction being executed, which for a frame
+    /// waiting on a call is the call itself. Used to report where a runtime
+    /// error occurred.
+    instruction: usize,
+
+    /// The index of the first stack slot of the frame, which holds the
+    /// function being called.
+    base: usize,
+}
+
+/// Why a frame stopped executing.
+enum Exit {
+    /// The function returned the value.
+    Return(Value),
+
+    /// The function called a Lox function, which has a new frame on top of it.
+    Call,
+
+    /// Execution ran out of fuel or was interrupted before the next instruction.
+    Budget(RuntimeError),
+}
+
+/// Stops a [`VM`] from another thread, see [`VM::interrupt_handle`].
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Asks the VM to stop before its next instruction with
+    /// [`RuntimeError::Interrupted`].
+    ///
+    /// If the VM is not running, it stops as soon as it runs any Lox code
+    /// again, except that [`VM::interpret`] forgets earlier interrupts.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How a [`VM`] executes bytecode.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExecutionMode {
+    /// Decodes each instruction as it is executed, checking that its opcode
+    /// and operands are valid.
+    #[default]
+    Checked,
+
+    /// Verifies each function before it is first run and decodes it ahead of
+    /// time, so instructions execute without being checked.
+    ///
+    /// A function that fails verification fails to run with
+    /// [`RuntimeError::InvalidBytecode`], even if the problem is in code that
+    /// would never be reached.
+    ///
+    /// Property accesses remember where they found the property for the
+    /// classes and record shapes they were used on, see [`CacheStats`].
+    Fast,
+}
+
+/// How often property accesses found the property in their inline cache, in
+/// [`ExecutionMode::Fast`].
+///
+/// A hit uses the member or record slot found before for an instance of the
+/// same class and [shape][crate::shape::Shape], while a miss looks the
+/// property up by name.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Called before each instruction is executed with the chunk being run, the
+/// offset of the instruction and the values on the stack, see
+/// [`VmBuilder::trace_hook`].
+pub type TraceHook = Box<dyn FnMut(&Chunk, usize, &[Value]) + Send>;
+
+/// Configures and creates a [`VM`], with the [standard library][stdlib] defined.
+///
+/// The VM owns what it writes to, so it can be kept in a struct without
+/// borrowing anything. Both the builder and the VM are `Send` when their
+/// writers are (see [`VM`]), so a VM can also be configured on one thread and
+/// built on another:
+///
+/// ```
+/// use std::thread;
+/// use rulox::{compiler, stdlib::Capability, vm::VmBuilder};
+///
+/// let builder = VmBuilder::new()
+///     .out(Vec::new())
+///     .err(Vec::new())
+///     .allow(Capability::Time)
+///     .fuel(1000);
+///
+/// let out = thread::spawn(move || {
+///     let mut vm = builder.build();
+///     let chunk = compiler::compile("print clock() > 0;").unwrap();
+///     vm.interpret(&chunk).unwrap();
+///     vm.into_writers().0
+/// })
+/// .join()
+/// .unwrap();
+///
+/// assert_eq!(out, b"true\n");
+/// ```
+pub struct VmBuilder<O: Write = Stdout, E: Write = Stderr> {
+    out: O,
+    err: E,
+    input: Option<Box<dyn BufRead + Send>>,
+    capabilities: Vec<Capability>,
+    natives: Vec<(String, u8, NativeFn)>,
+    classes: Vec<fn() -> Class>,
+    stack_limit: Option<usize>,
+    frame_limit: usize,
+    heap_limit: Option<usize>,
+    fuel: Option<u64>,
+    trace_hook: Option<TraceHook>,
+    mode: ExecutionMode,
+}
+
+impl VmBuilder {
+    /// Creates a builder for a VM writing to stdout and stderr, reading from
+    /// stdin, without any limits.
+    pub fn new() -> Self {
+        Self {
+            out: io::stdout(),
+            err: io::stderr(),
+            input: None,
+            capabilities: Vec::new(),
+            natives: Vec::new(),
+            classes: Vec::new(),
+            stack_limit: None,
+            frame_limit: FRAMES_MAX,
+            heap_limit: None,
+            fuel: None,
+            trace_hook: None,
+            mode: ExecutionMode::Checked,
+        }
+    }
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<O: Write, E: Write> VmBuilder<O, E> {
+    /// Sets where the program's output, such as that of `print`, is written to.
+    ///
+    /// Boxed writers work too, for hosts that choose where output goes at runtime.
+    pub fn out<W: Write>(self, out: W) -> VmBuilder<W, E> {
+        VmBuilder {
+            out,
+            err: self.err,
+            input: self.input,
+            capabilities: self.capabilities,
+            natives: self.natives,
+            classes: self.classes,
+            stack_limit: self.stack_limit,
+            frame_limit: self.frame_limit,
+            heap_limit: self.heap_limit,
+            fuel: self.fuel,
+            trace_hook: self.trace_hook,
+            mode: self.mode,
+        }
+    }
+
+    /// Sets where runtime errors and their stack traces are written to.
+    pub fn err<W: Write>(self, err: W) -> VmBuilder<O, W> {
+        VmBuilder {
+            out: self.out,
+            err,
+            input: self.input,
+            capabilities: self.capabilities,
+            natives: self.natives,
+            classes: self.classes,
+            stack_limit: self.stack_limit,
+            frame_limit: self.frame_limit,
+            heap_limit: self.heap_limit,
+            fuel: self.fuel,
+            trace_hook: self.trace_hook,
+            mode: self.mode,
+        }
+    }
+
+    /// Sets what `readLine()` reads from instead of stdin.
+    pub fn input(mut self, input: impl BufRead + Send + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Allows scripts to use the functions that need the capability.
+    ///
+    /// Allowing [`Capability::Fs`] again replaces the earlier root.
+    pub fn allow(mut self, capability: Capability) -> Self {
+        self.capabilities
+            .retain(|allowed| allowed.name() != capability.name());
+        self.capabilities.push(capability);
+        self
+    }
+
+    /// Defines a global function implemented in Rust, see [`VM::define_native`].
+    ///
+    /// Natives replace standard library functions with the same name.
+    pub fn native(mut self, name: &str, arity: u8, function: NativeFn) -> Self {
+        self.natives.push((name.to_string(), arity, function));
+        self
+    }
+
+    /// Defines a global class for the Rust type `T`, see [`VM::define_class`].
+    pub fn class<T: LoxClass>(mut self) -> Self {
+        self.classes.push(Class::new::<T>);
+        self
+    }
+
+    /// Limits the number of values on the stack, see [`VM::set_stack_limit`].
+    pub fn stack_limit(mut self, values: usize) -> Self {
+        self.stack_limit = Some(values);
+        self
+    }
+
+    /// Limits the number of calls in progress at once, including the script,
+    /// see [`VM::set_frame_limit`].
+    pub fn frame_limit(mut self, frames: usize) -> Self {
+        self.frame_limit = frames;
+        self
+    }
+
+    /// Limits the memory used by values reachable from the VM, in bytes, see
+    /// [`VM::set_heap_limit`].
+    ///
+    /// Values are freed as soon as they are no longer used rather than by a
+    /// garbage collector, so this is the only memory setting there is.
+    pub fn heap_limit(mut self, bytes: usize) -> Self {
+        self.heap_limit = Some(bytes);
+        self
+    }
+
+    /// Limits how many instructions may be executed, see [`VM::set_fuel`].
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Calls the hook before each instruction is executed, see [`VM::set_trace_hook`].
+    pub fn trace_hook(
+        mut self,
+        hook: impl FnMut(&Chunk, usize, &[Value]) + Send + 'static,
+    ) -> Self {
+        self.trace_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets how the VM executes bytecode, which cannot be changed once it is built.
+    pub fn execution_mode(mut self, mode: ExecutionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn build(self) -> VM<O, E> {
+        let mut vm = VM::new(self.out, self.err);
+        vm.mode = self.mode;
+        vm.input = self.input;
+        vm.capabilities = self.capabilities;
+        vm.stack_limit = self.stack_limit;
+        vm.frame_limit = self.frame_limit;
+        vm.fuel = self.fuel;
+        vm.trace_hook = self.trace_hook;
+
+        stdlib::define(|name, arity, function| vm.define_native(name, arity, function));
+        for (name, arity, function) in &self.natives {
+            vm.define_native(name, *arity, *function);
+        }
+        for class in self.classes {
+            let class = Arc::new(class());
+            vm.globals.insert(class.name.clone(), Value::Class(class));
+        }
+        vm.builtins = vm.globals.clone();
+
+        vm.set_heap_limit(self.heap_limit);
+        vm
+    }
+
+    /// Builds a [`RegisterVm`] for code compiled by the [`register`][crate::register] backend
+    /// instead.
+    ///
+    /// Only the frame limit applies to it. The stack and heap limits, fuel,
+    /// trace hook and execution mode are ignored.
+    pub fn build_register(self) -> RegisterVm<O, E> {
+        let mut vm = RegisterVm::new(self.out, self.err);
+        vm.input = self.input;
+        vm.capabilities = self.capabilities;
+        vm.frame_limit = self.frame_limit;
+
+        stdlib::define(|name, arity, function| vm.define_native(name, arity, function));
+        for (name, arity, function) in &self.natives {
+            vm.define_native(name, *arity, *function);
+        }
+        for class in self.classes {
+            let class = Arc::new(class());
+            vm.globals.insert(class.name.clone(), Value::Class(class));
+        }
+
+        vm
+    }
+}
+
+/// A virtual machine running compiled Lox code, writing the program's output
+/// to `O` and errors to `E`.
+///
+/// Create one with a [`VmBuilder`] to have the standard library defined.
+///
+/// A VM is `Send` when its writers are: values share their strings,
+/// instances and classes through `Arc`, so a VM can be moved to another thread
+/// between scripts, keeping its globals.
+///
+/// ```
+/// use std::thread;
+/// use rulox::{compiler, vm::VmBuilder};
+///
+/// let mut vm = VmBuilder::new().out(Vec::new()).err(Vec::new()).build();
+/// vm.interpret(&compiler::compile("var greeting = \"hello\";").unwrap()).unwrap();
+///
+/// let vm = thread::spawn(move || {
+///     vm.interpret(&compiler::compile("print greeting;").unwrap()).unwrap();
+///     vm
+/// })
+/// .join()
+/// .unwrap();
+///
+/// assert_eq!(vm.out(), b"hello\n");
+/// ```
+pub struct VM<O: Write, E: Write> {
     stack: Vec<Value>,
-    out: &'a mut O,
-    err: &'a mut E,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Arc<str>, Value>,
+
+    /// The globals the builder defined, which [`VM::reset`] puts back.
+    builtins: HashMap<Arc<str>, Value>,
+
+    /// The number of instructions that may still be executed, if limited.
+    fuel: Option<u64>,
+
+    interrupt: InterruptHandle,
+
+    /// The most bytes values reachable from the VM may use, if limited.
+    heap_limit: Option<usize>,
+
+    /// A running count of the heap size: what was last measured, plus what
+    /// has been allocated since, minus what the VM has freed. Only kept up to
+    /// date while the heap is limited.
+    heap_estimate: usize,
+
+    /// The most values the stack may hold, if limited.
+    stack_limit: Option<usize>,
+
+    /// The most calls that may be in progress at once, including the script.
+    frame_limit: usize,
+
+    /// Whether the script ran out of fuel or was interrupted, leaving its
+    /// frames in place to be resumed.
+    suspended: bool,
+
+    /// What the standard library is allowed to do.
+    capabilities: Vec<Capability>,
+
+    /// What `readLine()` reads from, stdin if `None`.
+    input: Option<Box<dyn BufRead + Send>>,
+
+    trace_hook: Option<TraceHook>,
+
+    /// How bytecode is executed. Frames of a suspended script refer to
+    /// instructions in the way of the mode, so it must not change.
+    mode: ExecutionMode,
+
+    /// How often property accesses hit their inline caches.
+    cache_stats: CacheStats,
+
+    out: O,
+    err: E,
 }
 
-impl<'a, O: Write, E: Write> VM<'a, O, E> {
-    pub fn new(out: &'a mut O, err: &'a mut E) -> Self {
+impl<O: Write, E: Write> VM<O, E> {
+    /// Creates a VM without anything defined, writing to `out` and `err`,
+    /// which may be owned writers or mutable references to them.
+    pub fn new(out: O, err: E) -> Self {
         Self {
             stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            builtins: HashMap::new(),
+            fuel: None,
+            interrupt: InterruptHandle::default(),
+            heap_limit: None,
+            heap_estimate: 0,
+            stack_limit: None,
+            frame_limit: FRAMES_MAX,
+            suspended: false,
+            capabilities: Vec::new(),
+            input: None,
+            trace_hook: None,
+            mode: ExecutionMode::Checked,
+            cache_stats: CacheStats::default(),
             out,
             err,
         }
     }
 
+    /// Runs a compiled script.
+    ///
+    /// A script that was suspended by running out of fuel or being
+    /// interrupted is abandoned first.
+    ///
+    /// The chunk is copied into a new [`Function`] each time, so in
+    /// [`ExecutionMode::Fast`] it is verified and decoded again on every call.
+    /// Use [`VM::interpret_function`] to run the same script more than once.
     pub fn interpret(&mut self, chunk: &Chunk) -> InterpretResult {
-        self.run(chunk)
+        self.interpret_function(Arc::new(Function::script(chunk.clone())))
+    }
+
+    /// Runs a compiled script given as the function it runs as, usually made
+    /// with [`Function::script`]. Any parameters it has are `nil`.
+    ///
+    /// The code decoded for [`ExecutionMode::Fast`] is kept with the function,
+    /// so running it again, on this VM or another, reuses it.
+    ///
+    /// A script that was suspended by running out of fuel or being
+    /// interrupted is abandoned first.
+    pub fn interpret_function(&mut self, script: Arc<Function>) -> InterpretResult {
+        self.stack.clear();
+        self.frames.clear();
+        self.suspended = false;
+        self.interrupt.interrupted.store(false, Ordering::Relaxed);
+
+        // The script occupies the first slot of its frame, like any other function.
+        let slots = 1 + usize::from(script.arity);
+        self.stack.resize(slots, Value::Nil);
+        self.frames.push(CallFrame {
+            function: script,
+            script: true,
+            ip: 0,
+            instruction: 0,
+            base: 0,
+        });
+
+        self.run_script()
+    }
+
+    /// Continues running a script that ran out of fuel or was interrupted,
+    /// from the instruction it stopped before.
+    ///
+    /// Does nothing if no script is suspended.
+    pub fn resume(&mut self) -> InterpretResult {
+        if !self.suspended {
+            return Ok(());
+        }
+
+        self.suspended = false;
+        self.run_script()
+    }
+
+    /// Returns whether a script ran out of fuel or was interrupted, and can be
+    /// continued with [`VM::resume`].
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Limits how many more instructions may be executed, or removes the
+    /// limit if `None`.
+    ///
+    /// Running out stops execution with [`RuntimeError::OutOfFuel`]. A script
+    /// stopped this way is suspended rather than reported as failed, and can
+    /// be continued with [`VM::resume`] once more fuel has been added.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Adds to the number of instructions that may be executed, if it is limited.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = &mut self.fuel {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    /// Returns how many more instructions may be executed, `None` if unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Limits the memory used by values reachable from the VM, in bytes, or
+    /// removes the limit if `None`.
+    ///
+    /// Allocating past the limit fails with [`RuntimeError::OutOfMemory`].
+    /// See [`memory`] for how usage is measured.
+    pub fn set_heap_limit(&mut self, bytes: Option<usize>) {
+        self.heap_limit = bytes;
+        self.heap_estimate = self.heap_bytes();
+    }
+
+    /// Limits the number of values on the stack, or removes the limit if `None`.
+    ///
+    /// Pushing past the limit fails with [`RuntimeError::StackOverflow`].
+    pub fn set_stack_limit(&mut self, values: Option<usize>) {
+        self.stack_limit = values;
+    }
+
+    /// Limits the number of calls in progress at once, including the script,
+    /// which defaults to 64.
+    ///
+    /// Calling past the limit fails with [`RuntimeError::StackOverflow`].
+    pub fn set_frame_limit(&mut self, frames: usize) {
+        self.frame_limit = frames;
+    }
+
+    /// Calls the hook before each instruction is executed, or stops calling
+    /// it if `None`.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.trace_hook = hook;
+    }
+
+    /// Returns what the program's output is written to.
+    pub fn out(&self) -> &O {
+        &self.out
+    }
+
+    /// Returns what runtime errors are written to.
+    pub fn err(&self) -> &E {
+        &self.err
+    }
+
+    /// Consumes the VM, returning what output and errors were written to.
+    pub fn into_writers(self) -> (O, E) {
+        (self.out, self.err)
+    }
+
+    /// Measures the memory the VM is currently using.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            heap_bytes: self.heap_bytes(),
+            stack_values: self.stack.len(),
+            frames: self.frames.len(),
+        }
+    }
+
+    /// Returns how often property accesses hit their inline caches since the
+    /// VM was created or the counts were last reset.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats
+    }
+
+    /// Resets the counts of cache hits and misses to zero.
+    pub fn reset_cache_stats(&mut self) {
+        self.cache_stats = CacheStats::default();
+    }
+
+    /// Returns a handle that can stop execution from another thread.
+    ///
+    /// A script stopped this way is suspended like one that ran out of fuel.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
     }
 
-    fn run(&mut self, chunk: &Chunk) -> InterpretResult {
-        macro_rules! binary_op {
-            ($op:tt) => { {
-                let right: f64 = self.pop_stack()?.try_into()?;
-                let left: f64 = self.pop_stack()?.try_into()?;
-                let result = left $op right;
-                self.stack.push(result.into());
-            } };
+    /// Defines a global function implemented in Rust, replacing any existing
+    /// global with the same name.
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        let native = Native::new(name, arity, function);
+        self.globals
+            .insert(name.into(), Value::Native(Arc::new(native)));
+    }
+
+    /// Defines a global class for the Rust type `T`, replacing any existing
+    /// global with the same name, and returns it so the host can create instances.
+    pub fn define_class<T: LoxClass>(&mut self) -> Arc<Class> {
+        let class = Arc::new(Class::new::<T>());
+        self.globals
+            .insert(T::NAME.into(), Value::Class(class.clone()));
+        class
+    }
+
+    /// Returns the value of a global variable, if it is defined.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
+    }
+
+    /// Defines a global variable, or replaces the value of an existing one.
+    pub fn set_global<T>(&mut self, name: &str, value: T)
+    where
+        T: Into<Value>,
+    {
+        self.globals.insert(name.into(), value.into());
+    }
+
+    /// Calls a Lox or native function with the given arguments, returning its result.
+    ///
+    /// Runtime errors are reported the same way as when interpreting a chunk.
+    /// Running out of fuel or being interrupted is an error too, as the call
+    /// cannot be resumed.
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, VmError> {
+        let frames = self.frames.len();
+
+        match self.call_nested(callee, args) {
+            Ok(value) => Ok(value),
+            Err(error) => {
+                self.report_runtime_error(&error, frames)?;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Returns the global variables currently defined, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value))
+    }
+
+    /// Returns the values currently on the stack, from the bottom up.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// Clears all global variables and the stack, abandoning any suspended script.
+    ///
+    /// The standard library and the natives and classes defined by the
+    /// [`VmBuilder`] are kept.
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.suspended = false;
+        self.globals.clone_from(&self.builtins);
+    }
+
+    /// Runs the script in the bottom frame, reporting runtime errors unless
+    /// it was suspended.
+    fn run_script(&mut self) -> InterpretResult {
+        let Err(error) = self.run(0, true) else {
+            return Ok(());
+        };
+
+        if !self.suspended {
+            self.stack.clear();
+            self.report_runtime_error(&error, 0)?;
+        }
+
+        Err(error.into())
+    }
+
+    /// Calls a function on behalf of the host or a native function, leaving
+    /// the stack as it was if the call fails.
+    ///
+    /// Running out of fuel or being interrupted fails the call, as the caller
+    /// cannot be suspended along with it.
+    fn call_nested(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let start = self.stack.len();
+        let frames = self.frames.len();
+        let arg_count = u8::try_from(args.len()).map_err(|_| RuntimeError::WrongArity {
+            expected: arity(callee).unwrap_or_default(),
+            got: args.len(),
+        })?;
+
+        self.check_stack(args.len() + 1)?;
+        self.stack.push(callee.clone());
+        self.stack.extend_from_slice(args);
+
+        let result = self.call_value(arg_count).and_then(|()| {
+            if self.frames.len() > frames {
+                self.run(frames, false)
+            } else {
+                self.pop_stack()
+            }
+        });
+
+        if result.is_err() {
+            self.stack.truncate(start);
+        }
+
+        result
+    }
+
+    /// Executes frames until the one at index `depth` returns, returning the
+    /// value it returns.
+    ///
+    /// Frames are left in place if execution fails, for the stack trace. If
+    /// `suspend` is set, running out of fuel or being interrupted suspends
+    /// execution instead of failing, so it can be resumed.
+    fn run(&mut self, depth: usize, suspend: bool) -> Result<Value, RuntimeError> {
+        loop {
+            let index = self.frames.len() - 1;
+            let frame = &self.frames[index];
+            let function = frame.function.clone();
+            let base = frame.base;
+
+            let (exit, ip, instruction) = match self.mode {
+                ExecutionMode::Checked => {
+                    let mut ip = IP::new(&function.chunk, frame.ip);
+                    let exit = self.execute(&mut ip, base);
+                    (exit, ip.offset, ip.instruction)
+                }
+                ExecutionMode::Fast => {
+                    let mut pc = frame.ip;
+                    match function.decoded() {
+                        Ok(code) => {
+                            let exit = self.execute_decoded(code, &function.chunk, &mut pc, base);
+
+                            // Only running out of budget stops before the
+                            // instruction at `pc` is taken. Anything else has
+                            // taken one, unless it failed before the first.
+                            let current = match exit {
+                                Ok(Exit::Budget(_)) => pc,
+                                _ => pc.saturating_sub(1),
+                            };
+                            (exit, pc, code.offsets[current])
+                        }
+                        Err(error) => (Err(RuntimeError::InvalidBytecode(error)), pc, 0),
+                    }
+                }
+            };
+
+            let frame = &mut self.frames[index];
+            frame.ip = ip;
+            frame.instruction = instruction;
+
+            match exit? {
+                Exit::Return(value) => {
+                    self.frames.pop();
+                    self.truncate_stack(base);
+
+                    if self.frames.len() == depth {
+                        return Ok(value);
+                    }
+
+                    self.stack.push(value);
+                }
+                Exit::Call => {}
+                Exit::Budget(error) => {
+                    self.suspended = suspend;
+                    return Err(error);
+                }
+            }
         }
+    }
 
-        let mut ip = IP::new(chunk, 0);
+    /// Executes the instructions of the frame whose slots start at `base`,
+    /// until it returns or calls a Lox function.
+    fn execute(&mut self, ip: &mut IP, base: usize) -> Result<Exit, RuntimeError> {
         loop {
             #[cfg(feature = "trace")]
             {
                 self.print_stack()?;
-                chunk.disassemble_instruction(ip.offset());
+                ip.chunk.disassemble_instruction(ip.offset());
+            }
+
+            if let Some(hook) = &mut self.trace_hook {
+                hook(ip.chunk, ip.offset, &self.stack);
             }
 
-            let instruction = ip.read();
+            if let Err(error) = self.use_fuel() {
+                ip.instruction = ip.offset;
+                return Ok(Exit::Budget(error));
+            }
+
+            let instruction = ip.read_instruction()?;
             let opcode = OpCode::try_from(instruction);
             match opcode {
                 Ok(OpCode::Return) => {
-                    let value = self.pop_stack()?;
-                    writeln!(self.out, "{}", value)?;
-                    return Ok(());
+                    let result = self.pop_stack()?;
+                    return Ok(Exit::Return(result));
                 }
 
                 Ok(OpCode::Constant) => {
-                    let value = ip.read_constant(false);
-                    self.stack.push(value);
+                    let value = ip.read_constant(false)?;
+                    self.push(value)?;
                 }
 
                 Ok(OpCode::ConstantLong) => {
-                    let value = ip.read_constant(true);
-                    self.stack.push(value);
+                    let value = ip.read_constant(true)?;
+                    self.push(value)?;
+                }
+
+                Ok(OpCode::Nil) => self.push(Value::Nil)?,
+                Ok(OpCode::True) => self.push(Value::Bool(true))?,
+                Ok(OpCode::False) => self.push(Value::Bool(false))?,
+
+                Ok(OpCode::Pop) => {
+                    let value = self.pop_stack()?;
+                    self.release(value);
+                }
+
+                Ok(OpCode::GetLocal) => {
+                    let slot = ip.read()? as usize;
+                    let value = self
+                        .stack
+                        .get(base + slot)
+                        .ok_or(RuntimeError::InvalidLocal(slot))?
+                        .clone();
+                    self.push(value)?;
+                }
+
+                Ok(OpCode::SetLocal) => {
+                    let slot = ip.read()? as usize;
+                    let value = self.peek(0)?.clone();
+                    let local = self
+                        .stack
+                        .get_mut(base + slot)
+                        .ok_or(RuntimeError::InvalidLocal(slot))?;
+                    let old = mem::replace(local, value);
+                    self.release(old);
+                }
+
+                Ok(OpCode::GetGlobal) => {
+                    let name = ip.read_string()?;
+                    self.push_global(name)?;
                 }
 
-                Ok(OpCode::Add) => binary_op!(+),
-                Ok(OpCode::Subtract) => binary_op!(-),
-                Ok(OpCode::Multiply) => binary_op!(*),
-                Ok(OpCode::Divide) => binary_op!(/),
+                Ok(OpCode::DefineGlobal) => {
+                    let name = ip.read_string()?;
+                    let value = self.pop_stack()?;
+                    if let Some(old) = self.globals.insert(name.clone(), value) {
+                        self.release(old);
+                    }
+                }
+
+                Ok(OpCode::SetGlobal) => {
+                    let name = ip.read_string()?;
+                    self.assign_global(name)?;
+                }
+
+                Ok(OpCode::Equal) => self.equal()?,
+                Ok(OpCode::Greater) => binary_op!(self, Value::Bool, >),
+                Ok(OpCode::Less) => binary_op!(self, Value::Bool, <),
+                Ok(OpCode::Add) => self.add()?,
+                Ok(OpCode::Subtract) => binary_op!(self, Value::Number, -),
+                Ok(OpCode::Multiply) => binary_op!(self, Value::Number, *),
+                Ok(OpCode::Divide) => binary_op!(self, Value::Number, /),
+                Ok(OpCode::Not) => self.not()?,
+                Ok(OpCode::Negate) => self.negate()?,
+
+                Ok(OpCode::GetProperty) => {
+                    let name = ip.read_string()?;
+                    self.get_property(name)?;
+                }
+
+                Ok(OpCode::SetProperty) => {
+                    let name = ip.read_string()?;
+                    self.set_property(name)?;
+                }
+
+                Ok(OpCode::Call) => {
+                    let arg_count = ip.read()?;
+                    if self.call_instruction(arg_count)? {
+                        return Ok(Exit::Call);
+                    }
+                }
+
+                Ok(OpCode::Jump) => {
+                    let jump = ip.read_short()?;
+                    ip.offset += jump;
+                }
+
+                Ok(OpCode::JumpIfFalse) => {
+                    let jump = ip.read_short()?;
+                    if self.peek(0)?.is_falsey() {
+                        ip.offset += jump;
+                    }
+                }
+
+                Ok(OpCode::Loop) => {
+                    let jump = ip.read_short()?;
+                    ip.offset = ip
+                        .offset
+                        .checked_sub(jump)
+                        .ok_or(RuntimeError::IpOutOfRange)?;
+                }
+
+                Ok(OpCode::IncrLocal) => {
+                    let slot = ip.read()? as usize;
+                    let value = ip.read_constant(false)?;
+                    let local = self
+                        .stack
+                        .get(base + slot)
+                        .ok_or(RuntimeError::InvalidLocal(slot))?
+                        .clone();
+                    self.incr_local(base + slot, local, value)?;
+                }
+
+                Ok(OpCode::AddConst) => {
+                    let value = ip.read_constant(false)?;
+                    self.push(value)?;
+                    self.add()?;
+                }
 
-                Ok(OpCode::Negate) => {
-                    let value: f64 = self.pop_stack()?.try_into()?;
-                    self.stack.push((-value).into());
+                Ok(OpCode::JumpIfNotLess) => {
+                    let jump = ip.read_short()?;
+                    if !self.less()? {
+                        ip.offset += jump;
+                    }
                 }
 
+                Ok(OpCode::Print) => self.print()?,
+
                 Err(_) => {
                     error!("Invalid opcode: {:?}", opcode);
-                    return Err(VmError::Runtime(RuntimeError::InvalidOpCode(instruction)));
+                    return Err(RuntimeError::InvalidOpCode(instruction));
                 }
             }
         }
     }
 
-    fn pop_stack(&mut self) -> Result<Value, VmError> {
-        if let Some(value) = self.stack.pop() {
-            Ok(value)
-        } else {
-            Err(VmError::Runtime(RuntimeError::PoppedEmptyStack))
+    /// Executes the decoded instructions of a frame whose slots start at
+    /// `base`, from the one at index `pc`, until it returns or calls a Lox
+    /// function.
+    ///
+    /// The code has been verified, so instructions, operands and local slots
+    /// are used without checking them.
+    fn execute_decoded(
+        &mut self,
+        code: &Decoded,
+        chunk: &Chunk,
+        pc: &mut usize,
+        base: usize,
+    ) -> Result<Exit, RuntimeError> {
+        loop {
+            #[cfg(feature = "trace")]
+            {
+                self.print_stack()?;
+                chunk.disassemble_instruction(code.offsets[*pc]);
+            }
+
+            if let Some(hook) = &mut self.trace_hook {
+                hook(chunk, code.offsets[*pc], &self.stack);
+            }
+
+            if let Err(error) = self.use_fuel() {
+                return Ok(Exit::Budget(error));
+            }
+
+            let op = &code.ops[*pc];
+            *pc += 1;
+
+            match op {
+                Op::Return => {
+                    let result = self.pop_stack()?;
+                    return Ok(Exit::Return(result));
+                }
+
+                Op::Constant(value) => self.push(value.clone())?,
+                Op::Nil => self.push(Value::Nil)?,
+                Op::True => self.push(Value::Bool(true))?,
+                Op::False => self.push(Value::Bool(false))?,
+
+                Op::Pop => {
+                    let value = self.pop_stack()?;
+                    self.release(value);
+                }
+
+                Op::GetLocal(slot) => {
+                    let value = self.stack[base + slot].clone();
+                    self.push(value)?;
+                }
+
+                Op::SetLocal(slot) => {
+                    let value = self.peek(0)?.clone();
+                    let old = mem::replace(&mut self.stack[base + slot], value);
+                    self.release(old);
+                }
+
+                Op::GetGlobal(name) => self.push_global(name)?,
+
+                Op::DefineGlobal(name) => {
+                    let value = self.pop_stack()?;
+                    if let Some(old) = self.globals.insert(name.clone(), value) {
+                        self.release(old);
+                    }
+                }
+
+                Op::SetGlobal(name) => self.assign_global(name)?,
+                Op::Equal => self.equal()?,
+                Op::Greater => binary_op!(self, Value::Bool, >),
+                Op::Less => binary_op!(self, Value::Bool, <),
+                Op::Add => self.add()?,
+                Op::Subtract => binary_op!(self, Value::Number, -),
+                Op::Multiply => binary_op!(self, Value::Number, *),
+                Op::Divide => binary_op!(self, Value::Number, /),
+                Op::Not => self.not()?,
+                Op::Negate => self.negate()?,
+                Op::GetProperty(name, cache) => {
+                    let object = self.pop_stack()?;
+                    let value = code.caches[*cache].get(&object, name, &mut self.cache_stats)?;
+                    self.stack.push(value);
+                }
+
+                Op::SetProperty(name, cache) => {
+                    let value = self.pop_stack()?;
+                    let object = self.pop_stack()?;
+                    self.allocate(size_of::<(Arc<str>, Value)>())?;
+                    code.caches[*cache].set(&object, name, value.clone(), &mut self.cache_stats)?;
+                    self.stack.push(value);
+                }
+
+                Op::Call(arg_count) => {
+                    if self.call_instruction(*arg_count)? {
+                        return Ok(Exit::Call);
+                    }
+                }
+
+                Op::Jump(target) => *pc = *target,
+
+                Op::JumpIfFalse(target) => {
+                    if self.peek(0)?.is_falsey() {
+                        *pc = *target;
+                    }
+                }
+
+                Op::IncrLocal(slot, value) => {
+                    let local = self.stack[base + slot].clone();
+                    self.incr_local(base + slot, local, value.clone())?;
+                }
+
+                Op::AddConst(value) => {
+                    self.push(value.clone())?;
+                    self.add()?;
+                }
+
+                Op::JumpIfNotLess(target) => {
+                    if !self.less()? {
+                        *pc = *target;
+                    }
+                }
+
+                Op::Print => self.print()?,
+            }
         }
     }
 
+    fn push_global(&mut self, name: &Arc<str>) -> Result<(), RuntimeError> {
+        let Some(value) = self.globals.get(name) else {
+            return Err(RuntimeError::UndefinedVariable(name.to_string()));
+        };
+
+        self.push(value.clone())
+    }
+
+    fn assign_global(&mut self, name: &Arc<str>) -> Result<(), RuntimeError> {
+        let value = self.peek(0)?.clone();
+        let Some(global) = self.globals.get_mut(name) else {
+            return Err(RuntimeError::UndefinedVariable(name.to_string()));
+        };
+
+        let old = mem::replace(global, value);
+        self.release(old);
+        Ok(())
+    }
+
+    fn equal(&mut self) -> Result<(), RuntimeError> {
+        let right = self.pop_stack()?;
+        let left = self.pop_stack()?;
+        self.stack.push(Value::Bool(left == right));
+        self.release(left);
+        self.release(right);
+        Ok(())
+    }
+
+    fn add(&mut self) -> Result<(), RuntimeError> {
+        let right = self.pop_stack()?;
+        let left = self.pop_stack()?;
+        let result = match (&left, &right) {
+            (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+            (Value::String(left), Value::String(right)) => {
+                self.allocate(memory::string_size(left.len() + right.len()))?;
+                Value::String(format!("{}{}", left, right).into())
+            }
+            _ => return Err(RuntimeError::OperandsMustBeNumbersOrStrings),
+        };
+
+        self.stack.push(result);
+        self.release(left);
+        self.release(right);
+        Ok(())
+    }
+
+    /// Adds the value to the local in the stack slot, pushing the result.
+    fn incr_local(&mut self, slot: usize, local: Value, value: Value) -> Result<(), RuntimeError> {
+        self.push(local)?;
+        self.push(value)?;
+        self.add()?;
+        let result = self.peek(0)?.clone();
+        let old = mem::replace(&mut self.stack[slot], result);
+        self.release(old);
+        Ok(())
+    }
+
+    /// Pops two numbers, returning whether the first is less than the second.
+    fn less(&mut self) -> Result<bool, RuntimeError> {
+        let (left, right) = self.pop_numbers()?;
+        Ok(left < right)
+    }
+
+    fn not(&mut self) -> Result<(), RuntimeError> {
+        let value = self.pop_stack()?;
+        self.stack.push(Value::Bool(value.is_falsey()));
+        self.release(value);
+        Ok(())
+    }
+
+    fn negate(&mut self) -> Result<(), RuntimeError> {
+        let Value::Number(value) = self.pop_stack()? else {
+            return Err(RuntimeError::OperandMustBeNumber);
+        };
+
+        self.stack.push(Value::Number(-value));
+        Ok(())
+    }
+
+    fn get_property(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let object = self.pop_stack()?;
+        self.stack.push(class::get_property(&object, name)?);
+        Ok(())
+    }
+
+    fn set_property(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let value = self.pop_stack()?;
+        let object = self.pop_stack()?;
+
+        // The property may be new, such as a field of a record.
+        self.allocate(size_of::<(Arc<str>, Value)>())?;
+        class::set_property(&object, name, value.clone())?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Calls the value below the arguments on top of the stack, returning
+    /// whether it is a Lox function with a new frame to run.
+    fn call_instruction(&mut self, arg_count: u8) -> Result<bool, RuntimeError> {
+        let frames = self.frames.len();
+        self.call_value(arg_count)?;
+        Ok(self.frames.len() > frames)
+    }
+
+    fn print(&mut self) -> Result<(), RuntimeError> {
+        let value = self.pop_stack()?;
+        writeln!(self.out, "{}", value)?;
+        self.release(value);
+        Ok(())
+    }
+
+    /// Calls the value below the arguments on top of the stack.
+    ///
+    /// Functions implemented in Rust run straight away, replacing the callee
+    /// and the arguments with their result, while Lox functions get a new
+    /// frame that runs once the current one hands over to it.
+    fn call_value(&mut self, arg_count: u8) -> Result<(), RuntimeError> {
+        match self.peek(arg_count as usize)?.clone() {
+            Value::Function(function) => self.call_function(function, arg_count),
+            callee @ (Value::Native(_) | Value::Class(_) | Value::BoundMethod(_)) => {
+                self.call_host(&callee, arg_count)
+            }
+            _ => Err(RuntimeError::NotCallable),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: Arc<Function>,
+        arg_count: u8,
+    ) -> Result<(), RuntimeError> {
+        check_arity(function.arity, arg_count)?;
+
+        if self.frames.len() >= self.frame_limit {
+            return Err(RuntimeError::StackOverflow);
+        }
+
+        self.frames.push(CallFrame {
+            script: false,
+            function,
+            ip: 0,
+            instruction: 0,
+            base: self.stack.len() - arg_count as usize - 1,
+        });
+
+        Ok(())
+    }
+
+    /// Calls a native function, or a class or method implemented in Rust.
+    fn call_host(&mut self, callee: &Value, arg_count: u8) -> Result<(), RuntimeError> {
+        let arity = arity(callee).ok_or(RuntimeError::NotCallable)?;
+        check_arity(arity as u8, arg_count)?;
+
+        let args = self.stack.split_off(self.stack.len() - arg_count as usize);
+        self.pop_stack()?;
+
+        // The arguments may be kept, such as by adding them to a list.
+        self.allocate(args.len() * size_of::<Value>())?;
+
+        let frames = self.frames.len();
+        let result = call_host(&mut VmContext::new(self), callee, &args)?;
+        self.allocate(memory::allocation_size(&result))?;
+
+        // A failed call back into Lox leaves its frames behind for the stack
+        // trace, which is not needed if the native function recovered.
+        self.frames.truncate(frames);
+        self.stack.push(result);
+
+        for arg in args {
+            self.release(arg);
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a value that grows the stack, checking its limit.
+    fn push(&mut self, value: Value) -> Result<(), RuntimeError> {
+        self.check_stack(1)?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Checks that the stack can grow by `values` without exceeding its limit.
+    fn check_stack(&self, values: usize) -> Result<(), RuntimeError> {
+        match self.stack_limit {
+            Some(limit) if self.stack.len() + values > limit => Err(RuntimeError::StackOverflow),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for an allocation of `bytes`, failing if it would take the
+    /// heap over its limit.
+    fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        if self.heap_limit.is_some() {
+            self.check_allocation_within_limit(bytes)?;
+            self.heap_estimate += bytes;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `bytes` can be allocated without taking the heap over its
+    /// limit, without accounting for them yet.
+    ///
+    /// The heap is only measured once the running count goes over the limit,
+    /// to catch up with memory freed by values the VM did not drop itself,
+    /// such as the fields of a freed instance.
+    fn check_allocation_within_limit(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        let Some(limit) = self.heap_limit else {
+            return Ok(());
+        };
+
+        if self.heap_estimate + bytes > limit {
+            self.heap_estimate = self.heap_bytes();
+            if self.heap_estimate + bytes > limit {
+                return Err(RuntimeError::OutOfMemory);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops a value the VM no longer holds, taking what it frees off the
+    /// running count of the heap.
+    fn release(&mut self, value: Value) {
+        if self.heap_limit.is_some() {
+            let freed = memory::freed_size(&value);
+            self.heap_estimate = self.heap_estimate.saturating_sub(freed);
+        }
+    }
+
+    /// Shortens the stack to `len` values, releasing the ones removed.
+    fn truncate_stack(&mut self, len: usize) {
+        if self.heap_limit.is_none() {
+            self.stack.truncate(len);
+            return;
+        }
+
+        while self.stack.len() > len {
+            if let Some(value) = self.stack.pop() {
+                self.release(value);
+            }
+        }
+    }
+
+    /// Measures the memory used by the values reachable from the globals,
+    /// the stack and the functions being run.
+    fn heap_bytes(&self) -> usize {
+        let mut tracer = Tracer::new();
+
+        tracer.bytes(self.globals.capacity() * size_of::<(Arc<str>, Value)>());
+        for (name, value) in &self.globals {
+            tracer.string(name);
+            tracer.value(value);
+        }
+
+        for value in &self.stack {
+            tracer.value(value);
+        }
+
+        for frame in &self.frames {
+            tracer.value(&Value::Function(frame.function.clone()));
+        }
+
+        tracer.total()
+    }
+
+    /// Uses up the fuel for one instruction, failing instead if there is none
+    /// left or execution has been interrupted.
+    fn use_fuel(&mut self) -> Result<(), RuntimeError> {
+        let interrupted = &self.interrupt.interrupted;
+        if interrupted.load(Ordering::Relaxed) {
+            interrupted.store(false, Ordering::Relaxed);
+            return Err(RuntimeError::Interrupted);
+        }
+
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError::OutOfFuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Reports a runtime error along with the calls it occurred in, from the
+    /// frame at index `from` up, and discards those frames.
+    fn report_runtime_error(&mut self, error: &RuntimeError, from: usize) -> Result<(), io::Error> {
+        writeln!(self.err, "{}", error)?;
+        for frame in self.frames.drain(from..).rev() {
+            match frame.function.chunk.line_for_offset(frame.instruction) {
+                Some(line) => write!(self.err, "[line {}] in ", line)?,
+                None => write!(self.err, "[offset {}] in ", frame.instruction)?,
+            }
+
+            if frame.script {
+                writeln!(self.err, "script")?;
+            } else {
+                writeln!(self.err, "{}()", frame.function.name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn pop_stack(&mut self) -> Result<Value, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::PoppedEmptyStack)
+    }
+
+    /// Pops the two operands of a numeric binary operator.
+    fn pop_numbers(&mut self) -> Result<(f64, f64), RuntimeError> {
+        let right = self.pop_stack()?;
+        let left = self.pop_stack()?;
+
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok((left, right)),
+            _ => Err(RuntimeError::OperandsMustBeNumbers),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> Result<&Value, RuntimeError> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .map(|index| &self.stack[index])
+            .ok_or(RuntimeError::PoppedEmptyStack)
+    }
+
+    #[cfg(feature = "trace")]
     fn print_stack(&mut self) -> Result<(), io::Error> {
         write!(self.err, "          ")?;
         for value in &self.stack {
@@ -180,3 +1638,72 @@ impl<'a, O: Write, E: Write> VM<'a, O, E> {
         Ok(())
     }
 }
+
+impl<O: Write, E: Write> Runtime for VM<O, E> {
+    fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.into(), value);
+    }
+
+    fn out(&mut self) -> &mut dyn Write {
+        &mut self.out
+    }
+
+    fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(line),
+            None => io::stdin().lock().read_line(line),
+        }
+    }
+
+    fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.call_nested(callee, args)
+    }
+
+    fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    fn check_allocation(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.check_allocation_within_limit(bytes)
+    }
+}
+
+/// Returns the number of arguments the value must be called with, if it can be called.
+pub(crate) fn arity(callee: &Value) -> Option<usize> {
+    match callee {
+        Value::Function(function) => Some(function.arity as usize),
+        Value::Native(native) => Some(native.arity as usize),
+        Value::Class(class) => class.arity().map(usize::from),
+        Value::BoundMethod(method) => Some(method.method.arity as usize),
+        _ => None,
+    }
+}
+
+/// Calls a native function, or a class or method implemented in Rust.
+pub(crate) fn call_host(
+    context: &mut VmContext,
+    callee: &Value,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    match callee {
+        Value::Native(native) => (native.function)(context, args),
+        Value::Class(class) => class.construct(context, args),
+        Value::BoundMethod(method) => method.call(context, args),
+        _ => Err(RuntimeError::NotCallable),
+    }
+}
+
+pub(crate) fn check_arity(arity: u8, arg_count: u8) -> Result<(), RuntimeError> {
+    if arity == arg_count {
+        Ok(())
+    } else {
+        Err(RuntimeError::WrongArity {
+            expected: arity as usize,
+            got: arg_count as usize,
+        })
+    }
+}
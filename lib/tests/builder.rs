@@ -0,0 +1,306 @@
+use std::{
+    io::{Cursor, Write},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use rulox::{
+    collections::List,
+    compiler::{self, OpCode},
+    native::{self, VmContext},
+    stdlib::Capability,
+    value::Value,
+    vm::{ExecutionMode, RuntimeError, VM, VmBuilder, VmError},
+};
+
+fn double(_: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let number: f64 = native::arg(args, 0)?;
+    Ok((number * 2.0).into())
+}
+
+/// Compiles and runs the source on the given VM.
+fn run<O: Write, E: Write>(vm: &mut VM<O, E>, source: &str) -> Result<(), VmError> {
+    let chunk = compiler::compile(source).expect("source should compile");
+    vm.interpret(&chunk)
+}
+
+/// A host keeping its VM between scripts, without borrowing its output.
+struct Host {
+    vm: VM<Vec<u8>, Vec<u8>>,
+}
+
+impl Host {
+    fn new() -> Self {
+        Self {
+            vm: VmBuilder::new().out(Vec::new()).err(Vec::new()).build(),
+        }
+    }
+
+    fn output(&self) -> &str {
+        std::str::from_utf8(self.vm.out()).unwrap()
+    }
+}
+
+#[test]
+fn vms_can_own_their_output() {
+    let mut host = Host::new();
+    run(&mut host.vm, "var greeting = \"hello\";").unwrap();
+    run(&mut host.vm, "print greeting;").unwrap();
+    assert_eq!(host.output(), "hello\n");
+
+    let mut host = Host::new();
+    assert!(run(&mut host.vm, "print missing;").is_err());
+    let (out, err) = host.vm.into_writers();
+    assert!(out.is_empty());
+    assert_eq!(err, b"Undefined variable 'missing'.\n[line 1] in script\n");
+}
+
+#[test]
+fn output_can_be_boxed() {
+    let out: Box<dyn Write + Send> = Box::new(Vec::new());
+    let mut vm = VmBuilder::new().out(out).err(Vec::new()).build();
+    run(&mut vm, "print 1;").unwrap();
+    assert!(vm.err().is_empty());
+}
+
+#[test]
+fn builders_can_be_sent_to_other_threads() {
+    let builder = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .native("double", 1, double);
+
+    let out = thread::spawn(move || {
+        let mut vm = builder.build();
+        run(&mut vm, "print double(21);").unwrap();
+        vm.into_writers().0
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(out, b"42\n");
+}
+
+#[test]
+fn vms_can_be_moved_to_other_threads() {
+    let mut host = Host {
+        vm: VmBuilder::new()
+            .out(Vec::new())
+            .err(Vec::new())
+            .class::<List>()
+            .execution_mode(ExecutionMode::Fast)
+            .build(),
+    };
+    run(
+        &mut host.vm,
+        "var list = List(); fun add(n) { list.push(n); } add(1);",
+    )
+    .unwrap();
+
+    let host = thread::spawn(move || {
+        run(&mut host.vm, "add(2); print list.get(0) + list.get(1);").unwrap();
+        host
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(host.output(), "3\n");
+}
+
+#[test]
+fn suspended_scripts_can_be_resumed_on_other_threads() {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .fuel(10)
+        .build();
+    let chunk = compiler::compile("var n = 0; while (n < 100) n = n + 1; print n;").unwrap();
+    assert!(vm.interpret(&chunk).is_err());
+    assert!(vm.is_suspended());
+
+    let vm = thread::spawn(move || {
+        vm.set_fuel(None);
+        vm.resume().unwrap();
+        vm
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(vm.out(), b"100\n");
+}
+
+/// Output shared between threads, as a host logging every VM to one place.
+#[derive(Clone, Default)]
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn boxed_output_can_be_shared_by_vms_on_other_threads() {
+    let shared = SharedOutput::default();
+
+    let workers: Vec<_> = (1..=4)
+        .map(|n| {
+            let out: Box<dyn Write + Send> = Box::new(shared.clone());
+            let builder = VmBuilder::new().out(out).err(Vec::new());
+            thread::spawn(move || {
+                let mut vm = builder.build();
+                run(&mut vm, &format!("var n = {};", n)).unwrap();
+                run(&mut vm, "print n * 10;").unwrap();
+                vm.into_writers().1
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        assert!(worker.join().unwrap().is_empty());
+    }
+
+    let out = String::from_utf8(shared.0.lock().unwrap().clone()).unwrap();
+    let mut lines: Vec<_> = out.lines().collect();
+    lines.sort();
+    assert_eq!(lines, ["10", "20", "30", "40"]);
+}
+
+#[test]
+fn input_can_be_given() {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .input(Cursor::new("first\nsecond\r\n"))
+        .allow(Capability::Io)
+        .build();
+
+    run(
+        &mut vm,
+        "print readLine(); print readLine(); print readLine();",
+    )
+    .unwrap();
+    assert_eq!(vm.out(), b"first\nsecond\nnil\n");
+}
+
+#[test]
+fn natives_and_classes_can_be_defined() {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .native("double", 1, double)
+        .native("clock", 0, |_, _| Ok(Value::Number(1.0)))
+        .class::<List>()
+        .build();
+
+    let source = "
+        var list = List();
+        list.push(double(2));
+        print list.get(0);
+        print clock();
+    ";
+    run(&mut vm, source).unwrap();
+    assert_eq!(vm.out(), b"4\n1\n");
+}
+
+#[test]
+fn reset_keeps_what_the_builder_defined() {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .native("double", 1, double)
+        .class::<List>()
+        .build();
+
+    run(&mut vm, "var a = 1;").unwrap();
+    vm.reset();
+    assert!(vm.get_global("a").is_none());
+
+    assert!(vm.get_global("clock").is_some());
+    run(&mut vm, "print double(2); print List();").unwrap();
+    assert_eq!(vm.out(), b"4\nList instance\n");
+}
+
+#[test]
+fn limits_can_be_set() {
+    let source = "fun f(n) { return f(n + 1); }\nf(0);";
+
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .frame_limit(3)
+        .build();
+    assert!(run(&mut vm, source).is_err());
+    assert_eq!(
+        vm.err(),
+        b"Stack overflow.\n[line 1] in f()\n[line 1] in f()\n[line 2] in script\n"
+    );
+
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .stack_limit(4)
+        .build();
+    assert!(matches!(
+        run(&mut vm, source),
+        Err(VmError::Runtime(RuntimeError::StackOverflow))
+    ));
+
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .heap_limit(1000)
+        .build();
+    assert!(matches!(
+        run(&mut vm, "var s = \"x\"; while (true) s = s + s;"),
+        Err(VmError::Runtime(RuntimeError::OutOfMemory))
+    ));
+
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .fuel(2)
+        .build();
+    assert!(matches!(
+        run(&mut vm, "print 1;"),
+        Err(VmError::Runtime(RuntimeError::OutOfFuel))
+    ));
+    assert!(vm.is_suspended());
+}
+
+#[test]
+fn trace_hooks_see_every_instruction() {
+    let offsets = Arc::new(Mutex::new(Vec::new()));
+    let recorded = offsets.clone();
+
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .trace_hook(move |chunk, offset, stack| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((offset, chunk.read(offset).unwrap(), stack.len()));
+        })
+        .build();
+
+    // Constant, print, and the implicit nil and return.
+    run(&mut vm, "print 1;").unwrap();
+    assert_eq!(
+        *offsets.lock().unwrap(),
+        [
+            (0, OpCode::Constant as u8, 1),
+            (2, OpCode::Print as u8, 2),
+            (3, OpCode::Nil as u8, 1),
+            (4, OpCode::Return as u8, 2)
+        ]
+    );
+
+    vm.set_trace_hook(None);
+    run(&mut vm, "print 2;").unwrap();
+    assert_eq!(offsets.lock().unwrap().len(), 4);
+}
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use rulox::{
+    class::{ClassBuilder, LoxClass},
+    collections::{List, Record},
+    compiler, native,
+    shape::Shape,
+    vm::{CacheStats, ExecutionMode, VM, VmBuilder},
+};
+
+struct Point {
+    x: f64,
+}
+
+impl LoxClass for Point {
+    const NAME: &'static str = "Point";
+
+    fn register(class: &mut ClassBuilder<Self>) {
+        class
+            .constructor(1, |_, args| {
+                Ok(Point {
+                    x: native::arg(args, 0)?,
+                })
+            })
+            .read_only_property("x", |point| point.x.into());
+    }
+}
+
+fn vm(mode: ExecutionMode) -> VM<Vec<u8>, Vec<u8>> {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .execution_mode(mode)
+        .build();
+    vm.define_class::<Record>();
+    vm.define_class::<List>();
+    vm.define_class::<Point>();
+    vm
+}
+
+/// Runs the source in the given mode, returning what it printed, its errors
+/// and how often property accesses hit their caches.
+fn run(source: &str, mode: ExecutionMode) -> (String, String, CacheStats) {
+    let chunk = compiler::compile(source).expect("source should compile");
+    let mut vm = vm(mode);
+    let _ = vm.interpret(&chunk);
+    let stats = vm.cache_stats();
+    let (out, err) = vm.into_writers();
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        stats,
+    )
+}
+
+#[test]
+fn records_with_the_same_fields_share_a_shape() {
+    let mut a = Record::new();
+    a.set("x", 1.0);
+    a.set("y", 2.0);
+
+    let mut b = Record::new();
+    b.set("x", 3.0);
+    b.set("y", 4.0);
+    b.set("x", 5.0);
+    assert!(Arc::ptr_eq(a.shape(), b.shape()));
+    assert_eq!(
+        a.shape().names(),
+        ["x", "y"].map(Arc::from).iter().collect::<Vec<_>>()
+    );
+    assert_eq!(a.shape().slot("y"), Some(1));
+
+    let mut c = Record::new();
+    c.set("y", 2.0);
+    c.set("x", 1.0);
+    assert!(!Arc::ptr_eq(a.shape(), c.shape()));
+    assert_ne!(a, c);
+
+    assert!(Arc::ptr_eq(Record::new().shape(), &Shape::empty()));
+}
+
+#[test]
+fn accesses_to_records_of_the_same_shape_hit() {
+    let source = "
+        fun make(n) { var r = Record(); r.x = n; r.y = n * 2; return r; }
+        var sum = 0;
+        for (var i = 0; i < 10; i = i + 1) {
+            var r = make(i);
+            sum = sum + r.x + r.y;
+        }
+        print sum;
+    ";
+
+    let (out, err, stats) = run(source, ExecutionMode::Fast);
+    assert_eq!((out.as_str(), err.as_str()), ("135\n", ""));
+
+    // Each of the four accesses misses the first time only.
+    assert_eq!(
+        stats,
+        CacheStats {
+            hits: 36,
+            misses: 4
+        }
+    );
+}
+
+#[test]
+fn caches_hold_several_shapes() {
+    let source = "
+        fun get(r) { return r.x; }
+        var a = Record(); a.x = 1;
+        var b = Record(); b.y = 2; b.x = 3;
+        var c = Point(4);
+        for (var i = 0; i < 3; i = i + 1) print get(a) + get(b) + get(c);
+    ";
+
+    let (out, _, stats) = run(source, ExecutionMode::Fast);
+    assert_eq!(out, "8\n8\n8\n");
+
+    // One miss per assignment and per kind of object read.
+    assert_eq!(stats, CacheStats { hits: 6, misses: 6 });
+}
+
+#[test]
+fn accesses_past_the_limit_are_looked_up() {
+    let source = "
+        fun get(r) { return r.x; }
+        var records = List();
+        for (var i = 0; i < 6; i = i + 1) {
+            var r = Record();
+            if (i > 0) r.a = 1;
+            if (i > 1) r.b = 1;
+            if (i > 2) r.c = 1;
+            if (i > 3) r.d = 1;
+            if (i > 4) r.e = 1;
+            r.x = i;
+            records.push(r);
+        }
+
+        var sum = 0;
+        for (var i = 0; i < 6; i = i + 1) sum = sum + get(records.get(i));
+        for (var i = 0; i < 6; i = i + 1) sum = sum + get(records.get(i));
+        print sum;
+    ";
+
+    let (checked, _, _) = run(source, ExecutionMode::Checked);
+    let (fast, err, stats) = run(source, ExecutionMode::Fast);
+    assert_eq!((fast.as_str(), err.as_str()), (checked.as_str(), ""));
+    assert_eq!(fast, "30\n");
+
+    // `r.x` is assigned and read on six shapes, of which only the first four
+    // are cached, so the last two always miss. The other assignments and the
+    // list methods see a single shape each.
+    let x = (4, 6 + 8);
+    let others = (4 + 3 + 2 + 1 + 3 * 5, 5 + 3);
+    assert_eq!(
+        stats,
+        CacheStats {
+            hits: x.0 + others.0,
+            misses: x.1 + others.1,
+        }
+    );
+}
+
+#[test]
+fn cached_accesses_fail_like_checked_ones() {
+    let sources = [
+        "var r = Record(); r.x = 1; fun f(o) { return o.x; } print f(r); print f(1);",
+        "var r = Record(); fun f(o) { return o.y; } print f(r);",
+        "fun f(p) { p.x = 1; } f(Point(1));",
+        "fun f(l) { return l.push; } print f(List()); f(List()).x = 1;",
+        "var r = Record(); fun f(o) { o.x = 1; } f(r); f(r); f(Point(1));",
+        "var p = Point(2); fun f(o) { return o.x; } print f(p); print f(p);",
+    ];
+
+    for source in sources {
+        let (checked_out, checked_err, stats) = run(source, ExecutionMode::Checked);
+        assert_eq!(stats, CacheStats::default(), "{}", source);
+
+        let (out, err, _) = run(source, ExecutionMode::Fast);
+        assert_eq!((out, err), (checked_out, checked_err), "{}", source);
+    }
+}
+
+#[test]
+fn stats_can_be_reset() {
+    let chunk = compiler::compile("var r = Record(); r.x = 1; print r.x;").unwrap();
+    let mut vm = vm(ExecutionMode::Fast);
+    vm.interpret(&chunk).unwrap();
+    assert_eq!(vm.cache_stats(), CacheStats { hits: 0, misses: 2 });
+
+    vm.reset_cache_stats();
+    assert_eq!(vm.cache_stats(), CacheStats::default());
+}
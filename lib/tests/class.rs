@@ -0,0 +1,250 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use rulox::{
+    class::{ClassBuilder, Instance, LoxClass},
+    compiler,
+    native::{self, VmContext},
+    value::Value,
+    vm::{RuntimeError, VM, VmError},
+};
+
+struct Counter {
+    count: f64,
+    step: f64,
+}
+
+impl Counter {
+    fn increment(&mut self, _: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+        self.count += self.step;
+        Ok(self.count.into())
+    }
+
+    /// Calls the given function with the counter, which is in use while it runs.
+    fn visit(&mut self, context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+        let counter = context
+            .global("counter")
+            .cloned()
+            .ok_or(RuntimeError::Native("No counter.".to_string()))?;
+
+        context.call(&args[0], &[counter])
+    }
+}
+
+impl LoxClass for Counter {
+    const NAME: &'static str = "Counter";
+
+    fn register(class: &mut ClassBuilder<Self>) {
+        class
+            .constructor(1, |_, args| {
+                Ok(Counter {
+                    count: 0.0,
+                    step: native::arg(args, 0)?,
+                })
+            })
+            .property(
+                "count",
+                |counter| counter.count.into(),
+                |counter, value| {
+                    counter.count = value
+                        .try_into()
+                        .map_err(|_| RuntimeError::InvalidPropertyValue("count".to_string()))?;
+                    Ok(())
+                },
+            )
+            .read_only_property("step", |counter| counter.step.into())
+            .method("increment", 0, Counter::increment)
+            .method("visit", 1, Counter::visit);
+    }
+}
+
+/// A class the host creates instances of, counting how many are alive.
+struct Handle {
+    alive: Arc<AtomicUsize>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.alive.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoxClass for Handle {
+    const NAME: &'static str = "Handle";
+
+    fn register(_: &mut ClassBuilder<Self>) {}
+}
+
+/// Compiles and runs the source with the `Counter` class defined, returning
+/// stdout, stderr and the result.
+fn run(source: &str) -> (String, String, Result<(), VmError>) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<Counter>();
+    let result = vm.interpret(&chunk);
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        result,
+    )
+}
+
+#[test]
+fn classes_construct_instances() {
+    let (out, _, result) = run("var c = Counter(2); print Counter; print c; print c.step;");
+    assert!(result.is_ok());
+    assert_eq!(out, "Counter\nCounter instance\n2\n");
+}
+
+#[test]
+fn properties_can_be_assigned() {
+    let (out, _, result) = run("var c = Counter(1); print c.count = 5; print c.count;");
+    assert!(result.is_ok());
+    assert_eq!(out, "5\n5\n");
+}
+
+#[test]
+fn methods_can_be_called_and_stored() {
+    let source = "
+        var c = Counter(3);
+        c.increment();
+        var increment = c.increment;
+        print increment;
+        print increment();
+        print c.count;
+    ";
+    let (out, _, result) = run(source);
+    assert!(result.is_ok());
+    assert_eq!(out, "<native fn>\n6\n6\n");
+}
+
+#[test]
+fn invalid_property_access_is_a_runtime_error() {
+    let (_, err, result) = run("var c = Counter(1);\nc.step = 2;");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::ReadOnlyProperty(_)))
+    ));
+    assert_eq!(
+        err,
+        "Can't assign to read-only property 'step'.\n[line 2] in script\n"
+    );
+
+    let (_, err, _) = run("var c = Counter(1);\nprint c.missing;");
+    assert_eq!(err, "Undefined property 'missing'.\n[line 2] in script\n");
+
+    let (_, err, _) = run("var c = Counter(1);\nc.count = \"many\";");
+    assert_eq!(
+        err,
+        "Invalid value for property 'count'.\n[line 2] in script\n"
+    );
+
+    let (_, err, _) = run("Counter();");
+    assert_eq!(err, "Expected 1 arguments but got 0.\n[line 1] in script\n");
+}
+
+#[test]
+fn instances_in_use_cannot_be_used_again() {
+    let source = "
+        var counter = Counter(1);
+        fun visitor(c) { return c.count; }
+        counter.visit(visitor);
+    ";
+    let (_, err, result) = run(source);
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::InstanceInUse))
+    ));
+    assert_eq!(
+        err,
+        "Instance is already in use.\n[line 3] in visitor()\n[line 4] in script\n"
+    );
+}
+
+#[test]
+fn host_owned_instances_can_be_shared_with_lox() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    let class = vm.define_class::<Counter>();
+    let counter = class.instantiate(Counter {
+        count: 10.0,
+        step: 5.0,
+    });
+    vm.set_global("counter", counter.clone());
+
+    let chunk =
+        compiler::compile("counter.increment(); counter.count = counter.count * 2;").unwrap();
+    vm.interpret(&chunk).unwrap();
+
+    let instance: Arc<Instance> = counter.try_into().unwrap();
+    assert!(instance.is::<Counter>());
+    assert!(instance.borrow::<Handle>().is_none());
+
+    assert_eq!(instance.borrow::<Counter>().unwrap().count, 30.0);
+    instance.borrow_mut::<Counter>().unwrap().count = 1.0;
+    assert_eq!(
+        vm.get_global("counter").map(|c| c.to_string()),
+        Some("Counter instance".to_string())
+    );
+}
+
+#[test]
+fn classes_without_constructors_cannot_be_called() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<Handle>();
+
+    let chunk = compiler::compile("Handle();").unwrap();
+    assert!(matches!(
+        vm.interpret(&chunk),
+        Err(VmError::Runtime(RuntimeError::NotCallable))
+    ));
+}
+
+#[test]
+fn instances_are_dropped_when_unreachable() {
+    let alive = Arc::new(AtomicUsize::new(0));
+
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    let class = vm.define_class::<Handle>();
+
+    for name in ["a", "b"] {
+        alive.fetch_add(1, Ordering::Relaxed);
+        let handle = class.instantiate(Handle {
+            alive: alive.clone(),
+        });
+        vm.set_global(name, handle);
+    }
+
+    let chunk = compiler::compile("var c = a; a = nil;").unwrap();
+    vm.interpret(&chunk).unwrap();
+    assert_eq!(alive.load(Ordering::Relaxed), 2);
+
+    let chunk = compiler::compile("b = nil; c = nil;").unwrap();
+    vm.interpret(&chunk).unwrap();
+    assert_eq!(alive.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+#[should_panic(expected = "class Counter was not built for this type")]
+fn instantiating_the_wrong_type_panics() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    let class = vm.define_class::<Counter>();
+    class.instantiate(Handle {
+        alive: Arc::new(AtomicUsize::new(1)),
+    });
+}
@@ -0,0 +1,82 @@
+use rulox::{
+    collections::{List, Record},
+    compiler,
+    vm::{VM, VmError},
+};
+
+/// Runs the source with the collection classes defined, returning stdout,
+/// stderr and the result.
+fn run(source: &str) -> (String, String, Result<(), VmError>) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<Record>();
+    vm.define_class::<List>();
+    let result = vm.interpret(&chunk);
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        result,
+    )
+}
+
+#[test]
+fn records_hold_any_fields() {
+    let source = "
+        var r = Record();
+        r.a = 1;
+        r.b = \"two\";
+        r.a = r.a + 1;
+        print r.a;
+        print r.b;
+        print r;
+    ";
+    let (out, _, result) = run(source);
+    assert!(result.is_ok());
+    assert_eq!(out, "2\ntwo\nRecord instance\n");
+
+    let (_, err, _) = run("var r = Record();\nprint r.missing;");
+    assert_eq!(err, "Undefined property 'missing'.\n[line 2] in script\n");
+}
+
+#[test]
+fn lists_are_indexed_from_zero() {
+    let source = "
+        var list = List();
+        list.push(\"a\");
+        list.push(\"b\");
+        print list.set(1, \"c\");
+        print list.get(0) + list.get(1);
+        print list.len();
+    ";
+    let (out, _, result) = run(source);
+    assert!(result.is_ok());
+    assert_eq!(out, "c\nac\n2\n");
+
+    for index in ["2", "-1", "0.5"] {
+        let source = format!(
+            "var list = List();\nlist.push(1);\nlist.push(2);\nlist.get({});",
+            index
+        );
+        let (_, err, _) = run(&source);
+        assert_eq!(
+            err,
+            format!("List index {} out of range.\n[line 4] in script\n", index)
+        );
+    }
+}
+
+#[test]
+fn records_keep_their_field_order() {
+    let mut record = Record::new();
+    record.set("b", 1);
+    record.set("a", 2);
+    record.set("b", 3);
+
+    let fields: Vec<_> = record.fields().map(|(name, _)| name).collect();
+    assert_eq!(fields, ["b", "a"]);
+    assert_eq!(record.get("b"), Some(&3.into()));
+}
@@ -0,0 +1,192 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use rulox::{
+    collections::List,
+    compiler::{Chunk, OpCode},
+    function::Function,
+    value::Value,
+    vm::{RuntimeError, VmBuilder, VmError},
+};
+
+/// Runs the code with a few constants, returning the runtime error it failed with.
+fn run(code: &[u8]) -> Option<RuntimeError> {
+    let mut chunk = Chunk::new();
+    chunk.constants.push(Value::Number(1.0));
+    chunk.constants.push("name".into());
+    for &byte in code {
+        chunk.write(byte, 1);
+    }
+
+    let mut vm = VmBuilder::new().out(Vec::new()).err(Vec::new()).build();
+    match vm.interpret(&chunk) {
+        Ok(()) => None,
+        Err(VmError::Runtime(error)) => Some(error),
+        Err(VmError::Compilation) => panic!("running a chunk can't fail to compile"),
+    }
+}
+
+#[test]
+fn truncated_instructions_are_errors() {
+    let truncated = [
+        vec![OpCode::Constant as u8],
+        vec![OpCode::ConstantLong as u8, 0, 0],
+        vec![OpCode::GetLocal as u8],
+        vec![OpCode::Jump as u8, 0],
+        vec![OpCode::Nil as u8, OpCode::JumpIfFalse as u8],
+        vec![OpCode::Call as u8],
+    ];
+
+    for code in truncated {
+        assert!(
+            matches!(run(&code), Some(RuntimeError::TruncatedInstruction)),
+            "{:?}",
+            code
+        );
+    }
+}
+
+#[test]
+fn bad_constant_indices_are_errors() {
+    assert!(matches!(
+        run(&[OpCode::Constant as u8, 2]),
+        Some(RuntimeError::InvalidConstant(2))
+    ));
+    assert!(matches!(
+        run(&[OpCode::ConstantLong as u8, 1, 0, 0]),
+        Some(RuntimeError::InvalidConstant(0x10000))
+    ));
+    assert!(matches!(
+        run(&[OpCode::GetGlobal as u8, 200]),
+        Some(RuntimeError::InvalidConstant(200))
+    ));
+}
+
+#[test]
+fn bad_local_slots_are_errors() {
+    assert!(matches!(
+        run(&[OpCode::GetLocal as u8, 3]),
+        Some(RuntimeError::InvalidLocal(3))
+    ));
+    assert!(matches!(
+        run(&[OpCode::Nil as u8, OpCode::SetLocal as u8, 9]),
+        Some(RuntimeError::InvalidLocal(9))
+    ));
+}
+
+#[test]
+fn leaving_the_code_is_an_error() {
+    assert!(matches!(run(&[]), Some(RuntimeError::IpOutOfRange)));
+    assert!(matches!(
+        run(&[OpCode::Nil as u8, OpCode::Pop as u8]),
+        Some(RuntimeError::IpOutOfRange)
+    ));
+    assert!(matches!(
+        run(&[OpCode::Jump as u8, 0, 10, OpCode::Return as u8]),
+        Some(RuntimeError::IpOutOfRange)
+    ));
+    assert!(matches!(
+        run(&[OpCode::Loop as u8, 0, 10]),
+        Some(RuntimeError::IpOutOfRange)
+    ));
+}
+
+/// A small xorshift generator, so failures can be reproduced from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Generates code that is mostly opcodes followed by a few operand bytes,
+    /// which gets further into execution than uniformly random bytes.
+    fn code(&mut self) -> Vec<u8> {
+        let len = self.below(48);
+        let mut code = Vec::new();
+
+        while (code.len() as u64) < len {
+            if self.below(4) == 0 {
+                code.push(self.next() as u8);
+                continue;
+            }
+
+            code.push(self.below(OpCode::JumpIfNotLess as u64 + 2) as u8);
+            for _ in 0..self.below(3) {
+                code.push(self.below(8) as u8);
+            }
+        }
+
+        code
+    }
+
+    fn chunk(&mut self, depth: usize) -> Chunk {
+        let mut chunk = Chunk::new();
+        for byte in self.code() {
+            chunk.write(byte, 1 + self.below(3) as usize);
+        }
+
+        chunk.constants.push(Value::Number(self.below(4) as f64));
+        chunk.constants.push("List".into());
+        chunk.constants.push(Value::Nil);
+        if depth > 0 {
+            let mut function = Function::new("f");
+            function.arity = self.below(3) as u8;
+            function.chunk = self.chunk(depth - 1);
+            chunk.constants.push(Value::Function(Arc::new(function)));
+        }
+
+        chunk
+    }
+}
+
+#[test]
+fn interpret_never_panics() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+
+    for case in 0..5000 {
+        let chunk = rng.chunk(2);
+        let mut vm = VmBuilder::new()
+            .out(Vec::new())
+            .err(Vec::new())
+            .class::<List>()
+            .fuel(1000)
+            .build();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = vm.interpret(&chunk);
+        }));
+
+        if result.is_err() {
+            let code: Vec<u8> = (0..).map_while(|offset| chunk.read(offset)).collect();
+            panic!("interpreting case {} panicked on {:?}", case, code);
+        }
+    }
+}
+
+#[test]
+fn disassembling_never_panics() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+    for case in 0..2000 {
+        let chunk = rng.chunk(2);
+        let code: Vec<u8> = (0..).map_while(|offset| chunk.read(offset)).collect();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            chunk.disassemble("case");
+            chunk.disassemble_instruction(code.len());
+        }));
+
+        if result.is_err() {
+            panic!("disassembling case {} panicked on {:?}", case, code);
+        }
+    }
+}
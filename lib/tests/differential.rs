@@ -0,0 +1,68 @@
+use rulox::{
+    differential::{self, Status},
+    generate::Generator,
+};
+
+/// Generated programs behave the same on the VM and the tree-walking interpreter.
+#[test]
+fn generated_programs_match() {
+    let mut generator = Generator::new(0);
+
+    for _ in 0..1000 {
+        let source = generator.program();
+        if let Err(mismatch) = differential::check(&source) {
+            panic!("{}\n{}", source, mismatch);
+        }
+    }
+}
+
+/// The generator produces programs that succeed as well as ones that fail to
+/// compile or fail at runtime, so all of those paths get compared.
+#[test]
+fn generated_programs_cover_every_status() {
+    let mut generator = Generator::new(0);
+    let statuses: Vec<_> = (0..1000)
+        .map(|_| differential::run_vm(&generator.program()).status)
+        .collect();
+
+    for status in [Status::Success, Status::CompileError, Status::RuntimeError] {
+        let count = statuses.iter().filter(|&&s| s == status).count();
+        assert!(count > 0, "no generated program ended with {:?}", status);
+    }
+}
+
+/// Runtime errors are reported on the line of the failing operation, which is
+/// that of its last operand.
+#[test]
+fn runtime_error_lines_match() {
+    let source = "var a = 1;\nprint a +\n\"b\";";
+    let vm = differential::run_vm(source);
+
+    assert_eq!(vm.status, Status::RuntimeError);
+    assert_eq!(
+        vm.stderr,
+        "Operands must be two numbers or two strings.\n[line 3] in script\n"
+    );
+    assert_eq!(vm, differential::run_interpreter(source));
+}
+
+/// Runtime errors inside calls keep the output printed before them and report
+/// the same stack trace on both implementations.
+#[test]
+fn runtime_errors_in_calls_match() {
+    let source = "fun inner(n) {\n  return n + \"x\";\n}\n\
+                  fun outer() {\n  print \"before\";\n  inner(1);\n  print \"after\";\n}\n\
+                  outer();";
+    let vm = differential::run_vm(source);
+
+    assert_eq!(vm.status, Status::RuntimeError);
+    assert_eq!(vm.stdout, "before\n");
+    assert_eq!(
+        vm.stderr,
+        "Operands must be two numbers or two strings.\n\
+         [line 2] in inner()\n\
+         [line 6] in outer()\n\
+         [line 9] in script\n"
+    );
+    assert_eq!(vm, differential::run_interpreter(source));
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use rulox::{
+    compiler::{self, Chunk, OpCode},
+    function::Function,
+    verify,
+    vm::{ExecutionMode, RuntimeError, VmBuilder, VmError},
+};
+
+/// Runs the chunk in the given mode, returning stdout, stderr and the result.
+fn run(
+    chunk: &Chunk,
+    mode: ExecutionMode,
+    fuel: Option<u64>,
+) -> (String, String, Result<(), VmError>) {
+    let mut builder = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .execution_mode(mode);
+    if let Some(fuel) = fuel {
+        builder = builder.fuel(fuel);
+    }
+
+    let mut vm = builder.build();
+    let result = vm.interpret(chunk);
+    let (out, err) = vm.into_writers();
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        result,
+    )
+}
+
+#[test]
+fn compiled_functions_verify() {
+    let source = "
+        fun add(a, b) { var sum = a + b; return sum; }
+        fun count(n) { for (var i = 0; i < n; i = i + 1) { if (i > 2 and i < 4) print i; } }
+        print add(1, 2);
+    ";
+    let chunk = compiler::compile(source).unwrap();
+    assert_eq!(verify::verify(&chunk), Ok(()));
+}
+
+#[test]
+fn fast_mode_behaves_like_checked_mode() {
+    let sources = [
+        "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }\nprint fib(15);",
+        "var s = \"\"; for (var i = 0; i < 5; i = i + 1) s = s + \"ab\"; print s;",
+        "var a = 1; { var b = 2; { var c = a + b; print c; a = c; } } print a or nil;",
+        "fun f(x) { return -x; }\nfun g() { return f(\"s\"); }\nprint 1;\ng();",
+        "print !nil == true;\nprint missing;",
+    ];
+
+    for source in sources {
+        let chunk = compiler::compile(source).unwrap();
+        let (out, err, result) = run(&chunk, ExecutionMode::Checked, None);
+        let (fast_out, fast_err, fast_result) = run(&chunk, ExecutionMode::Fast, None);
+
+        assert_eq!(fast_out, out, "{}", source);
+        assert_eq!(fast_err, err, "{}", source);
+        assert_eq!(fast_result.is_ok(), result.is_ok(), "{}", source);
+    }
+}
+
+#[test]
+fn fuel_is_used_the_same_in_fast_mode() {
+    let chunk = compiler::compile("var i = 0; while (i < 10) { print i; i = i + 1; }").unwrap();
+
+    for fuel in [0, 1, 7, 40, 1000] {
+        let (out, _, result) = run(&chunk, ExecutionMode::Checked, Some(fuel));
+        let (fast_out, _, fast_result) = run(&chunk, ExecutionMode::Fast, Some(fuel));
+
+        assert_eq!(fast_out, out, "fuel {}", fuel);
+        assert_eq!(
+            matches!(fast_result, Err(VmError::Runtime(RuntimeError::OutOfFuel))),
+            matches!(result, Err(VmError::Runtime(RuntimeError::OutOfFuel))),
+            "fuel {}",
+            fuel
+        );
+    }
+}
+
+#[test]
+fn suspended_scripts_resume_in_fast_mode() {
+    let chunk = compiler::compile("for (var i = 0; i < 3; i = i + 1) print i;").unwrap();
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .execution_mode(ExecutionMode::Fast)
+        .fuel(10)
+        .build();
+
+    assert!(vm.interpret(&chunk).is_err());
+    while vm.is_suspended() {
+        vm.add_fuel(10);
+        let _ = vm.resume();
+    }
+
+    assert_eq!(vm.out(), b"0\n1\n2\n");
+}
+
+#[test]
+fn scripts_are_decoded_once() {
+    let chunk = compiler::compile("fun f(n) { return n * 2; }\nprint f(21);").unwrap();
+    let script = Arc::new(Function::script(chunk));
+    assert!(!script.is_decoded());
+
+    for _ in 0..2 {
+        let mut vm = VmBuilder::new()
+            .out(Vec::new())
+            .err(Vec::new())
+            .execution_mode(ExecutionMode::Fast)
+            .build();
+
+        assert!(vm.interpret_function(script.clone()).is_ok());
+        assert!(script.is_decoded());
+        assert_eq!(vm.out(), b"42\n");
+    }
+}
+
+#[test]
+fn invalid_bytecode_is_rejected_before_running() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpCode::Nil, 1);
+    chunk.write(OpCode::Print, 1);
+    chunk.write(OpCode::GetLocal, 2);
+    chunk.write(3, 2);
+    chunk.write(OpCode::Return, 2);
+
+    let (out, _, result) = run(&chunk, ExecutionMode::Checked, None);
+    assert_eq!(out, "nil\n");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::InvalidLocal(3)))
+    ));
+
+    let (out, err, result) = run(&chunk, ExecutionMode::Fast, None);
+    assert_eq!(out, "");
+    assert_eq!(
+        err,
+        "Invalid bytecode: Instruction at offset 2 refers to missing local slot 3\n[line 1] in script\n"
+    );
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::InvalidBytecode(_)))
+    ));
+}
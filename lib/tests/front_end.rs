@@ -0,0 +1,201 @@
+use rulox::{
+    ast::{BinaryOperator, ExprKind, Literal, Position, Span, Stmt, StmtKind},
+    parser,
+    resolver::{self, Resolution},
+};
+
+/// Parses the source, which must be free of syntax errors.
+fn parse(source: &str) -> Vec<Stmt> {
+    let parse = parser::parse(source);
+    let errors: Vec<_> = parse.errors.iter().map(|e| e.error.to_string()).collect();
+    assert!(errors.is_empty(), "{}: {:?}", source, errors);
+    parse.program.statements
+}
+
+fn resolve(source: &str) -> Resolution {
+    resolver::resolve(&parser::parse(source).program)
+}
+
+fn errors(resolution: &Resolution) -> Vec<String> {
+    resolution
+        .errors
+        .iter()
+        .map(|diagnostic| diagnostic.error.to_string())
+        .collect()
+}
+
+fn position(line: i32, column: i32) -> Position {
+    Position { line, column }
+}
+
+#[test]
+fn nodes_have_spans() {
+    let statements = parse("var a = 1;\nprint a + 2;");
+
+    assert_eq!(statements.len(), 2);
+    assert_eq!(
+        statements[0].span,
+        Span::new(position(1, 1), position(1, 10))
+    );
+    assert_eq!(
+        statements[1].span,
+        Span::new(position(2, 1), position(2, 12))
+    );
+
+    let StmtKind::Print(expr) = &statements[1].kind else {
+        panic!("expected a print statement, got {:?}", statements[1]);
+    };
+    assert_eq!(expr.span, Span::new(position(2, 7), position(2, 11)));
+
+    let ExprKind::Binary {
+        operator,
+        left,
+        right,
+    } = &expr.kind
+    else {
+        panic!("expected a binary expression, got {:?}", expr);
+    };
+    assert_eq!(*operator, BinaryOperator::Add);
+    assert!(matches!(&left.kind, ExprKind::Variable(name) if name.name == "a"));
+    assert_eq!(right.kind, ExprKind::Literal(Literal::Number(2.0)));
+}
+
+#[test]
+fn precedence_is_respected() {
+    let statements = parse("1 + 2 * 3;");
+
+    let StmtKind::Expression(expr) = &statements[0].kind else {
+        panic!("expected an expression statement, got {:?}", statements[0]);
+    };
+    let ExprKind::Binary {
+        operator, right, ..
+    } = &expr.kind
+    else {
+        panic!("expected a binary expression, got {:?}", expr);
+    };
+    assert_eq!(*operator, BinaryOperator::Add);
+    assert!(matches!(
+        right.kind,
+        ExprKind::Binary {
+            operator: BinaryOperator::Multiply,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn parsing_recovers_after_errors() {
+    let parse = parser::parse("var = 1;\nprint 2;\nprint (3;");
+
+    let errors: Vec<_> = parse.errors.iter().map(|e| e.error.to_string()).collect();
+    assert_eq!(
+        errors,
+        [
+            "[line 1] Error at '=': Expect variable name.",
+            "[line 3] Error at ';': Expect ')' after expression.",
+        ]
+    );
+
+    let statements = &parse.program.statements;
+    assert_eq!(statements.len(), 3);
+    assert_eq!(statements[0].kind, StmtKind::Error);
+    assert!(matches!(statements[1].kind, StmtKind::Print(_)));
+}
+
+#[test]
+fn locals_are_resolved_to_slots() {
+    let source = "{ var a = 1; var b = 2; print b; print a; }\nprint a;";
+    let statements = parse(source);
+    let resolution = resolver::resolve(&parser::parse(source).program);
+
+    let StmtKind::Block(block) = &statements[0].kind else {
+        panic!("expected a block, got {:?}", statements[0]);
+    };
+    let slots: Vec<_> = block[2..]
+        .iter()
+        .map(|statement| match &statement.kind {
+            StmtKind::Print(expr) => match &expr.kind {
+                ExprKind::Variable(name) => resolution.slot(name),
+                _ => panic!("expected a variable, got {:?}", expr),
+            },
+            _ => panic!("expected a print statement, got {:?}", statement),
+        })
+        .collect();
+    assert_eq!(slots, [Some(2), Some(1)]);
+
+    let StmtKind::Print(expr) = &statements[1].kind else {
+        panic!("expected a print statement, got {:?}", statements[1]);
+    };
+    let ExprKind::Variable(name) = &expr.kind else {
+        panic!("expected a variable, got {:?}", expr);
+    };
+    assert_eq!(resolution.slot(name), None);
+}
+
+#[test]
+fn unused_locals_are_warned_about() {
+    let resolution = resolve(
+        "var global;\n\
+         fun f(unused) {\n  var a = 1;\n  var _b = 2;\n  var c = 3;\n  print c;\n}\n\
+         { var d; }",
+    );
+
+    let warnings: Vec<_> = resolution
+        .warnings
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "[line 3] Warning: Local variable 'a' is never used.",
+            "[line 8] Warning: Local variable 'd' is never used.",
+        ]
+    );
+    assert!(resolution.errors.is_empty());
+}
+
+#[test]
+fn assigning_a_local_is_not_using_it() {
+    let resolution = resolve("{ var a; a = 1; }");
+
+    assert_eq!(resolution.warnings.len(), 1);
+    assert_eq!(resolution.warnings[0].span.start, position(1, 7));
+}
+
+#[test]
+fn misuse_is_reported() {
+    let cases = [
+        (
+            "return 1;",
+            "[line 1] Error at 'return': Can't return from top-level code.",
+        ),
+        (
+            "print this;",
+            "[line 1] Error at 'this': Can't use 'this' outside of a class.",
+        ),
+        (
+            "fun f() { super.g(); }",
+            "[line 1] Error at 'super': Can't use 'super' outside of a class.",
+        ),
+        (
+            "{ var a = a; }",
+            "[line 1] Error at 'a': Can't read local variable in its own initializer.",
+        ),
+        (
+            "{ var a; var a; }",
+            "[line 1] Error at 'a': Already a variable with this name in this scope.",
+        ),
+    ];
+
+    for (source, error) in cases {
+        assert_eq!(errors(&resolve(source)), [error], "{}", source);
+    }
+}
+
+#[test]
+fn returning_from_functions_is_allowed() {
+    let resolution = resolve("fun f() { if (true) return 1; return; }");
+
+    assert!(resolution.errors.is_empty(), "{:?}", errors(&resolution));
+}
@@ -0,0 +1,160 @@
+use std::{thread, time::Duration};
+
+use rulox::{
+    compiler,
+    native::VmContext,
+    value::Value,
+    vm::{RuntimeError, VM, VmError},
+};
+
+/// Calls the function passed as the only argument.
+fn call(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    context.call(&args[0], &[])
+}
+
+/// Compiles and runs the source on the given VM.
+fn run(vm: &mut VM<&mut Vec<u8>, &mut Vec<u8>>, source: &str) -> Result<(), VmError> {
+    let chunk = compiler::compile(source).expect("source should compile");
+    vm.interpret(&chunk)
+}
+
+#[test]
+fn each_instruction_uses_one_unit_of_fuel() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    assert_eq!(vm.fuel(), None);
+
+    // Constant, pop, and the implicit nil and return.
+    vm.set_fuel(Some(10));
+    run(&mut vm, "1;").unwrap();
+    assert_eq!(vm.fuel(), Some(6));
+
+    vm.add_fuel(4);
+    assert_eq!(vm.fuel(), Some(10));
+
+    vm.set_fuel(None);
+    vm.add_fuel(4);
+    assert_eq!(vm.fuel(), None);
+}
+
+#[test]
+fn scripts_out_of_fuel_can_be_resumed() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_fuel(Some(100));
+
+    let source = "
+        fun count(n) {
+            var total = 0;
+            for (var i = 0; i < n; i = i + 1) total = total + 1;
+            return total;
+        }
+        print count(100);
+    ";
+    let mut result = run(&mut vm, source);
+
+    let mut refuels = 0;
+    while let Err(VmError::Runtime(RuntimeError::OutOfFuel)) = result {
+        assert!(vm.is_suspended());
+        assert_eq!(vm.fuel(), Some(0));
+
+        refuels += 1;
+        vm.add_fuel(100);
+        result = vm.resume();
+    }
+
+    result.unwrap();
+    assert!(!vm.is_suspended());
+    assert!(refuels > 10);
+    assert!(vm.stack().is_empty());
+    assert_eq!(out, b"100\n");
+    assert!(err.is_empty());
+}
+
+#[test]
+fn running_scripts_can_be_interrupted_from_another_thread() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    let handle = vm.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    let result = run(&mut vm, "var i = 0; while (true) i = i + 1;");
+    interrupter.join().unwrap();
+
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::Interrupted))
+    ));
+    assert!(vm.is_suspended());
+
+    // The loop continues where it stopped.
+    let Some(Value::Number(count)) = vm.get_global("i") else {
+        panic!("the loop has started");
+    };
+    vm.set_fuel(Some(10));
+    assert!(vm.resume().is_err());
+    assert!(matches!(vm.get_global("i"), Some(Value::Number(n)) if n > count));
+
+    // Interpreting another script abandons the loop.
+    vm.set_fuel(None);
+    vm.interrupt_handle().interrupt();
+    run(&mut vm, "print i > 0;").unwrap();
+    assert!(!vm.is_suspended());
+    assert_eq!(out, b"true\n");
+}
+
+#[test]
+fn running_out_of_fuel_in_a_callback_is_an_error() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_native("call", 1, call);
+    vm.set_fuel(Some(1000));
+
+    let source = "
+        fun spin() {
+            while (true) {}
+        }
+        call(spin);
+    ";
+    let result = run(&mut vm, source);
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::OutOfFuel))
+    ));
+    assert!(!vm.is_suspended());
+    assert_eq!(
+        String::from_utf8(err).unwrap(),
+        "Out of fuel.\n[line 3] in spin()\n[line 5] in script\n"
+    );
+}
+
+#[test]
+fn host_calls_cannot_be_suspended() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    run(&mut vm, "fun spin() {\n  while (true) {}\n}").unwrap();
+    let spin = vm.get_global("spin").unwrap();
+
+    vm.set_fuel(Some(1000));
+    let result = vm.call(&spin, &[]);
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::OutOfFuel))
+    ));
+    assert!(!vm.is_suspended());
+    assert!(vm.stack().is_empty());
+    assert_eq!(
+        String::from_utf8(err).unwrap(),
+        "Out of fuel.\n[line 2] in spin()\n"
+    );
+}
@@ -0,0 +1,158 @@
+use rulox::{
+    compiler,
+    native::{self, VmContext},
+    value::Value,
+    vm::{RuntimeError, VM, VmError},
+};
+
+/// Calls the function passed as the first argument with the second.
+fn apply(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let argument: f64 = native::arg(args, 1)?;
+    context.call(&args[0], &[argument.into()])
+}
+
+/// Compiles and runs the source on the given VM.
+fn run(vm: &mut VM<&mut Vec<u8>, &mut Vec<u8>>, source: &str) -> Result<(), VmError> {
+    let chunk = compiler::compile(source).expect("source should compile");
+    vm.interpret(&chunk)
+}
+
+#[test]
+fn globals_can_be_read_and_written() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    vm.set_global("greeting", "Hello");
+    run(&mut vm, "var answer = 6 * 7; print greeting + \"!\";").unwrap();
+
+    assert_eq!(vm.get_global("answer"), Some(Value::Number(42.0)));
+    assert_eq!(vm.get_global("missing"), None);
+    assert_eq!(out, b"Hello!\n");
+}
+
+#[test]
+fn lox_functions_can_be_called() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    run(&mut vm, "fun add(a, b) { print a; return a + b; }").unwrap();
+    let add = vm.get_global("add").unwrap();
+
+    let result = vm.call(&add, &[1.0.into(), 2.0.into()]).unwrap();
+    assert_eq!(result, Value::Number(3.0));
+    assert!(vm.stack().is_empty());
+    assert_eq!(out, b"1\n");
+}
+
+#[test]
+fn functions_keep_their_state_between_calls() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    run(
+        &mut vm,
+        "var count = 0; fun increment() { count = count + 1; }",
+    )
+    .unwrap();
+    let increment = vm.get_global("increment").unwrap();
+
+    for _ in 0..3 {
+        assert_eq!(vm.call(&increment, &[]).unwrap(), Value::Nil);
+    }
+
+    assert_eq!(vm.get_global("count"), Some(Value::Number(3.0)));
+}
+
+#[test]
+fn failed_calls_report_a_stack_trace() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    run(
+        &mut vm,
+        "fun inner() {\n  return -nil;\n}\nfun outer() {\n  inner();\n}",
+    )
+    .unwrap();
+    let outer = vm.get_global("outer").unwrap();
+
+    let result = vm.call(&outer, &[]);
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::OperandMustBeNumber))
+    ));
+
+    // The VM is still usable afterwards.
+    assert!(vm.stack().is_empty());
+    let inner = vm.get_global("inner").unwrap();
+    assert!(vm.call(&inner, &[]).is_err());
+
+    let err = String::from_utf8(err).unwrap();
+    assert_eq!(
+        err,
+        "Operand must be a number.\n[line 2] in inner()\n[line 5] in outer()\n\
+         Operand must be a number.\n[line 2] in inner()\n"
+    );
+}
+
+#[test]
+fn calls_are_checked() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    run(&mut vm, "fun f(a) {}").unwrap();
+    let f = vm.get_global("f").unwrap();
+
+    assert!(matches!(
+        vm.call(&f, &[]),
+        Err(VmError::Runtime(RuntimeError::WrongArity {
+            expected: 1,
+            got: 0
+        }))
+    ));
+    assert!(matches!(
+        vm.call(&Value::Nil, &[]),
+        Err(VmError::Runtime(RuntimeError::NotCallable))
+    ));
+}
+
+#[test]
+fn natives_can_call_back_into_lox() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_native("apply", 2, apply);
+
+    let source = "
+        fun double(n) { return n * 2; }
+        fun quadruple(n) { return apply(double, apply(double, n)); }
+        print apply(quadruple, 5);
+    ";
+    run(&mut vm, source).unwrap();
+
+    let quadruple = vm.get_global("quadruple").unwrap();
+    assert_eq!(
+        vm.call(&quadruple, &[1.0.into()]).unwrap(),
+        Value::Number(4.0)
+    );
+    assert_eq!(out, b"20\n");
+}
+
+#[test]
+fn unbounded_recursion_overflows_the_stack() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_native("apply", 2, apply);
+
+    let result = run(&mut vm, "fun f(n) { return apply(f, n); } f(1);");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::StackOverflow))
+    ));
+    assert!(vm.stack().is_empty());
+}
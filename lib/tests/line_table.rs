@@ -0,0 +1,85 @@
+use rulox::compiler::{self, Chunk, OpCode};
+
+/// Writes one byte per entry in `lines`, returning the chunk.
+fn chunk_with_lines(lines: &[usize]) -> Chunk {
+    let mut chunk = Chunk::new();
+    for &line in lines {
+        chunk.write(OpCode::Return, line);
+    }
+
+    chunk
+}
+
+/// Checks that every offset maps to the same line as in a plain per-byte line list.
+fn assert_lines(chunk: &Chunk, lines: &[usize]) {
+    for (offset, &line) in lines.iter().enumerate() {
+        assert_eq!(chunk.line_for_offset(offset), Some(line), "offset {offset}");
+    }
+
+    assert_eq!(chunk.line_for_offset(lines.len()), None);
+}
+
+#[test]
+fn empty_chunk_has_no_lines() {
+    let chunk = Chunk::new();
+
+    assert_eq!(chunk.line_for_offset(0), None);
+}
+
+#[test]
+fn single_line() {
+    let lines = [1, 1, 1, 1];
+
+    assert_lines(&chunk_with_lines(&lines), &lines);
+}
+
+#[test]
+fn multiple_runs() {
+    let lines = [1, 1, 2, 2, 2, 3, 7, 7, 8];
+
+    assert_lines(&chunk_with_lines(&lines), &lines);
+}
+
+#[test]
+fn lines_going_backwards() {
+    let lines = [3, 3, 1, 1, 3, 2, 2, 2, 1];
+
+    assert_lines(&chunk_with_lines(&lines), &lines);
+}
+
+#[test]
+fn constants_share_the_line_of_their_instruction() {
+    let mut chunk = Chunk::new();
+    chunk.write_constant(1.0, 1).unwrap();
+    chunk.write_constant(2.0, 2).unwrap();
+    chunk.write(OpCode::Add, 2);
+    chunk.write(OpCode::Return, 3);
+
+    assert_lines(&chunk, &[1, 1, 2, 2, 2, 3]);
+}
+
+#[test]
+fn long_constants_share_the_line_of_their_instruction() {
+    let mut chunk = Chunk::new();
+    for _ in 0..=u8::MAX {
+        chunk.write_constant(0.0, 1).unwrap();
+    }
+
+    chunk.write_constant(1.0, 2).unwrap();
+
+    let mut lines = vec![1; 2 * 256];
+    lines.extend([2; 4]);
+    assert_lines(&chunk, &lines);
+}
+
+#[test]
+fn lines_survive_serialization() {
+    let chunk = compiler::compile("print -(1 + 2)\n * 3\n\n / 4;").unwrap();
+    let restored = Chunk::deserialize(&chunk.serialize()).unwrap();
+
+    let lines: Vec<_> = (0..)
+        .map_while(|offset| chunk.line_for_offset(offset))
+        .collect();
+    assert_eq!(lines, [1, 1, 1, 1, 1, 1, 2, 2, 2, 4, 4, 4, 4, 4, 4]);
+    assert_lines(&restored, &lines);
+}
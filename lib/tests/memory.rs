@@ -0,0 +1,152 @@
+use rulox::{
+    collections::List,
+    compiler,
+    vm::{RuntimeError, VM, VmError},
+};
+
+/// Compiles and runs the source on the given VM.
+fn run(vm: &mut VM<&mut Vec<u8>, &mut Vec<u8>>, source: &str) -> Result<(), VmError> {
+    let chunk = compiler::compile(source).expect("source should compile");
+    vm.interpret(&chunk)
+}
+
+#[test]
+fn growing_strings_run_out_of_memory() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_heap_limit(Some(1 << 20));
+
+    let result = run(&mut vm, "var s = \"x\";\nwhile (true) s = s + s;");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::OutOfMemory))
+    ));
+    assert_eq!(
+        String::from_utf8(err).unwrap(),
+        "Out of memory.\n[line 2] in script\n"
+    );
+}
+
+#[test]
+fn memory_that_is_no_longer_used_does_not_count() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_heap_limit(Some(64 * 1024));
+
+    // Each iteration allocates 20 KB that is freed by the next.
+    let source = "
+        var s = \"0123456789\";
+        for (var i = 0; i < 10; i = i + 1) s = s + s;
+        var total = 0;
+        for (var i = 0; i < 1000; i = i + 1) {
+            var t = s + s;
+            total = total + 1;
+        }
+        print total;
+    ";
+    run(&mut vm, source).unwrap();
+    assert_eq!(out, b"1000\n");
+}
+
+#[test]
+fn temporaries_near_the_limit_do_not_run_out() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_heap_limit(Some(128 * 1024));
+
+    // 48 KB stays live while 16 KB strings are made and dropped in every way
+    // the VM drops values, taking the heap close to the limit.
+    let source = "
+        var s = \"0123456789012345\";
+        for (var i = 0; i < 10; i = i + 1) s = s + s;
+        var kept = s + s;
+        var g;
+        fun f(x) { var y = x + \"!\"; return y == nil; }
+        var total = 0;
+        for (var i = 0; i < 2000; i = i + 1) {
+            var t = s + \"a\";
+            t = t + \"b\";
+            g = s + \"c\";
+            s + \"d\";
+            f(s);
+            total = total + 1;
+        }
+        print total;
+    ";
+    run(&mut vm, source).unwrap();
+    assert_eq!(out, b"2000\n");
+}
+
+#[test]
+fn values_kept_by_native_functions_count() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_class::<List>();
+    vm.set_heap_limit(Some(1 << 20));
+
+    let result = run(&mut vm, "var l = List(); while (true) l.push(\"item\");");
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::OutOfMemory))
+    ));
+}
+
+#[test]
+fn usage_can_be_queried() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+
+    let before = vm.memory_usage();
+    assert_eq!(before.stack_values, 0);
+    assert_eq!(before.frames, 0);
+
+    run(
+        &mut vm,
+        "var s = \"0123456789\"; for (var i = 0; i < 10; i = i + 1) s = s + s;",
+    )
+    .unwrap();
+    let after = vm.memory_usage();
+    assert!(after.heap_bytes >= before.heap_bytes + 10 * 1024);
+    assert!(after.heap_bytes < before.heap_bytes + 12 * 1024);
+
+    // Values referred to more than once are only counted once.
+    run(&mut vm, "var t = s; var u = s;").unwrap();
+    assert!(vm.memory_usage().heap_bytes < before.heap_bytes + 12 * 1024);
+
+    run(&mut vm, "s = nil; t = nil; u = nil;").unwrap();
+    assert!(vm.memory_usage().heap_bytes < before.heap_bytes + 1024);
+}
+
+#[test]
+fn the_stack_can_be_limited() {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_stack_limit(Some(9));
+
+    // The script, the function and its four arguments take six slots, and
+    // the body needs three more.
+    let source = "
+        fun f(a, b, c, d) {
+            var e = a;
+            return e + b;
+        }
+        print f(1, 2, 3, 4);
+        print 1 + f(1, 2, 3, 4);
+    ";
+    let result = run(&mut vm, source);
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::StackOverflow))
+    ));
+    assert_eq!(out, b"3\n");
+    assert_eq!(
+        String::from_utf8(err).unwrap(),
+        "Stack overflow.\n[line 4] in f()\n[line 7] in script\n"
+    );
+}
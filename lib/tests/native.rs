@@ -0,0 +1,111 @@
+use rulox::{
+    compiler,
+    native::{self, VmContext},
+    value::Value,
+    vm::{RuntimeError, VM, VmError},
+};
+
+fn add(_: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let a: f64 = native::arg(args, 0)?;
+    let b: f64 = native::arg(args, 1)?;
+    Ok((a + b).into())
+}
+
+fn greet(context: &mut VmContext, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name: String = native::arg(args, 0)?;
+    writeln!(context.out(), "Hello, {}!", name).map_err(|_| RuntimeError::Io)?;
+    Ok(Value::Nil)
+}
+
+fn count(context: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+    let count = match context.global("calls") {
+        Some(Value::Number(count)) => count + 1.0,
+        _ => 1.0,
+    };
+
+    context.define_global("calls", count.into());
+    Ok(count.into())
+}
+
+fn fail(_: &mut VmContext, _: &[Value]) -> Result<Value, RuntimeError> {
+    Err(RuntimeError::Native("Something went wrong.".to_string()))
+}
+
+/// Runs the source with the test natives defined, returning stdout, stderr and the result.
+fn run(source: &str) -> (String, String, Result<(), VmError>) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.define_native("add", 2, add);
+    vm.define_native("greet", 1, greet);
+    vm.define_native("count", 0, count);
+    vm.define_native("fail", 0, fail);
+    let result = vm.interpret(&chunk);
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        result,
+    )
+}
+
+#[test]
+fn natives_receive_converted_arguments() {
+    let (out, _, result) = run("print add(1, add(2, 3));");
+
+    assert!(result.is_ok());
+    assert_eq!(out, "6\n");
+}
+
+#[test]
+fn natives_can_write_output() {
+    let (out, _, result) = run("print greet(\"Lox\");");
+
+    assert!(result.is_ok());
+    assert_eq!(out, "Hello, Lox!\nnil\n");
+}
+
+#[test]
+fn natives_can_access_globals() {
+    let (out, _, result) = run("count(); count(); print calls;");
+
+    assert!(result.is_ok());
+    assert_eq!(out, "2\n");
+}
+
+#[test]
+fn wrong_argument_types_are_runtime_errors() {
+    let (_, err, result) = run("print add(1,\n\"2\");");
+
+    assert!(matches!(
+        result,
+        Err(VmError::Runtime(RuntimeError::InvalidArgument(2)))
+    ));
+    assert_eq!(err, "Invalid type for argument 2.\n[line 2] in script\n");
+}
+
+#[test]
+fn wrong_argument_counts_are_runtime_errors() {
+    let (_, err, _) = run("add(1);");
+
+    assert_eq!(err, "Expected 2 arguments but got 1.\n[line 1] in script\n");
+}
+
+#[test]
+fn native_errors_are_reported() {
+    let (_, err, _) = run("fail();");
+
+    assert_eq!(err, "Something went wrong.\n[line 1] in script\n");
+}
+
+#[test]
+fn only_functions_can_be_called() {
+    let (_, err, _) = run("var a = 1; a();");
+
+    assert_eq!(
+        err,
+        "Can only call functions and classes.\n[line 1] in script\n"
+    );
+}
@@ -0,0 +1,160 @@
+use rulox::{
+    compiler::{self, Chunk, CompileOptions, OptLevel},
+    value::Value,
+    verify,
+    vm::VmBuilder,
+};
+
+const OPTIMIZED: CompileOptions = CompileOptions {
+    repl: false,
+    opt_level: OptLevel::Basic,
+};
+
+fn code(chunk: &Chunk) -> Vec<u8> {
+    (0..).map_while(|offset| chunk.read(offset)).collect()
+}
+
+/// Returns the code and constants the source compiles to when optimized.
+fn optimize(source: &str) -> (Vec<u8>, Vec<Value>) {
+    let chunk = compiler::compile_with_options(source, OPTIMIZED).unwrap();
+    assert_eq!(verify::verify(&chunk), Ok(()), "{}", source);
+    (code(&chunk), chunk.constants)
+}
+
+/// Returns the code and constants the source compiles to as written.
+fn unoptimized(source: &str) -> (Vec<u8>, Vec<Value>) {
+    let chunk = compiler::compile(source).unwrap();
+    (code(&chunk), chunk.constants)
+}
+
+/// Runs the chunk, returning what it printed and its errors.
+fn run(chunk: &Chunk) -> (String, String) {
+    let mut vm = VmBuilder::new().out(Vec::new()).err(Vec::new()).build();
+    let _ = vm.interpret(chunk);
+    let (out, err) = vm.into_writers();
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn constant_expressions_are_folded() {
+    let cases = [
+        ("print -(1 + 2) * 3;", "print -9;"),
+        ("print \"a\" + \"b\" + \"c\";", "print \"abc\";"),
+        ("print !(1 < 2) == false;", "print true;"),
+        ("print 2 >= 2 and 1 != 1;", "print true and false;"),
+        ("print !nil;", "print true;"),
+        ("var x = (4 - 1) / 2;", "var x = 1.5;"),
+    ];
+
+    // Negative numbers are themselves folded, so compare optimized code.
+    for (source, folded) in cases {
+        assert_eq!(optimize(source), optimize(folded), "{}", source);
+    }
+}
+
+#[test]
+fn expressions_that_fail_are_not_folded() {
+    for source in ["print -\"a\";", "print 1 + \"a\";", "print nil < 1;"] {
+        assert_eq!(optimize(source), unoptimized(source), "{}", source);
+    }
+
+    let chunk = compiler::compile_with_options("print 1;\nprint -(\"a\" + \"b\");", OPTIMIZED);
+    assert_eq!(
+        run(&chunk.unwrap()),
+        (
+            "1\n".to_string(),
+            "Operand must be a number.\n[line 2] in script\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn only_operands_are_folded() {
+    // Neither of these may be folded into the constant before them.
+    let sources = [
+        "var x = 1; print x + 2 + 3;",
+        "{ var a = 1; var b = -a; print 2 + b; }",
+        "print (nil and 1) + 2;",
+        "print (true or 1) == 1;",
+    ];
+
+    for source in sources {
+        let chunk = compiler::compile(source).unwrap();
+        let optimized = compiler::compile_with_options(source, OPTIMIZED).unwrap();
+        assert_eq!(run(&optimized), run(&chunk), "{}", source);
+    }
+}
+
+#[test]
+fn branches_that_cannot_run_are_removed() {
+    let cases = [
+        ("if (false) print 1;", ""),
+        ("if (false) print 1; else print 2;", "print 2;"),
+        ("if (1 < 2) { print 1; } else { print 2; }", "{ print 1; }"),
+        ("if (nil) { var a = 1; print a; }", ""),
+    ];
+
+    for (source, expected) in cases {
+        assert_eq!(optimize(source), unoptimized(expected), "{}", source);
+    }
+}
+
+#[test]
+fn code_after_return_is_removed() {
+    let (code, _) = optimize("fun f() { return 1; print 2; { print 3; } }");
+    let (expected, _) = unoptimized("fun f() { return 1; }");
+    assert_eq!(code, expected);
+
+    // Only the rest of the block the return is in can't run.
+    let source = "fun f(x) { { if (x) return 1; print 2; } print 3; return 4; }\nprint f(false);";
+    let chunk = compiler::compile_with_options(source, OPTIMIZED).unwrap();
+    assert_eq!(run(&chunk).0, "2\n3\n4\n");
+}
+
+#[test]
+fn dead_code_is_still_checked_for_errors() {
+    for source in ["if (false) print;", "fun f() { return; var a = ; }"] {
+        assert!(
+            compiler::compile_with_options(source, OPTIMIZED).is_err(),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn constants_are_stored_once() {
+    let source = "var a = 1; var b = 1; print a + b + \"x\" + \"x\" + a;";
+
+    let (_, constants) = unoptimized(source);
+    assert_eq!(constants.len(), 9);
+
+    let (_, constants) = optimize(source);
+    assert_eq!(
+        constants,
+        [
+            Value::from("a"),
+            Value::Number(1.0),
+            Value::from("b"),
+            Value::from("x")
+        ]
+    );
+
+    // Zeroes of either sign print differently, so they are kept apart.
+    let chunk = compiler::compile_with_options("print 0; print -0;", OPTIMIZED).unwrap();
+    assert_eq!(chunk.constants.len(), 2);
+    assert_eq!(run(&chunk).0, "0\n-0\n");
+}
+
+#[test]
+fn optimizing_is_off_by_default() {
+    assert_eq!(CompileOptions::default().opt_level, OptLevel::None);
+
+    let (code, constants) = unoptimized("print 1 + 2;");
+    assert_eq!(code.len(), 8);
+    assert_eq!(constants.len(), 2);
+}
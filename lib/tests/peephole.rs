@@ -0,0 +1,136 @@
+use rulox::{
+    compiler::{self, Chunk, CompileOptions, OpCode, OptLevel},
+    peephole,
+    value::Value,
+    verify,
+    vm::{ExecutionMode, VmBuilder},
+};
+
+const FULL: CompileOptions = CompileOptions {
+    repl: false,
+    opt_level: OptLevel::Full,
+};
+
+fn code(chunk: &Chunk) -> Vec<u8> {
+    (0..).map_while(|offset| chunk.read(offset)).collect()
+}
+
+/// Returns the code of the first function declared in the chunk.
+fn function_code(chunk: &Chunk) -> Vec<u8> {
+    chunk
+        .constants
+        .iter()
+        .find_map(|constant| match constant {
+            Value::Function(function) => Some(code(&function.chunk)),
+            _ => None,
+        })
+        .expect("a function is declared")
+}
+
+/// Runs the chunk in the given mode, returning what it printed and its errors.
+fn run(chunk: &Chunk, mode: ExecutionMode) -> (String, String) {
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .err(Vec::new())
+        .execution_mode(mode)
+        .build();
+    let _ = vm.interpret(chunk);
+    let (out, err) = vm.into_writers();
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn sequences_are_fused() {
+    let source = "fun f(n) { for (var i = 0; i < n; i = i + 1) print i + 1; }";
+    let chunk = compiler::compile_with_options(source, FULL).unwrap();
+    assert_eq!(verify::verify(&chunk), Ok(()));
+
+    let code = function_code(&chunk);
+    for opcode in [OpCode::IncrLocal, OpCode::AddConst, OpCode::JumpIfNotLess] {
+        assert!(code.contains(&(opcode as u8)), "{:?} in {:?}", opcode, code);
+    }
+}
+
+#[test]
+fn fused_code_behaves_the_same() {
+    let sources = [
+        "fun count(n) { var s = 0; for (var i = 0; i < n; i = i + 1) s = s + i; return s; }\nprint count(10);",
+        "fun f(x) { var s = \"a\"; while (x < 3) { s = s + \"b\"; x = x + 1; } return s; }\nprint f(0);",
+        "fun f(a, b) { if (a < b) return a; else return b; }\nprint f(1, 2); print f(4, 3);",
+        "fun f(a) { return a < 1 and a + 1; }\nprint f(0); print f(2);",
+        "fun f() { var x = nil; x = x + 1; }\nprint 1;\nf();",
+        "fun f(x) {\n  if (x < \"a\") print 1;\n}\nf(1);",
+    ];
+
+    for source in sources {
+        let plain = compiler::compile(source).unwrap();
+        let fused = compiler::compile_with_options(source, FULL).unwrap();
+        assert_eq!(verify::verify(&fused), Ok(()), "{}", source);
+
+        for mode in [ExecutionMode::Checked, ExecutionMode::Fast] {
+            assert_eq!(run(&fused, mode), run(&plain, mode), "{}", source);
+        }
+    }
+}
+
+#[test]
+fn errors_keep_their_lines() {
+    let source =
+        "fun f(x) {\n  var y = x\n    + 1;\n  if (y\n    < \"a\") print y;\n}\nf(nil);\nf(1);";
+    let chunk = compiler::compile_with_options(source, FULL).unwrap();
+    let (_, err) = run(&chunk, ExecutionMode::Checked);
+    assert_eq!(
+        err,
+        "Operands must be two numbers or two strings.\n[line 3] in f()\n[line 7] in script\n"
+    );
+
+    let chunk = compiler::compile_with_options(
+        "fun f(x) {\n  if (x\n    < \"a\") print x;\n}\nf(1);",
+        FULL,
+    )
+    .unwrap();
+    let (_, err) = run(&chunk, ExecutionMode::Fast);
+    assert_eq!(
+        err,
+        "Operands must be numbers.\n[line 3] in f()\n[line 5] in script\n"
+    );
+}
+
+#[test]
+fn jump_targets_are_not_fused() {
+    // The add is jumped to, skipping the constant, so the two can't be fused.
+    let mut chunk = Chunk::new();
+    chunk.constants.push(Value::Number(1.0));
+    let code = [
+        OpCode::Nil as u8,
+        OpCode::Nil as u8,
+        OpCode::JumpIfFalse as u8,
+        0,
+        2,
+        OpCode::Constant as u8,
+        0,
+        OpCode::Add as u8,
+        OpCode::Return as u8,
+    ];
+    for byte in code {
+        chunk.write(byte, 1);
+    }
+
+    peephole::optimize(&mut chunk);
+    assert_eq!(self::code(&chunk), code);
+}
+
+#[test]
+fn optimizing_twice_changes_nothing() {
+    let source = "fun f(n) { var i = 0; while (i < n) { i = i + 1; } return i + 2; }\nprint f(3);";
+    let mut chunk = compiler::compile_with_options(source, FULL).unwrap();
+    let once = code(&chunk);
+
+    peephole::optimize(&mut chunk);
+    assert_eq!(code(&chunk), once);
+    assert_eq!(run(&chunk, ExecutionMode::Checked).0, "5\n");
+}
@@ -0,0 +1,173 @@
+use rulox::{
+    collections::{List, Record},
+    differential::{self, Status},
+    generate::Generator,
+    register::{self, Instruction},
+    stdlib::Capability,
+    vm::VmBuilder,
+};
+
+/// Runs the source on both backends, asserting they behave the same.
+fn assert_same(source: &str) {
+    let register = differential::run_register_vm(source);
+    let stack = differential::run_vm(source);
+    assert_eq!(register, stack, "{}", source);
+}
+
+#[test]
+fn generated_programs_match_the_stack_vm() {
+    let mut generator = Generator::new(0);
+
+    for _ in 0..1000 {
+        assert_same(&generator.program());
+    }
+}
+
+#[test]
+fn programs_match_the_stack_vm() {
+    let sources = [
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(15);",
+        "var s = \"\"; for (var i = 0; i < 5; i = i + 1) { s = s + \"a\"; } print s;",
+        "{ var a = 1; var b = 2; var c; c = a < b and b <= 2 or a; print c; print a != b; }",
+        "{ var a = nil; print a or \"x\"; print !a; print -(a == nil and 3); }",
+        "fun f(a, b, c) { var d = a; { var e = b; d = d + e; } return d + c; } print f(1, 2, 3);",
+        "fun outer() { fun inner(x) { return x * 2; } return inner(inner(3)); } print outer();",
+        "var i = 0; while (i < 3) { var x = i; i = x + 1; print i; }",
+        "fun f() {} print f(); print f; print clock;",
+    ];
+
+    for source in sources {
+        assert_same(source);
+    }
+}
+
+/// An operand read before a later part of the expression assigns the same
+/// local keeps its old value, as on the stack VM.
+#[test]
+fn operands_are_read_before_later_assignments() {
+    let sources = [
+        "{ var a = 1; print a + (a = 2); print a; }",
+        "{ var a = 1; print a - (a = 5) * a; }",
+        "{ var a = 1; var b = a < (a = 0); print b; }",
+        "{ var a = 1; a = a + (a = 10); print a; }",
+        "fun f(a) { return a * (a = 3); } print f(2);",
+        "{ var a = 1; print (a = 2) + a; }",
+    ];
+
+    for source in sources {
+        let output = differential::run_register_vm(source);
+        assert_eq!(output.status, Status::Success, "{}", output);
+        assert_same(source);
+    }
+}
+
+#[test]
+fn errors_match_the_stack_vm() {
+    let sources = [
+        "print 1 +;\nvar = 2;\na + b = c;\nprint \"unterminated",
+        "{ var a = a; }",
+        "return 1;",
+        "var a = 1;\nprint a +\n\"b\";",
+        "fun f(n) {\n  return -n;\n}\nfun g() { return f(\"x\"); }\ng();",
+        "print undefined;",
+        "undefined = 1;",
+        "fun f(a) {} f(1, 2);",
+        "var x = 1; x();",
+        "fun f() { f(); } f();",
+    ];
+
+    for source in sources {
+        assert_same(source);
+    }
+}
+
+#[test]
+fn binary_operators_write_to_their_target() {
+    let code = register::compile("{ var a; var b = 1; var c = 2; a = b + c; }").unwrap();
+
+    assert!(
+        code.instructions().contains(&Instruction::Add {
+            dst: 1,
+            left: 2,
+            right: 3
+        }),
+        "{:?}",
+        code.instructions()
+    );
+    assert!(
+        !code
+            .instructions()
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::Move { .. }))
+    );
+}
+
+#[test]
+fn register_code_executes_fewer_instructions() {
+    let source = "
+        fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        var total = 0;
+        for (var i = 0; i < 10; i = i + 1) total = total + fib(i);
+        print total;
+    ";
+
+    let chunk = rulox::compiler::compile(source).unwrap();
+    let mut stack = VmBuilder::new().out(Vec::new()).fuel(u64::MAX).build();
+    stack.interpret(&chunk).unwrap();
+    let stack_instructions = u64::MAX - stack.fuel().unwrap();
+
+    let code = register::compile(source).unwrap();
+    let mut vm = VmBuilder::new().out(Vec::new()).build_register();
+    vm.interpret(&code).unwrap();
+
+    assert!(vm.instruction_count() < stack_instructions);
+    assert_eq!(vm.into_writers().0, stack.into_writers().0);
+}
+
+#[test]
+fn natives_and_classes_are_shared_with_the_stack_vm() {
+    let source = "
+        var list = List();
+        for (var i = 0; i < 4; i = i + 1) list.push(i * i);
+        var r = Record();
+        r.total = 0;
+        for (var i = 0; i < list.len(); i = i + 1) r.total = r.total + list.get(i);
+        print r.total;
+        var s = r;
+        s.x = (s = 1);
+        print r.x;
+        print clock() >= 0;
+    ";
+
+    let code = register::compile(source).unwrap();
+    let mut vm = VmBuilder::new()
+        .out(Vec::new())
+        .class::<Record>()
+        .class::<List>()
+        .allow(Capability::Time)
+        .build_register();
+    vm.interpret(&code).unwrap();
+
+    assert_eq!(vm.into_writers().0, b"14\n1\ntrue\n");
+}
+
+#[test]
+fn frame_limit_applies() {
+    let code = register::compile("fun f(n) { if (n > 0) f(n - 1); } f(10);").unwrap();
+
+    let mut vm = VmBuilder::new().frame_limit(12).build_register();
+    assert!(vm.interpret(&code).is_ok());
+
+    let mut vm = VmBuilder::new()
+        .err(Vec::new())
+        .frame_limit(11)
+        .build_register();
+    assert!(vm.interpret(&code).is_err());
+
+    let err = String::from_utf8(vm.into_writers().1).unwrap();
+    assert!(
+        err.starts_with("Stack overflow.\n[line 1] in f()\n"),
+        "{}",
+        err
+    );
+}
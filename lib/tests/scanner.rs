@@ -0,0 +1,77 @@
+use rulox::scanner::{Scanner, Token, TokenType};
+
+/// Scans the source, which must be a single token.
+fn scan(source: &str) -> Token<'_> {
+    let mut scanner = Scanner::new(source);
+    let token = scanner.scan_token();
+    assert_eq!(
+        scanner.scan_token().token_type,
+        TokenType::EOF,
+        "{}",
+        source
+    );
+    token
+}
+
+#[test]
+fn number_literals() {
+    let cases = [
+        ("0", 0.0),
+        ("123", 123.0),
+        ("1.5", 1.5),
+        ("1_000_000", 1_000_000.0),
+        ("1_0.2_5", 10.25),
+        ("1e3", 1000.0),
+        ("2.5E-1", 0.25),
+        ("1e+2", 100.0),
+        ("1e1_0", 1e10),
+        ("0xff", 255.0),
+        ("0XFF_FF", 65535.0),
+        ("0b1010", 10.0),
+        ("0B1_0", 2.0),
+        ("0o17", 15.0),
+        ("0O7_7", 63.0),
+    ];
+
+    for (source, value) in cases {
+        let token = scan(source);
+        assert_eq!(token.token_type, TokenType::Number, "{}", source);
+        assert_eq!(token.lexeme, source);
+        assert_eq!(token.number_value(), Some(value), "{}", source);
+    }
+}
+
+#[test]
+fn malformed_number_literals() {
+    let cases = [
+        ("1__0", "Digit separator must be between digits."),
+        ("1_", "Digit separator must be between digits."),
+        ("1e", "Missing digits in exponent."),
+        ("1e+", "Missing digits in exponent."),
+        ("0x", "Missing digits in hexadecimal number."),
+        ("0b", "Missing digits in binary number."),
+        ("0o", "Missing digits in octal number."),
+        ("0x_1", "Digit separator must be between digits."),
+        ("0x1g", "Invalid digit in hexadecimal number."),
+        ("0b102", "Invalid digit in binary number."),
+        ("0o78", "Invalid digit in octal number."),
+    ];
+
+    for (source, message) in cases {
+        // The whole literal is skipped, leaving no stray tokens behind.
+        let token = scan(source);
+        assert_eq!(token.token_type, TokenType::Error, "{}", source);
+        assert_eq!(token.lexeme, message, "{}", source);
+    }
+}
+
+#[test]
+fn a_dot_without_digits_is_not_part_of_a_number() {
+    let mut scanner = Scanner::new("1.foo");
+
+    let number = scanner.scan_token();
+    assert_eq!(number.token_type, TokenType::Number);
+    assert_eq!(number.lexeme, "1");
+    assert_eq!(scanner.scan_token().token_type, TokenType::Dot);
+    assert_eq!(scanner.scan_token().token_type, TokenType::Identifier);
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use rulox::{
+    collections::{List, Record},
+    compiler,
+    serde::{Segment, from_value, to_value},
+    value::Value,
+    vm::VM,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    debug: bool,
+    servers: Vec<Server>,
+    timeout: Option<f64>,
+    mode: Mode,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Server {
+    host: String,
+    port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Mode {
+    Fast,
+    Limited(u32),
+    Custom { level: i8 },
+}
+
+fn config() -> Config {
+    Config {
+        name: "example".to_string(),
+        debug: false,
+        servers: vec![
+            Server {
+                host: "localhost".to_string(),
+                port: 8080,
+            },
+            Server {
+                host: "example.com".to_string(),
+                port: 443,
+            },
+        ],
+        timeout: None,
+        mode: Mode::Limited(3),
+    }
+}
+
+/// Runs the source with `input` defined as a global, returning stdout and the
+/// value of the global `output`.
+fn run(source: &str, input: Value) -> (String, Value) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = VM::new(&mut out, &mut err);
+    vm.set_global("input", input);
+    vm.interpret(&chunk).expect("source should run");
+    let output = vm.get_global("output").unwrap_or(Value::Nil);
+
+    (String::from_utf8(out).unwrap(), output)
+}
+
+#[test]
+fn rust_data_round_trips() {
+    let value = to_value(&config()).unwrap();
+    assert_eq!(from_value::<Config>(value).unwrap(), config());
+
+    for mode in [Mode::Fast, Mode::Limited(1), Mode::Custom { level: -2 }] {
+        let value = to_value(&mode).unwrap();
+        assert_eq!(from_value::<Mode>(value).unwrap(), mode);
+    }
+}
+
+#[test]
+fn scripts_can_read_and_modify_converted_data() {
+    let source = "
+        print input.name;
+        print input.servers.get(1).host;
+        print input.timeout;
+        print input.mode.Limited;
+        input.servers.get(0).port = 9090;
+        input.timeout = 2.5;
+        input.mode = \"Fast\";
+        var output = input;
+    ";
+    let (out, output) = run(source, to_value(&config()).unwrap());
+    assert_eq!(out, "example\nexample.com\nnil\n3\n");
+
+    let mut expected = config();
+    expected.servers[0].port = 9090;
+    expected.timeout = Some(2.5);
+    expected.mode = Mode::Fast;
+    assert_eq!(from_value::<Config>(output).unwrap(), expected);
+}
+
+#[test]
+fn json_converts_to_and_from_values() {
+    let json = r#"{"items":[1,"two",true,null],"nested":{"value":0.5}}"#;
+    let value: Value = serde_json::from_str(json).unwrap();
+
+    let source = "
+        print input.items.len();
+        input.items.push(input.nested.value * 2);
+        var output = input;
+    ";
+    let (out, output) = run(source, value);
+    assert_eq!(out, "4\n");
+    assert_eq!(
+        serde_json::to_string(&output).unwrap(),
+        r#"{"items":[1.0,"two",true,null,1.0],"nested":{"value":0.5}}"#
+    );
+}
+
+#[test]
+fn type_mismatches_report_where_they_are() {
+    let value = to_value(&config()).unwrap();
+    let Value::Instance(instance) = value.clone() else {
+        panic!("structs convert to instances");
+    };
+
+    let servers = instance.borrow::<Record>().unwrap().get("servers").cloned();
+    let Some(Value::Instance(servers)) = servers else {
+        panic!("sequences convert to instances");
+    };
+
+    let second = servers.borrow::<List>().unwrap().items[1].clone();
+    let Value::Instance(second) = second else {
+        panic!("structs convert to instances");
+    };
+
+    second.borrow_mut::<Record>().unwrap().set("port", "https");
+    let error = from_value::<Config>(value.clone()).unwrap_err();
+    assert_eq!(
+        error.path,
+        [
+            Segment::Field("servers".to_string()),
+            Segment::Index(1),
+            Segment::Field("port".to_string())
+        ]
+    );
+    assert_eq!(
+        error.to_string(),
+        "servers[1].port: invalid type: string \"https\", expected u16"
+    );
+
+    second.borrow_mut::<Record>().unwrap().set("port", 70000);
+    let error = from_value::<Config>(value.clone()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "servers[1].port: invalid value: integer `70000`, expected u16"
+    );
+
+    second.borrow_mut::<Record>().unwrap().set("port", 443);
+    instance.borrow_mut::<Record>().unwrap().set("mode", "Slow");
+    let error = from_value::<Config>(value).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "mode: unknown variant `Slow`, expected one of `Fast`, `Limited`, `Custom`"
+    );
+}
+
+#[test]
+fn missing_fields_are_reported() {
+    let mut record = Record::new();
+    record.set("host", "localhost");
+
+    let error = from_value::<Server>(record.into()).unwrap_err();
+    assert_eq!(error.to_string(), "missing field `port`");
+}
+
+#[test]
+fn values_that_cannot_be_converted_are_errors() {
+    let error = to_value(&BTreeMap::from([(1, 2)])).unwrap_err();
+    assert_eq!(error.to_string(), "map keys must be strings, found 1");
+
+    let error = to_value(&vec![u64::MAX]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[0]: integer 18446744073709551615 cannot be represented exactly as a number"
+    );
+
+    let chunk = compiler::compile("fun f() {}").unwrap();
+    let function = chunk
+        .constants
+        .iter()
+        .find(|constant| matches!(constant, Value::Function(_)))
+        .cloned()
+        .unwrap();
+    let error = serde_json::to_string(&function).unwrap_err();
+    assert_eq!(error.to_string(), "cannot serialize <fn f>");
+
+    let error = from_value::<Config>(function).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid type: <fn f>, expected struct Config"
+    );
+}
@@ -0,0 +1,184 @@
+use rulox::{
+    compiler::{self, Chunk, OpCode},
+    serialize::{DeserializeError, FORMAT_VERSION, MAGIC},
+    verify::VerifyError,
+};
+
+const HEADER_SIZE: usize = 10;
+
+/// Wraps the body in a header with a matching checksum.
+fn with_header(body: &[u8]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+fn u32(value: u32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+/// Returns the body of a chunk whose code is `Nil; Return` on line 1, with
+/// the given line runs and constants.
+fn body(runs: &[(u32, u32)], constants: &[&[u8]]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&u32(2));
+    body.extend_from_slice(&[OpCode::Nil.into(), OpCode::Return.into()]);
+
+    body.extend_from_slice(&u32(runs.len() as u32));
+    for &(line, count) in runs {
+        body.extend_from_slice(&u32(line));
+        body.extend_from_slice(&u32(count));
+    }
+
+    body.extend_from_slice(&u32(constants.len() as u32));
+    for constant in constants {
+        body.extend_from_slice(constant);
+    }
+
+    body
+}
+
+/// Deserializes the bytes, which must fail.
+fn deserialize_error(bytes: &[u8]) -> DeserializeError {
+    match Chunk::deserialize(bytes) {
+        Ok(_) => panic!("deserialized a malformed chunk"),
+        Err(error) => error,
+    }
+}
+
+fn serialized() -> Vec<u8> {
+    compiler::compile("var a = \"x\"; print a + \"y\";")
+        .unwrap()
+        .serialize()
+}
+
+#[test]
+fn hand_written_body_deserializes() {
+    let chunk = Chunk::deserialize(&with_header(&body(&[(1, 2)], &[]))).unwrap();
+
+    assert_eq!(chunk.read(0), Some(OpCode::Nil.into()));
+    assert_eq!(chunk.line_for_offset(1), Some(1));
+}
+
+#[test]
+fn bad_magic() {
+    let mut bytes = serialized();
+    bytes[..4].copy_from_slice(b"LOXB");
+
+    assert_eq!(deserialize_error(&bytes), DeserializeError::InvalidMagic);
+    assert_eq!(deserialize_error(b""), DeserializeError::InvalidMagic);
+}
+
+#[test]
+fn unsupported_version() {
+    let mut bytes = serialized();
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+    assert_eq!(
+        deserialize_error(&bytes),
+        DeserializeError::UnsupportedVersion(FORMAT_VERSION + 1)
+    );
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut bytes = serialized();
+    let expected = u32::from_le_bytes(bytes[6..10].try_into().unwrap());
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    let actual = crc32fast::hash(&bytes[HEADER_SIZE..]);
+
+    assert_eq!(
+        deserialize_error(&bytes),
+        DeserializeError::ChecksumMismatch { expected, actual }
+    );
+}
+
+#[test]
+fn truncated_input() {
+    let bytes = serialized();
+
+    // Cut short inside the header.
+    assert_eq!(
+        deserialize_error(&bytes[..7]),
+        DeserializeError::UnexpectedEof
+    );
+
+    // Cut short inside the body, with a checksum that matches what is left.
+    for len in [0, 3, 20, bytes.len() - HEADER_SIZE - 1] {
+        let truncated = with_header(&bytes[HEADER_SIZE..HEADER_SIZE + len]);
+        assert_eq!(
+            deserialize_error(&truncated),
+            DeserializeError::UnexpectedEof,
+            "{} bytes of body",
+            len
+        );
+    }
+}
+
+#[test]
+fn unknown_constant_tag() {
+    let bytes = with_header(&body(&[(1, 2)], &[&[9]]));
+
+    assert_eq!(
+        deserialize_error(&bytes),
+        DeserializeError::InvalidConstantTag(9)
+    );
+}
+
+#[test]
+fn invalid_string_constant() {
+    let mut string = vec![1];
+    string.extend_from_slice(&u32(2));
+    string.extend_from_slice(&[0xc3, 0x28]);
+    let bytes = with_header(&body(&[(1, 2)], &[&string]));
+
+    assert_eq!(deserialize_error(&bytes), DeserializeError::InvalidString);
+}
+
+#[test]
+fn bad_line_table() {
+    let cases: [&[(u32, u32)]; 4] = [
+        // Too few bytes covered.
+        &[(1, 1)],
+        // Too many bytes covered.
+        &[(1, 1), (2, 2)],
+        // An empty run.
+        &[(1, 0), (1, 2)],
+        // No runs at all.
+        &[],
+    ];
+
+    for runs in cases {
+        assert_eq!(
+            deserialize_error(&with_header(&body(runs, &[]))),
+            DeserializeError::InvalidLineTable,
+            "{:?}",
+            runs
+        );
+    }
+}
+
+#[test]
+fn trailing_bytes() {
+    let mut body = body(&[(1, 2)], &[]);
+    body.push(0);
+
+    assert_eq!(
+        deserialize_error(&with_header(&body)),
+        DeserializeError::TrailingData
+    );
+}
+
+#[test]
+fn unverifiable_code() {
+    let mut body = body(&[(1, 2)], &[]);
+    body[4] = OpCode::Pop.into();
+
+    assert_eq!(
+        deserialize_error(&with_header(&body)),
+        DeserializeError::Verify(VerifyError::StackUnderflow { offset: 0 })
+    );
+}
@@ -0,0 +1,191 @@
+use std::{fs, path::PathBuf, process};
+
+use rulox::{
+    compiler,
+    stdlib::Capability,
+    vm::{RuntimeError, VmBuilder, VmError},
+};
+
+/// Runs the source on a VM allowed the capabilities, returning stdout, stderr
+/// and the runtime error if there was one.
+fn run(builder: VmBuilder, source: &str) -> (String, String, Option<RuntimeError>) {
+    let chunk = compiler::compile(source).expect("source should compile");
+
+    let mut vm = builder.out(Vec::new()).err(Vec::new()).build();
+    let error = match vm.interpret(&chunk) {
+        Ok(()) => None,
+        Err(VmError::Runtime(error)) => Some(error),
+        Err(VmError::Compilation) => panic!("compiled chunks can't fail to compile"),
+    };
+    let (out, err) = vm.into_writers();
+
+    (
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+        error,
+    )
+}
+
+/// Creates an empty directory for a test to use as the root of the file system.
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rulox-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+#[test]
+fn functions_are_denied_unless_allowed() {
+    let calls = [
+        ("readLine()", "readLine", "io"),
+        ("readFile(\"a\")", "readFile", "fs"),
+        ("writeFile(\"a\", \"b\")", "writeFile", "fs"),
+        ("fileExists(\"a\")", "fileExists", "fs"),
+        ("getEnv(\"PATH\")", "getEnv", "os"),
+        ("clock()", "clock", "time"),
+        ("random()", "random", "random"),
+    ];
+
+    for (call, function, capability) in calls {
+        let source = format!("print \"before\";\n{};", call);
+        let (out, err, error) = run(VmBuilder::new(), &source);
+
+        assert_eq!(out, "before\n");
+        assert_eq!(
+            err,
+            format!(
+                "Calling '{}' requires the '{}' capability.\n[line 2] in script\n",
+                function, capability
+            )
+        );
+        assert!(matches!(error, Some(RuntimeError::CapabilityDenied { .. })));
+    }
+}
+
+#[test]
+fn allowed_functions_can_be_called() {
+    let builder = VmBuilder::new()
+        .allow(Capability::Os)
+        .allow(Capability::Time)
+        .allow(Capability::Random);
+
+    let source = "
+        print getEnv(\"PATH\") != nil;
+        print getEnv(\"RULOX_UNSET_VARIABLE\");
+        print clock() > 0;
+        var r = random();
+        print r >= 0 and r < 1;
+        print random() != random();
+    ";
+    let (out, err, error) = run(builder, source);
+
+    assert_eq!(out, "true\nnil\ntrue\ntrue\ntrue\n");
+    assert_eq!(err, "");
+    assert!(error.is_none());
+}
+
+#[test]
+fn files_can_be_read_and_written_below_the_root() {
+    let root = temp_root("files");
+    fs::create_dir(root.join("dir")).unwrap();
+    let builder = VmBuilder::new().allow(Capability::Fs { root: root.clone() });
+
+    let source = "
+        print fileExists(\"dir/greeting.txt\");
+        writeFile(\"dir/greeting.txt\", \"hello\");
+        print fileExists(\"./dir/greeting.txt\");
+        print readFile(\"dir/greeting.txt\") + \" world\";
+    ";
+    let (out, err, _) = run(builder, source);
+
+    assert_eq!(out, "false\ntrue\nhello world\n");
+    assert_eq!(err, "");
+    assert_eq!(
+        fs::read_to_string(root.join("dir/greeting.txt")).unwrap(),
+        "hello"
+    );
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn paths_outside_the_root_are_refused() {
+    let root = temp_root("escape");
+    let outside = root.parent().unwrap().join("rulox-outside.txt");
+
+    for path in [
+        "../rulox-outside.txt",
+        "dir/../../x",
+        "",
+        outside.to_str().unwrap(),
+    ] {
+        let builder = VmBuilder::new().allow(Capability::Fs { root: root.clone() });
+        let source = format!("writeFile(\"{}\", \"escaped\");", path);
+        let (_, err, _) = run(builder, &source);
+
+        assert_eq!(
+            err,
+            format!(
+                "Path '{}' is outside the allowed directory.\n[line 1] in script\n",
+                path
+            )
+        );
+    }
+
+    assert!(!outside.exists());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn missing_files_are_runtime_errors() {
+    let root = temp_root("missing");
+    let builder = VmBuilder::new().allow(Capability::Fs { root: root.clone() });
+    let (_, err, error) = run(builder, "readFile(\"missing.txt\");");
+
+    assert!(err.starts_with("Could not read file 'missing.txt': "));
+    assert!(matches!(error, Some(RuntimeError::Native(_))));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn files_too_big_for_the_heap_limit_are_not_read() {
+    let root = temp_root("big");
+    fs::write(root.join("big.txt"), "x".repeat(1 << 20)).unwrap();
+    fs::write(root.join("small.txt"), "x".repeat(1 << 10)).unwrap();
+    let builder = || {
+        VmBuilder::new()
+            .allow(Capability::Fs { root: root.clone() })
+            .heap_limit(64 * 1024)
+    };
+
+    let (out, _, error) = run(builder(), "print readFile(\"small.txt\") == nil;");
+    assert_eq!(out, "false\n");
+    assert!(error.is_none());
+
+    let (_, err, error) = run(builder(), "readFile(\"big.txt\");");
+    assert_eq!(err, "Out of memory.\n[line 1] in script\n");
+    assert!(matches!(error, Some(RuntimeError::OutOfMemory)));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn allowing_fs_again_replaces_the_root() {
+    let first = temp_root("first");
+    let second = temp_root("second");
+    fs::write(second.join("file.txt"), "second").unwrap();
+
+    let builder = VmBuilder::new()
+        .allow(Capability::Fs {
+            root: first.clone(),
+        })
+        .allow(Capability::Fs {
+            root: second.clone(),
+        });
+    let (out, _, _) = run(builder, "print readFile(\"file.txt\");");
+    assert_eq!(out, "second\n");
+
+    fs::remove_dir_all(first).unwrap();
+    fs::remove_dir_all(second).unwrap();
+}
@@ -0,0 +1,164 @@
+use rulox::{
+    compiler::{Chunk, OpCode},
+    value::Value,
+    verify::{self, VerifyError},
+};
+
+/// Builds a chunk from raw bytes, as if it had been loaded from a file.
+fn chunk(code: &[u8], constants: Vec<Value>) -> Chunk {
+    let mut chunk = Chunk::new();
+    for &byte in code {
+        chunk.write(byte, 1);
+    }
+    chunk.constants = constants;
+
+    chunk
+}
+
+fn op(opcode: OpCode) -> u8 {
+    opcode.into()
+}
+
+#[test]
+fn well_formed_chunk_verifies() {
+    let code = [
+        op(OpCode::Constant),
+        0,
+        op(OpCode::Print),
+        op(OpCode::Nil),
+        op(OpCode::Return),
+    ];
+
+    assert_eq!(verify::verify(&chunk(&code, vec![1.0.into()])), Ok(()));
+}
+
+#[test]
+fn invalid_opcode() {
+    let code = [op(OpCode::Nil), 0xff, op(OpCode::Return)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::InvalidOpCode {
+            offset: 1,
+            opcode: 0xff
+        })
+    );
+}
+
+#[test]
+fn truncated_instruction() {
+    let code = [op(OpCode::Nil), op(OpCode::Jump), 0];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::TruncatedInstruction { offset: 1 })
+    );
+}
+
+#[test]
+fn invalid_constant() {
+    let code = [op(OpCode::Constant), 1, op(OpCode::Return)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![1.0.into()])),
+        Err(VerifyError::InvalidConstant {
+            offset: 0,
+            index: 1
+        })
+    );
+}
+
+#[test]
+fn invalid_name() {
+    let code = [op(OpCode::GetGlobal), 0, op(OpCode::Return)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![1.0.into()])),
+        Err(VerifyError::InvalidName {
+            offset: 0,
+            index: 0
+        })
+    );
+}
+
+#[test]
+fn invalid_local() {
+    // Only slot 0, holding the script itself, exists.
+    let code = [op(OpCode::GetLocal), 1, op(OpCode::Return)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::InvalidLocal { offset: 0, slot: 1 })
+    );
+}
+
+#[test]
+fn stack_underflow() {
+    let code = [op(OpCode::Nil), op(OpCode::Add), op(OpCode::Return)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::StackUnderflow { offset: 1 })
+    );
+}
+
+#[test]
+fn jump_into_an_operand() {
+    let code = [
+        op(OpCode::Jump),
+        0,
+        1,
+        op(OpCode::Constant),
+        0,
+        op(OpCode::Return),
+    ];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![1.0.into()])),
+        Err(VerifyError::InvalidJump {
+            offset: 0,
+            target: 4
+        })
+    );
+}
+
+#[test]
+fn loop_before_the_start() {
+    let code = [op(OpCode::Loop), 0, 5];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::InvalidJump {
+            offset: 0,
+            target: 0
+        })
+    );
+}
+
+#[test]
+fn inconsistent_stack() {
+    // The return is reached with one value more when the jump is not taken.
+    let code = [
+        op(OpCode::Nil),
+        op(OpCode::JumpIfFalse),
+        0,
+        1,
+        op(OpCode::Nil),
+        op(OpCode::Return),
+    ];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::InconsistentStack { offset: 5 })
+    );
+}
+
+#[test]
+fn missing_return() {
+    let code = [op(OpCode::Nil), op(OpCode::Print)];
+
+    assert_eq!(
+        verify::verify(&chunk(&code, vec![])),
+        Err(VerifyError::MissingReturn)
+    );
+}